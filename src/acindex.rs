@@ -0,0 +1,287 @@
+//! A compact on-disk index of per-allele AC/AN from a cohort so that frequency filters
+//! can be applied without the full cohort VCF/BCF.
+//! Build with `vcfexpress build-ac-index cohort.bcf -o cohort.acidx` and query in an
+//! expression with `acindex:lookup(variant)`.
+use mlua::{AnyUserData, Lua, UserData, UserDataMethods, Value};
+use rust_htslib::bcf::{self, Read};
+use rustc_hash::FxHashMap;
+use std::io::{BufReader, BufWriter, Read as IoRead, Write};
+
+use crate::variant::Variant;
+
+const MAGIC: &[u8; 8] = b"VXACIDX1";
+
+#[derive(Debug, PartialEq)]
+struct Entry {
+    pos: i64,
+    ac: u32,
+    an: u32,
+    ref_allele: Vec<u8>,
+    alt: Vec<u8>,
+}
+
+/// AcIndex holds AC/AN for each (chrom, pos, ref, alt) sorted by position within each chromosome.
+#[derive(Default)]
+pub struct AcIndex {
+    contigs: FxHashMap<String, Vec<Entry>>,
+}
+
+impl UserData for AcIndex {}
+
+/// Allele counts from the genotypes. Returns AC for each alt and AN.
+fn genotype_counts(record: &bcf::Record) -> Option<(Vec<u32>, u32)> {
+    let gts = record.format(b"GT").integer().ok()?;
+    let mut ac = vec![0u32; record.allele_count().saturating_sub(1) as usize];
+    let mut an = 0;
+    for gt in gts.iter() {
+        for &a in gt
+            .iter()
+            .take_while(|&&a| a != rust_htslib::htslib::bcf_int32_vector_end)
+        {
+            let a = crate::genotypes::allele_index(a);
+            if a < 0 {
+                continue;
+            }
+            an += 1;
+            if a > 0 && (a as usize) <= ac.len() {
+                ac[a as usize - 1] += 1;
+            }
+        }
+    }
+    Some((ac, an))
+}
+
+/// Allele counts from the AC and AN INFO fields for sites-only files.
+fn info_counts(record: &bcf::Record) -> Option<(Vec<u32>, u32)> {
+    let ac = record.info(b"AC").integer().ok()??;
+    let an = record.info(b"AN").integer().ok()??;
+    Some((ac.iter().map(|&v| v.max(0) as u32).collect(), an[0].max(0) as u32))
+}
+
+impl AcIndex {
+    /// Build the index from a VCF/BCF. AC/AN are counted from GT when samples are present,
+    /// otherwise the AC and AN INFO fields are used.
+    pub fn build(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut reader = match path {
+            "-" | "stdin" => bcf::Reader::from_stdin()?,
+            _ => bcf::Reader::from_path(path)?,
+        };
+        _ = reader.set_threads(2);
+        let has_samples = reader.header().sample_count() > 0;
+        let mut idx = AcIndex::default();
+        for record in reader.records() {
+            let record = record?;
+            let counts = if has_samples {
+                genotype_counts(&record)
+            } else {
+                info_counts(&record)
+            };
+            let (ac, an) = match counts {
+                Some(c) => c,
+                None => {
+                    log::warn!(
+                        "skipping record at position {} without GT or AC/AN",
+                        record.pos() + 1
+                    );
+                    continue;
+                }
+            };
+            let chrom = record
+                .rid()
+                .and_then(|rid| record.header().rid2name(rid).ok())
+                .map(|c| String::from_utf8_lossy(c).to_string())
+                .unwrap_or_default();
+            let alleles = record.alleles();
+            let entries = idx.contigs.entry(chrom).or_default();
+            for (alt, ac) in alleles.iter().skip(1).zip(ac) {
+                entries.push(Entry {
+                    pos: record.pos(),
+                    ac,
+                    an,
+                    ref_allele: alleles[0].to_vec(),
+                    alt: alt.to_vec(),
+                });
+            }
+        }
+        for entries in idx.contigs.values_mut() {
+            entries.sort_by_key(|e| e.pos);
+        }
+        Ok(idx)
+    }
+
+    pub fn write<W: Write>(&self, w: W) -> std::io::Result<()> {
+        let mut w = BufWriter::new(w);
+        w.write_all(MAGIC)?;
+        let mut names = self.contigs.keys().collect::<Vec<_>>();
+        names.sort();
+        w.write_all(&(names.len() as u32).to_le_bytes())?;
+        for name in names {
+            let entries = &self.contigs[name];
+            w.write_all(&(name.len() as u32).to_le_bytes())?;
+            w.write_all(name.as_bytes())?;
+            w.write_all(&(entries.len() as u64).to_le_bytes())?;
+            for e in entries {
+                w.write_all(&e.pos.to_le_bytes())?;
+                w.write_all(&e.ac.to_le_bytes())?;
+                w.write_all(&e.an.to_le_bytes())?;
+                w.write_all(&(e.ref_allele.len() as u32).to_le_bytes())?;
+                w.write_all(&e.ref_allele)?;
+                w.write_all(&(e.alt.len() as u32).to_le_bytes())?;
+                w.write_all(&e.alt)?;
+            }
+        }
+        w.flush()
+    }
+
+    pub fn from_path(path: &str) -> std::io::Result<Self> {
+        let mut r = BufReader::new(std::fs::File::open(path)?);
+        let mut magic = [0u8; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{} is not an AC index built by vcfexpress", path),
+            ));
+        }
+        let mut idx = AcIndex::default();
+        for _ in 0..read_u32(&mut r)? {
+            let name = String::from_utf8_lossy(&read_bytes(&mut r)?).to_string();
+            let mut buf = [0u8; 8];
+            r.read_exact(&mut buf)?;
+            let n = u64::from_le_bytes(buf) as usize;
+            let mut entries = Vec::with_capacity(n);
+            for _ in 0..n {
+                r.read_exact(&mut buf)?;
+                let pos = i64::from_le_bytes(buf);
+                let ac = read_u32(&mut r)?;
+                let an = read_u32(&mut r)?;
+                let ref_allele = read_bytes(&mut r)?;
+                let alt = read_bytes(&mut r)?;
+                entries.push(Entry {
+                    pos,
+                    ac,
+                    an,
+                    ref_allele,
+                    alt,
+                });
+            }
+            idx.contigs.insert(name, entries);
+        }
+        Ok(idx)
+    }
+
+    /// Find AC/AN for an allele.
+    pub fn get(&self, chrom: &str, pos: i64, ref_allele: &[u8], alt: &[u8]) -> Option<(u32, u32)> {
+        let entries = self.contigs.get(chrom)?;
+        let start = entries.partition_point(|e| e.pos < pos);
+        entries[start..]
+            .iter()
+            .take_while(|e| e.pos == pos)
+            .find(|e| e.ref_allele == ref_allele && e.alt == alt)
+            .map(|e| (e.ac, e.an))
+    }
+}
+
+fn read_u32<R: IoRead>(r: &mut R) -> std::io::Result<u32> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_bytes<R: IoRead>(r: &mut R) -> std::io::Result<Vec<u8>> {
+    let n = read_u32(r)? as usize;
+    let mut v = vec![0u8; n];
+    r.read_exact(&mut v)?;
+    Ok(v)
+}
+
+pub fn register_acindex(lua: &Lua) -> mlua::Result<()> {
+    lua.register_userdata_type::<AcIndex>(|reg| {
+        // returns a table of AC (one per ALT, 0 if the allele is not in the cohort) and the AN.
+        // returns nil if no allele at the site is in the index.
+        reg.add_method(
+            "lookup",
+            |lua: &Lua, this: &AcIndex, variant: AnyUserData| {
+                let variant = variant.borrow::<Variant>()?;
+                let record = variant.record();
+                let chrom = record
+                    .rid()
+                    .and_then(|rid| record.header().rid2name(rid).ok())
+                    .map(|c| String::from_utf8_lossy(c).to_string())
+                    .unwrap_or_default();
+                let alleles = record.alleles();
+                let mut an = None;
+                let ac = lua.create_table_with_capacity(alleles.len().saturating_sub(1), 0)?;
+                for (i, alt) in alleles.iter().skip(1).enumerate() {
+                    let (c, n) = this
+                        .get(&chrom, record.pos(), alleles[0], alt)
+                        .map_or((0, None), |(c, n)| (c, Some(n)));
+                    an = an.max(n);
+                    ac.raw_set(i + 1, c)?;
+                }
+                match an {
+                    Some(an) => Ok((Value::Table(ac), Value::Integer(an as _))),
+                    None => Ok((Value::Nil, Value::Nil)),
+                }
+            },
+        );
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::variant::{register_variant, HeaderMap};
+
+    #[test]
+    fn test_build_write_lookup() {
+        let mut header = bcf::Header::new();
+        header.push_record(r#"##contig=<ID=chr1,length=10000>"#.as_bytes());
+        header.push_record(
+            r#"##FORMAT=<ID=GT,Number=1,Type=String,Description="Genotype">"#.as_bytes(),
+        );
+        header.push_sample(b"S1");
+        header.push_sample(b"S2");
+        let mut vcf =
+            bcf::Writer::from_path("_test.ac.vcf", &header, true, bcf::Format::Vcf).unwrap();
+        let mut record = vcf.empty_record();
+        record.set_rid(Some(vcf.header().name2rid(b"chr1").unwrap()));
+        record.set_pos(9);
+        record.set_alleles(&[b"A", b"T", b"G"]).unwrap();
+        record
+            .push_genotypes(&[
+                bcf::record::GenotypeAllele::Unphased(0),
+                bcf::record::GenotypeAllele::Unphased(1),
+                bcf::record::GenotypeAllele::Unphased(1),
+                bcf::record::GenotypeAllele::UnphasedMissing,
+            ])
+            .unwrap();
+        vcf.write(&record).unwrap();
+        drop(vcf);
+
+        let idx = AcIndex::build("_test.ac.vcf").unwrap();
+        idx.write(std::fs::File::create("_test.acidx").unwrap())
+            .unwrap();
+        let idx = AcIndex::from_path("_test.acidx").unwrap();
+        assert_eq!(idx.get("chr1", 9, b"A", b"T"), Some((2, 3)));
+        assert_eq!(idx.get("chr1", 9, b"A", b"G"), Some((0, 3)));
+        assert_eq!(idx.get("chr1", 10, b"A", b"T"), None);
+
+        let lua = Lua::new();
+        register_variant(&lua).unwrap();
+        register_acindex(&lua).unwrap();
+        lua.globals().set("acindex", idx).unwrap();
+        let mut variant = Variant::new(record, HeaderMap::new());
+        lua.scope(|scope| {
+            lua.globals()
+                .set("variant", scope.create_any_userdata_ref_mut(&mut variant)?)?;
+            let (ac, an): (Vec<u32>, u32) = lua.load("return acindex:lookup(variant)").eval()?;
+            assert_eq!(ac, vec![2, 0]);
+            assert_eq!(an, 3);
+            Ok(())
+        })
+        .unwrap();
+        _ = std::fs::remove_file("_test.ac.vcf");
+        _ = std::fs::remove_file("_test.acidx");
+    }
+}
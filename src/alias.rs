@@ -0,0 +1,88 @@
+//! Sample aliases (`--sample-alias`) so that expressions, PED files and `--samples` can use
+//! external (e.g. institutional) IDs for samples that have other names (e.g. barcodes) in the VCF.
+//! The file has 2 tab-separated columns: alias and VCF sample name. Lines starting with `#` are skipped.
+use rustc_hash::FxHashMap;
+use std::borrow::Cow;
+use std::io::BufRead;
+
+#[derive(Debug, Default)]
+pub struct SampleAliases {
+    /// alias -> VCF sample name
+    names: FxHashMap<String, String>,
+}
+
+impl SampleAliases {
+    pub fn from_path(path: &str) -> std::io::Result<Self> {
+        Self::from_reader(std::io::BufReader::new(std::fs::File::open(path)?))
+    }
+
+    pub fn from_reader<R: BufRead>(reader: R) -> std::io::Result<Self> {
+        let mut aliases = SampleAliases::default();
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((alias, name)) = line.split_once('\t') else {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!(
+                        "expected alias<TAB>sample on line {} of sample aliases",
+                        i + 1
+                    ),
+                ));
+            };
+            let (alias, name) = (alias.trim(), name.split('\t').next().unwrap_or("").trim());
+            if let Some(prev) = aliases.names.insert(alias.to_string(), name.to_string()) {
+                if prev != name {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("alias {} is given for both {} and {}", alias, prev, name),
+                    ));
+                }
+            }
+        }
+        Ok(aliases)
+    }
+
+    /// The VCF sample name for `name`, which is returned as-is if it is not an alias.
+    pub fn resolve<'a>(&'a self, name: &'a str) -> &'a str {
+        self.names.get(name).map(|s| s.as_str()).unwrap_or(name)
+    }
+
+    pub(crate) fn resolve_bytes<'a>(&'a self, name: &'a [u8]) -> Cow<'a, [u8]> {
+        match std::str::from_utf8(name)
+            .ok()
+            .and_then(|n| self.names.get(n))
+        {
+            Some(s) => Cow::Borrowed(s.as_bytes()),
+            None => Cow::Borrowed(name),
+        }
+    }
+}
+
+/// The VCF sample name for `name` using the aliases set for this lua state, if any.
+pub(crate) fn resolve<'a>(lua: &'a mlua::Lua, name: &'a [u8]) -> Cow<'a, [u8]> {
+    match lua.app_data_ref::<SampleAliases>() {
+        Some(aliases) => Cow::Owned(aliases.resolve_bytes(name).into_owned()),
+        None => Cow::Borrowed(name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aliases() {
+        let aliases = SampleAliases::from_reader(
+            "#alias\tsample\nPT-001\tBC1234\nPT-002\tBC5678\n".as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(aliases.resolve("PT-001"), "BC1234");
+        assert_eq!(aliases.resolve("BC5678"), "BC5678");
+        assert_eq!(&*aliases.resolve_bytes(b"PT-002"), b"BC5678");
+        assert!(SampleAliases::from_reader("PT-001 BC1234\n".as_bytes()).is_err());
+        assert!(SampleAliases::from_reader("A\tB\nA\tC\n".as_bytes()).is_err());
+    }
+}
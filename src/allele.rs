@@ -0,0 +1,254 @@
+//! Per-allele evaluation (`--per-allele`) of multi-allelic sites. Expressions are called once per
+//! ALT with `allele`, `allele_index` and `allele_info` (the Number=A and Number=R INFO values for
+//! that allele, and in `allele_info.format` those of FORMAT fields with one value per sample) set.
+//! Alleles for which no expression passes are removed from the output record.
+use mlua::{Lua, Table, Value};
+use rust_htslib::bcf::header::{Id, TagLength, TagType};
+use rust_htslib::bcf::{self};
+use rust_htslib::htslib;
+
+use crate::variant::Sentinel;
+
+/// Set as lua app data so that `*` and symbolic `<NON_REF>`/`<*>` alleles are not counted by
+/// `variant:aaf()` and `variant:update_allele_counts()` and are not evaluated with `--per-allele`.
+pub(crate) struct SkipSymbolicAlleles;
+
+/// True if symbolic alleles are skipped for this lua state.
+pub(crate) fn skip_symbolic(lua: &Lua) -> bool {
+    lua.app_data_ref::<SkipSymbolicAlleles>().is_some()
+}
+
+/// True for the spanning-deletion allele `*` and the gVCF `<NON_REF>` and `<*>` alleles.
+pub fn is_symbolic(allele: &[u8]) -> bool {
+    matches!(allele, b"*" | b"<NON_REF>" | b"<*>")
+}
+
+/// INFO tags present in the record, in record order.
+fn info_tags(record: &bcf::Record) -> Vec<Vec<u8>> {
+    let inner = unsafe {
+        htslib::bcf_unpack(record.inner, htslib::BCF_UN_INFO as i32);
+        &*record.inner
+    };
+    (0..inner.n_info() as usize)
+        .map(|i| {
+            let key = unsafe { (*inner.d.info.add(i)).key };
+            record.header().id_to_name(Id(key as u32))
+        })
+        .collect()
+}
+
+/// One table per ALT with the value of each Number=A or Number=R INFO field for that allele.
+/// `format` holds the Number=A and Number=R FORMAT fields with the allele's value for each sample.
+/// Missing values are nil.
+pub(crate) fn allele_info_tables<'lua>(
+    lua: &'lua Lua,
+    record: &bcf::Record,
+) -> mlua::Result<Vec<Table<'lua>>> {
+    let n_alt = record.allele_count().saturating_sub(1) as usize;
+    let tables = (0..n_alt)
+        .map(|_| lua.create_table())
+        .collect::<mlua::Result<Vec<_>>>()?;
+    let decode = crate::percent::enabled(lua);
+    for tag in info_tags(record) {
+        let Ok((typ, num)) = record.header().info_type(&tag) else {
+            continue;
+        };
+        // Number=R has the REF value first.
+        let offset = match num {
+            TagLength::AltAlleles => 0,
+            TagLength::Alleles => 1,
+            _ => continue,
+        };
+        let key = lua.create_string(&tag)?;
+        let info = record.info(&tag);
+        let err = |e| mlua::Error::RuntimeError(format!("error reading INFO {:?}: {}", key, e));
+        let values = match typ {
+            TagType::Integer => match info.integer().map_err(err)? {
+                Some(v) => v
+                    .iter()
+                    .map(|v| (!v.is_missing_value() && !v.is_vector_end()).then(|| v.to_lua()))
+                    .collect::<Vec<_>>(),
+                None => continue,
+            },
+            TagType::Float => match info.float().map_err(err)? {
+                Some(v) => v
+                    .iter()
+                    .map(|v| (!v.is_missing_value() && !v.is_vector_end()).then(|| v.to_lua()))
+                    .collect(),
+                None => continue,
+            },
+            TagType::String => match info.string().map_err(err)? {
+                Some(v) => v
+                    .iter()
+                    .map(|&s| match s {
+                        b"." => Ok(None),
+                        s if decode => lua
+                            .create_string(crate::percent::decode(s))
+                            .map(|s| Some(Value::String(s))),
+                        s => lua.create_string(s).map(|s| Some(Value::String(s))),
+                    })
+                    .collect::<mlua::Result<_>>()?,
+                None => continue,
+            },
+            TagType::Flag => continue,
+        };
+        for (i, t) in tables.iter().enumerate() {
+            if let Some(Some(v)) = values.get(i + offset) {
+                t.raw_set(key.clone(), v.clone())?;
+            }
+        }
+    }
+    add_allele_formats(lua, record, &tables)?;
+    Ok(tables)
+}
+
+/// Set `format` in each of the allele `tables` to the Number=A and Number=R FORMAT values for that
+/// allele with one entry per sample.
+fn add_allele_formats(lua: &Lua, record: &bcf::Record, tables: &[Table]) -> mlua::Result<()> {
+    let formats = tables
+        .iter()
+        .map(|t| {
+            let f = lua.create_table()?;
+            t.raw_set("format", f.clone())?;
+            Ok(f)
+        })
+        .collect::<mlua::Result<Vec<_>>>()?;
+    for tag in crate::sample::format_tags(record) {
+        let Ok((typ, num)) = record.header().format_type(&tag) else {
+            continue;
+        };
+        let offset = match num {
+            TagLength::AltAlleles => 0,
+            TagLength::Alleles => 1,
+            _ => continue,
+        };
+        let fmt = record.format(&tag);
+        // per sample, the value for each allele.
+        let values: Vec<Vec<Option<Value>>> = match typ {
+            TagType::Integer => match fmt.integer() {
+                Ok(v) => v.iter().map(|s| allele_values(s)).collect(),
+                Err(_) => continue,
+            },
+            TagType::Float => match fmt.float() {
+                Ok(v) => v.iter().map(|s| allele_values(s)).collect(),
+                Err(_) => continue,
+            },
+            TagType::String => match fmt.string() {
+                Ok(v) => v
+                    .iter()
+                    .map(|s| {
+                        s.split(|&c| c == b',')
+                            .map(|s| match s {
+                                b"" | b"." => Ok(None),
+                                s => lua.create_string(s).map(|s| Some(Value::String(s))),
+                            })
+                            .collect::<mlua::Result<Vec<_>>>()
+                    })
+                    .collect::<mlua::Result<_>>()?,
+                Err(_) => continue,
+            },
+            TagType::Flag => continue,
+        };
+        let key = lua.create_string(&tag)?;
+        for (i, f) in formats.iter().enumerate() {
+            let t = lua.create_table_with_capacity(values.len(), 0)?;
+            for (j, sample) in values.iter().enumerate() {
+                if let Some(Some(v)) = sample.get(i + offset) {
+                    t.raw_set(j + 1, v.clone())?;
+                }
+            }
+            f.raw_set(key.clone(), t)?;
+        }
+    }
+    Ok(())
+}
+
+/// The values of one sample with nil for missing and the vector-end padding dropped.
+fn allele_values<'lua, T: Sentinel>(values: &[T]) -> Vec<Option<Value<'lua>>> {
+    values
+        .iter()
+        .take_while(|v| !v.is_vector_end())
+        .map(|v| (!v.is_missing_value()).then(|| v.to_lua()))
+        .collect()
+}
+
+/// Remove the ALT alleles for which `keep` (one entry per ALT) is false, updating Number=A, R
+/// and G fields and GT.
+pub(crate) fn remove_alleles(record: &mut bcf::Record, keep: &[bool]) -> std::io::Result<()> {
+    if keep.iter().all(|&k| k) {
+        return Ok(());
+    }
+    unsafe {
+        let rm = htslib::kbs_init(keep.len() + 1);
+        for (i, _) in keep.iter().enumerate().filter(|(_, &k)| !k) {
+            htslib::kbs_insert(rm, (i + 1) as i32);
+        }
+        let ret = htslib::bcf_remove_allele_set(record.header().inner, record.inner, rm);
+        htslib::kbs_destroy(rm);
+        if ret != 0 {
+            return Err(std::io::Error::other(format!(
+                "error removing alleles at position {}",
+                record.pos() + 1
+            )));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_per_allele() {
+        let mut header = bcf::Header::new();
+        header.push_record(br#"##contig=<ID=chr1,length=10000>"#);
+        header.push_record(br#"##INFO=<ID=AF,Number=A,Type=Float,Description="Frequency">"#);
+        header.push_record(br#"##INFO=<ID=AD,Number=R,Type=Integer,Description="Depth">"#);
+        header.push_record(br#"##INFO=<ID=DP,Number=1,Type=Integer,Description="Depth">"#);
+        header.push_record(br#"##FORMAT=<ID=GT,Number=1,Type=String,Description="Genotype">"#);
+        header.push_record(br#"##FORMAT=<ID=AD,Number=R,Type=Integer,Description="Depth">"#);
+        header.push_sample(b"S1");
+        let vcf =
+            bcf::Writer::from_path("_test.allele.vcf", &header, true, bcf::Format::Vcf).unwrap();
+        let mut record = vcf.empty_record();
+        record.set_rid(Some(0));
+        record.set_pos(9);
+        record.set_alleles(&[b"A", b"T", b"G"]).unwrap();
+        record.push_info_float(b"AF", &[0.25, 0.5]).unwrap();
+        record.push_info_integer(b"AD", &[10, 3, 5]).unwrap();
+        record.push_info_integer(b"DP", &[18]).unwrap();
+        record
+            .push_genotypes(&[
+                bcf::record::GenotypeAllele::Unphased(0),
+                bcf::record::GenotypeAllele::Unphased(2),
+            ])
+            .unwrap();
+        record.push_format_integer(b"AD", &[7, 1, 6]).unwrap();
+
+        let lua = Lua::new();
+        let tables = allele_info_tables(&lua, &record).unwrap();
+        assert_eq!(tables.len(), 2);
+        assert_eq!(tables[1].get::<_, f64>("AF").unwrap(), 0.5);
+        assert_eq!(tables[0].get::<_, i32>("AD").unwrap(), 3);
+        assert!(tables[0].get::<_, Option<i32>>("DP").unwrap().is_none());
+        let ad =
+            |t: &Table| -> Vec<i32> { t.get::<_, Table>("format").unwrap().get("AD").unwrap() };
+        assert_eq!((ad(&tables[0]), ad(&tables[1])), (vec![1], vec![6]));
+
+        remove_alleles(&mut record, &[false, true]).unwrap();
+        assert_eq!(record.alleles(), vec![b"A" as &[u8], b"G"]);
+        assert_eq!(
+            record.info(b"AF").float().unwrap().unwrap().to_vec(),
+            vec![0.5]
+        );
+        assert_eq!(
+            record.info(b"AD").integer().unwrap().unwrap().to_vec(),
+            vec![10, 5]
+        );
+        let gts = record.genotypes().unwrap();
+        assert_eq!(gts.get(0).to_string(), "0/1");
+        drop(vcf);
+        _ = std::fs::remove_file("_test.allele.vcf");
+    }
+}
@@ -0,0 +1,234 @@
+//! Allele-specific FILTER: the `ALT_FILTER` INFO field (Number=A) holds, for each ALT, `PASS` or
+//! the filters that the allele failed separated by `|`, e.g. `PASS,LowVAF|StrandBias`. It is set
+//! from lua with `variant:set_alt_filter(i, name)` and read with `variant:alt_filter(i)`.
+//! `--alt-filter-to-filter` and `--filter-to-alt-filter` translate between it and the record
+//! FILTER, e.g. after failing alleles are removed with --per-allele or for records that will be
+//! joined into one multi-allelic site.
+use rust_htslib::bcf;
+use std::io;
+
+pub const ALT_FILTER: &str = "ALT_FILTER";
+pub(crate) const ALT_FILTER_HEADER: &str = r#"##INFO=<ID=ALT_FILTER,Number=A,Type=String,Description="FILTER for each ALT allele: PASS or the filters the allele failed separated by '|'">"#;
+
+const MISSING: &str = ".";
+const PASS: &str = "PASS";
+
+/// The direction in which to translate `ALT_FILTER` for each output record.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AltFilterTranslation {
+    /// Add the filters of the ALTs to the record FILTER if every ALT failed.
+    ToFilter,
+    /// Add the record FILTER to the `ALT_FILTER` of every ALT.
+    FromFilter,
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+/// Add the `ALT_FILTER` line to the header if it is not there.
+pub(crate) fn add_header(hv: &mut bcf::header::HeaderView) -> io::Result<()> {
+    if hv.info_type(ALT_FILTER.as_bytes()).is_ok() {
+        return Ok(());
+    }
+    let line = std::ffi::CString::new(ALT_FILTER_HEADER)?;
+    unsafe {
+        rust_htslib::htslib::bcf_hdr_append(hv.inner, line.as_ptr());
+        rust_htslib::htslib::bcf_hdr_sync(hv.inner);
+    }
+    Ok(())
+}
+
+/// The `ALT_FILTER` value of each ALT; "." for an ALT without one.
+pub(crate) fn get(record: &bcf::Record) -> Vec<String> {
+    let n = record.allele_count().saturating_sub(1) as usize;
+    let mut values = match record.info(ALT_FILTER.as_bytes()).string() {
+        Ok(Some(v)) => v
+            .iter()
+            .map(|s| String::from_utf8_lossy(s).to_string())
+            .collect::<Vec<_>>(),
+        _ => vec![],
+    };
+    values.resize(n, MISSING.to_string());
+    values
+}
+
+fn set(record: &mut bcf::Record, values: &[String]) -> io::Result<()> {
+    let values = values.iter().map(|v| v.as_bytes()).collect::<Vec<_>>();
+    record
+        .push_info_string(ALT_FILTER.as_bytes(), &values)
+        .map_err(|e| invalid(format!("error setting {}: {}", ALT_FILTER, e)))
+}
+
+/// The filters in an `ALT_FILTER` value; empty for PASS or missing.
+fn filters(value: &str) -> Vec<&str> {
+    value
+        .split('|')
+        .filter(|f| !f.is_empty() && *f != MISSING && *f != PASS)
+        .collect()
+}
+
+/// Add `filter` to `value`. PASS replaces any filters.
+fn add(value: &str, filter: &str) -> String {
+    if filter == PASS {
+        return PASS.to_string();
+    }
+    let mut fs = filters(value);
+    if !fs.contains(&filter) {
+        fs.push(filter);
+    }
+    fs.join("|")
+}
+
+/// Add `filter` to ALT `i` (1-based, as `allele_index`) of the record.
+pub(crate) fn set_alt_filter(record: &mut bcf::Record, i: usize, filter: &str) -> io::Result<()> {
+    if record.header().info_type(ALT_FILTER.as_bytes()).is_err() {
+        return Err(invalid(format!("{} is not in the header", ALT_FILTER)));
+    }
+    let mut values = get(record);
+    if i == 0 || i > values.len() {
+        return Err(invalid(format!(
+            "set_alt_filter: ALT index {} is out of range for {} ALT allele(s)",
+            i,
+            values.len()
+        )));
+    }
+    if filter != PASS && record.header().name_to_id(filter.as_bytes()).is_err() {
+        return Err(invalid(format!(
+            "set_alt_filter: FILTER {} is not in the header; add it with header:add_filter in the prelude",
+            filter
+        )));
+    }
+    values[i - 1] = add(&values[i - 1], filter);
+    set(record, &values)
+}
+
+/// The `ALT_FILTER` value of ALT `i` (1-based); None if it is missing.
+pub(crate) fn alt_filter(record: &bcf::Record, i: usize) -> Option<String> {
+    get(record)
+        .into_iter()
+        .nth(i.checked_sub(1)?)
+        .filter(|v| v != MISSING)
+}
+
+/// Translate the `ALT_FILTER` of `record` to or from the record FILTER.
+pub(crate) fn translate(
+    record: &mut bcf::Record,
+    direction: AltFilterTranslation,
+) -> io::Result<()> {
+    let values = get(record);
+    if values.is_empty() {
+        return Ok(());
+    }
+    match direction {
+        AltFilterTranslation::ToFilter => {
+            // the record passes if any ALT passes (or was not filtered).
+            if values.iter().any(|v| filters(v).is_empty()) {
+                return Ok(());
+            }
+            let mut fs: Vec<&str> = vec![];
+            for f in values.iter().flat_map(|v| filters(v)) {
+                if !fs.contains(&f) {
+                    fs.push(f);
+                }
+            }
+            let ids = fs
+                .iter()
+                .map(|f| {
+                    record.header().name_to_id(f.as_bytes()).map_err(|_| {
+                        invalid(format!(
+                            "{} has FILTER {} that is not in the header",
+                            ALT_FILTER, f
+                        ))
+                    })
+                })
+                .collect::<io::Result<Vec<_>>>()?;
+            for id in ids {
+                record.push_filter(&id).map_err(io::Error::other)?;
+            }
+            Ok(())
+        }
+        AltFilterTranslation::FromFilter => {
+            let header = record.header();
+            let record_filters = record
+                .filters()
+                .map(|id| String::from_utf8_lossy(&header.id_to_name(id)).to_string())
+                .collect::<Vec<_>>();
+            if record_filters.is_empty() {
+                return Ok(());
+            }
+            let values = values
+                .iter()
+                .map(|v| {
+                    let mut v = v.clone();
+                    for f in &record_filters {
+                        // a PASS record does not clear the filters of an ALT.
+                        if f != PASS || filters(&v).is_empty() {
+                            v = add(&v, f);
+                        }
+                    }
+                    v
+                })
+                .collect::<Vec<_>>();
+            set(record, &values)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_alt_filter() {
+        let mut header = bcf::Header::new();
+        header.push_record(br#"##contig=<ID=chr1,length=10000>"#);
+        header.push_record(br#"##FILTER=<ID=LowVAF,Description="LowVAF">"#);
+        header.push_record(br#"##FILTER=<ID=SB,Description="SB">"#);
+        header.push_record(ALT_FILTER_HEADER.as_bytes());
+        let vcf =
+            bcf::Writer::from_path("_test.altfilter.vcf", &header, true, bcf::Format::Vcf).unwrap();
+        let mut record = vcf.empty_record();
+        record.set_rid(Some(0));
+        record.set_alleles(&[b"A", b"T", b"C"]).unwrap();
+        assert_eq!(get(&record), [".", "."]);
+        assert_eq!(alt_filter(&record, 1), None);
+
+        set_alt_filter(&mut record, 1, "LowVAF").unwrap();
+        set_alt_filter(&mut record, 1, "SB").unwrap();
+        set_alt_filter(&mut record, 1, "LowVAF").unwrap();
+        assert_eq!(get(&record), ["LowVAF|SB", "."]);
+        assert!(set_alt_filter(&mut record, 3, "SB").is_err());
+        assert!(set_alt_filter(&mut record, 2, "Nope").is_err());
+
+        // ALT 2 was not filtered so the record is unchanged.
+        translate(&mut record, AltFilterTranslation::ToFilter).unwrap();
+        assert_eq!(record.filters().count(), 0);
+
+        set_alt_filter(&mut record, 2, "SB").unwrap();
+        assert_eq!(alt_filter(&record, 2).as_deref(), Some("SB"));
+        translate(&mut record, AltFilterTranslation::ToFilter).unwrap();
+        let names = record
+            .filters()
+            .map(|id| record.header().id_to_name(id))
+            .collect::<Vec<_>>();
+        assert_eq!(names, [b"LowVAF".to_vec(), b"SB".to_vec()]);
+
+        set_alt_filter(&mut record, 1, "PASS").unwrap();
+        assert_eq!(get(&record), ["PASS", "SB"]);
+
+        let mut record = vcf.empty_record();
+        record.set_rid(Some(0));
+        record.set_alleles(&[b"A", b"T", b"C"]).unwrap();
+        record.push_filter(b"LowVAF".as_slice()).unwrap();
+        set_alt_filter(&mut record, 2, "SB").unwrap();
+        translate(&mut record, AltFilterTranslation::FromFilter).unwrap();
+        assert_eq!(get(&record), ["LowVAF", "SB|LowVAF"]);
+
+        record.set_filters(&[b"PASS".as_slice()]).unwrap();
+        translate(&mut record, AltFilterTranslation::FromFilter).unwrap();
+        assert_eq!(get(&record), ["LowVAF", "SB|LowVAF"]);
+        drop(vcf);
+        _ = std::fs::remove_file("_test.altfilter.vcf");
+    }
+}
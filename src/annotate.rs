@@ -0,0 +1,1193 @@
+//! Add INFO fields to each record of a VCF/BCF from other files (`vcfexpress annotate`): columns
+//! of a tabix-indexed TSV (`--tsv`) for the lines at the position of the variant and, with
+//! `--ref-alt-columns`, with the same REF and ALT, and INFO fields and ID from an indexed VCF/BCF
+//! (`--vcf`) for the records with the same (normalized) alleles, and a flag or the names of the
+//! overlapping regions of a BED file (`--bed`). The INFO fields are added to the header.
+use rust_htslib::bcf::{
+    self,
+    header::{HeaderRecord, TagLength, TagType},
+    Read,
+};
+use rust_htslib::tbx::{self, Read as TbxRead};
+use rustc_hash::FxHashMap;
+use std::io::{self, BufRead};
+
+use crate::retype::type_name;
+use crate::variant::Sentinel;
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+/// A source of INFO fields for `annotate`.
+pub trait Annotator {
+    /// The `##INFO` lines for the fields that `annotate` sets.
+    fn header_lines(&self) -> Vec<String>;
+    /// Set the fields on `record`, which has the output header.
+    fn annotate(&mut self, record: &mut bcf::Record) -> io::Result<()>;
+}
+
+/// A TSV column to add as an INFO field.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Column {
+    /// 0-based.
+    pub index: usize,
+    pub tag: String,
+    pub typ: TagType,
+}
+
+/// Parse `5:GENE,6:SCORE:Float`: the 1-based column, the INFO name and optionally the Type
+/// (String, the default, Integer or Float).
+pub fn parse_columns(args: &[String]) -> io::Result<Vec<Column>> {
+    args.iter()
+        .flat_map(|a| a.split(','))
+        .filter(|c| !c.is_empty())
+        .map(|c| {
+            let err = || {
+                invalid(format!(
+                    "expected column:NAME or column:NAME:Type in --columns, got '{}'",
+                    c
+                ))
+            };
+            let mut parts = c.split(':');
+            let index = parts
+                .next()
+                .and_then(|i| i.parse::<usize>().ok())
+                .filter(|i| *i > 0)
+                .ok_or_else(err)?;
+            let tag = parts.next().filter(|t| !t.is_empty()).ok_or_else(err)?;
+            let typ = match parts.next() {
+                None | Some("String") => TagType::String,
+                Some("Integer") => TagType::Integer,
+                Some("Float") => TagType::Float,
+                Some(_) => return Err(err()),
+            };
+            if parts.next().is_some() {
+                return Err(err());
+            }
+            Ok(Column {
+                index: index - 1,
+                tag: tag.to_string(),
+                typ,
+            })
+        })
+        .collect()
+}
+
+/// Parse the 1-based `ref:alt` columns of `--ref-alt-columns` to 0-based.
+pub fn parse_ref_alt_columns(arg: &str) -> io::Result<(usize, usize)> {
+    let parse = |c: &str| c.parse::<usize>().ok().filter(|c| *c > 0).map(|c| c - 1);
+    match arg.split_once(':') {
+        Some((r, a)) => match (parse(r), parse(a)) {
+            (Some(r), Some(a)) => Ok((r, a)),
+            _ => Err(invalid(format!(
+                "expected ref:alt columns e.g. 3:4, got '{}'",
+                arg
+            ))),
+        },
+        None => Err(invalid(format!(
+            "expected ref:alt columns e.g. 3:4, got '{}'",
+            arg
+        ))),
+    }
+}
+
+/// Set INFO `tag` of type `typ` to `values` ("." or None for missing). Nothing is set if every
+/// value is missing.
+pub(crate) fn set_info(
+    record: &mut bcf::Record,
+    tag: &str,
+    typ: &TagType,
+    values: &[Option<&str>],
+    source: &str,
+) -> io::Result<()> {
+    let values = values
+        .iter()
+        .map(|v| v.filter(|v| !v.is_empty() && *v != "."))
+        .collect::<Vec<_>>();
+    if values.iter().all(|v| v.is_none()) {
+        return Ok(());
+    }
+    let err = |v: &str| invalid(format!("{}: {} value '{}' is not a number", source, tag, v));
+    let result = match typ {
+        TagType::Integer => {
+            let values = values
+                .iter()
+                .map(|v| match v {
+                    Some(v) => v.parse::<i32>().map_err(|_| err(v)),
+                    None => Ok(i32::missing()),
+                })
+                .collect::<io::Result<Vec<_>>>()?;
+            record.push_info_integer(tag.as_bytes(), &values)
+        }
+        TagType::Float => {
+            let values = values
+                .iter()
+                .map(|v| match v {
+                    Some(v) => v.parse::<f32>().map_err(|_| err(v)),
+                    None => Ok(f32::missing()),
+                })
+                .collect::<io::Result<Vec<_>>>()?;
+            record.push_info_float(tag.as_bytes(), &values)
+        }
+        TagType::Flag => record.push_info_flag(tag.as_bytes()),
+        TagType::String => {
+            let values = values
+                .iter()
+                .map(|v| v.unwrap_or(".").as_bytes())
+                .collect::<Vec<_>>();
+            record.push_info_string(tag.as_bytes(), &values)
+        }
+    };
+    result.map_err(|e| invalid(format!("error setting INFO {}: {}", tag, e)))
+}
+
+/// Columns from a bgzipped, tabix-indexed TSV.
+pub struct TsvAnnotator {
+    path: String,
+    reader: tbx::Reader,
+    columns: Vec<Column>,
+    // 0-based REF and ALT columns. Lines are then matched by allele and the fields are Number=A.
+    ref_alt: Option<(usize, usize)>,
+    line: Vec<u8>,
+}
+
+impl TsvAnnotator {
+    pub fn new(
+        path: &str,
+        columns: Vec<Column>,
+        ref_alt: Option<(usize, usize)>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        if columns.is_empty() {
+            return Err("--tsv needs --columns".into());
+        }
+        let reader = tbx::Reader::from_path(path).map_err(|e| {
+            format!(
+                "error opening {} (it must be bgzipped and tabix-indexed): {}",
+                path, e
+            )
+        })?;
+        Ok(TsvAnnotator {
+            path: path.to_string(),
+            reader,
+            columns,
+            ref_alt,
+            line: vec![],
+        })
+    }
+}
+
+impl Annotator for TsvAnnotator {
+    fn header_lines(&self) -> Vec<String> {
+        let number = if self.ref_alt.is_some() { "A" } else { "1" };
+        self.columns
+            .iter()
+            .map(|c| {
+                format!(
+                    r#"##INFO=<ID={},Number={},Type={},Description="Column {} of {}">"#,
+                    c.tag,
+                    number,
+                    type_name(&c.typ),
+                    c.index + 1,
+                    self.path
+                )
+            })
+            .collect()
+    }
+
+    fn annotate(&mut self, record: &mut bcf::Record) -> io::Result<()> {
+        let Some(rid) = record.rid() else {
+            return Ok(());
+        };
+        let chrom =
+            String::from_utf8_lossy(record.header().rid2name(rid).map_err(io::Error::other)?)
+                .to_string();
+        // a contig without lines in the TSV.
+        let Ok(tid) = self.reader.tid(&chrom) else {
+            return Ok(());
+        };
+        let pos = record.pos() as u64;
+        if self.reader.fetch(tid, pos, pos + 1).is_err() {
+            return Ok(());
+        }
+        let alleles = record.alleles();
+        let slots = match self.ref_alt {
+            Some(_) => alleles.len() - 1,
+            None => 1,
+        };
+        // the first matching line for each slot (ALT, or the record without --ref-alt-columns).
+        let mut lines: Vec<Option<Vec<String>>> = vec![None; slots];
+        while self.reader.read(&mut self.line).map_err(io::Error::other)? {
+            let fields = String::from_utf8_lossy(&self.line)
+                .split('\t')
+                .map(|f| f.to_string())
+                .collect::<Vec<_>>();
+            let slot = match self.ref_alt {
+                None => Some(0),
+                Some((r, a)) => match (fields.get(r), fields.get(a)) {
+                    (Some(r), Some(a)) if r.as_bytes() == alleles[0] => {
+                        alleles[1..].iter().position(|alt| *alt == a.as_bytes())
+                    }
+                    _ => None,
+                },
+            };
+            if let Some(slot) = slot {
+                if lines[slot].is_none() {
+                    lines[slot] = Some(fields);
+                }
+            }
+            if lines.iter().all(|l| l.is_some()) {
+                break;
+            }
+        }
+        drop(alleles);
+        for c in &self.columns {
+            let values = lines
+                .iter()
+                .map(|l| {
+                    l.as_ref()
+                        .and_then(|fields| fields.get(c.index))
+                        .map(|v| v.as_str())
+                })
+                .collect::<Vec<_>>();
+            set_info(record, &c.tag, &c.typ, &values, &self.path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Trim the bases shared at the end and then at the start of `reference` and `alt` (keeping at
+/// least one) and uppercase them, so that e.g. `CAT>CT` at 10 and `CA>C` at 10 are the same and
+/// `AC>AT` at 10 is `C>T` at 11.
+pub fn normalize(pos: i64, reference: &[u8], alt: &[u8]) -> (i64, Vec<u8>, Vec<u8>) {
+    let (mut r, mut a) = (reference, alt);
+    while r.len() > 1 && a.len() > 1 && r[r.len() - 1].eq_ignore_ascii_case(&a[a.len() - 1]) {
+        r = &r[..r.len() - 1];
+        a = &a[..a.len() - 1];
+    }
+    let mut pos = pos;
+    while r.len() > 1 && a.len() > 1 && r[0].eq_ignore_ascii_case(&a[0]) {
+        r = &r[1..];
+        a = &a[1..];
+        pos += 1;
+    }
+    (pos, r.to_ascii_uppercase(), a.to_ascii_uppercase())
+}
+
+/// The values of INFO `tag` as strings ("." for missing); None if the record does not have it.
+fn info_strings(record: &bcf::Record, tag: &[u8], typ: &TagType) -> Option<Vec<String>> {
+    let mut info = record.info(tag);
+    match typ {
+        TagType::Integer => info.integer().ok()?.map(|v| {
+            v.iter()
+                .filter(|v| !v.is_vector_end())
+                .map(|v| match v.is_missing_value() {
+                    true => ".".to_string(),
+                    false => v.to_string(),
+                })
+                .collect()
+        }),
+        TagType::Float => info.float().ok()?.map(|v| {
+            v.iter()
+                .filter(|v| !v.is_vector_end())
+                .map(|v| match v.is_missing_value() {
+                    true => ".".to_string(),
+                    false => v.to_string(),
+                })
+                .collect()
+        }),
+        TagType::String => info.string().ok()?.map(|v| {
+            v.iter()
+                .map(|s| String::from_utf8_lossy(s).to_string())
+                .collect()
+        }),
+        TagType::Flag => match info.flag() {
+            Ok(true) => Some(vec!["1".to_string()]),
+            _ => None,
+        },
+    }
+}
+
+/// An INFO field copied from the `--vcf` source.
+struct VcfField {
+    source: String,
+    tag: String,
+    typ: TagType,
+    length: TagLength,
+}
+
+/// INFO fields (and optionally ID) from the records of an indexed VCF/BCF with the same
+/// chromosome, position and alleles after [`normalize`]. Number=A and Number=R fields are matched
+/// to each ALT; other fields come from the first matching record.
+pub struct VcfAnnotator {
+    path: String,
+    reader: bcf::IndexedReader,
+    fields: Vec<VcfField>,
+    id: bool,
+    header_lines: Vec<String>,
+    record: bcf::Record,
+}
+
+impl VcfAnnotator {
+    /// `fields` are INFO fields of `path` that are written as `prefix` + field. With `id`, the
+    /// ID of the matching records is set on variants that do not have one.
+    pub fn new(
+        path: &str,
+        fields: &[String],
+        prefix: &str,
+        id: bool,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let fields = fields
+            .iter()
+            .flat_map(|f| f.split(','))
+            .filter(|f| !f.is_empty())
+            .collect::<Vec<_>>();
+        if fields.is_empty() && !id {
+            return Err("--vcf needs --fields or --id".into());
+        }
+        if !crate::regions::has_index(path) {
+            return Err(format!(
+                "no index (.csi or .tbi) found for {}; create one with `bcftools index`",
+                path
+            )
+            .into());
+        }
+        let mut reader = bcf::IndexedReader::from_path(path)?;
+        _ = reader.set_threads(2);
+        let hv = reader.header();
+        let mut header_lines = vec![];
+        let mut vcf_fields = vec![];
+        for f in fields {
+            let (typ, length) = hv
+                .info_type(f.as_bytes())
+                .map_err(|_| format!("{} is not an INFO field in {}", f, path))?;
+            let description = hv
+                .header_records()
+                .into_iter()
+                .find_map(|rec| match rec {
+                    HeaderRecord::Info { values, .. }
+                        if values.get("ID").map(|id| id.as_str()) == Some(f) =>
+                    {
+                        values
+                            .get("Description")
+                            .map(|d| d.trim_matches('"').to_string())
+                    }
+                    _ => None,
+                })
+                .unwrap_or_default();
+            let tag = format!("{}{}", prefix, f);
+            header_lines.push(format!(
+                r#"##INFO=<ID={},Number={},Type={},Description="{} (from {})">"#,
+                tag,
+                crate::check::number(length),
+                type_name(&typ),
+                description,
+                path
+            ));
+            vcf_fields.push(VcfField {
+                source: f.to_string(),
+                tag,
+                typ,
+                length,
+            });
+        }
+        let record = reader.empty_record();
+        Ok(VcfAnnotator {
+            path: path.to_string(),
+            reader,
+            fields: vcf_fields,
+            id,
+            header_lines,
+            record,
+        })
+    }
+}
+
+/// A source record with at least one ALT matching the variant.
+struct Match {
+    id: String,
+    values: Vec<Option<Vec<String>>>,
+}
+
+impl Annotator for VcfAnnotator {
+    fn header_lines(&self) -> Vec<String> {
+        self.header_lines.clone()
+    }
+
+    fn annotate(&mut self, record: &mut bcf::Record) -> io::Result<()> {
+        let Some(rid) = record.rid() else {
+            return Ok(());
+        };
+        let chrom = record.header().rid2name(rid).map_err(io::Error::other)?;
+        // a contig that is not in the source.
+        let Ok(source_rid) = self.reader.header().name2rid(chrom) else {
+            return Ok(());
+        };
+        let pos = record.pos();
+        let alleles = record.alleles();
+        let wanted = alleles[1..]
+            .iter()
+            .map(|alt| normalize(pos, alleles[0], alt))
+            .collect::<Vec<_>>();
+        let end = pos as u64 + alleles[0].len().max(1) as u64 - 1;
+        drop(alleles);
+        if self
+            .reader
+            .fetch(source_rid, pos as u64, Some(end))
+            .is_err()
+        {
+            return Ok(());
+        }
+        let mut matches: Vec<Match> = vec![];
+        // for each ALT, the match and the (1-based) ALT of the source record.
+        let mut alts: Vec<Option<(usize, usize)>> = vec![None; wanted.len()];
+        while let Some(r) = self.reader.read(&mut self.record) {
+            r.map_err(io::Error::other)?;
+            let source_alleles = self.record.alleles();
+            let mut matched = false;
+            for (j, alt) in source_alleles.iter().enumerate().skip(1) {
+                let n = normalize(self.record.pos(), source_alleles[0], alt);
+                for (i, w) in wanted.iter().enumerate() {
+                    if alts[i].is_none() && *w == n {
+                        alts[i] = Some((matches.len(), j));
+                        matched = true;
+                    }
+                }
+            }
+            if matched {
+                matches.push(Match {
+                    id: String::from_utf8_lossy(&self.record.id()).to_string(),
+                    values: self
+                        .fields
+                        .iter()
+                        .map(|f| info_strings(&self.record, f.source.as_bytes(), &f.typ))
+                        .collect(),
+                });
+            }
+            if alts.iter().all(|a| a.is_some()) {
+                break;
+            }
+        }
+        if matches.is_empty() {
+            return Ok(());
+        }
+        for (k, f) in self.fields.iter().enumerate() {
+            let value = |m: usize, i: usize| -> Option<&str> {
+                matches[m].values[k]
+                    .as_ref()
+                    .and_then(|v| v.get(i))
+                    .map(|v| v.as_str())
+            };
+            let values = match f.length {
+                TagLength::AltAlleles => alts
+                    .iter()
+                    .map(|a| a.and_then(|(m, j)| value(m, j - 1)))
+                    .collect(),
+                TagLength::Alleles => {
+                    let (m, _) = alts.iter().flatten().next().expect("a match");
+                    std::iter::once(value(*m, 0))
+                        .chain(alts.iter().map(|a| a.and_then(|(m, j)| value(m, j))))
+                        .collect()
+                }
+                _ => match matches.iter().find_map(|m| m.values[k].as_ref()) {
+                    Some(v) => v.iter().map(|v| Some(v.as_str())).collect(),
+                    None => vec![],
+                },
+            };
+            set_info(record, &f.tag, &f.typ, &values, &self.path)?;
+        }
+        if self.id && record.id() == b"." {
+            let mut ids: Vec<&str> = vec![];
+            for id in matches.iter().flat_map(|m| m.id.split(';')) {
+                if id != "." && !ids.contains(&id) {
+                    ids.push(id);
+                }
+            }
+            if !ids.is_empty() {
+                record
+                    .set_id(ids.join(";").as_bytes())
+                    .map_err(io::Error::other)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Split a `--bed` argument `regions.bed[:TAG]` into path and INFO name. Without `:TAG`, the name
+/// is the file name up to the first `.`, e.g. `exons` for `data/exons.bed.gz`.
+pub fn parse_bed_arg(arg: &str) -> (String, String) {
+    if let Some((path, tag)) = arg.rsplit_once(':') {
+        if !tag.is_empty() && !tag.contains('/') {
+            return (path.to_string(), tag.to_string());
+        }
+    }
+    let (tag, _) = crate::coverage::parse_coverage_arg(arg);
+    (arg.to_string(), tag)
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Interval {
+    chrom: String,
+    // 0-based, half-open.
+    start: u64,
+    end: u64,
+    name: Option<String>,
+}
+
+type BedLines = io::Lines<crate::bed::Reader>;
+
+fn open_bed(path: &str) -> io::Result<BedLines> {
+    Ok(crate::bed::open(path)?.lines())
+}
+
+/// The next interval of a BED file; None at the end.
+fn next_interval(
+    lines: &mut BedLines,
+    line_no: &mut usize,
+    path: &str,
+) -> io::Result<Option<Interval>> {
+    for line in lines.by_ref() {
+        let line = line?;
+        *line_no += 1;
+        let Some(l) = crate::bed::parse(path, *line_no - 1, &line)? else {
+            continue;
+        };
+        let name = l
+            .fields
+            .get(3)
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty() && *s != ".")
+            .map(|s| s.to_string());
+        return Ok(Some(Interval {
+            chrom: l.chrom.to_string(),
+            start: l.start,
+            end: l.end,
+            name,
+        }));
+    }
+    Ok(None)
+}
+
+/// The intervals of one chromosome sorted by start with the largest end of each prefix so that a
+/// query can stop once no earlier interval reaches it.
+#[derive(Debug, Default)]
+pub(crate) struct IntervalIndex {
+    intervals: Vec<Interval>,
+    max_end: Vec<u64>,
+}
+
+impl IntervalIndex {
+    fn build(mut intervals: Vec<Interval>) -> Self {
+        intervals.sort_by_key(|iv| iv.start);
+        let mut max_end = Vec::with_capacity(intervals.len());
+        let mut m = 0;
+        for iv in &intervals {
+            m = m.max(iv.end);
+            max_end.push(m);
+        }
+        IntervalIndex { intervals, max_end }
+    }
+
+    pub(crate) fn overlaps(&self, start: u64, end: u64) -> impl Iterator<Item = &Interval> {
+        let n = self.intervals.partition_point(|iv| iv.start < end);
+        (0..n)
+            .rev()
+            .take_while(move |&i| self.max_end[i] > start)
+            .map(move |i| &self.intervals[i])
+            .filter(move |iv| iv.end > start)
+    }
+}
+
+/// A sweep over a sorted BED file (each chromosome in one block, by start) for VCF records in the
+/// same chromosome order. Only the intervals that can still overlap a record are kept.
+struct BedSweep {
+    lines: BedLines,
+    line_no: usize,
+    next: Option<Interval>,
+    // the order of the chromosomes in the BED.
+    ranks: FxHashMap<String, usize>,
+    // (rank, start) of the last record to check that the VCF is in the order of the BED.
+    last_record: Option<(usize, u64)>,
+    window: Vec<(usize, Interval)>,
+}
+
+impl BedSweep {
+    /// The intervals on `chrom` overlapping `start..end`; None if the VCF is not in the order of
+    /// the BED.
+    fn overlaps(
+        &mut self,
+        path: &str,
+        chrom: &str,
+        start: u64,
+        end: u64,
+    ) -> io::Result<Option<Vec<Interval>>> {
+        let Some(&rank) = self.ranks.get(chrom) else {
+            return Ok(Some(vec![]));
+        };
+        if self.last_record.is_some_and(|last| (rank, start) < last) {
+            return Ok(None);
+        }
+        self.last_record = Some((rank, start));
+        self.window.retain(|(r, iv)| *r == rank && iv.end > start);
+        while let Some(iv) = self.next.take() {
+            let r = self.ranks[&iv.chrom];
+            if (r, iv.start) >= (rank, end) {
+                self.next = Some(iv);
+                break;
+            }
+            if r == rank && iv.end > start {
+                self.window.push((r, iv));
+            }
+            self.next = next_interval(&mut self.lines, &mut self.line_no, path)?;
+        }
+        Ok(Some(
+            self.window
+                .iter()
+                .filter(|(_, iv)| iv.start < end)
+                .map(|(_, iv)| iv.clone())
+                .collect(),
+        ))
+    }
+}
+
+/// Read a whole BED file into an index by chromosome.
+pub(crate) fn index_bed(path: &str) -> io::Result<FxHashMap<String, IntervalIndex>> {
+    let mut lines = open_bed(path)?;
+    let mut line_no = 0;
+    let mut chroms: FxHashMap<String, Vec<Interval>> = FxHashMap::default();
+    while let Some(iv) = next_interval(&mut lines, &mut line_no, path)? {
+        chroms.entry(iv.chrom.clone()).or_default().push(iv);
+    }
+    Ok(chroms
+        .into_iter()
+        .map(|(chrom, intervals)| (chrom, IntervalIndex::build(intervals)))
+        .collect())
+}
+
+enum BedLookup {
+    Sweep(BedSweep),
+    Index(FxHashMap<String, IntervalIndex>),
+}
+
+/// A flag, or the names (4th column) of the overlapping regions of a BED file. A sorted file is
+/// swept with the VCF while the VCF is in the same order; otherwise it is read into an index by
+/// chromosome.
+pub struct BedAnnotator {
+    path: String,
+    tag: String,
+    // set the names of the regions rather than a flag. From the first line of the BED.
+    names: bool,
+    lookup: BedLookup,
+}
+
+impl BedAnnotator {
+    pub fn new(path: &str, tag: &str) -> io::Result<Self> {
+        // check that the file is sorted (and get the chromosome order) before it is swept so that
+        // no record has been written with only some of its regions.
+        let mut lines = open_bed(path)?;
+        let mut line_no = 0;
+        let mut names = None;
+        let mut ranks: FxHashMap<String, usize> = FxHashMap::default();
+        let mut last: Option<(usize, u64)> = None;
+        let mut sorted = true;
+        while let Some(iv) = next_interval(&mut lines, &mut line_no, path)? {
+            names.get_or_insert(iv.name.is_some());
+            let n = ranks.len();
+            let r = *ranks.entry(iv.chrom).or_insert(n);
+            if last.is_some_and(|last| (r, iv.start) < last) {
+                sorted = false;
+                break;
+            }
+            last = Some((r, iv.start));
+        }
+        let lookup = if sorted {
+            let mut lines = open_bed(path)?;
+            let mut line_no = 0;
+            let next = next_interval(&mut lines, &mut line_no, path)?;
+            BedLookup::Sweep(BedSweep {
+                lines,
+                line_no,
+                next,
+                ranks,
+                last_record: None,
+                window: vec![],
+            })
+        } else {
+            log::info!("{} is not sorted; reading it into memory", path);
+            BedLookup::Index(index_bed(path)?)
+        };
+        Ok(BedAnnotator {
+            path: path.to_string(),
+            tag: tag.to_string(),
+            names: names.unwrap_or(false),
+            lookup,
+        })
+    }
+}
+
+impl Annotator for BedAnnotator {
+    fn header_lines(&self) -> Vec<String> {
+        vec![if self.names {
+            format!(
+                r#"##INFO=<ID={},Number=.,Type=String,Description="Names of the overlapping regions of {}">"#,
+                self.tag, self.path
+            )
+        } else {
+            format!(
+                r#"##INFO=<ID={},Number=0,Type=Flag,Description="Overlaps a region of {}">"#,
+                self.tag, self.path
+            )
+        }]
+    }
+
+    fn annotate(&mut self, record: &mut bcf::Record) -> io::Result<()> {
+        let Some(rid) = record.rid() else {
+            return Ok(());
+        };
+        let start = record.pos() as u64;
+        let end = (record.end() as u64).max(start + 1);
+        let chrom =
+            String::from_utf8_lossy(record.header().rid2name(rid).map_err(io::Error::other)?)
+                .to_string();
+        let swept = match &mut self.lookup {
+            BedLookup::Sweep(sweep) => {
+                let found = sweep.overlaps(&self.path, &chrom, start, end)?;
+                if found.is_none() {
+                    log::info!(
+                        "the VCF is not in the order of {}; reading it into memory",
+                        self.path
+                    );
+                    self.lookup = BedLookup::Index(index_bed(&self.path)?);
+                }
+                found
+            }
+            BedLookup::Index(_) => None,
+        };
+        let found = match (swept, &self.lookup) {
+            (Some(found), _) => found,
+            (None, BedLookup::Index(index)) => index
+                .get(&chrom)
+                .map(|ix| {
+                    // in the order of the file as with the sweep.
+                    let mut found = ix.overlaps(start, end).cloned().collect::<Vec<_>>();
+                    found.reverse();
+                    found
+                })
+                .unwrap_or_default(),
+            (None, BedLookup::Sweep(_)) => {
+                unreachable!("an unsorted sweep is replaced by an index")
+            }
+        };
+        if found.is_empty() {
+            return Ok(());
+        }
+        let result = if self.names {
+            let mut names: Vec<&str> = vec![];
+            for name in found.iter().filter_map(|iv| iv.name.as_deref()) {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+            if names.is_empty() {
+                return Ok(());
+            }
+            let names = names.iter().map(|n| n.as_bytes()).collect::<Vec<_>>();
+            record.push_info_string(self.tag.as_bytes(), &names)
+        } else {
+            record.push_info_flag(self.tag.as_bytes())
+        };
+        result.map_err(|e| invalid(format!("error setting INFO {}: {}", self.tag, e)))
+    }
+}
+
+/// Write the records of `path` to `output` (stdout if None) with the fields from each of
+/// `annotators` added. A field that is already in the header keeps its definition.
+pub fn annotate(
+    path: &str,
+    output: Option<&str>,
+    annotators: &mut [Box<dyn Annotator>],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut reader = match path {
+        "-" | "stdin" => bcf::Reader::from_stdin()?,
+        _ => bcf::Reader::from_path(path)?,
+    };
+    _ = reader.set_threads(2);
+    let mut header = bcf::Header::from_template(reader.header());
+    for a in annotators.iter() {
+        for line in a.header_lines() {
+            let id = line
+                .split_once("ID=")
+                .and_then(|(_, rest)| rest.split(',').next())
+                .unwrap_or("");
+            if reader.header().info_type(id.as_bytes()).is_err() {
+                header.push_record(line.as_bytes());
+            }
+        }
+    }
+    let mut wtr = match output {
+        Some(output) if output != "-" => {
+            let format = crate::vcfexpress::get_vcf_format(output);
+            bcf::Writer::from_path(output, &header, !output.ends_with(".gz"), format)?
+        }
+        _ => bcf::Writer::from_stdout(&header, true, bcf::Format::Vcf)?,
+    };
+    for r in reader.records() {
+        let mut record = r?;
+        wtr.translate(&mut record);
+        for a in annotators.iter_mut() {
+            a.annotate(&mut record)?;
+        }
+        wtr.write(&record)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// bgzip `text` to `path` and build a tabix index with the chrom in column 1 and the
+    /// position in column 2.
+    fn write_tabix(path: &str, text: &str) {
+        let mut w = rust_htslib::bgzf::Writer::from_path(path).unwrap();
+        w.write_all(text.as_bytes()).unwrap();
+        drop(w);
+        let conf = rust_htslib::htslib::tbx_conf_t {
+            preset: 0,
+            sc: 1,
+            bc: 2,
+            ec: 2,
+            meta_char: b'#' as i32,
+            line_skip: 0,
+        };
+        let p = std::ffi::CString::new(path).unwrap();
+        assert_eq!(
+            unsafe { rust_htslib::htslib::tbx_index_build(p.as_ptr(), 0, &conf) },
+            0
+        );
+    }
+
+    #[test]
+    fn test_parse_columns() {
+        let columns = parse_columns(&["5:GENE,6:SCORE:Float".to_string()]).unwrap();
+        assert_eq!(
+            columns,
+            [
+                Column {
+                    index: 4,
+                    tag: "GENE".to_string(),
+                    typ: TagType::String
+                },
+                Column {
+                    index: 5,
+                    tag: "SCORE".to_string(),
+                    typ: TagType::Float
+                }
+            ]
+        );
+        for bad in ["0:GENE", "GENE", "5:", "5:X:Flag", "5:X:Float:1"] {
+            assert!(parse_columns(&[bad.to_string()]).is_err(), "{}", bad);
+        }
+        assert_eq!(parse_ref_alt_columns("3:4").unwrap(), (2, 3));
+        assert!(parse_ref_alt_columns("3").is_err());
+    }
+
+    #[test]
+    fn test_annotate_tsv() {
+        write_tabix(
+            "_test.anno.tsv.gz",
+            "#chrom\tpos\tref\talt\tgene\tscore\n\
+             chr1\t10\tA\tT\tG1\t0.5\n\
+             chr1\t10\tA\tC\tG1\t2.5\n\
+             chr1\t30\tG\tA\tG2\tx\n",
+        );
+        let mut header = bcf::Header::new();
+        header.push_record(br#"##contig=<ID=chr1,length=10000>"#);
+        header.push_record(br#"##contig=<ID=chr2,length=10000>"#);
+        let mut vcf =
+            bcf::Writer::from_path("_test.anno.vcf", &header, true, bcf::Format::Vcf).unwrap();
+        for (rid, pos, alleles) in [
+            (0, 9, vec!["A", "C", "G"]),
+            (0, 19, vec!["A", "T"]),
+            (1, 9, vec!["A", "T"]),
+        ] {
+            let mut record = vcf.empty_record();
+            record.set_rid(Some(rid));
+            record.set_pos(pos);
+            let alleles = alleles.iter().map(|a| a.as_bytes()).collect::<Vec<_>>();
+            record.set_alleles(&alleles).unwrap();
+            vcf.write(&record).unwrap();
+        }
+        drop(vcf);
+
+        let columns = parse_columns(&["5:GENE,6:SCORE:Float".to_string()]).unwrap();
+        let tsv = TsvAnnotator::new("_test.anno.tsv.gz", columns.clone(), None).unwrap();
+        assert_eq!(
+            tsv.header_lines()[1],
+            r#"##INFO=<ID=SCORE,Number=1,Type=Float,Description="Column 6 of _test.anno.tsv.gz">"#
+        );
+        let mut annotators: Vec<Box<dyn Annotator>> = vec![Box::new(tsv)];
+        annotate(
+            "_test.anno.vcf",
+            Some("_test.anno.out.vcf"),
+            &mut annotators,
+        )
+        .unwrap();
+        let out = std::fs::read_to_string("_test.anno.out.vcf").unwrap();
+        let records = out
+            .lines()
+            .filter(|l| !l.starts_with('#'))
+            .collect::<Vec<_>>();
+        assert!(records[0].ends_with("\tGENE=G1;SCORE=0.5"));
+        assert!(records[1].ends_with("\t."));
+        assert!(records[2].ends_with("\t."));
+
+        let tsv = TsvAnnotator::new("_test.anno.tsv.gz", columns, Some((2, 3))).unwrap();
+        let mut annotators: Vec<Box<dyn Annotator>> = vec![Box::new(tsv)];
+        annotate(
+            "_test.anno.vcf",
+            Some("_test.anno.out.vcf"),
+            &mut annotators,
+        )
+        .unwrap();
+        let out = std::fs::read_to_string("_test.anno.out.vcf").unwrap();
+        assert!(out.contains("##INFO=<ID=GENE,Number=A,Type=String"));
+        let records = out
+            .lines()
+            .filter(|l| !l.starts_with('#'))
+            .collect::<Vec<_>>();
+        assert!(records[0].ends_with("\tGENE=G1,.;SCORE=2.5,."));
+
+        assert!(TsvAnnotator::new("_test.anno.vcf", vec![], None).is_err());
+        for f in [
+            "_test.anno.tsv.gz",
+            "_test.anno.tsv.gz.tbi",
+            "_test.anno.vcf",
+            "_test.anno.out.vcf",
+        ] {
+            _ = std::fs::remove_file(f);
+        }
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(
+            normalize(10, b"CAT", b"CT"),
+            (10, b"CA".to_vec(), b"C".to_vec())
+        );
+        assert_eq!(
+            normalize(10, b"ac", b"at"),
+            (11, b"C".to_vec(), b"T".to_vec())
+        );
+        assert_eq!(
+            normalize(10, b"A", b"T"),
+            (10, b"A".to_vec(), b"T".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_annotate_vcf() {
+        let mut header = bcf::Header::new();
+        header.push_record(br#"##contig=<ID=chr1,length=10000>"#);
+        header.push_record(br#"##contig=<ID=chr2,length=10000>"#);
+        header.push_record(br#"##INFO=<ID=AF,Number=A,Type=Float,Description="Allele frequency">"#);
+        header.push_record(br#"##INFO=<ID=DP,Number=1,Type=Integer,Description="Depth">"#);
+        let mut src =
+            bcf::Writer::from_path("_test.annovcf.src.bcf", &header, false, bcf::Format::Bcf)
+                .unwrap();
+        for (pos, alleles, id, af) in [
+            (9, vec!["A", "T", "C"], "rs1", vec![0.1, 0.2]),
+            (19, vec!["CAT", "CT"], "rs2", vec![0.3]),
+            (30, vec!["C", "T"], ".", vec![0.4]),
+        ] {
+            let mut record = src.empty_record();
+            record.set_rid(Some(0));
+            record.set_pos(pos);
+            record.set_id(id.as_bytes()).unwrap();
+            let alleles = alleles.iter().map(|a| a.as_bytes()).collect::<Vec<_>>();
+            record.set_alleles(&alleles).unwrap();
+            record.push_info_float(b"AF", &af).unwrap();
+            record.push_info_integer(b"DP", &[30]).unwrap();
+            src.write(&record).unwrap();
+        }
+        drop(src);
+        bcf::index::build("_test.annovcf.src.bcf", None, 1, bcf::index::Type::Csi(14)).unwrap();
+
+        let mut vcf =
+            bcf::Writer::from_path("_test.annovcf.vcf", &header, true, bcf::Format::Vcf).unwrap();
+        for (rid, pos, alleles) in [
+            (0, 9, vec!["A", "C", "G"]),
+            (0, 19, vec!["CA", "C"]),
+            (0, 29, vec!["AC", "AT"]),
+            (1, 9, vec!["A", "T"]),
+        ] {
+            let mut record = vcf.empty_record();
+            record.set_rid(Some(rid));
+            record.set_pos(pos);
+            let alleles = alleles.iter().map(|a| a.as_bytes()).collect::<Vec<_>>();
+            record.set_alleles(&alleles).unwrap();
+            vcf.write(&record).unwrap();
+        }
+        drop(vcf);
+
+        let fields = ["AF,DP".to_string()];
+        let anno = VcfAnnotator::new("_test.annovcf.src.bcf", &fields, "db_", true).unwrap();
+        assert_eq!(
+            anno.header_lines()[0],
+            r#"##INFO=<ID=db_AF,Number=A,Type=Float,Description="Allele frequency (from _test.annovcf.src.bcf)">"#
+        );
+        let mut annotators: Vec<Box<dyn Annotator>> = vec![Box::new(anno)];
+        annotate(
+            "_test.annovcf.vcf",
+            Some("_test.annovcf.out.vcf"),
+            &mut annotators,
+        )
+        .unwrap();
+        let out = std::fs::read_to_string("_test.annovcf.out.vcf").unwrap();
+        let records = out
+            .lines()
+            .filter(|l| !l.starts_with('#'))
+            .map(|l| l.split('\t').collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(records[0][2], "rs1");
+        assert_eq!(records[0][7], "db_AF=0.2,.;db_DP=30");
+        assert_eq!(
+            (records[1][2], records[1][7]),
+            ("rs2", "db_AF=0.3;db_DP=30")
+        );
+        assert_eq!((records[2][2], records[2][7]), (".", "db_AF=0.4;db_DP=30"));
+        assert_eq!((records[3][2], records[3][7]), (".", "."));
+
+        assert!(VcfAnnotator::new("_test.annovcf.src.bcf", &[], "", false).is_err());
+        assert!(
+            VcfAnnotator::new("_test.annovcf.src.bcf", &["XX".to_string()], "", false).is_err()
+        );
+        assert!(VcfAnnotator::new("_test.annovcf.vcf", &fields, "", false).is_err());
+        for f in [
+            "_test.annovcf.src.bcf",
+            "_test.annovcf.src.bcf.csi",
+            "_test.annovcf.vcf",
+            "_test.annovcf.out.vcf",
+        ] {
+            _ = std::fs::remove_file(f);
+        }
+    }
+
+    #[test]
+    fn test_annotate_bed() {
+        assert_eq!(
+            parse_bed_arg("data/exons.bed.gz:EXON"),
+            ("data/exons.bed.gz".to_string(), "EXON".to_string())
+        );
+        assert_eq!(
+            parse_bed_arg("data/exons.bed.gz"),
+            ("data/exons.bed.gz".to_string(), "exons".to_string())
+        );
+
+        let mut header = bcf::Header::new();
+        header.push_record(br#"##contig=<ID=chr1,length=10000>"#);
+        header.push_record(br#"##contig=<ID=chr2,length=10000>"#);
+        let mut vcf =
+            bcf::Writer::from_path("_test.annobed.vcf", &header, true, bcf::Format::Vcf).unwrap();
+        for (rid, pos, alleles) in [
+            (0, 9, vec!["A", "T"]),
+            (0, 19, vec!["ACGT", "A"]),
+            (0, 49, vec!["A", "G"]),
+            (1, 4, vec!["A", "T"]),
+        ] {
+            let mut record = vcf.empty_record();
+            record.set_rid(Some(rid));
+            record.set_pos(pos);
+            let alleles = alleles.iter().map(|a| a.as_bytes()).collect::<Vec<_>>();
+            record.set_alleles(&alleles).unwrap();
+            vcf.write(&record).unwrap();
+        }
+        drop(vcf);
+
+        let lines = [
+            "chr1\t0\t10\tg1",
+            "chr1\t5\t12\tg2",
+            "chr1\t22\t30\tg3",
+            "chrX\t0\t100\tgx",
+            "chr2\t0\t10\tg4",
+        ];
+        std::fs::write("_test.annobed.bed", lines.join("\n")).unwrap();
+        let mut reversed = lines.to_vec();
+        reversed.reverse();
+        std::fs::write("_test.annobed.unsorted.bed", reversed.join("\n")).unwrap();
+        std::fs::write("_test.annobed.flag.bed", "track name=x\nchr1\t40\t60\n").unwrap();
+
+        for bed in ["_test.annobed.bed", "_test.annobed.unsorted.bed"] {
+            let mut annotators: Vec<Box<dyn Annotator>> = vec![
+                Box::new(BedAnnotator::new(bed, "REGION").unwrap()),
+                Box::new(BedAnnotator::new("_test.annobed.flag.bed", "IN").unwrap()),
+            ];
+            annotate(
+                "_test.annobed.vcf",
+                Some("_test.annobed.out.vcf"),
+                &mut annotators,
+            )
+            .unwrap();
+            let out = std::fs::read_to_string("_test.annobed.out.vcf").unwrap();
+            assert!(out.contains("##INFO=<ID=REGION,Number=.,Type=String"));
+            assert!(out.contains("##INFO=<ID=IN,Number=0,Type=Flag"));
+            let info = out
+                .lines()
+                .filter(|l| !l.starts_with('#'))
+                .map(|l| l.split('\t').nth(7).unwrap().to_string())
+                .collect::<Vec<_>>();
+            assert_eq!(
+                info,
+                ["REGION=g1,g2", "REGION=g3", "IN", "REGION=g4"],
+                "{}",
+                bed
+            );
+        }
+
+        // a VCF that goes back to chr1 after chr2 falls back to the index.
+        let mut vcf = bcf::Writer::from_path(
+            "_test.annobed.unsorted.vcf",
+            &header,
+            true,
+            bcf::Format::Vcf,
+        )
+        .unwrap();
+        for (rid, pos) in [(1, 4), (0, 9)] {
+            let mut record = vcf.empty_record();
+            record.set_rid(Some(rid));
+            record.set_pos(pos);
+            record.set_alleles(&[b"A", b"T"]).unwrap();
+            vcf.write(&record).unwrap();
+        }
+        drop(vcf);
+        let mut annotators: Vec<Box<dyn Annotator>> = vec![Box::new(
+            BedAnnotator::new("_test.annobed.bed", "REGION").unwrap(),
+        )];
+        annotate(
+            "_test.annobed.unsorted.vcf",
+            Some("_test.annobed.out.vcf"),
+            &mut annotators,
+        )
+        .unwrap();
+        let out = std::fs::read_to_string("_test.annobed.out.vcf").unwrap();
+        let info = out
+            .lines()
+            .filter(|l| !l.starts_with('#'))
+            .map(|l| l.split('\t').nth(7).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(info, ["REGION=g4", "REGION=g1,g2"]);
+
+        assert!(BedAnnotator::new("_test.annobed.missing.bed", "X").is_err());
+        for f in [
+            "_test.annobed.vcf",
+            "_test.annobed.unsorted.vcf",
+            "_test.annobed.bed",
+            "_test.annobed.unsorted.bed",
+            "_test.annobed.flag.bed",
+            "_test.annobed.out.vcf",
+        ] {
+            _ = std::fs::remove_file(f);
+        }
+    }
+}
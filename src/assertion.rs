@@ -0,0 +1,90 @@
+//! `assert_variant(cond, msg)` for invariants such as "AF must be between 0 and 1".
+//! A failure is logged with the locus of the current variant and the run stops with an error once
+//! there are more than `--max-assert-failures` failures (0 by default).
+use mlua::{AnyUserData, Lua, Value};
+
+use crate::variant::Variant;
+
+/// Set as lua app data to count assertion failures.
+#[derive(Debug, Default)]
+pub struct Assertions {
+    pub failures: usize,
+    /// the number of failures that are allowed before the run stops.
+    pub max_failures: usize,
+}
+
+/// chrom:pos (1-based) of the `variant` global or None outside of an evaluation.
+fn locus(lua: &Lua) -> Option<String> {
+    let ud = lua.globals().raw_get::<_, Option<AnyUserData>>("variant").ok()??;
+    let variant = ud.borrow::<Variant>().ok()?;
+    let record = variant.record();
+    let chrom = record
+        .rid()
+        .and_then(|rid| record.header().rid2name(rid).ok())
+        .unwrap_or_default();
+    Some(format!(
+        "{}:{}",
+        String::from_utf8_lossy(chrom),
+        record.pos() + 1
+    ))
+}
+
+/// Add `assert_variant(cond, msg)` to the globals. It returns `cond` as a boolean.
+pub(crate) fn register_assert(lua: &Lua) -> mlua::Result<()> {
+    let f = lua.create_function(|lua, (cond, msg): (Value, Option<String>)| {
+        if !matches!(cond, Value::Nil | Value::Boolean(false)) {
+            return Ok(true);
+        }
+        let msg = format!(
+            "assertion failed at {}: {}",
+            locus(lua).unwrap_or_else(|| "?".to_string()),
+            msg.unwrap_or_else(|| "assert_variant".to_string())
+        );
+        let (failures, max_failures) = {
+            let mut a = match lua.app_data_mut::<Assertions>() {
+                Some(a) => a,
+                None => {
+                    lua.set_app_data(Assertions::default());
+                    lua.app_data_mut::<Assertions>().unwrap()
+                }
+            };
+            a.failures += 1;
+            (a.failures, a.max_failures)
+        };
+        if failures > max_failures {
+            return Err(mlua::Error::runtime(format!(
+                "{} ({} failure(s); allowed: {})",
+                msg, failures, max_failures
+            )));
+        }
+        log::error!("{}", msg);
+        Ok(false)
+    })?;
+    lua.globals().raw_set("assert_variant", f)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_assert_variant() {
+        let lua = Lua::new();
+        register_assert(&lua).unwrap();
+        assert!(lua.load("return assert_variant(1 < 2, 'ok')").eval::<bool>().unwrap());
+        let err = lua
+            .load("assert_variant(false, 'AF must be <= 1')")
+            .exec()
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("assertion failed at ?: AF must be <= 1 (1 failure(s); allowed: 0)"));
+
+        lua.set_app_data(Assertions {
+            failures: 0,
+            max_failures: 1,
+        });
+        assert!(!lua.load("return assert_variant(nil)").eval::<bool>().unwrap());
+        assert!(lua.load("assert_variant(nil)").exec().is_err());
+        assert_eq!(lua.app_data_ref::<Assertions>().unwrap().failures, 2);
+    }
+}
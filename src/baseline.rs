@@ -0,0 +1,105 @@
+//! Compare the passing records to a previous filtered output (`--baseline previous.vcf.gz`).
+//! Each record is `new` or `unchanged` depending on whether the same site (chrom, pos, REF and ALTs)
+//! is in the baseline; baseline sites that no record passes for are `dropped`.
+use rust_htslib::bcf::{self, Read};
+use rustc_hash::FxHashMap;
+
+/// (chrom, pos, alleles)
+type Site = (Vec<u8>, i64, Vec<Vec<u8>>);
+
+#[derive(Debug, Default)]
+pub struct Baseline {
+    /// site -> whether a passing record matched it in this run.
+    sites: FxHashMap<Site, bool>,
+    new: usize,
+    unchanged: usize,
+}
+
+fn site(record: &bcf::Record) -> Site {
+    let chrom = record
+        .rid()
+        .and_then(|rid| record.header().rid2name(rid).ok())
+        .unwrap_or_default();
+    (
+        chrom.to_vec(),
+        record.pos(),
+        record.alleles().iter().map(|a| a.to_vec()).collect(),
+    )
+}
+
+impl Baseline {
+    pub fn from_path(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut reader = bcf::Reader::from_path(path)?;
+        _ = reader.set_threads(2);
+        let mut baseline = Baseline::default();
+        for r in reader.records() {
+            baseline.sites.insert(site(&r?), false);
+        }
+        Ok(baseline)
+    }
+
+    /// "unchanged" if the site of `record` is in the baseline, otherwise "new".
+    pub fn status(&self, record: &bcf::Record) -> &'static str {
+        if self.sites.contains_key(&site(record)) {
+            "unchanged"
+        } else {
+            "new"
+        }
+    }
+
+    /// Count a passing record.
+    pub fn add_passing(&mut self, record: &bcf::Record) {
+        match self.sites.get_mut(&site(record)) {
+            Some(seen) => {
+                if !*seen {
+                    self.unchanged += 1;
+                }
+                *seen = true;
+            }
+            None => self.new += 1,
+        }
+    }
+
+    /// The number of (new, unchanged, dropped) sites.
+    pub fn summary(&self) -> (usize, usize, usize) {
+        let dropped = self.sites.values().filter(|seen| !**seen).count();
+        (self.new, self.unchanged, dropped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_baseline() {
+        let mut header = bcf::Header::new();
+        header.push_record(br#"##contig=<ID=chr1,length=10000>"#);
+        let mut vcf =
+            bcf::Writer::from_path("_test.baseline.vcf", &header, true, bcf::Format::Vcf).unwrap();
+        let mut records = vec![];
+        for (pos, alt) in [(9, b"T"), (19, b"C"), (29, b"G")] {
+            let mut record = vcf.empty_record();
+            record.set_rid(Some(0));
+            record.set_pos(pos);
+            record.set_alleles(&[b"A", alt]).unwrap();
+            // the first 2 are in the baseline.
+            if pos < 20 {
+                vcf.write(&record).unwrap();
+            }
+            records.push(record);
+        }
+        drop(vcf);
+        let mut baseline = Baseline::from_path("_test.baseline.vcf").unwrap();
+        assert_eq!(baseline.status(&records[0]), "unchanged");
+        assert_eq!(baseline.status(&records[2]), "new");
+        baseline.add_passing(&records[0]);
+        baseline.add_passing(&records[2]);
+        assert_eq!(baseline.summary(), (1, 1, 1));
+
+        // a different ALT at the same position is a different site.
+        records[1].set_alleles(&[b"A", b"G"]).unwrap();
+        assert_eq!(baseline.status(&records[1]), "new");
+        _ = std::fs::remove_file("_test.baseline.vcf");
+    }
+}
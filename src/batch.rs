@@ -0,0 +1,213 @@
+//! Run the same expressions over many files (e.g. thousands of single-sample VCFs) on a pool of
+//! threads. The expressions and preludes are compiled once up front so that a typo fails before
+//! any file is processed; each thread then processes one file at a time with its own lua state
+//! that loads the compiled bytecode.
+use mlua::Lua;
+use rust_htslib::bcf::Read;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use crate::luau::Bytecode;
+use crate::variant::HeaderMap;
+use crate::vcfexpress::VCFExpress;
+
+#[derive(Debug, Default, Clone)]
+pub struct BatchOptions {
+    pub expressions: Vec<String>,
+    pub set_expressions: Vec<String>,
+    pub template: Option<String>,
+    pub lua_prelude: Vec<String>,
+    /// each input is written here with the same file name.
+    pub output_dir: String,
+    pub threads: usize,
+    pub sandbox: bool,
+}
+
+/// The result of processing one input file.
+#[derive(Debug)]
+pub struct FileSummary {
+    pub path: String,
+    pub output: String,
+    /// (evaluated, passing) or the error message if the file could not be processed.
+    pub result: Result<(usize, usize), String>,
+}
+
+impl std::fmt::Display for FileSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.result {
+            Ok((evaluated, passing)) => write!(
+                f,
+                "{}\t{}\t{}\t{}",
+                self.path, self.output, evaluated, passing
+            ),
+            Err(e) => write!(f, "{}\t{}\terror\t{}", self.path, self.output, e),
+        }
+    }
+}
+
+fn output_path(opts: &BatchOptions, path: &str) -> String {
+    let name = std::path::Path::new(path)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.to_string());
+    let name = if opts.template.is_some() {
+        format!("{}.txt", name)
+    } else {
+        name
+    };
+    std::path::Path::new(&opts.output_dir)
+        .join(name)
+        .to_string_lossy()
+        .to_string()
+}
+
+/// Compile each expression and prelude once so syntax errors are reported before any file is read.
+fn compile(opts: &BatchOptions) -> Result<Bytecode, Box<dyn std::error::Error>> {
+    let lua = Lua::new();
+    let mut bytecode = Bytecode::default();
+    for (i, exp) in opts.expressions.iter().enumerate() {
+        bytecode.compile(&lua, exp, &format!("expression {}", i + 1))?;
+    }
+    for exp in &opts.set_expressions {
+        let Some((_, code)) = exp.split_once('=') else {
+            return Err(format!("expected TAG=expression in set expression, got '{}'", exp).into());
+        };
+        bytecode.compile(&lua, code, exp)?;
+    }
+    for path in &opts.lua_prelude {
+        let code = std::fs::read_to_string(path)?;
+        bytecode.compile(&lua, &code, path)?;
+    }
+    Ok(bytecode)
+}
+
+fn process(
+    opts: &BatchOptions,
+    bytecode: &Bytecode,
+    path: &str,
+    output: &str,
+) -> Result<(usize, usize), String> {
+    let lua = Lua::new();
+    lua.set_app_data(bytecode.clone());
+    let mut vcfexpr = VCFExpress::new(
+        &lua,
+        path.to_string(),
+        opts.expressions.clone(),
+        opts.set_expressions.clone(),
+        opts.template.clone(),
+        opts.lua_prelude.clone(),
+        Some(output.to_string()),
+        opts.sandbox,
+    )
+    .map_err(|e| e.to_string())?;
+    let mut reader = vcfexpr.reader();
+    let mut writer = vcfexpr.writer().map_err(|e| e.to_string())?;
+    let header_map = HeaderMap::new();
+    vcfexpr.start().map_err(|e| e.to_string())?;
+    for record in reader.records() {
+        let mut record = record.map_err(|e| e.to_string())?;
+        vcfexpr
+            .translate(&mut writer, &mut record)
+            .map_err(|e| e.to_string())?;
+        let mut sob = vcfexpr
+            .evaluate(record, header_map.clone())
+            .map_err(|e| e.to_string())?;
+        writer.write(&mut sob).map_err(|e| e.to_string())?;
+    }
+    vcfexpr.finish(&mut writer).map_err(|e| e.to_string())?;
+    Ok(vcfexpr.counts())
+}
+
+/// Process each of `paths` into `opts.output_dir` using `opts.threads` threads. A file that fails
+/// does not stop the others; its summary has the error. Summaries are in the order of `paths`.
+pub fn run_batch(
+    opts: &BatchOptions,
+    paths: &[String],
+) -> Result<Vec<FileSummary>, Box<dyn std::error::Error>> {
+    let bytecode = compile(opts)?;
+    let outputs = paths
+        .iter()
+        .map(|p| output_path(opts, p))
+        .collect::<Vec<_>>();
+    for (i, o) in outputs.iter().enumerate() {
+        if outputs[..i].contains(o) {
+            return Err(format!("more than one input would be written to {}", o).into());
+        }
+    }
+    std::fs::create_dir_all(&opts.output_dir)?;
+
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(Vec::with_capacity(paths.len()));
+    std::thread::scope(|s| {
+        for _ in 0..opts.threads.clamp(1, paths.len().max(1)) {
+            s.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                if i >= paths.len() {
+                    break;
+                }
+                let result = process(opts, &bytecode, &paths[i], &outputs[i]);
+                results.lock().unwrap().push((i, result));
+            });
+        }
+    });
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(i, _)| *i);
+    Ok(results
+        .into_iter()
+        .map(|(i, result)| FileSummary {
+            path: paths[i].clone(),
+            output: outputs[i].clone(),
+            result,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_htslib::bcf;
+
+    #[test]
+    fn test_run_batch() {
+        let mut header = bcf::Header::new();
+        header.push_record(br#"##contig=<ID=chr1,length=10000>"#);
+        let mut paths = vec![];
+        for n in 1..=3 {
+            let path = format!("_test.batch{}.vcf", n);
+            let mut vcf = bcf::Writer::from_path(&path, &header, true, bcf::Format::Vcf).unwrap();
+            for pos in 0..n * 2 {
+                let mut record = vcf.empty_record();
+                record.set_rid(Some(0));
+                record.set_pos(pos);
+                record.set_alleles(&[b"A", b"T"]).unwrap();
+                vcf.write(&record).unwrap();
+            }
+            paths.push(path);
+        }
+        paths.push("_test.batch.missing.vcf".to_string());
+        let opts = BatchOptions {
+            expressions: vec!["return variant.pos % 2 == 0".to_string()],
+            output_dir: "_test.batch.out".to_string(),
+            threads: 2,
+            ..Default::default()
+        };
+        let summaries = run_batch(&opts, &paths).unwrap();
+        assert_eq!(summaries.len(), 4);
+        assert_eq!(summaries[2].result, Ok((6, 3)));
+        assert_eq!(summaries[2].output, "_test.batch.out/_test.batch3.vcf");
+        assert!(summaries[3].result.is_err());
+        let out = std::fs::read_to_string("_test.batch.out/_test.batch1.vcf").unwrap();
+        assert_eq!(out.lines().filter(|l| !l.starts_with('#')).count(), 1);
+
+        let bad = BatchOptions {
+            expressions: vec!["return variant.pos >".to_string()],
+            ..opts
+        };
+        assert!(run_batch(&bad, &paths).is_err());
+
+        for p in &paths {
+            _ = std::fs::remove_file(p);
+        }
+        _ = std::fs::remove_dir_all("_test.batch.out");
+    }
+}
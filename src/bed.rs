@@ -0,0 +1,77 @@
+//! Read the BED files of --targets-file, --annotate, --exclude-bed, --coverage and --roh. Files
+//! may be plain or (b)gzipped. Empty, `#`, `track` and `browser` lines are skipped.
+use std::io::{self, BufReader};
+
+pub(crate) type Reader = BufReader<rust_htslib::bgzf::Reader>;
+
+/// Open a plain or (b)gzipped file.
+pub(crate) fn open(path: &str) -> io::Result<Reader> {
+    let rdr = rust_htslib::bgzf::Reader::from_path(path).map_err(io::Error::other)?;
+    Ok(BufReader::new(rdr))
+}
+
+/// A BED line with the 0-based, half-open start and end parsed.
+#[derive(Debug)]
+pub(crate) struct Line<'a> {
+    pub chrom: &'a str,
+    pub start: u64,
+    pub end: u64,
+    /// all of the columns, including the first three.
+    pub fields: Vec<&'a str>,
+}
+
+/// Parse the (0-based) `line_number` of `source` (the path or a description used in errors).
+/// Returns None for a line that is skipped.
+pub(crate) fn parse<'a>(
+    source: &str,
+    line_number: usize,
+    line: &'a str,
+) -> io::Result<Option<Line<'a>>> {
+    let line = line.trim_end_matches(['\r', '\n']);
+    if line.is_empty()
+        || line.starts_with('#')
+        || line.starts_with("track")
+        || line.starts_with("browser")
+    {
+        return Ok(None);
+    }
+    let fields = line.split('\t').collect::<Vec<_>>();
+    let parse = |i: usize| fields.get(i).and_then(|s| s.trim().parse::<u64>().ok());
+    match (parse(1), parse(2)) {
+        (Some(start), Some(end)) if !fields[0].is_empty() => Ok(Some(Line {
+            chrom: fields[0],
+            start,
+            end,
+            fields,
+        })),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "invalid BED line {} in {}: {}",
+                line_number + 1,
+                source,
+                line
+            ),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let l = parse("a.bed", 0, "chr1\t10\t20\tname\r\n")
+            .unwrap()
+            .unwrap();
+        assert_eq!((l.chrom, l.start, l.end), ("chr1", 10, 20));
+        assert_eq!(l.fields, vec!["chr1", "10", "20", "name"]);
+        for skipped in ["", "#chrom\tstart", "track name=x", "browser position chr1"] {
+            assert!(parse("a.bed", 0, skipped).unwrap().is_none());
+        }
+        let err = parse("a.bed", 2, "chr1\tx\t20").unwrap_err();
+        assert_eq!(err.to_string(), "invalid BED line 3 in a.bed: chr1\tx\t20");
+        assert!(parse("a.bed", 0, "\t1\t2").is_err());
+    }
+}
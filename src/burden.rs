@@ -0,0 +1,175 @@
+//! Gene-level burden (`--burden`): count the passing (qualifying) variants in each gene and, for
+//! each sample, the number of those variants where it carries an alternate allele. The genes of a
+//! variant come from a lua expression, e.g. using the CSQ parsing in scripts/csq.lua, that returns
+//! a gene, a table of genes or nil. The output is a gene x sample matrix:
+//! `#gene variants carriers <sample>...` where `carriers` is the number of samples with at least
+//! one qualifying variant in the gene.
+use mlua::Value;
+use rust_htslib::bcf::{self};
+use rustc_hash::FxHashMap;
+
+use crate::genotypes::GtData;
+
+struct Gene {
+    name: String,
+    variants: usize,
+    // qualifying variants carried by each sample.
+    samples: Vec<usize>,
+}
+
+pub(crate) struct Burden<'lua> {
+    genes: mlua::Function<'lua>,
+    samples: Vec<String>,
+    rows: Vec<Gene>,
+    index: FxHashMap<String, usize>,
+}
+
+impl<'lua> Burden<'lua> {
+    /// `genes` is the compiled `--burden` expression.
+    pub(crate) fn new(genes: mlua::Function<'lua>, samples: Vec<String>) -> Self {
+        Burden {
+            genes,
+            samples,
+            rows: vec![],
+            index: FxHashMap::default(),
+        }
+    }
+
+    /// Evaluate the `--burden` expression for the current `variant`. A gene returned more than
+    /// once (e.g. for several transcripts) is counted once.
+    pub(crate) fn genes(&self) -> mlua::Result<Vec<String>> {
+        let mut genes = match self.genes.call::<_, Value>(())? {
+            Value::Nil => vec![],
+            Value::Table(t) => t
+                .sequence_values::<String>()
+                .collect::<mlua::Result<Vec<_>>>()?,
+            Value::String(s) => vec![s.to_str()?.to_string()],
+            v => {
+                return Err(mlua::Error::runtime(format!(
+                    "--burden expression must return a gene, a table of genes or nil, got {}",
+                    v.type_name()
+                )))
+            }
+        };
+        genes.retain(|g| !g.is_empty() && g != ".");
+        genes.sort();
+        genes.dedup();
+        Ok(genes)
+    }
+
+    /// Add a qualifying record to each of `genes`.
+    pub(crate) fn add(&mut self, genes: Vec<String>, record: &bcf::Record) -> std::io::Result<()> {
+        if genes.is_empty() {
+            return Ok(());
+        }
+        let gts = GtData::from_record(record).map_err(std::io::Error::other)?;
+        let carriers = (0..self.samples.len())
+            .map(|i| {
+                gts.alleles(i)
+                    .is_some_and(|alleles| alleles.iter().any(|&a| a > 0))
+            })
+            .collect::<Vec<_>>();
+        for gene in genes {
+            let i = match self.index.get(&gene) {
+                Some(i) => *i,
+                None => {
+                    self.index.insert(gene.clone(), self.rows.len());
+                    self.rows.push(Gene {
+                        name: gene,
+                        variants: 0,
+                        samples: vec![0; self.samples.len()],
+                    });
+                    self.rows.len() - 1
+                }
+            };
+            let row = &mut self.rows[i];
+            row.variants += 1;
+            for (n, carrier) in row.samples.iter_mut().zip(&carriers) {
+                *n += *carrier as usize;
+            }
+        }
+        Ok(())
+    }
+
+    /// The header and one line per gene in the order the genes were first seen.
+    pub(crate) fn rows(&self) -> Vec<String> {
+        let mut header = vec!["#gene", "variants", "carriers"];
+        header.extend(self.samples.iter().map(|s| s.as_str()));
+        let mut rows = vec![header.join("\t")];
+        for g in &self.rows {
+            let mut row = vec![
+                g.name.clone(),
+                g.variants.to_string(),
+                g.samples.iter().filter(|n| **n > 0).count().to_string(),
+            ];
+            row.extend(g.samples.iter().map(|n| n.to_string()));
+            rows.push(row.join("\t"));
+        }
+        rows
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mlua::Lua;
+    use rust_htslib::bcf::record::GenotypeAllele;
+
+    #[test]
+    fn test_burden() {
+        let mut header = bcf::Header::new();
+        header.push_record(br#"##contig=<ID=chr1,length=10000>"#);
+        header.push_record(br#"##FORMAT=<ID=GT,Number=1,Type=String,Description="GT">"#);
+        header.push_sample(b"S1");
+        header.push_sample(b"S2");
+        header.push_sample(b"S3");
+        let vcf =
+            bcf::Writer::from_path("_test.burden.vcf", &header, true, bcf::Format::Vcf).unwrap();
+        let lua = Lua::new();
+        let f = lua
+            .load("return genes")
+            .set_name("burden")
+            .into_function()
+            .unwrap();
+        let samples = ["S1", "S2", "S3"].map(String::from).to_vec();
+        let mut burden = Burden::new(f, samples);
+
+        for (genes, gts) in [
+            ("{'A', 'B', 'A'}", [(0, 1), (0, 0), (-1, -1)]),
+            ("'A'", [(1, 1), (0, 0), (0, 0)]),
+            ("nil", [(1, 1), (1, 1), (1, 1)]),
+        ] {
+            let mut record = vcf.empty_record();
+            record.set_rid(Some(0));
+            record.set_alleles(&[b"A", b"T"]).unwrap();
+            let alleles = gts
+                .iter()
+                .flat_map(|&(a, b)| {
+                    [a, b].map(|x| {
+                        if x < 0 {
+                            GenotypeAllele::UnphasedMissing
+                        } else {
+                            GenotypeAllele::Unphased(x)
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+            record.push_genotypes(&alleles).unwrap();
+            lua.load(format!("genes = {}", genes)).exec().unwrap();
+            let genes = burden.genes().unwrap();
+            burden.add(genes, &record).unwrap();
+        }
+        assert_eq!(
+            burden.rows(),
+            [
+                "#gene\tvariants\tcarriers\tS1\tS2\tS3",
+                "A\t2\t1\t2\t0\t0",
+                "B\t1\t1\t1\t0\t0"
+            ]
+        );
+        lua.load("genes = 3").exec().unwrap();
+        assert!(burden.genes().is_err());
+        drop(vcf);
+        _ = std::fs::remove_file("_test.burden.vcf");
+    }
+}
@@ -0,0 +1,630 @@
+//! Static checks of expressions against the header of a VCF/BCF without reading any records
+//! (`vcfexpress check`).
+use rust_htslib::bcf::header::{HeaderView, TagLength, TagType};
+
+use crate::alias::SampleAliases;
+
+/// Methods that take sample names as their first argument.
+const SAMPLE_METHODS: &[&str] = &[
+    "sample",
+    "samples",
+    "set_phased",
+    "set_phase_set",
+    "transmitted_allele",
+    "is_carrier",
+];
+
+/// String literals in the first argument of calls to methods that take sample names,
+/// e.g. "NA12878" from `variant:sample("NA12878")` or `variant:samples({"A", "B"})`.
+pub fn sample_literals(code: &str) -> Vec<String> {
+    let mut names = vec![];
+    for method in SAMPLE_METHODS {
+        let call = format!(":{}(", method);
+        let mut rest = code;
+        while let Some(i) = rest.find(&call) {
+            rest = &rest[i + call.len()..];
+            names.extend(first_arg_strings(rest));
+        }
+    }
+    names
+}
+
+/// String literals in `s` up to the end of the first argument (a `,` or `)` at depth 0).
+fn first_arg_strings(s: &str) -> Vec<String> {
+    let mut out = vec![];
+    let mut depth = 0;
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' | '\'' => {
+                let mut lit = String::new();
+                for d in chars.by_ref() {
+                    if d == c {
+                        break;
+                    }
+                    lit.push(d);
+                }
+                out.push(lit);
+            }
+            '(' | '{' | '[' => depth += 1,
+            ')' | '}' | ']' if depth > 0 => depth -= 1,
+            ')' | ',' if depth == 0 => break,
+            _ => {}
+        }
+    }
+    out
+}
+
+/// Problems with the sample names (or aliases) used in `code`, one message per unknown name.
+pub fn check_samples(
+    hv: &HeaderView,
+    name: &str,
+    code: &str,
+    aliases: Option<&SampleAliases>,
+) -> Vec<String> {
+    sample_literals(code)
+        .into_iter()
+        .filter(|s| {
+            let s = aliases.map_or(s.as_str(), |a| a.resolve(s));
+            hv.sample_id(s.as_bytes()).is_none()
+        })
+        .map(|s| {
+            format!(
+                "{}: {}",
+                name,
+                crate::sample::unknown_sample_message(hv, &s)
+            )
+        })
+        .collect()
+}
+
+/// Globals and methods that read from an annotation source given on the command line.
+const SOURCES: &[(&str, &str)] = &[
+    ("acindex", "--ac-index"),
+    ("coverage", "--coverage"),
+    ("baseline", "--baseline"),
+    (":transmitted_allele(", "--ped"),
+    (":in_roh(", "--roh"),
+    ("stats", "stats"),
+];
+
+/// Helper functions defined in the prelude or registered by vcfexpress.
+const HELPERS: &[&str] = &["map", "filter", "all", "any", "pprint", "assert_variant"];
+
+/// What a single expression, template or prelude uses.
+#[derive(Debug, Default, PartialEq)]
+pub struct Plan {
+    pub info: Vec<String>,
+    pub format: Vec<String>,
+    pub samples: Vec<String>,
+    pub sources: Vec<String>,
+    /// prelude helpers and `variant`/`sample` methods.
+    pub helpers: Vec<String>,
+}
+
+fn push_unique(v: &mut Vec<String>, s: &str) {
+    if !v.iter().any(|x| x == s) {
+        v.push(s.to_string());
+    }
+}
+
+/// The identifiers in `code` that follow `.` or `:`, with the character before each.
+fn members(code: &str) -> Vec<(char, String)> {
+    let mut out = vec![];
+    let mut chars = code.char_indices().peekable();
+    while let Some((_, c)) = chars.next() {
+        if c != '.' && c != ':' {
+            continue;
+        }
+        let mut ident = String::new();
+        while let Some((_, d)) = chars.peek() {
+            if d.is_alphanumeric() || *d == '_' {
+                ident.push(*d);
+                chars.next();
+            } else {
+                break;
+            }
+        }
+        if ident.starts_with(|d: char| d.is_alphabetic() || d == '_') {
+            out.push((c, ident));
+        }
+    }
+    out
+}
+
+/// The INFO and FORMAT tags, samples, annotation sources and helpers used by `code`.
+/// Tags are read from `variant:info("TAG")` and `variant:format("TAG")` and from fields such as
+/// `sample.DP` that are FORMAT tags in the header.
+pub fn plan(hv: &HeaderView, code: &str) -> Plan {
+    let mut p = Plan::default();
+    for (method, tags) in [(":info(", &mut p.info), (":format(", &mut p.format)] {
+        let mut rest = code;
+        while let Some(i) = rest.find(method) {
+            rest = &rest[i + method.len()..];
+            for tag in first_arg_strings(rest) {
+                push_unique(tags, &tag);
+            }
+        }
+    }
+    for (c, ident) in members(code) {
+        if c == ':' {
+            push_unique(&mut p.helpers, &format!(":{}", ident));
+        } else if hv.format_type(ident.as_bytes()).is_ok() {
+            push_unique(&mut p.format, &ident);
+        }
+    }
+    for s in sample_literals(code) {
+        push_unique(&mut p.samples, &s);
+    }
+    for (token, source) in SOURCES {
+        if code.contains(token) {
+            push_unique(&mut p.sources, source);
+        }
+    }
+    for helper in HELPERS {
+        if code.contains(&format!("{}(", helper)) {
+            push_unique(&mut p.helpers, helper);
+        }
+    }
+    p
+}
+
+/// `plan` as indented lines with tags that are not in the header marked.
+pub fn explain(hv: &HeaderView, name: &str, code: &str) -> String {
+    let p = plan(hv, code);
+    let mark = |tags: &[String], exists: &dyn Fn(&str) -> bool| {
+        tags.iter()
+            .map(|t| {
+                if exists(t) {
+                    t.clone()
+                } else {
+                    format!("{} (not in header)", t)
+                }
+            })
+            .collect::<Vec<_>>()
+    };
+    let mut out = format!("{}: {}\n", name, code.lines().next().unwrap_or(""));
+    for (label, values) in [
+        (
+            "INFO",
+            mark(&p.info, &|t| hv.info_type(t.as_bytes()).is_ok()),
+        ),
+        (
+            "FORMAT",
+            mark(&p.format, &|t| hv.format_type(t.as_bytes()).is_ok()),
+        ),
+        ("samples", p.samples),
+        ("sources", p.sources),
+        ("helpers", p.helpers),
+    ] {
+        if !values.is_empty() {
+            out.push_str(&format!("  {}: {}\n", label, values.join(", ")));
+        }
+    }
+    out
+}
+
+const COMPARISONS: &[&str] = &["<=", ">=", "==", "~=", "<", ">"];
+const ORDERINGS: &[&str] = &["<=", ">=", "<", ">"];
+
+/// The comparison operator at the start of `s` (after whitespace), if any.
+fn comparison(s: &str) -> Option<&'static str> {
+    let s = s.trim_start();
+    COMPARISONS.iter().find(|op| s.starts_with(**op)).copied()
+}
+
+/// The index just past the `)` that closes a call whose arguments start at `s`.
+fn call_end(s: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut quote = None;
+    for (i, c) in s.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '(' | '{' | '[') => depth += 1,
+            (None, ')') if depth == 0 => return Some(i + 1),
+            (None, ')' | '}' | ']') => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+pub(crate) fn number(length: TagLength) -> String {
+    match length {
+        TagLength::Fixed(n) => n.to_string(),
+        TagLength::AltAlleles => "A".to_string(),
+        TagLength::Alleles => "R".to_string(),
+        TagLength::Genotypes => "G".to_string(),
+        TagLength::Variable => ".".to_string(),
+    }
+}
+
+/// A number literal with a decimal point at the start of `s` (after whitespace).
+fn float_literal(s: &str) -> Option<&str> {
+    let s = s.trim_start();
+    let end = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-'))
+        .unwrap_or(s.len());
+    let lit = &s[..end];
+    (lit.contains('.') && lit.chars().any(|c| c.is_ascii_digit())).then_some(lit)
+}
+
+/// Likely mistakes in `code`, each with a suggested fix:
+/// a Number=A/R/G/. field compared to a scalar, `==` with a float, a Flag compared with `<` or `>`,
+/// a String field ordered with `<` or `>`, a numeric INFO field compared to a string and the
+/// 0-based `variant.pos` compared to a number.
+pub fn lints(hv: &HeaderView, code: &str) -> Vec<String> {
+    let mut out = vec![];
+    let mut rest = code;
+    while let Some(i) = rest.find(":info(") {
+        rest = &rest[i + ":info(".len()..];
+        let (Some(tag), Some(end)) = (first_arg_strings(rest).pop(), call_end(rest)) else {
+            continue;
+        };
+        let Some(op) = comparison(&rest[end..]) else {
+            continue;
+        };
+        let operand = rest[end..].trim_start()[op.len()..].trim_start();
+        match hv.info_type(tag.as_bytes()) {
+            Ok((TagType::Flag, _)) if op != "==" && op != "~=" => out.push(format!(
+                "INFO {} is a Flag so variant:info(\"{}\") is true or false; use it directly \
+                 instead of comparing with {}",
+                tag, tag, op
+            )),
+            Ok((TagType::Flag, _)) => {}
+            Ok((TagType::String, _)) if ORDERINGS.contains(&op) => out.push(format!(
+                "INFO {} is Type=String so {} compares it as text; use \
+                 tonumber(variant:info(\"{}\")) {} ... to compare numbers",
+                tag, op, tag, op
+            )),
+            Ok((TagType::Integer | TagType::Float, TagLength::Fixed(1)))
+                if operand.starts_with(['"', '\'']) =>
+            {
+                out.push(format!(
+                    "INFO {} is a number so variant:info(\"{}\") {} a string is never true; \
+                     compare with a number",
+                    tag, tag, op
+                ))
+            }
+            Ok((_, length)) if !matches!(length, TagLength::Fixed(1)) => out.push(format!(
+                "INFO {} is Number={} so variant:info(\"{}\") is a table; compare an element \
+                 e.g. variant:info(\"{}\")[1] {} ...",
+                tag,
+                number(length),
+                tag,
+                tag,
+                op
+            )),
+            _ => {}
+        }
+    }
+    for (i, _) in code.match_indices('.') {
+        let ident = code[i + 1..]
+            .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .next()
+            .unwrap_or("");
+        if ident.is_empty() || ident == "GT" {
+            continue;
+        }
+        let Some(op) = comparison(&code[i + 1 + ident.len()..]) else {
+            continue;
+        };
+        if ident == "pos" && code[..i].ends_with("variant") {
+            out.push(format!(
+                "variant.pos is 0-based; for the 1-based position in the VCF use variant.POS {} ...",
+                op
+            ));
+        } else if let Ok((typ, length)) = hv.format_type(ident.as_bytes()) {
+            if typ == TagType::String && ORDERINGS.contains(&op) {
+                out.push(format!(
+                    "FORMAT {} is Type=String so {} compares it as text; use tonumber(.{}) {} ... \
+                     to compare numbers",
+                    ident, op, ident, op
+                ));
+            } else if !matches!(length, TagLength::Fixed(1)) {
+                out.push(format!(
+                    "FORMAT {} is Number={} so .{} is a table; compare an element e.g. .{}[1] {} ...",
+                    ident,
+                    number(length),
+                    ident,
+                    ident,
+                    op
+                ));
+            }
+        }
+    }
+    for op in ["==", "~="] {
+        for (i, _) in code.match_indices(op) {
+            let before = code[..i].trim_end();
+            let start = before
+                .rfind(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-'))
+                .map_or(0, |j| j + 1);
+            let Some(lit) =
+                float_literal(&code[i + op.len()..]).or(float_literal(&before[start..]))
+            else {
+                continue;
+            };
+            out.push(format!(
+                "{} with the float {} is rarely true after rounding; use math.abs(x - {}) < 1e-6",
+                op, lit, lit
+            ));
+        }
+    }
+    out
+}
+
+/// The IDs of the INFO and FORMAT fields added with `header:add_info({ID="X", ...})` and
+/// `header:add_format(...)` in `code`.
+pub fn added_tags(code: &str) -> (Vec<String>, Vec<String>) {
+    let mut added = (vec![], vec![]);
+    for (method, tags) in [(":add_info(", &mut added.0), (":add_format(", &mut added.1)] {
+        let mut rest = code;
+        while let Some(i) = rest.find(method) {
+            rest = &rest[i + method.len()..];
+            let args = &rest[..call_end(rest).unwrap_or(rest.len())];
+            let Some(j) = args.find("ID") else { continue };
+            let value = args[j + 2..].trim_start();
+            if let Some(value) = value.strip_prefix('=') {
+                if let Some(tag) = first_arg_strings(value).into_iter().next() {
+                    push_unique(tags, &tag);
+                }
+            }
+        }
+    }
+    added
+}
+
+/// The tag set by a -s or --set-format expression and the lua code to compile for `name`
+/// (-e N, -s N, --set-format N, -t N or the path of a prelude).
+fn lua_source<'a>(name: &str, code: &'a str) -> (Option<&'a str>, String) {
+    if name.starts_with("-s ") || name.starts_with("--set-format ") {
+        match code.split_once('=') {
+            Some((tag, exp)) => (Some(tag), exp.to_string()),
+            None => (Some(code), String::new()),
+        }
+    } else if name.starts_with("-t ") {
+        (None, crate::vcfexpress::template_code(code))
+    } else {
+        (None, code.to_string())
+    }
+}
+
+/// Compile each code without running it and check the tags it reads or sets against the
+/// header: a syntax error, a -s or --set-format field that is not in the header and an INFO or
+/// FORMAT tag read with `variant:info("X")` or `variant:format("X")` that is not in the header
+/// (or added by a prelude) are problems.
+pub fn check_code(hv: &HeaderView, codes: &[(String, String)]) -> Vec<String> {
+    let lua = mlua::Lua::new();
+    let (mut info, mut format) = (vec![], vec![]);
+    for (_, code) in codes {
+        let (i, f) = added_tags(code);
+        info.extend(i);
+        format.extend(f);
+    }
+    // added to the header by vcfexpress when they are used.
+    info.push(crate::sort::SORTKEY.to_string());
+    info.push(crate::altfilter::ALT_FILTER.to_string());
+    let has_info = |t: &str| hv.info_type(t.as_bytes()).is_ok() || info.iter().any(|i| i == t);
+    let has_format =
+        |t: &str| hv.format_type(t.as_bytes()).is_ok() || format.iter().any(|f| f == t);
+    let mut problems = vec![];
+    for (name, code) in codes {
+        let (set_tag, source) = lua_source(name, code);
+        if let Err(e) = lua.load(&source).set_name(name).into_function() {
+            problems.push(format!("{}: {}", name, e));
+        }
+        match set_tag {
+            Some(tag) if name.starts_with("-s ") && !has_info(tag) => problems.push(format!(
+                "{}: INFO {} is not in the header; add it with header:add_info in a prelude",
+                name, tag
+            )),
+            Some(tag) if name.starts_with("--set-format ") && !has_format(tag) => {
+                problems.push(format!(
+                "{}: FORMAT {} is not in the header; add it with header:add_format in a prelude",
+                name, tag
+            ))
+            }
+            _ => {}
+        }
+        let p = plan(hv, code);
+        for tag in p.info.iter().filter(|t| !has_info(t)) {
+            problems.push(format!("{}: INFO {} is not in the header", name, tag));
+        }
+        for tag in p.format.iter().filter(|t| !has_format(t)) {
+            problems.push(format!("{}: FORMAT {} is not in the header", name, tag));
+        }
+    }
+    problems
+}
+
+/// Check each (name, code) against the header of `path` and return the problems found.
+pub fn check(
+    path: &str,
+    codes: &[(String, String)],
+    aliases: Option<&SampleAliases>,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let reader = rust_htslib::bcf::Reader::from_path(path)?;
+    let hv = rust_htslib::bcf::Read::header(&reader);
+    let mut problems = check_code(hv, codes);
+    problems.extend(codes.iter().flat_map(|(name, code)| {
+        let mut problems = check_samples(hv, name, code, aliases);
+        problems.extend(
+            lints(hv, code)
+                .into_iter()
+                .map(|l| format!("{}: {}", name, l)),
+        );
+        problems
+    }));
+    Ok(problems)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_htslib::bcf;
+
+    #[test]
+    fn test_check_samples() {
+        assert_eq!(
+            sample_literals(
+                r#"variant:sample("A").DP > 2 and all(f, variant:samples({'B', "C"}))"#
+            ),
+            vec!["A", "B", "C"]
+        );
+        assert_eq!(
+            sample_literals(r#"variant:set_phased("A", "B")"#),
+            vec!["A"]
+        );
+        assert!(sample_literals("variant:sample(1)").is_empty());
+
+        let mut header = bcf::Header::new();
+        header.push_sample(b"A");
+        header.push_sample(b"B");
+        let vcf =
+            bcf::Writer::from_path("_test.check.vcf", &header, true, bcf::Format::Vcf).unwrap();
+        let problems = check_samples(vcf.header(), "-e 1", r#"variant:samples({"A", "X"})"#, None);
+        assert_eq!(
+            problems,
+            vec!["-e 1: sample 'X' not found in VCF. available samples: A, B"]
+        );
+        let aliases = SampleAliases::from_reader("X\tB\n".as_bytes()).unwrap();
+        assert!(check_samples(
+            vcf.header(),
+            "-e 1",
+            r#"variant:sample("X")"#,
+            Some(&aliases)
+        )
+        .is_empty());
+        drop(vcf);
+        _ = std::fs::remove_file("_test.check.vcf");
+    }
+
+    #[test]
+    fn test_plan() {
+        let mut header = bcf::Header::new();
+        header.push_record(br#"##INFO=<ID=AF,Number=A,Type=Float,Description="AF">"#);
+        header.push_record(br#"##FORMAT=<ID=DP,Number=1,Type=Integer,Description="DP">"#);
+        header.push_record(br#"##FORMAT=<ID=GQ,Number=1,Type=Integer,Description="GQ">"#);
+        header.push_sample(b"A");
+        let vcf =
+            bcf::Writer::from_path("_test.plan.vcf", &header, true, bcf::Format::Vcf).unwrap();
+        let code = r#"variant:info("AF")[1] < 0.01 and variant:sample("A").DP > 10
+            and all(function(s) return s.GQ > 20 end, variant:samples({"A"}))
+            and variant:info('CSQ') ~= nil and acindex:lookup(variant) < 3"#;
+        let p = plan(vcf.header(), code);
+        assert_eq!(p.info, vec!["AF", "CSQ"]);
+        assert_eq!(p.format, vec!["DP", "GQ"]);
+        assert_eq!(p.samples, vec!["A"]);
+        assert_eq!(p.sources, vec!["--ac-index"]);
+        assert_eq!(
+            p.helpers,
+            vec![":info", ":sample", ":samples", ":lookup", "all"]
+        );
+        let text = explain(vcf.header(), "-e 1", code);
+        assert!(text.starts_with("-e 1: variant:info(\"AF\")[1] < 0.01"));
+        assert!(text.contains("\n  INFO: AF, CSQ (not in header)\n"));
+        assert!(text.contains("\n  FORMAT: DP, GQ\n"));
+        drop(vcf);
+        _ = std::fs::remove_file("_test.plan.vcf");
+    }
+
+    #[test]
+    fn test_lints() {
+        let mut header = bcf::Header::new();
+        header.push_record(br#"##INFO=<ID=AF,Number=A,Type=Float,Description="AF">"#);
+        header.push_record(br#"##INFO=<ID=DP,Number=1,Type=Integer,Description="DP">"#);
+        header.push_record(br#"##INFO=<ID=DB,Number=0,Type=Flag,Description="DB">"#);
+        header.push_record(br#"##FORMAT=<ID=AD,Number=R,Type=Integer,Description="AD">"#);
+        header.push_record(br#"##FORMAT=<ID=GQ,Number=1,Type=Integer,Description="GQ">"#);
+        header.push_record(br#"##INFO=<ID=CSQ,Number=1,Type=String,Description="CSQ">"#);
+        header.push_record(br#"##FORMAT=<ID=FT,Number=1,Type=String,Description="FT">"#);
+        let vcf =
+            bcf::Writer::from_path("_test.lint.vcf", &header, true, bcf::Format::Vcf).unwrap();
+        let hv = vcf.header();
+        let l = lints(hv, r#"return variant:info("AF") > 0.1"#);
+        assert_eq!(l.len(), 1);
+        assert!(l[0].contains(r#"compare an element e.g. variant:info("AF")[1] > ..."#));
+        let l = lints(hv, r#"return variant:info('DB') > 0"#);
+        assert!(l[0].starts_with("INFO DB is a Flag"));
+        let l = lints(hv, "return sample.AD >= 10 and sample.GQ > 20");
+        assert_eq!(
+            l,
+            vec!["FORMAT AD is Number=R so .AD is a table; compare an element e.g. .AD[1] >= ..."]
+        );
+        let l = lints(hv, r#"return variant:info("CSQ") > 3 or sample.FT <= "5""#);
+        assert_eq!(l.len(), 2);
+        assert!(l[0].starts_with("INFO CSQ is Type=String"));
+        assert!(l[1].starts_with("FORMAT FT is Type=String"));
+        let l = lints(hv, r#"return variant:info("DP") == "10""#);
+        assert!(l[0].starts_with("INFO DP is a number"));
+        let l = lints(hv, "return variant.pos == 12345");
+        assert!(l[0].starts_with("variant.pos is 0-based"));
+        let l = lints(hv, "return 0.5 == variant:info('AF')[1]");
+        assert_eq!(
+            l,
+            vec![
+                "== with the float 0.5 is rarely true after rounding; use math.abs(x - 0.5) < 1e-6"
+            ]
+        );
+
+        assert!(lints(
+            hv,
+            r#"return variant:info("AF")[1] > 0.1 and variant:info("DP") > 10 and variant:info("DB")
+               and sample.AD[2] > 3 and variant.pos + 1 == 12345 and variant:info("DP") == 3
+               and variant:info("CSQ") == "x" and sample.FT == "PASS""#
+        )
+        .is_empty());
+        drop(vcf);
+        _ = std::fs::remove_file("_test.lint.vcf");
+    }
+
+    #[test]
+    fn test_check_code() {
+        let mut header = bcf::Header::new();
+        header.push_record(br#"##INFO=<ID=DP,Number=1,Type=Integer,Description="DP">"#);
+        header.push_record(br#"##FORMAT=<ID=GQ,Number=1,Type=Integer,Description="GQ">"#);
+        let vcf =
+            bcf::Writer::from_path("_test.checkcode.vcf", &header, true, bcf::Format::Vcf).unwrap();
+        let prelude = r#"header:add_info({ID="DP2", Number=1, Type="Integer", Description="x"})
+            header:add_format({Type="Float", ID = 'AB', Number=1, Description="y"})"#;
+        assert_eq!(
+            added_tags(prelude),
+            (vec!["DP2".to_string()], vec!["AB".to_string()])
+        );
+        let codes = [
+            (
+                "-e 1",
+                r#"return variant:info("DP") > 3 and variant:info("AF") < 0.1"#,
+            ),
+            ("-e 2", "return variant.qual >"),
+            ("-s 1", r#"DP2=return variant:info("DP") * 2"#),
+            ("-s 2", "XX=return 1"),
+            ("--set-format 1", "AB=return sample.GQ / 2"),
+            ("--set-format 2", r#"YY=return variant:format("PL")"#),
+            ("-t 1", "{variant.chrom}"),
+            ("prelude.lua", prelude),
+        ]
+        .map(|(n, c)| (n.to_string(), c.to_string()));
+        let problems = check_code(vcf.header(), &codes);
+        assert_eq!(problems.len(), 5, "{:?}", problems);
+        assert_eq!(problems[0], "-e 1: INFO AF is not in the header");
+        assert!(
+            problems[1].starts_with("-e 2: syntax error: "),
+            "{}",
+            problems[1]
+        );
+        assert_eq!(
+            problems[2],
+            "-s 2: INFO XX is not in the header; add it with header:add_info in a prelude"
+        );
+        assert!(problems[3].starts_with("--set-format 2: FORMAT YY is not in the header"));
+        assert_eq!(
+            problems[4],
+            "--set-format 2: FORMAT PL is not in the header"
+        );
+        drop(vcf);
+        _ = std::fs::remove_file("_test.checkcode.vcf");
+    }
+}
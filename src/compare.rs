@@ -0,0 +1,302 @@
+//! Compare two VCF/BCF files (`vcfexpress compare a.vcf b.vcf`): the alleles that are shared or in
+//! only one file, how the shared alleles matched (exactly or only after trimming shared bases with
+//! [`crate::annotate::normalize`]), the alleles of `a` at a position of `b` with other alleles, and
+//! the genotype concordance of each sample in both files at the shared alleles. Multi-allelic
+//! records are compared by ALT. The alleles of `b` are held in memory. The output is
+//! `section key value` lines as for `stats`.
+use rust_htslib::bcf::{self, Read};
+use rustc_hash::{FxHashMap, FxHashSet};
+use std::io::Write;
+
+use crate::annotate::normalize;
+use crate::genotypes::allele_index;
+
+/// chrom, normalized position, REF and ALT.
+type AlleleKey = (String, i64, Vec<u8>, Vec<u8>);
+
+struct BAllele {
+    // the position, REF and ALT as in the file.
+    raw: (i64, Vec<u8>, Vec<u8>),
+    // the number of copies of the ALT for each sample in both files; None for a missing GT.
+    dosages: Vec<Option<u8>>,
+    matched: bool,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Concordance {
+    pub compared: usize,
+    pub concordant: usize,
+    // the GT is missing in one or both files.
+    pub missing: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct Comparison {
+    pub shared: usize,
+    pub a_only: usize,
+    pub b_only: usize,
+    pub exact: usize,
+    pub normalized: usize,
+    pub mismatch: usize,
+    // samples in both files, in the order of `a`.
+    pub samples: Vec<(String, Concordance)>,
+}
+
+fn open(path: &str) -> Result<bcf::Reader, Box<dyn std::error::Error>> {
+    let mut reader = match path {
+        "-" | "stdin" => bcf::Reader::from_stdin()?,
+        _ => bcf::Reader::from_path(path)?,
+    };
+    _ = reader.set_threads(2);
+    Ok(reader)
+}
+
+fn chrom(record: &bcf::Record) -> String {
+    record
+        .rid()
+        .and_then(|rid| record.header().rid2name(rid).ok())
+        .map(|c| String::from_utf8_lossy(c).to_string())
+        .unwrap_or_default()
+}
+
+/// The copies of ALT `alt` in each GT of `samples` (indexes into the record); None if the GT is
+/// missing or the record has no GT.
+fn dosages(record: &bcf::Record, samples: &[usize], alt: i32) -> Vec<Option<u8>> {
+    let Ok(gts) = record.format(b"GT").integer() else {
+        return vec![None; samples.len()];
+    };
+    samples
+        .iter()
+        .map(|&i| {
+            let mut n = 0;
+            for &a in gts[i]
+                .iter()
+                .take_while(|&&a| a != rust_htslib::htslib::bcf_int32_vector_end)
+            {
+                match allele_index(a) {
+                    a if a < 0 => return None,
+                    a if a == alt => n += 1,
+                    _ => {}
+                }
+            }
+            Some(n)
+        })
+        .collect()
+}
+
+/// Compare the records of `a` to those of `b` ("-" for stdin for one of them).
+pub fn compare(a: &str, b: &str) -> Result<Comparison, Box<dyn std::error::Error>> {
+    let mut a_reader = open(a)?;
+    let mut b_reader = open(b)?;
+    let a_samples = a_reader
+        .header()
+        .samples()
+        .iter()
+        .map(|s| String::from_utf8_lossy(s).to_string())
+        .collect::<Vec<_>>();
+    let b_samples = b_reader
+        .header()
+        .samples()
+        .iter()
+        .map(|s| String::from_utf8_lossy(s).to_string())
+        .collect::<Vec<_>>();
+    // (index in a, index in b) of the samples in both files.
+    let shared = a_samples
+        .iter()
+        .enumerate()
+        .filter_map(|(i, s)| b_samples.iter().position(|b| b == s).map(|j| (i, j)))
+        .collect::<Vec<_>>();
+    let a_idx = shared.iter().map(|(i, _)| *i).collect::<Vec<_>>();
+    let b_idx = shared.iter().map(|(_, j)| *j).collect::<Vec<_>>();
+
+    let mut b_alleles: FxHashMap<AlleleKey, BAllele> = FxHashMap::default();
+    let mut b_positions: FxHashSet<(String, i64)> = FxHashSet::default();
+    for r in b_reader.records() {
+        let record = r?;
+        let chrom = chrom(&record);
+        let alleles = record.alleles();
+        for (j, alt) in alleles.iter().enumerate().skip(1) {
+            let (pos, reference, alt) = normalize(record.pos(), alleles[0], alt);
+            b_alleles
+                .entry((chrom.clone(), pos, reference, alt))
+                .or_insert_with(|| BAllele {
+                    raw: (record.pos(), alleles[0].to_vec(), alleles[j].to_vec()),
+                    dosages: dosages(&record, &b_idx, j as i32),
+                    matched: false,
+                });
+        }
+        b_positions.insert((chrom, record.pos()));
+    }
+
+    let mut cmp = Comparison {
+        samples: shared
+            .iter()
+            .map(|(i, _)| (a_samples[*i].clone(), Concordance::default()))
+            .collect(),
+        ..Default::default()
+    };
+    for r in a_reader.records() {
+        let record = r?;
+        let chrom = chrom(&record);
+        let alleles = record.alleles();
+        for (j, alt) in alleles.iter().enumerate().skip(1) {
+            let (pos, reference, norm_alt) = normalize(record.pos(), alleles[0], alt);
+            let key = (chrom.clone(), pos, reference, norm_alt);
+            let Some(b) = b_alleles.get_mut(&key).filter(|b| !b.matched) else {
+                cmp.a_only += 1;
+                if b_positions.contains(&(key.0, record.pos())) {
+                    cmp.mismatch += 1;
+                }
+                continue;
+            };
+            b.matched = true;
+            cmp.shared += 1;
+            if b.raw == (record.pos(), alleles[0].to_vec(), alt.to_vec()) {
+                cmp.exact += 1;
+            } else {
+                cmp.normalized += 1;
+            }
+            let a_dosages = dosages(&record, &a_idx, j as i32);
+            for ((_, c), (da, db)) in cmp
+                .samples
+                .iter_mut()
+                .zip(a_dosages.iter().zip(b.dosages.iter()))
+            {
+                match (da, db) {
+                    (Some(da), Some(db)) => {
+                        c.compared += 1;
+                        if da == db {
+                            c.concordant += 1;
+                        }
+                    }
+                    _ => c.missing += 1,
+                }
+            }
+        }
+    }
+    cmp.b_only = b_alleles.values().filter(|b| !b.matched).count();
+    Ok(cmp)
+}
+
+impl Comparison {
+    pub fn write<W: Write>(&self, w: &mut W) -> std::io::Result<()> {
+        writeln!(w, "#section\tkey\tvalue")?;
+        writeln!(w, "alleles\tshared\t{}", self.shared)?;
+        writeln!(w, "alleles\ta_only\t{}", self.a_only)?;
+        writeln!(w, "alleles\tb_only\t{}", self.b_only)?;
+        writeln!(w, "match\texact\t{}", self.exact)?;
+        writeln!(w, "match\tnormalized\t{}", self.normalized)?;
+        writeln!(w, "match\tallele_mismatch\t{}", self.mismatch)?;
+        writeln!(w, "samples\tshared\t{}", self.samples.len())?;
+        for (sample, c) in &self.samples {
+            let concordance = if c.compared == 0 {
+                ".".to_string()
+            } else {
+                format!("{:.3}", c.concordant as f64 / c.compared as f64)
+            };
+            writeln!(w, "concordance\t{}\t{}", sample, concordance)?;
+            writeln!(w, "compared\t{}\t{}", sample, c.compared)?;
+            writeln!(w, "discordant\t{}\t{}", sample, c.compared - c.concordant)?;
+            writeln!(w, "missing\t{}\t{}", sample, c.missing)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_htslib::bcf::record::GenotypeAllele;
+
+    /// position, alleles and a diploid GT for each sample (-1 for missing).
+    type TestRecord<'a> = (i64, Vec<&'a str>, Vec<[i32; 2]>);
+
+    fn write_vcf(path: &str, samples: &[&str], records: &[TestRecord]) {
+        let mut header = bcf::Header::new();
+        header.push_record(br#"##contig=<ID=chr1,length=10000>"#);
+        header.push_record(br#"##FORMAT=<ID=GT,Number=1,Type=String,Description="Genotype">"#);
+        for s in samples {
+            header.push_sample(s.as_bytes());
+        }
+        let mut vcf = bcf::Writer::from_path(path, &header, true, bcf::Format::Vcf).unwrap();
+        for (pos, alleles, gts) in records {
+            let mut record = vcf.empty_record();
+            record.set_rid(Some(0));
+            record.set_pos(*pos);
+            let alleles = alleles.iter().map(|a| a.as_bytes()).collect::<Vec<_>>();
+            record.set_alleles(&alleles).unwrap();
+            let gts = gts
+                .iter()
+                .flat_map(|gt| {
+                    gt.iter().map(|&a| match a {
+                        -1 => GenotypeAllele::UnphasedMissing,
+                        a => GenotypeAllele::Unphased(a),
+                    })
+                })
+                .collect::<Vec<_>>();
+            record.push_genotypes(&gts).unwrap();
+            vcf.write(&record).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_compare() {
+        write_vcf(
+            "_test.compare.a.vcf",
+            &["S1", "S2", "S3"],
+            &[
+                (9, vec!["A", "T", "C"], vec![[0, 1], [1, 2], [0, 0]]),
+                (19, vec!["CAT", "CT"], vec![[0, 1], [-1, -1], [1, 1]]),
+                (29, vec!["G", "A"], vec![[0, 1], [0, 0], [0, 0]]),
+                (39, vec!["T", "G"], vec![[0, 1], [0, 0], [0, 0]]),
+            ],
+        );
+        write_vcf(
+            "_test.compare.b.vcf",
+            &["S3", "S1", "S4"],
+            &[
+                (9, vec!["A", "T"], vec![[0, 0], [0, 1], [0, 0]]),
+                (19, vec!["CA", "C"], vec![[0, 1], [0, 1], [0, 0]]),
+                (29, vec!["G", "C"], vec![[0, 0], [0, 1], [0, 0]]),
+                (49, vec!["T", "G"], vec![[0, 0], [0, 1], [0, 0]]),
+            ],
+        );
+        let cmp = compare("_test.compare.a.vcf", "_test.compare.b.vcf").unwrap();
+        assert_eq!(
+            (cmp.shared, cmp.a_only, cmp.b_only),
+            (2, 3, 2),
+            "T and CT>C are shared"
+        );
+        // A>C at 10 and G>A at 30 are at positions of b with other alleles.
+        assert_eq!((cmp.exact, cmp.normalized, cmp.mismatch), (1, 1, 2));
+        assert_eq!(
+            cmp.samples,
+            [
+                (
+                    "S1".to_string(),
+                    Concordance {
+                        compared: 2,
+                        concordant: 2,
+                        missing: 0
+                    }
+                ),
+                (
+                    "S3".to_string(),
+                    Concordance {
+                        compared: 2,
+                        concordant: 1,
+                        missing: 0
+                    }
+                ),
+            ]
+        );
+        let mut out = vec![];
+        cmp.write(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("concordance\tS3\t0.500\n"));
+        assert!(out.contains("match\tallele_mismatch\t2\n"));
+        for f in ["_test.compare.a.vcf", "_test.compare.b.vcf"] {
+            _ = std::fs::remove_file(f);
+        }
+    }
+}
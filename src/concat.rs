@@ -0,0 +1,330 @@
+//! Concatenate VCF/BCFs with the same samples (`vcfexpress concat`), e.g. files for each
+//! chromosome or region. The headers must be compatible as for [`crate::headercheck`]: the same
+//! samples in the same order and the same definition of any field or contig that they share; the
+//! output header has the lines of all of them. Records are written in genomic order (the inputs
+//! must each be sorted) or, with `naive`, file by file. If the files list their contigs in
+//! different orders, a record order that is sorted in one file is not in the merged header, so
+//! the output is sorted with a [`Sorter`] instead. Optional `-e` expressions are applied as with
+//! `filter`.
+use mlua::Lua;
+use rust_htslib::bcf::{self, header::HeaderView, Read};
+use rust_htslib::htslib;
+
+use crate::sort::{Order, SortOptions, Sorter};
+use crate::variant::HeaderMap;
+use crate::vcfexpress::{StringOrVariant, VCFExpress};
+
+struct Head {
+    path: String,
+    reader: bcf::Reader,
+    record: bcf::Record,
+    has_record: bool,
+    // (rid in the output header, position) of the current record.
+    key: (u32, i64),
+}
+
+impl Head {
+    fn advance(&mut self, hv: &HeaderView) -> Result<(), Box<dyn std::error::Error>> {
+        match self.reader.read(&mut self.record) {
+            None => self.has_record = false,
+            Some(r) => {
+                r?;
+                let chrom = self
+                    .record
+                    .rid()
+                    .and_then(|rid| self.record.header().rid2name(rid).ok())
+                    .ok_or_else(|| format!("record without a contig in {}", self.path))?;
+                let key = (hv.name2rid(chrom)?, self.record.pos());
+                if self.has_record && key < self.key {
+                    return Err(format!(
+                        "{} is not sorted at {}:{}; use --naive to concatenate the files in the order given",
+                        self.path,
+                        String::from_utf8_lossy(chrom),
+                        self.record.pos() + 1
+                    )
+                    .into());
+                }
+                self.key = key;
+                self.has_record = true;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The first of `headers` with contigs in a different order than in `hv`.
+fn reordered<'a>(paths: &'a [String], headers: &[&HeaderView], hv: &HeaderView) -> Option<&'a str> {
+    paths.iter().zip(headers).find_map(|(path, h)| {
+        let rids = crate::header::contigs(h)
+            .iter()
+            .filter_map(|(name, _)| hv.name2rid(name.as_bytes()).ok())
+            .collect::<Vec<_>>();
+        (!rids.windows(2).all(|w| w[0] < w[1])).then_some(path.as_str())
+    })
+}
+
+/// Concatenate `paths` to `output` (stdout if None), in genomic order unless `naive`. With
+/// `expressions`, only records that pass any of them (after running the preludes) are written.
+/// `options` are used if the output must be sorted.
+pub fn concat(
+    paths: &[String],
+    output: Option<&str>,
+    naive: bool,
+    expressions: Vec<String>,
+    lua_prelude: Vec<String>,
+    options: SortOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let conflicts = crate::headercheck::check_headers(paths)?
+        .into_iter()
+        .filter(|d| d.is_conflict())
+        .collect::<Vec<_>>();
+    if !conflicts.is_empty() {
+        for d in &conflicts {
+            log::error!("{}", d);
+        }
+        return Err(format!(
+            "{} incompatible header difference(s) among {} files; see `vcfexpress check-headers`",
+            conflicts.len(),
+            paths.len()
+        )
+        .into());
+    }
+    let mut heads = vec![];
+    for path in paths {
+        let mut reader = bcf::Reader::from_path(path)?;
+        _ = reader.set_threads(2);
+        let record = reader.empty_record();
+        heads.push(Head {
+            path: path.clone(),
+            reader,
+            record,
+            has_record: false,
+            key: (0, 0),
+        });
+    }
+    let header = bcf::Header::from_template(heads[0].reader.header());
+    for h in &heads[1..] {
+        unsafe {
+            htslib::bcf_hdr_merge(header.inner, h.reader.header().inner);
+        }
+    }
+    let mut wtr = match output {
+        Some(output) if output != "-" => {
+            let format = crate::vcfexpress::get_vcf_format(output);
+            bcf::Writer::from_path(output, &header, !output.ends_with(".gz"), format)?
+        }
+        _ => bcf::Writer::from_stdout(&header, true, bcf::Format::Vcf)?,
+    };
+    let hv = wtr.header().clone();
+    let mut sorter = None;
+    if !naive {
+        let headers = heads.iter().map(|h| h.reader.header()).collect::<Vec<_>>();
+        if let Some(path) = reordered(paths, &headers, &hv) {
+            log::info!(
+                "the contigs of {} are in a different order than in the output header; sorting the output",
+                path
+            );
+            sorter = Some(Sorter::new(&hv, Order::Position, options)?);
+        }
+    }
+
+    let lua = Lua::new();
+    let mut vcfexpr = if expressions.is_empty() {
+        None
+    } else {
+        let vcfexpr = VCFExpress::new(
+            &lua,
+            paths[0].clone(),
+            expressions,
+            vec![],
+            None,
+            lua_prelude,
+            None,
+            false,
+        )?;
+        vcfexpr.start()?;
+        Some(vcfexpr)
+    };
+    let sorting = sorter.is_some();
+    let header_map = HeaderMap::new();
+    let mut write = |mut record: bcf::Record| -> Result<(), Box<dyn std::error::Error>> {
+        wtr.translate(&mut record);
+        let record = match &mut vcfexpr {
+            None => record,
+            Some(vcfexpr) => match vcfexpr.evaluate(record, header_map.clone())? {
+                StringOrVariant::Variant(Some(record)) => record,
+                _ => return Ok(()),
+            },
+        };
+        match &mut sorter {
+            Some(sorter) => sorter.push(record)?,
+            None => wtr.write(&record)?,
+        }
+        Ok(())
+    };
+
+    if naive || sorting {
+        for h in heads.iter_mut() {
+            for r in h.reader.records() {
+                write(r?)?;
+            }
+        }
+        if let Some(sorter) = sorter {
+            sorter.finish(&mut wtr)?;
+        }
+        return Ok(());
+    }
+    for h in heads.iter_mut() {
+        h.advance(&hv)?;
+    }
+    // the earliest record; the first file for records at the same position.
+    while let Some(k) = (0..heads.len())
+        .filter(|&k| heads[k].has_record)
+        .min_by_key(|&k| heads[k].key)
+    {
+        let record = heads[k].record.clone();
+        write(record)?;
+        heads[k].advance(&hv)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_htslib::bcf::record::GenotypeAllele;
+
+    fn write_vcf(path: &str, dp_type: &str, samples: &[&str], records: &[(u32, i64)]) {
+        write_contigs_vcf(path, &["chr1", "chr2"], dp_type, samples, records)
+    }
+
+    fn write_contigs_vcf(
+        path: &str,
+        contigs: &[&str],
+        dp_type: &str,
+        samples: &[&str],
+        records: &[(u32, i64)],
+    ) {
+        let mut header = bcf::Header::new();
+        for c in contigs {
+            header.push_record(format!("##contig=<ID={},length=10000>", c).as_bytes());
+        }
+        header.push_record(br#"##FORMAT=<ID=GT,Number=1,Type=String,Description="Genotype">"#);
+        header.push_record(
+            format!(
+                r#"##INFO=<ID=DP,Number=1,Type={},Description="Depth">"#,
+                dp_type
+            )
+            .as_bytes(),
+        );
+        for s in samples {
+            header.push_sample(s.as_bytes());
+        }
+        let mut vcf = bcf::Writer::from_path(path, &header, true, bcf::Format::Vcf).unwrap();
+        for (rid, pos) in records {
+            let mut record = vcf.empty_record();
+            record.set_rid(Some(*rid));
+            record.set_pos(*pos);
+            record.set_alleles(&[b"A", b"T"]).unwrap();
+            record
+                .push_genotypes(&[GenotypeAllele::Unphased(0), GenotypeAllele::Unphased(1)])
+                .unwrap();
+            vcf.write(&record).unwrap();
+        }
+    }
+
+    fn positions(path: &str) -> Vec<String> {
+        std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .filter(|l| !l.starts_with('#'))
+            .map(|l| l.split('\t').take(2).collect::<Vec<_>>().join(":"))
+            .collect()
+    }
+
+    #[test]
+    fn test_concat() {
+        write_vcf("_test.concat.a.vcf", "Integer", &["S1"], &[(0, 9), (0, 29)]);
+        write_vcf("_test.concat.b.vcf", "Integer", &["S1"], &[(0, 19), (1, 4)]);
+        write_vcf("_test.concat.c.vcf", "Float", &["S1"], &[(0, 19)]);
+        write_vcf("_test.concat.d.vcf", "Integer", &["S2"], &[(0, 19)]);
+        let paths = [
+            "_test.concat.a.vcf".to_string(),
+            "_test.concat.b.vcf".to_string(),
+        ];
+        let out = Some("_test.concat.out.vcf");
+
+        concat(&paths, out, false, vec![], vec![], Default::default()).unwrap();
+        assert_eq!(
+            positions("_test.concat.out.vcf"),
+            ["chr1:10", "chr1:20", "chr1:30", "chr2:5"]
+        );
+        concat(&paths, out, true, vec![], vec![], Default::default()).unwrap();
+        assert_eq!(
+            positions("_test.concat.out.vcf"),
+            ["chr1:10", "chr1:30", "chr1:20", "chr2:5"]
+        );
+        concat(
+            &paths,
+            out,
+            false,
+            vec!["return variant.pos > 15".to_string()],
+            vec![],
+            Default::default(),
+        )
+        .unwrap();
+        assert_eq!(positions("_test.concat.out.vcf"), ["chr1:20", "chr1:30"]);
+
+        // chr2 is before chr1 in e, so its records are sorted into the order of a.
+        write_contigs_vcf(
+            "_test.concat.e.vcf",
+            &["chr2", "chr1"],
+            "Integer",
+            &["S1"],
+            &[(0, 2), (1, 14)],
+        );
+        concat(
+            &[paths[0].clone(), "_test.concat.e.vcf".to_string()],
+            out,
+            false,
+            vec![],
+            vec![],
+            Default::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            positions("_test.concat.out.vcf"),
+            ["chr1:10", "chr1:15", "chr1:30", "chr2:3"]
+        );
+
+        let err = concat(
+            &[paths[0].clone(), "_test.concat.c.vcf".to_string()],
+            out,
+            false,
+            vec![],
+            vec![],
+            Default::default(),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("1 incompatible"), "{}", err);
+        assert!(concat(
+            &[paths[0].clone(), "_test.concat.d.vcf".to_string()],
+            out,
+            false,
+            vec![],
+            vec![],
+            Default::default(),
+        )
+        .is_err());
+        for f in [
+            "_test.concat.a.vcf",
+            "_test.concat.b.vcf",
+            "_test.concat.c.vcf",
+            "_test.concat.d.vcf",
+            "_test.concat.e.vcf",
+            "_test.concat.out.vcf",
+        ] {
+            _ = std::fs::remove_file(f);
+        }
+    }
+}
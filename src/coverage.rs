@@ -0,0 +1,203 @@
+//! Per-sample depth from mosdepth `*.regions.bed.gz`, `*.per-base.bed.gz` or other bedgraph files
+//! (`--coverage`) so that expressions can tell a hom-ref call from a site with no data with
+//! `coverage:at(sample, variant)`.
+use mlua::{AnyUserData, Lua, UserData, UserDataMethods, Value};
+use rustc_hash::FxHashMap;
+use std::io::BufRead;
+
+use crate::variant::Variant;
+
+/// (start, end, depth) sorted by start. Coordinates are 0-based, half-open.
+type Intervals = Vec<(u64, u64, f32)>;
+
+/// Split `intervals` (sorted by start) where they overlap so that each position is in at most
+/// one. An overlapped position has the depth of the interval that starts last.
+fn disjoint(intervals: &[(u64, u64, f32)]) -> Intervals {
+    let mut bounds = intervals
+        .iter()
+        .flat_map(|r| [r.0, r.1])
+        .collect::<Vec<_>>();
+    bounds.sort_unstable();
+    bounds.dedup();
+    // (start, index) of the intervals that started; the top is the one that started last.
+    let mut started = std::collections::BinaryHeap::new();
+    let mut next = 0;
+    let mut out: Intervals = Vec::with_capacity(intervals.len());
+    for w in bounds.windows(2) {
+        let (start, end) = (w[0], w[1]);
+        while next < intervals.len() && intervals[next].0 <= start {
+            started.push((intervals[next].0, next));
+            next += 1;
+        }
+        while started
+            .peek()
+            .is_some_and(|&(_, i)| intervals[i].1 <= start)
+        {
+            started.pop();
+        }
+        let Some(&(_, i)) = started.peek() else {
+            continue;
+        };
+        let depth = intervals[i].2;
+        match out.last_mut() {
+            Some(last) if last.1 == start && last.2 == depth => last.1 = end,
+            _ => out.push((start, end, depth)),
+        }
+    }
+    out
+}
+
+/// Depth intervals by sample and chrom.
+#[derive(Debug, Default)]
+pub struct Coverage {
+    /// sample -> chrom -> intervals
+    samples: FxHashMap<String, FxHashMap<String, Intervals>>,
+}
+
+impl UserData for Coverage {}
+
+/// Split a `--coverage` argument into sample and path. Without `SAMPLE=`, the sample is the
+/// file name up to the first `.` as in mosdepth output e.g. `NA12878.regions.bed.gz`.
+pub fn parse_coverage_arg(arg: &str) -> (String, String) {
+    if let Some((sample, path)) = arg.split_once('=') {
+        return (sample.to_string(), path.to_string());
+    }
+    let name = std::path::Path::new(arg)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let sample = name.split('.').next().unwrap_or_default().to_string();
+    (sample, arg.to_string())
+}
+
+impl Coverage {
+    /// Read the depth of `sample` from a plain or (b)gzipped BED file with depth in the 4th column.
+    pub fn add_path(&mut self, sample: &str, path: &str) -> std::io::Result<()> {
+        self.add_reader(sample, crate::bed::open(path)?)
+    }
+
+    pub fn add_reader<R: BufRead>(&mut self, sample: &str, reader: R) -> std::io::Result<()> {
+        let chroms = self.samples.entry(sample.to_string()).or_default();
+        let source = format!("coverage file for {}", sample);
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            let Some(l) = crate::bed::parse(&source, i, &line)? else {
+                continue;
+            };
+            // mosdepth regions files with a name column have the depth last.
+            let depth = l.fields[3..]
+                .last()
+                .and_then(|s| s.trim().parse::<f32>().ok())
+                .ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("no depth on line {} in {}: {}", i + 1, source, line),
+                    )
+                })?;
+            chroms
+                .entry(l.chrom.to_string())
+                .or_default()
+                .push((l.start, l.end, depth));
+        }
+        for intervals in chroms.values_mut() {
+            intervals.sort_by_key(|r| r.0);
+            *intervals = disjoint(intervals);
+        }
+        Ok(())
+    }
+
+    pub fn has_sample(&self, sample: &str) -> bool {
+        self.samples.contains_key(sample)
+    }
+
+    /// Depth of `sample` at the 0-based position `pos` on `chrom` or None if no interval contains it.
+    pub fn at(&self, sample: &str, chrom: &str, pos: u64) -> Option<f32> {
+        let intervals = self.samples.get(sample)?.get(chrom)?;
+        // the intervals are disjoint so only the last that starts at or before pos can contain it.
+        let n = intervals.partition_point(|r| r.0 <= pos);
+        intervals[..n].last().filter(|r| pos < r.1).map(|r| r.2)
+    }
+}
+
+pub fn register_coverage(lua: &Lua) -> mlua::Result<()> {
+    lua.register_userdata_type::<Coverage>(|reg| {
+        // the depth of the sample (a name or a table from variant:sample) at the variant position.
+        // nil if the position is not in any interval for that sample.
+        reg.add_method(
+            "at",
+            |lua: &Lua, this: &Coverage, (sample, variant): (Value, AnyUserData)| {
+                let sample = match sample {
+                    Value::Table(t) => crate::sample::sample_name(lua, &t)?,
+                    v => lua
+                        .coerce_string(v)?
+                        .map(|s| s.to_string_lossy().to_string()),
+                };
+                let Some(sample) = sample else {
+                    return Err(mlua::Error::RuntimeError(
+                        "coverage:at expects a sample name or a table from variant:sample"
+                            .to_string(),
+                    ));
+                };
+                if !this.has_sample(&sample) {
+                    return Err(mlua::Error::RuntimeError(format!(
+                        "no coverage file given for sample {}",
+                        sample
+                    )));
+                }
+                let variant = variant.borrow::<Variant>()?;
+                let record = variant.record();
+                let chrom = record
+                    .rid()
+                    .and_then(|rid| record.header().rid2name(rid).ok())
+                    .unwrap_or_default();
+                Ok(this.at(
+                    &sample,
+                    &String::from_utf8_lossy(chrom),
+                    record.pos() as u64,
+                ))
+            },
+        );
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_coverage() {
+        assert_eq!(
+            parse_coverage_arg("data/NA12878.regions.bed.gz"),
+            (
+                "NA12878".to_string(),
+                "data/NA12878.regions.bed.gz".to_string()
+            )
+        );
+        assert_eq!(
+            parse_coverage_arg("S1=x.bed"),
+            ("S1".to_string(), "x.bed".to_string())
+        );
+        let mut cov = Coverage::default();
+        cov.add_reader("S1", "chr1\t0\t100\t0\nchr1\t100\t200\t31.5\n".as_bytes())
+            .unwrap();
+        cov.add_reader("S2", "chr1\t150\t160\texon1\t12.00\n".as_bytes())
+            .unwrap();
+        assert_eq!(cov.at("S1", "chr1", 99), Some(0.0));
+        assert_eq!(cov.at("S1", "chr1", 100), Some(31.5));
+        assert_eq!(cov.at("S1", "chr1", 200), None);
+        assert_eq!(cov.at("S2", "chr1", 155), Some(12.0));
+        assert_eq!(cov.at("S2", "chr2", 155), None);
+        assert!(!cov.has_sample("S3"));
+        // a nested interval has the depth inside it and the outer one resumes after it.
+        cov.add_reader(
+            "S4",
+            "chr1\t0\t100\t5\nchr1\t10\t20\t8\nchr1\t500\t600\t1\n".as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(cov.at("S4", "chr1", 15), Some(8.0));
+        assert_eq!(cov.at("S4", "chr1", 20), Some(5.0));
+        assert_eq!(cov.at("S4", "chr1", 300), None);
+        assert_eq!(cov.at("S4", "chr1", 599), Some(1.0));
+        assert!(cov.add_reader("S3", "chr1\t1\t2\n".as_bytes()).is_err());
+    }
+}
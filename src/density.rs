@@ -0,0 +1,123 @@
+//! Count passing variants in fixed genomic windows (`--window-density 100000` or `100000:50000` for
+//! a step smaller than the window) and write a BED-like `chrom start end count` track, e.g. for QC
+//! or finding hotspots. Windows are written as soon as the input has moved past them, so the input
+//! must be sorted. Windows without variants are not written.
+use std::collections::BTreeMap;
+
+fn invalid(msg: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, msg)
+}
+
+#[derive(Debug, Clone)]
+pub struct WindowDensity {
+    size: i64,
+    step: i64,
+    chrom: Option<String>,
+    last_pos: i64,
+    /// window index (start / step) -> count for the windows that can still get variants.
+    open: BTreeMap<i64, usize>,
+}
+
+impl std::str::FromStr for WindowDensity {
+    type Err = std::io::Error;
+
+    /// Parse `size` or `size:step`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |v: &str| match v.parse::<i64>() {
+            Ok(n) if n > 0 => Ok(n),
+            _ => Err(invalid(format!(
+                "expected size or size:step with positive integers for --window-density, got '{}'",
+                s
+            ))),
+        };
+        let (size, step) = match s.split_once(':') {
+            Some((size, step)) => (parse(size)?, parse(step)?),
+            None => (parse(s)?, parse(s)?),
+        };
+        Ok(WindowDensity {
+            size,
+            step,
+            chrom: None,
+            last_pos: 0,
+            open: BTreeMap::new(),
+        })
+    }
+}
+
+impl WindowDensity {
+    fn row(&self, chrom: &str, k: i64, count: usize) -> String {
+        let start = k * self.step;
+        format!("{}\t{}\t{}\t{}", chrom, start, start + self.size, count)
+    }
+
+    /// Count a variant at 0-based `pos` and return the rows for the windows that end before it.
+    pub fn add(&mut self, chrom: &str, pos: i64) -> std::io::Result<Vec<String>> {
+        let mut rows = vec![];
+        if self.chrom.as_deref() != Some(chrom) {
+            rows = self.flush();
+            self.chrom = Some(chrom.to_string());
+        } else if pos < self.last_pos {
+            return Err(invalid(format!(
+                "--window-density requires sorted input; {}:{} is after {}:{}",
+                chrom,
+                pos + 1,
+                chrom,
+                self.last_pos + 1
+            )));
+        }
+        self.last_pos = pos;
+        // windows [k * step, k * step + size) that end at or before pos are complete.
+        while let Some((&k, &count)) = self.open.first_key_value() {
+            if k * self.step + self.size > pos {
+                break;
+            }
+            self.open.pop_first();
+            rows.push(self.row(chrom, k, count));
+        }
+        let first = (pos - self.size + self.step).max(0) / self.step;
+        for k in first..=pos / self.step {
+            *self.open.entry(k).or_insert(0) += 1;
+        }
+        Ok(rows)
+    }
+
+    /// The rows of the windows that are still open. Call this after the last variant.
+    pub fn flush(&mut self) -> Vec<String> {
+        let Some(chrom) = self.chrom.take() else {
+            return vec![];
+        };
+        std::mem::take(&mut self.open)
+            .into_iter()
+            .map(|(k, count)| self.row(&chrom, k, count))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_window_density() {
+        assert!("0".parse::<WindowDensity>().is_err());
+        assert!("10:x".parse::<WindowDensity>().is_err());
+
+        let mut d = "10".parse::<WindowDensity>().unwrap();
+        assert!(d.add("chr1", 1).unwrap().is_empty());
+        assert!(d.add("chr1", 9).unwrap().is_empty());
+        assert_eq!(d.add("chr1", 25).unwrap(), ["chr1\t0\t10\t2"]);
+        assert_eq!(d.add("chr2", 3).unwrap(), ["chr1\t20\t30\t1"]);
+        assert!(d.add("chr2", 2).is_err());
+        assert_eq!(d.flush(), ["chr2\t0\t10\t1"]);
+
+        // overlapping windows: each variant is counted in every window that contains it.
+        let mut d = "10:5".parse::<WindowDensity>().unwrap();
+        assert!(d.add("chr1", 7).unwrap().is_empty());
+        assert_eq!(d.add("chr1", 12).unwrap(), ["chr1\t0\t10\t1"]);
+        assert_eq!(
+            d.add("chr1", 30).unwrap(),
+            ["chr1\t5\t15\t2", "chr1\t10\t20\t1"]
+        );
+        assert_eq!(d.flush(), ["chr1\t25\t35\t1", "chr1\t30\t40\t1"]);
+    }
+}
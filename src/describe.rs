@@ -0,0 +1,107 @@
+//! The INFO and FORMAT tags of a VCF/BCF with the lua to read each one (`vcfexpress describe`),
+//! e.g. `variant:info("AF")[1]  -- Float, Number=A: Allele frequency`.
+use rust_htslib::bcf::header::{HeaderRecord, HeaderView, TagLength, TagType};
+
+use crate::check::number;
+use crate::retype::type_name;
+
+/// The lua to read an INFO or FORMAT (from a `sample` table) tag. A field with more than one value
+/// is a table so the first value is shown.
+pub fn access(format: bool, id: &str, typ: &TagType, length: &TagLength) -> String {
+    let index = if matches!(length, TagLength::Fixed(0 | 1)) {
+        ""
+    } else {
+        "[1]"
+    };
+    match (format, typ) {
+        (false, TagType::Flag) => format!("variant:info(\"{}\")", id),
+        (false, _) => format!("variant:info(\"{}\"){}", id, index),
+        (true, _) if id == "GT" => "sample.GT[1]".to_string(),
+        (true, _)
+            if id.chars().all(|c| c.is_alphanumeric() || c == '_')
+                && !id.starts_with(|c: char| c.is_ascii_digit()) =>
+        {
+            format!("sample.{}{}", id, index)
+        }
+        (true, _) => format!("sample[\"{}\"]{}", id, index),
+    }
+}
+
+/// One line for each INFO and then each FORMAT tag in `hv`, in header order.
+pub fn describe(hv: &HeaderView) -> Vec<String> {
+    let mut info = vec![];
+    let mut format = vec![];
+    for rec in hv.header_records() {
+        let (is_format, values) = match &rec {
+            HeaderRecord::Info { values, .. } => (false, values),
+            HeaderRecord::Format { values, .. } => (true, values),
+            _ => continue,
+        };
+        let Some(id) = values.get("ID") else { continue };
+        let tag = if is_format {
+            hv.format_type(id.as_bytes())
+        } else {
+            hv.info_type(id.as_bytes())
+        };
+        let Ok((typ, length)) = tag else { continue };
+        let mut line = format!(
+            "{}  -- {}",
+            access(is_format, id, &typ, &length),
+            type_name(&typ)
+        );
+        if typ != TagType::Flag {
+            line.push_str(&format!(", Number={}", number(length)));
+        }
+        let description = values
+            .get("Description")
+            .map_or("", |d| d.trim_matches('"'));
+        if !description.is_empty() {
+            line.push_str(&format!(": {}", description));
+        }
+        if is_format {
+            format.push(line);
+        } else {
+            info.push(line);
+        }
+    }
+    let mut lines = vec!["# INFO".to_string()];
+    lines.extend(info);
+    lines
+        .push("# FORMAT (sample = variant:sample(\"name\") or from variant:samples())".to_string());
+    lines.extend(format);
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_htslib::bcf;
+
+    #[test]
+    fn test_describe() {
+        let mut header = bcf::Header::new();
+        header.push_record(br#"##INFO=<ID=DP,Number=1,Type=Integer,Description="Total depth">"#);
+        header.push_record(br#"##INFO=<ID=AF,Number=A,Type=Float,Description="Allele frequency">"#);
+        header.push_record(br#"##INFO=<ID=DB,Number=0,Type=Flag,Description="dbSNP">"#);
+        header.push_record(br#"##FORMAT=<ID=GT,Number=1,Type=String,Description="Genotype">"#);
+        header.push_record(br#"##FORMAT=<ID=AD,Number=R,Type=Integer,Description="Depths">"#);
+        header.push_record(br#"##FORMAT=<ID=1X,Number=1,Type=Float,Description="">"#);
+        let vcf =
+            bcf::Writer::from_path("_test.describe.vcf", &header, true, bcf::Format::Vcf).unwrap();
+        assert_eq!(
+            describe(vcf.header()),
+            [
+                "# INFO",
+                "variant:info(\"DP\")  -- Integer, Number=1: Total depth",
+                "variant:info(\"AF\")[1]  -- Float, Number=A: Allele frequency",
+                "variant:info(\"DB\")  -- Flag: dbSNP",
+                "# FORMAT (sample = variant:sample(\"name\") or from variant:samples())",
+                "sample.GT[1]  -- String, Number=1: Genotype",
+                "sample.AD[1]  -- Integer, Number=R: Depths",
+                "sample[\"1X\"]  -- Float, Number=1",
+            ]
+        );
+        drop(vcf);
+        _ = std::fs::remove_file("_test.describe.vcf");
+    }
+}
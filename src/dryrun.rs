@@ -0,0 +1,130 @@
+//! `filter --dry-run`: the fully resolved configuration of a run (inputs, expressions after
+//! snippet expansion, preludes, outputs to be created, threads and luau options) as
+//! `key<TAB>value` lines. The code is compiled, but not run, so a syntax error fails here, and no
+//! records are read and no outputs are created.
+use mlua::Lua;
+use std::fmt;
+
+#[derive(Debug, Default)]
+pub struct DryRun {
+    lines: Vec<(String, String)>,
+}
+
+impl DryRun {
+    /// Add a setting.
+    pub fn add<V: fmt::Display>(&mut self, key: &str, value: V) {
+        self.lines.push((key.to_string(), value.to_string()));
+    }
+
+    /// Add a line for each of `values`.
+    pub fn add_each<V: fmt::Display>(&mut self, key: &str, values: impl IntoIterator<Item = V>) {
+        for v in values {
+            self.add(key, v);
+        }
+    }
+
+    /// Compile a lua expression (e.g. -e or --group-by) with the same chunk name as when it is
+    /// run and add it.
+    pub fn expression(&mut self, lua: &Lua, key: &str, code: &str) -> mlua::Result<()> {
+        lua.load(code).set_name(code).into_function()?;
+        self.add(key, code);
+        Ok(())
+    }
+
+    /// Compile a `NAME=expression` from -s or --set-format and add it.
+    pub fn set_expression(&mut self, lua: &Lua, key: &str, code: &str) -> mlua::Result<()> {
+        let (_, exp) = code.split_once('=').ok_or_else(|| {
+            mlua::Error::runtime(format!("{} should be NAME=expression, got {}", key, code))
+        })?;
+        lua.load(exp).set_name(code).into_function()?;
+        self.add(key, code);
+        Ok(())
+    }
+
+    /// Compile a template (-t) and add it.
+    pub fn template(&mut self, lua: &Lua, template: &str) -> mlua::Result<()> {
+        lua.load(crate::vcfexpress::template_code(template))
+            .set_name("template")
+            .into_function()?;
+        self.add("template", template);
+        Ok(())
+    }
+
+    /// Compile (without running) a prelude file and add its path.
+    pub fn prelude(&mut self, lua: &Lua, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let code = std::fs::read_to_string(path)?;
+        lua.load(&code).set_name(path).into_function()?;
+        self.add("prelude", path);
+        Ok(())
+    }
+
+    /// Add the main output: its path ("stdout" without one) and format. `text` is for template,
+    /// --group-by and other non-VCF output.
+    pub fn output(&mut self, output: Option<&str>, text: bool) {
+        let path = match output {
+            Some(o) if o != "-" => o,
+            _ => "stdout",
+        };
+        let format = if text {
+            "text"
+        } else if path == "stdout" {
+            "VCF"
+        } else {
+            match (
+                crate::vcfexpress::get_vcf_format(path),
+                path.ends_with(".gz"),
+            ) {
+                (rust_htslib::bcf::Format::Bcf, true) => "BCF (compressed)",
+                (rust_htslib::bcf::Format::Bcf, false) => "BCF",
+                (_, true) => "VCF (bgzipped)",
+                (_, false) => "VCF",
+            }
+        };
+        self.add("output", format!("{}\t{}", path, format));
+    }
+}
+
+impl fmt::Display for DryRun {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (key, value) in &self.lines {
+            writeln!(f, "{}\t{}", key, value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dry_run() {
+        let lua = Lua::new();
+        let mut dry = DryRun::default();
+        dry.add("input", "in.vcf");
+        dry.expression(&lua, "expression", "return variant.qual > 20")
+            .unwrap();
+        dry.set_expression(&lua, "set_expression", "DP2=return variant:info('DP') * 2")
+            .unwrap();
+        dry.template(&lua, "{variant.chrom}:{variant.pos}").unwrap();
+        dry.output(Some("out.bcf"), false);
+        dry.output(None, true);
+        assert_eq!(
+            dry.to_string(),
+            "input\tin.vcf
+expression\treturn variant.qual > 20
+set_expression\tDP2=return variant:info('DP') * 2
+template\t{variant.chrom}:{variant.pos}
+output\tout.bcf\tBCF
+output\tstdout\ttext
+"
+        );
+        assert!(dry
+            .expression(&lua, "expression", "return variant.qual >")
+            .is_err());
+        assert!(dry
+            .set_expression(&lua, "set_expression", "return 1")
+            .is_err());
+        assert!(dry.prelude(&lua, "_test.missing.lua").is_err());
+    }
+}
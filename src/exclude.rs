@@ -0,0 +1,100 @@
+//! Drop records that overlap the intervals of BED files (`--exclude-bed`), e.g. the ENCODE
+//! blacklist or low-complexity regions, before they are evaluated. Each file is read into an
+//! interval index per contig, so neither the BED files nor the input need to be sorted.
+use rust_htslib::bcf;
+use rustc_hash::FxHashMap;
+use std::io;
+
+use crate::annotate::{index_bed, IntervalIndex};
+
+#[derive(Default)]
+pub struct ExcludeBed {
+    /// contig -> intervals for each file.
+    files: Vec<FxHashMap<String, IntervalIndex>>,
+    dropped: usize,
+}
+
+impl ExcludeBed {
+    pub fn new(paths: &[String]) -> io::Result<Self> {
+        Ok(ExcludeBed {
+            files: paths
+                .iter()
+                .map(|p| index_bed(p))
+                .collect::<io::Result<_>>()?,
+            dropped: 0,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// False if `record` overlaps an interval of any of the files.
+    pub fn keep(&mut self, record: &bcf::Record) -> io::Result<bool> {
+        let Some(rid) = record.rid() else {
+            return Ok(true);
+        };
+        let chrom = record.header().rid2name(rid).map_err(io::Error::other)?;
+        let chrom = String::from_utf8_lossy(chrom);
+        let start = record.pos() as u64;
+        let end = (record.end() as u64).max(start + 1);
+        let overlaps = self.files.iter().any(|f| {
+            f.get(chrom.as_ref())
+                .is_some_and(|ix| ix.overlaps(start, end).next().is_some())
+        });
+        if overlaps {
+            self.dropped += 1;
+        }
+        Ok(!overlaps)
+    }
+
+    /// The number of records that overlapped an interval.
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exclude_bed() {
+        std::fs::write("_test.exclude.a.bed", "chr1\t500\t600\nchr1\t100\t200\n").unwrap();
+        std::fs::write("_test.exclude.b.bed", "#header\nchr2\t0\t10\n").unwrap();
+        let mut header = bcf::Header::new();
+        header.push_record(br#"##contig=<ID=chr1,length=10000>"#);
+        header.push_record(br#"##contig=<ID=chr2,length=10000>"#);
+        let wtr =
+            bcf::Writer::from_path("_test.exclude.vcf", &header, true, bcf::Format::Vcf).unwrap();
+        let mut exclude = ExcludeBed::new(&[
+            "_test.exclude.a.bed".to_string(),
+            "_test.exclude.b.bed".to_string(),
+        ])
+        .unwrap();
+        let keep = |exclude: &mut ExcludeBed, rid: u32, pos: i64, reflen: usize| {
+            let mut record = wtr.empty_record();
+            record.set_rid(Some(rid));
+            record.set_pos(pos);
+            let r = vec![b'A'; reflen];
+            record.set_alleles(&[&r, b"T"]).unwrap();
+            exclude.keep(&record).unwrap()
+        };
+        assert!(keep(&mut exclude, 0, 99, 1));
+        // a deletion that spans the start of an interval.
+        assert!(!keep(&mut exclude, 0, 98, 3));
+        assert!(!keep(&mut exclude, 0, 550, 1));
+        assert!(keep(&mut exclude, 0, 50, 1));
+        assert!(!keep(&mut exclude, 1, 9, 1));
+        assert!(keep(&mut exclude, 1, 10, 1));
+        assert_eq!(exclude.dropped(), 3);
+        assert!(ExcludeBed::new(&["_test.exclude.missing.bed".to_string()]).is_err());
+        for f in [
+            "_test.exclude.a.bed",
+            "_test.exclude.b.bed",
+            "_test.exclude.vcf",
+        ] {
+            _ = std::fs::remove_file(f);
+        }
+    }
+}
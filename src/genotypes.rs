@@ -1,17 +1,40 @@
 use mlua::{AnyUserData, Lua, MetaMethod, UserData, UserDataFields, UserDataMethods, Value};
 use parking_lot::Mutex;
 use rust_htslib::bcf;
-use rust_htslib::bcf::record::{self, GenotypeAllele};
+use rust_htslib::bcf::record::GenotypeAllele;
 use std::sync::Arc;
 
-pub(crate) struct I32Buffer(
-    pub(crate) bcf::record::BufferBacked<'static, Vec<&'static [i32]>, record::Buffer>,
-);
-
 struct GTAllele(bcf::record::GenotypeAllele);
-struct Genotype(Vec<GTAllele>);
+/// Genotype keeps the raw GT values for one sample; alleles are decoded only when accessed.
+pub(crate) struct Genotype(Vec<i32>);
+
+/// The raw GT values for all samples, `stride` values per sample padded with vector_end.
+/// This is shared between the variant and any `variant.genotypes` objects so that
+/// assignments are visible to later reads and can be written back to the record.
+pub(crate) struct GtData {
+    values: Vec<i32>,
+    stride: usize,
+    dirty: bool,
+}
+
+pub(crate) struct Genotypes(pub(crate) Arc<Mutex<GtData>>);
+
+/// Number of samples in each genotype class from [`GtData::counts`].
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct GtCounts {
+    pub(crate) hom_ref: usize,
+    pub(crate) het: usize,
+    pub(crate) hom_alt: usize,
+    pub(crate) missing: usize,
+}
 
-pub(crate) struct Genotypes(pub(crate) Arc<Mutex<I32Buffer>>);
+impl GtCounts {
+    /// Fraction of samples with a called genotype. None without samples.
+    pub(crate) fn call_rate(&self) -> Option<f64> {
+        let n = self.hom_ref + self.het + self.hom_alt + self.missing;
+        (n > 0).then(|| (n - self.missing) as f64 / n as f64)
+    }
+}
 
 impl std::fmt::Debug for GTAllele {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -19,58 +42,361 @@ impl std::fmt::Debug for GTAllele {
     }
 }
 
-unsafe impl Send for I32Buffer {}
-impl UserData for I32Buffer {}
 impl UserData for GTAllele {}
 impl UserData for Genotype {}
 impl UserData for Genotypes {}
 
+impl Genotype {
+    fn alleles(&self) -> impl Iterator<Item = GenotypeAllele> + '_ {
+        self.0.iter().map(|&a| GenotypeAllele::from(a))
+    }
+
+    /// The encoded GT values as stored in the record.
+    pub(crate) fn raw(&self) -> &[i32] {
+        &self.0
+    }
+
+    /// From a genotype string such as "0|1", a Genotype or a table of (unphased) allele indexes.
+    pub(crate) fn from_lua_value(value: &Value) -> mlua::Result<Self> {
+        match value {
+            Value::String(s) => s
+                .to_str()?
+                .parse::<Genotype>()
+                .map_err(mlua::Error::RuntimeError),
+            Value::UserData(ud) => Ok(Genotype(ud.borrow::<Genotype>()?.0.clone())),
+            Value::Table(t) => {
+                let alleles = t
+                    .clone()
+                    .sequence_values::<i32>()
+                    .collect::<mlua::Result<Vec<_>>>()?;
+                if alleles.is_empty() {
+                    return Err(mlua::Error::RuntimeError(
+                        "empty genotype table".to_string(),
+                    ));
+                }
+                Ok(Genotype::new(&alleles, None))
+            }
+            v => Err(mlua::Error::RuntimeError(format!(
+                "expected Genotype or genotype string, got {}",
+                v.type_name()
+            ))),
+        }
+    }
+
+    /// From allele indexes (-1 for missing) and optional phase for each allele.
+    /// As in VCF, the phase of an allele is the separator before it, so the first is ignored.
+    fn new(alleles: &[i32], phased: Option<&[bool]>) -> Self {
+        Genotype(
+            alleles
+                .iter()
+                .enumerate()
+                .map(|(i, &a)| {
+                    let p = i > 0 && phased.is_some_and(|p| p.get(i).copied().unwrap_or(false));
+                    i32::from(match (a >= 0, p) {
+                        (true, true) => GenotypeAllele::Phased(a),
+                        (true, false) => GenotypeAllele::Unphased(a),
+                        (false, true) => GenotypeAllele::PhasedMissing,
+                        (false, false) => GenotypeAllele::UnphasedMissing,
+                    })
+                })
+                .collect(),
+        )
+    }
+}
+
+impl std::str::FromStr for Genotype {
+    type Err = String;
+
+    /// Parse a VCF genotype string such as "0/1", "1|2", "./." or "1".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut alleles = vec![];
+        let mut phased = vec![];
+        for (i, part) in s.split_inclusive(['/', '|']).enumerate() {
+            let (allele, sep) = match part.strip_suffix(['/', '|']) {
+                Some(a) => (a, part.chars().last()),
+                None => (part, None),
+            };
+            if i == 0 {
+                phased.push(false);
+            }
+            alleles.push(match allele {
+                "." => -1,
+                a => a
+                    .parse::<u32>()
+                    .map_err(|_| format!("invalid genotype '{}'", s))? as i32,
+            });
+            if let Some(sep) = sep {
+                phased.push(sep == '|');
+            }
+        }
+        if alleles.is_empty() || alleles.len() != phased.len() {
+            return Err(format!("invalid genotype '{}'", s));
+        }
+        Ok(Genotype::new(&alleles, Some(&phased)))
+    }
+}
+
+impl GtData {
+    /// Copy the GT values from the record.
+    pub(crate) fn from_record(record: &bcf::Record) -> rust_htslib::errors::Result<Self> {
+        let gts = record.format(b"GT").integer()?;
+        let stride = gts.iter().map(|g| g.len()).max().unwrap_or(0);
+        let mut values = Vec::with_capacity(stride * gts.len());
+        for g in gts.iter() {
+            values.extend_from_slice(g);
+            values.resize(values.len() + stride - g.len(), vector_end());
+        }
+        Ok(GtData {
+            values,
+            stride,
+            dirty: false,
+        })
+    }
+
+    fn len(&self) -> usize {
+        self.values.len().checked_div(self.stride).unwrap_or(0)
+    }
+
+    fn get(&self, i: usize) -> Option<Genotype> {
+        if i >= self.len() {
+            return None;
+        }
+        Some(Genotype(
+            self.values[i * self.stride..(i + 1) * self.stride]
+                .iter()
+                .take_while(|&&a| a != vector_end())
+                .copied()
+                .collect(),
+        ))
+    }
+
+    /// Allele indexes (-1 for missing) of the sample at 0-based `i`.
+    pub(crate) fn alleles(&self, i: usize) -> Option<Vec<i32>> {
+        self.get(i)
+            .map(|g| g.0.iter().map(|&a| allele_index(a)).collect())
+    }
+
+    pub(crate) fn set(&mut self, i: usize, gt: &Genotype) {
+        if gt.0.len() > self.stride {
+            // widen every sample to the new ploidy.
+            let n = self.len();
+            let stride = gt.0.len();
+            let mut values = vec![vector_end(); n * stride];
+            for s in 0..n {
+                values[s * stride..s * stride + self.stride]
+                    .copy_from_slice(&self.values[s * self.stride..(s + 1) * self.stride]);
+            }
+            self.values = values;
+            self.stride = stride;
+        }
+        let row = &mut self.values[i * self.stride..(i + 1) * self.stride];
+        row.fill(vector_end());
+        row[..gt.0.len()].copy_from_slice(&gt.0);
+        self.dirty = true;
+    }
+
+    /// Set every allele of the sample at 0-based `i` to missing, keeping the ploidy.
+    pub(crate) fn mask(&mut self, i: usize) {
+        if let Some(g) = self.get(i) {
+            let n = g.0.len().max(1);
+            self.set(i, &Genotype::new(&vec![-1; n], None));
+        }
+    }
+
+    /// Set the phase of every allele after the first for the sample at 0-based `i`.
+    pub(crate) fn set_phased(&mut self, i: usize, phased: bool) -> bool {
+        if i >= self.len() {
+            return false;
+        }
+        for a in self.values[i * self.stride..(i + 1) * self.stride]
+            .iter_mut()
+            .skip(1)
+            .take_while(|a| **a != vector_end())
+        {
+            *a = (*a & !1) | phased as i32;
+        }
+        self.dirty = true;
+        true
+    }
+
+    /// Count the samples by genotype class. A genotype with any missing allele (or no alleles)
+    /// is missing; haploid calls are hom-ref or hom-alt.
+    pub(crate) fn counts(&self) -> GtCounts {
+        let mut c = GtCounts::default();
+        for i in 0..self.len() {
+            let mut alleles = self.values[i * self.stride..(i + 1) * self.stride]
+                .iter()
+                .take_while(|&&a| a != vector_end())
+                .map(|&a| allele_index(a));
+            match alleles.next() {
+                Some(first) if first >= 0 => {
+                    let (mut missing, mut same) = (false, true);
+                    for a in alleles {
+                        missing |= a < 0;
+                        same &= a == first;
+                    }
+                    match (missing, same, first) {
+                        (true, _, _) => c.missing += 1,
+                        (false, true, 0) => c.hom_ref += 1,
+                        (false, true, _) => c.hom_alt += 1,
+                        (false, false, _) => c.het += 1,
+                    }
+                }
+                _ => c.missing += 1,
+            }
+        }
+        c
+    }
+
+    /// Frequency of the given alt allele (or of all alt alleles) among the called alleles.
+    /// Calls of the alleles in `skip` are not counted. None if no alleles are called.
+    pub(crate) fn alt_frequency(&self, alt: Option<i32>, skip: &[i32]) -> Option<f64> {
+        let (mut called, mut n) = (0usize, 0usize);
+        for &a in self.values.iter().filter(|&&a| a != vector_end()) {
+            let a = allele_index(a);
+            if a < 0 || skip.contains(&a) {
+                continue;
+            }
+            called += 1;
+            if alt.map_or(a > 0, |alt| a == alt) {
+                n += 1;
+            }
+        }
+        (called > 0).then(|| n as f64 / called as f64)
+    }
+
+    /// Count of each of `n_alleles` alleles (REF first) among called alleles, and the number
+    /// of called alleles (AN). Allele indexes >= `n_alleles` are counted in AN only.
+    pub(crate) fn allele_counts(&self, n_alleles: usize) -> (Vec<usize>, usize) {
+        let mut counts = vec![0; n_alleles];
+        let mut an = 0;
+        for &a in self.values.iter().filter(|&&a| a != vector_end()) {
+            let a = allele_index(a);
+            if a < 0 {
+                continue;
+            }
+            an += 1;
+            if let Some(c) = counts.get_mut(a as usize) {
+                *c += 1;
+            }
+        }
+        (counts, an)
+    }
+
+    /// Write the genotypes to the record if any were changed.
+    pub(crate) fn flush(&mut self, record: &mut bcf::Record) -> rust_htslib::errors::Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        // same as record.push_genotypes, but keeps the vector_end padding for mixed ploidy.
+        record.push_format_integer(b"GT", &self.values)?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+#[inline]
+fn vector_end() -> i32 {
+    rust_htslib::htslib::bcf_int32_vector_end
+}
+
 use std::fmt;
 impl fmt::Display for Genotype {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let Genotype(alleles) = self;
-        write!(f, "{}", alleles[0].0)?;
-        // convert to the alleles
-        for allele in alleles[1..].iter() {
-            let allele = allele.0;
-            let sep = match allele {
-                GenotypeAllele::Phased(_) | GenotypeAllele::PhasedMissing => "|",
-                GenotypeAllele::Unphased(_) | GenotypeAllele::UnphasedMissing => "/",
-            };
-            write!(f, "{}{}", sep, allele)?;
+        for (i, allele) in self.alleles().enumerate() {
+            if i > 0 {
+                let sep = match allele {
+                    GenotypeAllele::Phased(_) | GenotypeAllele::PhasedMissing => "|",
+                    GenotypeAllele::Unphased(_) | GenotypeAllele::UnphasedMissing => "/",
+                };
+                write!(f, "{}", sep)?;
+            }
+            write!(f, "{}", allele)?;
         }
         Ok(())
     }
 }
 
+/// Decode a raw GT value into an allele index, -1 for missing.
+#[inline]
+pub(crate) fn allele_index(raw: i32) -> i32 {
+    (raw >> 1) - 1
+}
+
+/// True if the raw GT values for a sample contain a non-reference allele (or the given alt allele).
+pub(crate) fn is_carrier(gt: &[i32], alt: Option<i32>) -> bool {
+    gt.iter()
+        .take_while(|&&a| a != rust_htslib::htslib::bcf_int32_vector_end)
+        .map(|&a| allele_index(a))
+        .any(|a| match alt {
+            Some(alt) => a == alt,
+            None => a > 0,
+        })
+}
+
+/// Bitset with one bit per sample set when the sample carries a non-reference
+/// allele (or the given alt allele). This avoids creating any per-sample lua values.
+pub(crate) fn carrier_bits(record: &bcf::Record, alt: Option<i32>) -> mlua::Result<Vec<u64>> {
+    let gts = record
+        .format(b"GT")
+        .integer()
+        .map_err(|e| mlua::Error::ExternalError(Arc::new(e)))?;
+    let mut bits = vec![0u64; gts.len().div_ceil(64)];
+    for (i, gt) in gts.iter().enumerate() {
+        if is_carrier(gt, alt) {
+            bits[i / 64] |= 1 << (i % 64);
+        }
+    }
+    Ok(bits)
+}
+
+/// Iterate the 0-based indexes of the set bits.
+pub(crate) fn bit_indexes(bits: &[u64]) -> impl Iterator<Item = usize> + '_ {
+    bits.iter().enumerate().flat_map(|(w, &word)| {
+        let mut word = word;
+        std::iter::from_fn(move || {
+            if word == 0 {
+                return None;
+            }
+            let b = word.trailing_zeros() as usize;
+            word &= word - 1;
+            Some(w * 64 + b)
+        })
+    })
+}
+
 pub fn register_genotypes(lua: &Lua) -> mlua::Result<()> {
     lua.register_userdata_type::<Genotype>(|reg| {
         reg.add_meta_function(MetaMethod::ToString, |_lua, this: AnyUserData| {
             let gts = format!("{}", this.borrow::<Genotype>()?);
             Ok(gts)
         });
-        reg.add_field_method_get("alts", |_lua, this: &Genotype| {
+        // number of non-reference, non-missing alleles.
+        reg.add_method("alts", |_lua, this: &Genotype, ()| {
             Ok(this
-                .0
-                .iter()
-                .map(|x| match x.0 {
-                    GenotypeAllele::Phased(i) => {
-                        if i != 0 {
-                            1
-                        } else {
-                            0
-                        }
-                    }
-                    GenotypeAllele::Unphased(i) => {
-                        if i != 0 {
-                            1
-                        } else {
-                            0
-                        }
-                    }
-                    _ => 0,
+                .alleles()
+                .filter(|x| {
+                    matches!(x, GenotypeAllele::Phased(i) | GenotypeAllele::Unphased(i) if *i != 0)
                 })
-                .sum::<i32>())
+                .count())
+        });
+        // true if any allele is missing (e.g. "./." or "./1").
+        reg.add_method("is_missing", |_lua, this: &Genotype, ()| {
+            Ok(this.alleles().any(|x| {
+                matches!(
+                    x,
+                    GenotypeAllele::PhasedMissing | GenotypeAllele::UnphasedMissing
+                )
+            }))
+        });
+        // true if every allele after the first is phased. haploid calls are phased.
+        reg.add_method("is_phased", |_lua, this: &Genotype, ()| {
+            Ok(this.alleles().skip(1).all(|x| {
+                matches!(x, GenotypeAllele::Phased(_) | GenotypeAllele::PhasedMissing)
+            }))
+        });
+        reg.add_meta_function(MetaMethod::Len, |_lua, this: AnyUserData| {
+            Ok(this.borrow::<Genotype>()?.0.len())
         });
 
         // index to get GTAllele
@@ -78,12 +404,11 @@ pub fn register_genotypes(lua: &Lua) -> mlua::Result<()> {
             MetaMethod::Index,
             |_lua, (this, idx): (AnyUserData, usize)| {
                 let gts = this.borrow::<Genotype>()?;
-                gts.0
-                    .get(idx - 1)
-                    .map(|allele| GTAllele(allele.0))
+                idx.checked_sub(1)
+                    .and_then(|i| gts.0.get(i))
+                    .map(|&allele| GTAllele(GenotypeAllele::from(allele)))
                     .ok_or_else(|| {
-                        let msg =
-                            format!("index out of bounds: {} in len: {}", idx - 1, gts.0.len());
+                        let msg = format!("index out of bounds: {} in len: {}", idx, gts.0.len());
                         mlua::Error::RuntimeError(msg)
                     })
             },
@@ -112,31 +437,48 @@ pub fn register_genotypes(lua: &Lua) -> mlua::Result<()> {
         reg.add_meta_function(
             MetaMethod::Index,
             |_lua, (this, idx): (AnyUserData, usize)| {
-                let ab = this.borrow::<Genotypes>()?;
-                let buffer = &ab.0.lock().0;
-                let len = buffer.len();
-                buffer
-                    .get(idx - 1)
-                    .map(|&x| {
-                        let gts = x
-                            .iter()
-                            .map(|&allele_int| {
-                                GTAllele(bcf::record::GenotypeAllele::from(allele_int))
-                            })
-                            .collect::<Vec<GTAllele>>();
-                        Genotype(gts)
-                    })
-                    .ok_or_else(|| {
-                        let msg = format!("index out of bounds: {} in len: {}", idx - 1, len);
-                        mlua::Error::RuntimeError(msg)
-                    })
+                let gts = this.borrow::<Genotypes>()?;
+                let data = gts.0.lock();
+                idx.checked_sub(1).and_then(|i| data.get(i)).ok_or_else(|| {
+                    let msg = format!("index out of bounds: {} in len: {}", idx, data.len());
+                    mlua::Error::RuntimeError(msg)
+                })
+            },
+        );
+        // variant.genotypes[i] = Genotype.new({0, 1}) or variant.genotypes[i] = "0|1"
+        reg.add_meta_function(
+            MetaMethod::NewIndex,
+            |_lua, (this, idx, value): (AnyUserData, usize, Value)| {
+                let gt = Genotype::from_lua_value(&value)?;
+                let gts = this.borrow::<Genotypes>()?;
+                let mut data = gts.0.lock();
+                let len = data.len();
+                match idx.checked_sub(1).filter(|&i| i < len) {
+                    Some(i) => {
+                        data.set(i, &gt);
+                        Ok(())
+                    }
+                    None => Err(mlua::Error::RuntimeError(format!(
+                        "index out of bounds: {} in len: {}",
+                        idx, len
+                    ))),
+                }
             },
         );
         reg.add_meta_function(MetaMethod::Len, |_lua, this: AnyUserData| {
-            let len = this.borrow::<Genotypes>()?.0.lock().0.len();
+            let len = this.borrow::<Genotypes>()?.0.lock().len();
             Ok(len)
         });
-    })
+    })?;
+
+    let genotype = lua.create_table()?;
+    genotype.set(
+        "new",
+        lua.create_function(|_lua, (alleles, phased): (Vec<i32>, Option<Vec<bool>>)| {
+            Ok(Genotype::new(&alleles, phased.as_deref()))
+        })?,
+    )?;
+    lua.globals().set("Genotype", genotype)
 }
 
 #[cfg(test)]
@@ -161,7 +503,7 @@ mod tests {
         let tmp_path = "_test.bcf";
         let vcf = bcf::Writer::from_path(tmp_path, &header, true, bcf::Format::Vcf).unwrap();
         let mut record = vcf.empty_record();
-        let _ = record.set_rid(Some(vcf.header().name2rid(b"chr1").unwrap()));
+        record.set_rid(Some(vcf.header().name2rid(b"chr1").unwrap()));
         record.set_pos(6);
         record.set_id(b"rs1234").unwrap();
         let alleles = &[
@@ -203,4 +545,159 @@ mod tests {
         })
         .unwrap();
     }
+
+    #[test]
+    fn test_genotype_methods() {
+        let (lua, mut record) = setup();
+        record
+            .push_genotypes(&[
+                bcf::record::GenotypeAllele::UnphasedMissing,
+                bcf::record::GenotypeAllele::Unphased(1),
+                bcf::record::GenotypeAllele::Unphased(1),
+                bcf::record::GenotypeAllele::Phased(2),
+            ])
+            .unwrap();
+        let globals = lua.globals();
+        let mut variant = Variant::new(record, HeaderMap::new());
+        let exp = lua
+            .load(
+                r#"
+            local gts = variant.genotypes
+            local a, b = gts[1], gts[2]
+            return table.concat({tostring(a), tostring(b), a:alts(), b:alts(),
+                tostring(a:is_missing()), tostring(b:is_missing()), #b, b[2].allele,
+                tostring(b[2].phased), tostring(a[1].allele)}, ";")
+            "#,
+            )
+            .set_name("test_genotype_methods")
+            .into_function()
+            .unwrap();
+        lua.scope(|scope| {
+            globals.raw_set("variant", scope.create_any_userdata_ref_mut(&mut variant)?)?;
+            assert_eq!(
+                exp.call::<_, String>(())?,
+                "./1;1|2;1;2;true;false;2;2;true;nil"
+            );
+            assert!(lua.load("return variant.genotypes[3]").exec().is_err());
+            assert!(lua.load("return variant.genotypes[1][0]").exec().is_err());
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_genotype_assignment() {
+        let (lua, record) = setup();
+        let globals = lua.globals();
+        let mut variant = Variant::new(record, HeaderMap::new());
+        lua.scope(|scope| {
+            globals.raw_set("variant", scope.create_any_userdata_ref_mut(&mut variant)?)?;
+            lua.load(
+                r#"
+                variant.genotypes[1] = Genotype.new({1, -1}, {false, true})
+                variant.genotypes[2] = "1/2/2"
+                "#,
+            )
+            .exec()?;
+            let s: String = lua
+                .load("return tostring(variant.genotypes[1]) .. ',' .. tostring(variant.genotypes[2])")
+                .eval()?;
+            assert_eq!(s, "1|.,1/2/2");
+            assert!(lua.load("variant.genotypes[3] = '0/1'").exec().is_err());
+            assert!(lua.load("variant.genotypes[1] = '0/x'").exec().is_err());
+            Ok(())
+        })
+        .unwrap();
+        variant.flush_genotypes().unwrap();
+        let record = variant.take();
+        let gts = record.genotypes().unwrap();
+        assert_eq!(gts.get(0).to_string(), "1|.");
+        assert_eq!(gts.get(1).to_string(), "1/2/2");
+    }
+
+    #[test]
+    fn test_parse_genotype() {
+        let gt = "0|1".parse::<Genotype>().unwrap();
+        assert_eq!(gt.to_string(), "0|1");
+        assert_eq!("./.".parse::<Genotype>().unwrap().to_string(), "./.");
+        assert_eq!("2".parse::<Genotype>().unwrap().to_string(), "2");
+        assert!("0/".parse::<Genotype>().is_err());
+        assert!("".parse::<Genotype>().is_err());
+    }
+
+    #[test]
+    fn test_carriers() {
+        let (lua, record) = setup();
+        let globals = lua.globals();
+        let mut variant = Variant::new(record, HeaderMap::new());
+        let exp = lua
+            .load(
+                r#"
+            local c = variant:carriers()
+            return table.concat(c, ",") .. ";" .. #variant:carriers(2) .. ";"
+                .. tostring(variant:is_carrier("NA12878")) .. ";" .. tostring(variant:is_carrier(1, 2))
+            "#,
+            )
+            .set_name("test_carriers")
+            .into_function()
+            .unwrap();
+        lua.scope(|scope| {
+            globals.raw_set("variant", scope.create_any_userdata_ref_mut(&mut variant)?)?;
+            assert_eq!(exp.call::<_, String>(())?, "1,2;0;true;false");
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_genotype_counts() {
+        let (lua, record) = setup();
+        let mut variant = Variant::new(record, HeaderMap::new());
+        lua.scope(|scope| {
+            lua.globals()
+                .raw_set("variant", scope.create_any_userdata_ref_mut(&mut variant)?)?;
+            let counts: (usize, usize, usize, usize, f64) = lua
+                .load("return variant:num_het(), variant:num_hom_alt(), variant:num_hom_ref(), variant:num_missing(), variant:call_rate()")
+                .eval()?;
+            assert_eq!(counts, (1, 1, 0, 0, 1.0));
+            let aaf: f64 = lua.load("return variant:aaf()").eval()?;
+            assert_eq!(aaf, 0.75);
+            // AC/AN/AF are not in this header.
+            assert!(lua.load("variant:update_allele_counts()").exec().is_err());
+            // counts reflect assigned genotypes.
+            let counts: (usize, usize, f64) = lua
+                .load(r#"variant.genotypes[1] = "0/."; return variant:num_het(), variant:num_missing(), variant:call_rate()"#)
+                .eval()?;
+            assert_eq!(counts, (0, 1, 0.5));
+            Ok(())
+        })
+        .unwrap();
+
+        let gts = GtData {
+            values: vec![2, 2, 2, vector_end(), 4, 6, 0, 0],
+            stride: 2,
+            dirty: false,
+        };
+        // 0/0, 0, 1/2, ./.
+        assert_eq!(gts.alt_frequency(None, &[]), Some(0.4));
+        assert_eq!(gts.alt_frequency(Some(2), &[]), Some(0.2));
+        assert_eq!(gts.alt_frequency(Some(3), &[]), Some(0.0));
+        assert_eq!(gts.alt_frequency(None, &[2]), Some(0.25));
+        assert_eq!(gts.allele_counts(3), (vec![3, 1, 1], 5));
+        assert_eq!(
+            gts.counts(),
+            GtCounts {
+                hom_ref: 2,
+                het: 1,
+                hom_alt: 0,
+                missing: 1
+            }
+        );
+    }
+
+    #[test]
+    fn test_bit_indexes() {
+        let bits = [0b101u64, 1 << 63];
+        assert_eq!(bit_indexes(&bits).collect::<Vec<_>>(), vec![0, 2, 127]);
+    }
 }
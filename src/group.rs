@@ -0,0 +1,324 @@
+//! Bucket passing variants by the value of a lua expression (`--group-by`) and write one row per
+//! group, either from built-in reducers (`--group-reduce count,mean:DP`) or from the text returned
+//! by `aggregate(key, variants)` when a prelude defines it.
+use mlua::Lua;
+use rust_htslib::bcf::header::{HeaderView, TagType};
+use rust_htslib::bcf::{self};
+use rustc_hash::FxHashMap;
+
+use crate::variant::{HeaderMap, Sentinel, Variant};
+
+fn invalid(msg: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, msg)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reducer {
+    Count,
+    Sum(String),
+    Mean(String),
+    Min(String),
+    Max(String),
+}
+
+impl Reducer {
+    fn tag(&self) -> Option<&str> {
+        match self {
+            Reducer::Count => None,
+            Reducer::Sum(t) | Reducer::Mean(t) | Reducer::Min(t) | Reducer::Max(t) => Some(t),
+        }
+    }
+
+    /// The column name in the output e.g. mean_DP.
+    fn name(&self) -> String {
+        match self {
+            Reducer::Count => "count".to_string(),
+            Reducer::Sum(t) => format!("sum_{}", t),
+            Reducer::Mean(t) => format!("mean_{}", t),
+            Reducer::Min(t) => format!("min_{}", t),
+            Reducer::Max(t) => format!("max_{}", t),
+        }
+    }
+}
+
+/// Parse `count` and `sum:TAG`, `mean:TAG`, `min:TAG`, `max:TAG` for numeric INFO fields.
+/// May be given multiple times or comma-separated.
+pub fn parse_reducers(args: &[String]) -> std::io::Result<Vec<Reducer>> {
+    args.iter()
+        .flat_map(|a| a.split(','))
+        .filter(|r| !r.is_empty())
+        .map(|r| match r.split_once(':') {
+            None if r == "count" => Ok(Reducer::Count),
+            Some(("sum", tag)) if !tag.is_empty() => Ok(Reducer::Sum(tag.to_string())),
+            Some(("mean", tag)) if !tag.is_empty() => Ok(Reducer::Mean(tag.to_string())),
+            Some(("min", tag)) if !tag.is_empty() => Ok(Reducer::Min(tag.to_string())),
+            Some(("max", tag)) if !tag.is_empty() => Ok(Reducer::Max(tag.to_string())),
+            _ => Err(invalid(format!(
+                "expected count, sum:TAG, mean:TAG, min:TAG or max:TAG in --group-reduce, got '{}'",
+                r
+            ))),
+        })
+        .collect()
+}
+
+/// Running sum, number of values, min and max of an INFO field in a group.
+#[derive(Debug, Clone, Copy)]
+struct Acc {
+    sum: f64,
+    n: usize,
+    min: f64,
+    max: f64,
+}
+
+impl Default for Acc {
+    fn default() -> Self {
+        Acc {
+            sum: 0.0,
+            n: 0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+}
+
+impl Acc {
+    fn add(&mut self, v: f64) {
+        self.sum += v;
+        self.n += 1;
+        self.min = self.min.min(v);
+        self.max = self.max.max(v);
+    }
+}
+
+struct Group {
+    key: String,
+    count: usize,
+    accs: Vec<Acc>,
+    // the variants in the group, kept only when `aggregate` is defined.
+    variants: Option<Vec<Variant>>,
+}
+
+pub(crate) struct Groups<'lua> {
+    key: mlua::Function<'lua>,
+    reducers: Vec<Reducer>,
+    aggregate: Option<mlua::Function<'lua>>,
+    groups: Vec<Group>,
+    index: FxHashMap<String, usize>,
+}
+
+/// The first value of a numeric INFO field, if it is present and not missing.
+fn info_value(record: &bcf::Record, tag: &str) -> Option<f64> {
+    if let Ok(Some(v)) = record.info(tag.as_bytes()).integer() {
+        return v
+            .first()
+            .filter(|v| !v.is_missing_value())
+            .map(|v| *v as f64);
+    }
+    match record.info(tag.as_bytes()).float() {
+        Ok(Some(v)) => v
+            .first()
+            .filter(|v| !v.is_missing_value())
+            .map(|v| *v as f64),
+        _ => None,
+    }
+}
+
+// INFO floats are f32 so print at that precision (0.33, not 0.33000001311302185).
+fn format_value(v: f64) -> String {
+    if v.is_finite() {
+        format!("{}", v as f32)
+    } else {
+        ".".to_string()
+    }
+}
+
+impl<'lua> Groups<'lua> {
+    /// `key` is the compiled `--group-by` expression. Without reducers or `aggregate`, groups are counted.
+    pub(crate) fn new(key: mlua::Function<'lua>, aggregate: Option<mlua::Function<'lua>>) -> Self {
+        Groups {
+            key,
+            reducers: vec![Reducer::Count],
+            aggregate,
+            groups: vec![],
+            index: FxHashMap::default(),
+        }
+    }
+
+    /// Use `reducers` for the columns. INFO fields are checked against the output header.
+    pub(crate) fn set_reducers(
+        &mut self,
+        reducers: Vec<Reducer>,
+        hv: &HeaderView,
+    ) -> std::io::Result<()> {
+        if reducers.is_empty() {
+            return Ok(());
+        }
+        if self.aggregate.is_some() {
+            return Err(invalid(
+                "--group-reduce can not be used when the prelude defines aggregate(key, variants)"
+                    .to_string(),
+            ));
+        }
+        for tag in reducers.iter().filter_map(|r| r.tag()) {
+            match hv.info_type(tag.as_bytes()) {
+                Ok((TagType::Integer | TagType::Float, _)) => {}
+                Ok(_) => {
+                    return Err(invalid(format!(
+                        "--group-reduce requires a numeric INFO field; {} is not",
+                        tag
+                    )))
+                }
+                Err(_) => {
+                    return Err(invalid(format!(
+                        "INFO {} given to --group-reduce not found in header",
+                        tag
+                    )))
+                }
+            }
+        }
+        self.reducers = reducers;
+        Ok(())
+    }
+
+    /// Evaluate the `--group-by` expression for the current `variant`. nil means the variant is
+    /// not added to any group.
+    pub(crate) fn key(&self) -> mlua::Result<Option<String>> {
+        self.key.call::<_, Option<String>>(())
+    }
+
+    /// Add a passing record to the group for `key`.
+    pub(crate) fn add(&mut self, key: String, record: &bcf::Record, header_map: HeaderMap) {
+        let i = match self.index.get(&key) {
+            Some(i) => *i,
+            None => {
+                let variants = self.aggregate.as_ref().map(|_| vec![]);
+                self.index.insert(key.clone(), self.groups.len());
+                self.groups.push(Group {
+                    key,
+                    count: 0,
+                    accs: vec![Acc::default(); self.reducers.len()],
+                    variants,
+                });
+                self.groups.len() - 1
+            }
+        };
+        let group = &mut self.groups[i];
+        group.count += 1;
+        for (acc, reducer) in group.accs.iter_mut().zip(&self.reducers) {
+            if let Some(v) = reducer.tag().and_then(|t| info_value(record, t)) {
+                acc.add(v);
+            }
+        }
+        if let Some(variants) = &mut group.variants {
+            variants.push(Variant::new(record.clone(), header_map));
+        }
+    }
+
+    /// One line per group in the order the keys were first seen. With `aggregate`, each line is
+    /// its result (nil skips the group); otherwise a `#key` header and a column per reducer.
+    pub(crate) fn rows(&mut self, lua: &'lua Lua) -> mlua::Result<Vec<String>> {
+        let mut rows = vec![];
+        if let Some(aggregate) = &self.aggregate {
+            for g in self.groups.iter_mut() {
+                let row = lua.scope(|scope| {
+                    let variants = lua.create_table()?;
+                    for v in g.variants.iter_mut().flatten() {
+                        variants.raw_push(scope.create_any_userdata_ref_mut(v)?)?;
+                    }
+                    aggregate.call::<_, Option<String>>((g.key.as_str(), variants))
+                })?;
+                rows.extend(row);
+            }
+            return Ok(rows);
+        }
+        let mut header = vec!["#key".to_string()];
+        header.extend(self.reducers.iter().map(|r| r.name()));
+        rows.push(header.join("\t"));
+        for g in &self.groups {
+            let mut row = vec![g.key.clone()];
+            for (acc, reducer) in g.accs.iter().zip(&self.reducers) {
+                row.push(match reducer {
+                    Reducer::Count => g.count.to_string(),
+                    _ if acc.n == 0 => ".".to_string(),
+                    Reducer::Sum(_) => format_value(acc.sum),
+                    Reducer::Mean(_) => format_value(acc.sum / acc.n as f64),
+                    Reducer::Min(_) => format_value(acc.min),
+                    Reducer::Max(_) => format_value(acc.max),
+                });
+            }
+            rows.push(row.join("\t"));
+        }
+        Ok(rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_group_by() {
+        let reducers =
+            parse_reducers(&["count,mean:DP".to_string(), "max:DP".to_string()]).unwrap();
+        assert_eq!(reducers[1], Reducer::Mean("DP".to_string()));
+        assert!(parse_reducers(&["median:DP".to_string()]).is_err());
+        assert!(parse_reducers(&["mean".to_string()]).is_err());
+
+        let mut header = bcf::Header::new();
+        header.push_record(br#"##contig=<ID=chr1,length=10000>"#);
+        header.push_record(br#"##contig=<ID=chr2,length=10000>"#);
+        header.push_record(br#"##INFO=<ID=DP,Number=1,Type=Integer,Description="DP">"#);
+        header.push_record(br#"##INFO=<ID=GENE,Number=1,Type=String,Description="GENE">"#);
+        let vcf =
+            bcf::Writer::from_path("_test.group.vcf", &header, true, bcf::Format::Vcf).unwrap();
+        let hv = vcf.header().clone();
+        let lua = Lua::new();
+        crate::register(&lua).unwrap();
+        let key = lua.load("return variant.chrom").into_function().unwrap();
+        let bad = vec![Reducer::Mean("GENE".to_string())];
+        assert!(Groups::new(key.clone(), None)
+            .set_reducers(bad, &hv)
+            .is_err());
+
+        let mut records = vec![];
+        for (rid, dp) in [(0, Some(10)), (1, Some(3)), (0, None), (0, Some(20))] {
+            let mut record = vcf.empty_record();
+            record.set_rid(Some(rid));
+            record.set_alleles(&[b"A", b"T"]).unwrap();
+            if let Some(dp) = dp {
+                record.push_info_integer(b"DP", &[dp]).unwrap();
+            }
+            records.push(record);
+        }
+        let chrom = |r: &bcf::Record| {
+            String::from_utf8_lossy(hv.rid2name(r.rid().unwrap()).unwrap()).to_string()
+        };
+
+        let mut groups = Groups::new(key.clone(), None);
+        groups.set_reducers(reducers, &hv).unwrap();
+        for r in &records {
+            groups.add(chrom(r), r, HeaderMap::new());
+        }
+        assert_eq!(
+            groups.rows(&lua).unwrap(),
+            [
+                "#key\tcount\tmean_DP\tmax_DP",
+                "chr1\t3\t15\t20",
+                "chr2\t1\t3\t3"
+            ]
+        );
+
+        let aggregate = lua
+            .load(r#"return function(key, variants) return `{key}:{#variants}:{variants[1].chrom}` end"#)
+            .eval::<mlua::Function>()
+            .unwrap();
+        let mut groups = Groups::new(key, Some(aggregate));
+        assert!(groups.set_reducers(vec![Reducer::Count], &hv).is_err());
+        for r in &records {
+            groups.add(chrom(r), r, HeaderMap::new());
+        }
+        assert_eq!(groups.rows(&lua).unwrap(), ["chr1:3:chr1", "chr2:1:chr2"]);
+        drop(vcf);
+        _ = std::fs::remove_file("_test.group.vcf");
+    }
+}
@@ -28,10 +28,18 @@ macro_rules! find_record_match {
                 if values.get("ID") != Some(&$key.to_string()) {
                     return None;
                 }
+                // IDX is internal to htslib and quotes are only part of the VCF text.
                 Some(HashMap::from_iter(
                     values
                         .into_iter()
-                        .map(|(k, v)| (k.to_string(), v.to_string())),
+                        .filter(|(k, _)| *k != "IDX")
+                        .map(|(k, v)| {
+                            let v = v
+                                .strip_prefix('"')
+                                .and_then(|v| v.strip_suffix('"'))
+                                .unwrap_or(v);
+                            (k.to_string(), v.to_string())
+                        }),
                 ))
             }
             _ => None,
@@ -43,8 +51,8 @@ fn find_record(
     records: &[HeaderRecord],
     key: &str,
     hdr_type: ::libc::c_uint,
-) -> Result<HashMap<String, String>, mlua::Error> {
-    let hrec = records
+) -> Option<HashMap<String, String>> {
+    records
         .iter()
         .filter_map(|x| {
             if hdr_type == rust_htslib::htslib::BCF_HL_INFO {
@@ -55,15 +63,625 @@ fn find_record(
                 None
             }
         })
-        .next();
+        .next()
+}
+
+/// Like [`find_record`] but an error if the tag is not in the header.
+fn get_record(
+    records: &[HeaderRecord],
+    key: &str,
+    hdr_type: ::libc::c_uint,
+) -> Result<HashMap<String, String>, mlua::Error> {
+    find_record(records, key, hdr_type).ok_or_else(|| {
+        let kind = if hdr_type == rust_htslib::htslib::BCF_HL_INFO {
+            "INFO"
+        } else {
+            "FORMAT"
+        };
+        mlua::Error::ExternalError(Arc::new(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{} {} not found in header", kind, key),
+        )))
+    })
+}
+
+/// The header as VCF text.
+pub(crate) fn header_text(hv: &HeaderView) -> mlua::Result<String> {
+    let mut kstr = rust_htslib::htslib::kstring_t {
+        l: 0,
+        m: 0,
+        s: std::ptr::null_mut(),
+    };
+    if unsafe { rust_htslib::htslib::bcf_hdr_format(hv.inner, 0, &mut kstr) } != 0 {
+        return Err(mlua::Error::ExternalError(Arc::new(
+            std::io::Error::last_os_error(),
+        )));
+    }
+    let s = unsafe {
+        let s = String::from_utf8_lossy(std::slice::from_raw_parts(
+            kstr.s as *const u8,
+            kstr.l as usize,
+        ))
+        .to_string();
+        libc::free(kstr.s as *mut libc::c_void);
+        s
+    };
+    Ok(s)
+}
+
+/// VCF versions accepted by [`set_version`].
+pub const VCF_VERSIONS: &[&str] = &["4.1", "4.2", "4.3", "4.4"];
+
+/// The VCF version of the header, e.g. "4.4" for `##fileformat=VCFv4.4`.
+pub fn version(hv: &HeaderView) -> String {
+    let v = unsafe { std::ffi::CStr::from_ptr(rust_htslib::htslib::bcf_hdr_get_version(hv.inner)) };
+    let v = v.to_string_lossy();
+    v.strip_prefix("VCFv").unwrap_or(&v).to_string()
+}
+
+/// A VCF version as (major, minor).
+pub type Version = (u32, u32);
+
+/// The (major, minor) of a version such as "4.4" or "VCFv4.4". None if it is not of that form.
+pub fn parse_version(version: &str) -> Option<Version> {
+    let v = version.strip_prefix("VCFv").unwrap_or(version);
+    let (major, minor) = v.split_once('.')?;
+    Some((major.parse().ok()?, minor.parse().ok()?))
+}
+
+/// INFO and FORMAT tags reserved by the VCF spec as (kind, ID, Number, Type, first version).
+const RESERVED_TAGS: &[(&str, &str, &str, &str, Version)] = &[
+    ("INFO", "AA", "1", "String", (4, 3)),
+    ("INFO", "AC", "A", "Integer", (4, 3)),
+    ("INFO", "AD", "R", "Integer", (4, 3)),
+    ("INFO", "ADF", "R", "Integer", (4, 3)),
+    ("INFO", "ADR", "R", "Integer", (4, 3)),
+    ("INFO", "AF", "A", "Float", (4, 3)),
+    ("INFO", "AN", "1", "Integer", (4, 3)),
+    ("INFO", "BQ", "1", "Float", (4, 3)),
+    ("INFO", "CIGAR", "A", "String", (4, 3)),
+    ("INFO", "DB", "0", "Flag", (4, 3)),
+    ("INFO", "DP", "1", "Integer", (4, 3)),
+    ("INFO", "END", "1", "Integer", (4, 3)),
+    ("INFO", "H2", "0", "Flag", (4, 3)),
+    ("INFO", "H3", "0", "Flag", (4, 3)),
+    ("INFO", "MQ", "1", "Float", (4, 3)),
+    ("INFO", "MQ0", "1", "Integer", (4, 3)),
+    ("INFO", "NS", "1", "Integer", (4, 3)),
+    ("INFO", "SB", "4", "Integer", (4, 3)),
+    ("INFO", "SOMATIC", "0", "Flag", (4, 3)),
+    ("INFO", "VALIDATED", "0", "Flag", (4, 3)),
+    ("INFO", "1000G", "0", "Flag", (4, 3)),
+    ("INFO", "SVLEN", "A", "Integer", (4, 4)),
+    ("INFO", "SVCLAIM", "A", "String", (4, 4)),
+    ("INFO", "MATEID", "A", "String", (4, 4)),
+    ("INFO", "EVENT", "A", "String", (4, 4)),
+    ("FORMAT", "AD", "R", "Integer", (4, 3)),
+    ("FORMAT", "ADF", "R", "Integer", (4, 3)),
+    ("FORMAT", "ADR", "R", "Integer", (4, 3)),
+    ("FORMAT", "DP", "1", "Integer", (4, 3)),
+    ("FORMAT", "EC", "A", "Integer", (4, 3)),
+    ("FORMAT", "FT", "1", "String", (4, 3)),
+    ("FORMAT", "GL", "G", "Float", (4, 3)),
+    ("FORMAT", "GP", "G", "Float", (4, 3)),
+    ("FORMAT", "GQ", "1", "Integer", (4, 3)),
+    ("FORMAT", "GT", "1", "String", (4, 3)),
+    ("FORMAT", "HQ", "2", "Integer", (4, 3)),
+    ("FORMAT", "MQ", "1", "Integer", (4, 3)),
+    ("FORMAT", "PL", "G", "Integer", (4, 3)),
+    ("FORMAT", "PP", "G", "Integer", (4, 3)),
+    ("FORMAT", "PQ", "1", "Integer", (4, 3)),
+    ("FORMAT", "PS", "1", "Integer", (4, 3)),
+    ("FORMAT", "CN", "1", "Float", (4, 4)),
+    ("FORMAT", "CNQ", "1", "Float", (4, 4)),
+    ("FORMAT", "CNL", "G", "Float", (4, 4)),
+    ("FORMAT", "CNP", "G", "Float", (4, 4)),
+    ("FORMAT", "NQ", "1", "Integer", (4, 4)),
+    ("FORMAT", "HAP", "1", "Integer", (4, 4)),
+    ("FORMAT", "AHAP", "1", "Integer", (4, 4)),
+    ("FORMAT", "PSL", "P", "String", (4, 4)),
+    ("FORMAT", "PSO", "P", "Integer", (4, 4)),
+    ("FORMAT", "PSQ", "P", "Integer", (4, 4)),
+];
+
+/// Set the `##fileformat` line to the given version, e.g. "4.4" or "VCFv4.4".
+pub fn set_version(hv: &HeaderView, version: &str) -> std::io::Result<()> {
+    let v = version.strip_prefix("VCFv").unwrap_or(version);
+    if !VCF_VERSIONS.contains(&v) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "unsupported VCF version '{}'; expected one of {}",
+                version,
+                VCF_VERSIONS.join(", ")
+            ),
+        ));
+    }
+    let c = std::ffi::CString::new(format!("VCFv{}", v))?;
+    if unsafe { rust_htslib::htslib::bcf_hdr_set_version(hv.inner, c.as_ptr()) } != 0 {
+        return Err(std::io::Error::other("error setting VCF version"));
+    }
+    Ok(())
+}
+
+/// Header lines that are not valid in the given VCF version. ##META was added in VCF 4.3 and
+/// Number=P in 4.4. Reserved INFO and FORMAT tags must have the Number and Type of the spec.
+pub fn version_problems(hv: &HeaderView, version: &str) -> Vec<String> {
+    let v = parse_version(version).unwrap_or((4, 2));
+    let mut problems = vec![];
+    for rec in hv.header_records() {
+        let (kind, values) = match rec {
+            HeaderRecord::Structured { key, values } if key == "META" => {
+                if v < (4, 3) {
+                    problems.push(format!(
+                        "##META line for {} requires VCF 4.3 or later",
+                        values.get("ID").map_or(".", |v| v.as_str())
+                    ));
+                }
+                continue;
+            }
+            HeaderRecord::Info { values, .. } => ("INFO", values),
+            HeaderRecord::Format { values, .. } => ("FORMAT", values),
+            _ => continue,
+        };
+        let get = |k: &str| values.get(k).map_or(".", |v| v.as_str());
+        let (id, number, typ) = (get("ID"), get("Number"), get("Type"));
+        if number == "P" && v < (4, 4) {
+            problems.push(format!(
+                "{} {} has Number=P which requires VCF 4.4 or later",
+                kind, id
+            ));
+        }
+        if let Some((_, _, n, t, since)) = RESERVED_TAGS
+            .iter()
+            .find(|(k, i, ..)| *k == kind && *i == id)
+        {
+            if v >= *since && (number, typ) != (*n, *t) {
+                problems.push(format!(
+                    "{} {} is reserved in VCF {}.{} with Number={},Type={} but has Number={},Type={}",
+                    kind, id, v.0, v.1, n, t, number, typ
+                ));
+            }
+        }
+    }
+    problems
+}
+
+/// With a `<*>` or `<NON_REF>` ALT, AD (Number=R) must have a value for that allele too. Returns a
+/// description of the first sample with fewer AD values than alleles.
+pub fn non_ref_ad_problem(record: &rust_htslib::bcf::Record) -> Option<String> {
+    let alleles = record.alleles();
+    let non_ref = alleles
+        .iter()
+        .skip(1)
+        .find(|a| matches!(**a, b"<*>" | b"<NON_REF>"))?;
+    let ad = record.format(b"AD").integer().ok()?;
+    let samples = record.header().samples();
+    ad.iter().enumerate().find_map(|(i, values)| {
+        let n = values
+            .iter()
+            .take_while(|&&v| v != rust_htslib::htslib::bcf_int32_vector_end)
+            .count();
+        // a single missing value is allowed.
+        let missing = n == 1 && values[0] == rust_htslib::htslib::bcf_int32_missing;
+        (n != alleles.len() && !missing).then(|| {
+            format!(
+                "{}:{} has a {} allele but AD of {} has {} values for {} alleles",
+                String::from_utf8_lossy(
+                    record
+                        .rid()
+                        .and_then(|rid| record.header().rid2name(rid).ok())
+                        .unwrap_or(b".")
+                ),
+                record.pos() + 1,
+                String::from_utf8_lossy(non_ref),
+                String::from_utf8_lossy(samples[i]),
+                n,
+                alleles.len()
+            )
+        })
+    })
+}
+
+/// The ##META lines in the header.
+fn meta_records(hv: &HeaderView) -> Vec<Vec<(String, String)>> {
+    hv.header_records()
+        .into_iter()
+        .filter_map(|rec| match rec {
+            HeaderRecord::Structured { key, values } if key == "META" => {
+                Some(values.into_iter().collect())
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// True if the header defines `id` as INFO (`BCF_HL_INFO`) or FORMAT (`BCF_HL_FMT`).
+/// Unlike `HeaderView::info_type`, this is false for definitions removed with `bcf_hdr_remove`.
+pub(crate) fn has_tag(hv: &HeaderView, typ: u32, id: &str) -> bool {
+    let key = std::ffi::CString::new("ID").unwrap();
+    let c_id = std::ffi::CString::new(id).expect("CString::new failed");
+    !unsafe {
+        rust_htslib::htslib::bcf_hdr_get_hrec(
+            hv.inner,
+            typ as i32,
+            key.as_ptr(),
+            c_id.as_ptr(),
+            std::ptr::null(),
+        )
+    }
+    .is_null()
+}
+
+/// Remove the INFO (`BCF_HL_INFO`) or FORMAT (`BCF_HL_FMT`) definition of `id`.
+fn remove_tag(hv: &mut HeaderView, typ: u32, id: &str) -> mlua::Result<()> {
+    let kind = match typ {
+        rust_htslib::htslib::BCF_HL_INFO => "INFO",
+        _ => "FORMAT",
+    };
+    if !has_tag(hv, typ, id) {
+        return Err(mlua::Error::runtime(format!(
+            "{} {} not found in header",
+            kind, id
+        )));
+    }
+    let c_id = std::ffi::CString::new(id).expect("CString::new failed");
+    unsafe {
+        rust_htslib::htslib::bcf_hdr_remove(hv.inner, typ as i32, c_id.as_ptr());
+        rust_htslib::htslib::bcf_hdr_sync(hv.inner);
+    }
+    Ok(())
+}
+
+/// Rewrite the INFO (`BCF_HL_INFO`) or FORMAT (`BCF_HL_FMT`) definition of `id` with the
+/// values in `updates` (e.g. Number and Description) replacing the existing ones. The tag keeps its
+/// id so records are not affected. The Type can not be changed here; use --retype-info.
+fn update_tag(
+    hv: &mut HeaderView,
+    typ: u32,
+    id: &str,
+    updates: &HashMap<String, String>,
+) -> mlua::Result<()> {
+    let kind = match typ {
+        rust_htslib::htslib::BCF_HL_INFO => "INFO",
+        _ => "FORMAT",
+    };
+    let mut values = get_record(&hv.header_records(), id, typ)?;
+    for (k, v) in updates {
+        match k.as_str() {
+            "ID" | "IDX" => {
+                return Err(mlua::Error::runtime(format!(
+                    "{} can not be changed with update_{}",
+                    k,
+                    kind.to_lowercase()
+                )))
+            }
+            "Type" if values.get("Type") != Some(v) => {
+                return Err(mlua::Error::runtime(format!(
+                    "the Type of {} {} can not be changed in the header; use --retype-info",
+                    kind, id
+                )))
+            }
+            _ => _ = values.insert(k.clone(), v.clone()),
+        }
+    }
+    let mut line = format!("##{}=<ID={}", kind, id);
+    for k in ["Number", "Type"] {
+        if let Some(v) = values.get(k) {
+            line.push_str(&format!(",{}={}", k, v));
+        }
+    }
+    let description = values.get("Description").map(|d| d.as_str()).unwrap_or("");
+    line.push_str(&format!(
+        ",Description=\"{}\"",
+        description.replace('"', "\\\"")
+    ));
+    let mut keys = values
+        .keys()
+        .filter(|k| !["ID", "Number", "Type", "Description"].contains(&k.as_str()))
+        .collect::<Vec<_>>();
+    keys.sort();
+    for k in keys {
+        // the quotes are removed when the header is read. Source and Version are always quoted.
+        let v = &values[k];
+        if matches!(k.as_str(), "Source" | "Version")
+            || v.is_empty()
+            || v.contains(|c: char| c.is_whitespace() || ",=\"<>".contains(c))
+        {
+            line.push_str(&format!(",{}=\"{}\"", k, v.replace('"', "\\\"")));
+        } else {
+            line.push_str(&format!(",{}={}", k, v));
+        }
+    }
+    line.push('>');
+
+    remove_tag(hv, typ, id)?;
+    let c_str = std::ffi::CString::new(line).expect("CString::new failed");
+    if unsafe { rust_htslib::htslib::bcf_hdr_append(hv.inner, c_str.as_ptr()) } != 0 {
+        return Err(mlua::Error::runtime(format!(
+            "error updating {} {} in the header",
+            kind, id
+        )));
+    }
+    _ = unsafe { rust_htslib::htslib::bcf_hdr_sync(hv.inner) };
+    Ok(())
+}
+
+/// The INFO and FORMAT tags (as `BCF_HL_INFO` or `BCF_HL_FMT` and ID) that are defined in `input`
+/// but were removed from `output`, e.g. with `header:remove_info` in the prelude.
+pub(crate) fn removed_tags(input: &HeaderView, output: &HeaderView) -> Vec<(u32, String)> {
+    input
+        .header_records()
+        .into_iter()
+        .filter_map(|rec| match rec {
+            HeaderRecord::Info { values, .. } => {
+                let id = values.get("ID")?;
+                let typ = rust_htslib::htslib::BCF_HL_INFO;
+                (!has_tag(output, typ, id)).then(|| (typ, id.to_string()))
+            }
+            HeaderRecord::Format { values, .. } => {
+                let id = values.get("ID")?;
+                let typ = rust_htslib::htslib::BCF_HL_FMT;
+                (!has_tag(output, typ, id)).then(|| (typ, id.to_string()))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Remove the values of the `removed_tags` from a record that has not yet been translated.
+pub(crate) fn strip_tags(record: &mut rust_htslib::bcf::Record, tags: &[(u32, String)]) {
+    for (typ, id) in tags {
+        let c_id = std::ffi::CString::new(id.as_str()).expect("CString::new failed");
+        // with no values, htslib removes the tag whatever the type.
+        unsafe {
+            if *typ == rust_htslib::htslib::BCF_HL_INFO {
+                rust_htslib::htslib::bcf_update_info(
+                    record.header().inner,
+                    record.inner,
+                    c_id.as_ptr(),
+                    std::ptr::null(),
+                    0,
+                    rust_htslib::htslib::BCF_HT_INT as i32,
+                );
+            } else {
+                rust_htslib::htslib::bcf_update_format(
+                    record.header().inner,
+                    record.inner,
+                    c_id.as_ptr(),
+                    std::ptr::null(),
+                    0,
+                    rust_htslib::htslib::BCF_HT_INT as i32,
+                );
+            }
+        }
+    }
+}
+
+/// Keep only `samples` in the header. Samples keep their order in the header.
+pub fn subset_samples(hv: &mut HeaderView, samples: &[String]) -> std::io::Result<()> {
+    if let Some(s) = samples
+        .iter()
+        .find(|s| hv.sample_id(s.as_bytes()).is_none())
+    {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            crate::sample::unknown_sample_message(hv, s),
+        ));
+    }
+    let keep = hv
+        .samples()
+        .into_iter()
+        .filter(|s| samples.iter().any(|k| k.as_bytes() == *s))
+        .collect::<Vec<_>>();
+    let header = Header::from_template_subset(hv, &keep).map_err(std::io::Error::other)?;
+    *hv = HeaderView::new(unsafe { rust_htslib::htslib::bcf_hdr_dup(header.inner) });
+    Ok(())
+}
+
+/// Rename samples (old -> new) by re-parsing the header text with a new #CHROM line.
+pub fn rename_samples(
+    hv: &mut HeaderView,
+    renames: &HashMap<String, String>,
+) -> std::io::Result<()> {
+    let invalid = |msg: String| std::io::Error::new(std::io::ErrorKind::InvalidInput, msg);
+    let samples = hv
+        .samples()
+        .iter()
+        .map(|s| String::from_utf8_lossy(s).to_string())
+        .collect::<Vec<_>>();
+    if let Some(old) = renames.keys().find(|old| !samples.contains(old)) {
+        return Err(invalid(crate::sample::unknown_sample_message(hv, old)));
+    }
+    let new_samples = samples
+        .iter()
+        .map(|s| renames.get(s).unwrap_or(s).as_str())
+        .collect::<Vec<_>>();
+    for (i, s) in new_samples.iter().enumerate() {
+        if new_samples[..i].contains(s) {
+            return Err(invalid(format!("duplicate sample '{}' after rename", s)));
+        }
+    }
+    let text = header_text(hv).map_err(std::io::Error::other)?;
+    let Some(chrom_line) = text.lines().rfind(|l| l.starts_with("#CHROM")) else {
+        return Err(invalid("no #CHROM line in header".to_string()));
+    };
+    let fixed = chrom_line.split('\t').take(9).collect::<Vec<_>>();
+    let text = format!(
+        "{}{}\t{}\n",
+        &text[..text.len() - chrom_line.len() - 1],
+        fixed.join("\t"),
+        new_samples.join("\t")
+    );
+    *hv = parse_header(&text)
+        .map_err(|_| std::io::Error::other("error parsing header with renamed samples"))?;
+    Ok(())
+}
+
+/// Read `old new` contig names, one pair per line, e.g. `1 chr1` to add the chr prefix.
+/// Empty lines and lines starting with '#' are skipped.
+pub fn read_contig_renames(path: &str) -> std::io::Result<HashMap<String, String>> {
+    let text = std::fs::read_to_string(path)?;
+    let mut renames = HashMap::new();
+    for line in text.lines() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.split_whitespace().collect::<Vec<_>>()[..] {
+            [old, new] => {
+                renames.insert(old.to_string(), new.to_string());
+            }
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!(
+                        "expected 'old new' contig names in {}; got '{}'",
+                        path, line
+                    ),
+                ))
+            }
+        }
+    }
+    Ok(renames)
+}
+
+/// Rename contigs (old -> new) by re-parsing the header text with new `##contig` IDs. Contigs
+/// keep their order, and so their ids. Returns the number of contigs renamed; names in
+/// `renames` that are not in the header are ignored.
+pub fn rename_contigs(
+    hv: &mut HeaderView,
+    renames: &HashMap<String, String>,
+) -> std::io::Result<usize> {
+    let text = header_text(hv).map_err(std::io::Error::other)?;
+    let mut out = String::with_capacity(text.len());
+    let mut names = vec![];
+    let mut renamed = 0;
+    for line in text.lines() {
+        match line.strip_prefix("##contig=<ID=") {
+            Some(rest) => {
+                let end = rest.find([',', '>']).unwrap_or(rest.len());
+                let name = renames.get(&rest[..end]).map_or(&rest[..end], |new| {
+                    renamed += 1;
+                    new.as_str()
+                });
+                if names.contains(&name) {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("duplicate contig '{}' after rename", name),
+                    ));
+                }
+                names.push(name);
+                out.push_str(&format!("##contig=<ID={}{}\n", name, &rest[end..]));
+            }
+            None => {
+                out.push_str(line);
+                out.push('\n');
+            }
+        }
+    }
+    *hv = parse_header(&out)?;
+    Ok(renamed)
+}
+
+/// Parse VCF header text (the `##` lines and the `#CHROM` line).
+pub(crate) fn parse_header(text: &str) -> std::io::Result<HeaderView> {
+    let mut text = std::ffi::CString::new(text)?.into_bytes_with_nul();
+    let mode = std::ffi::CString::new("r").unwrap();
+    unsafe {
+        let h = rust_htslib::htslib::bcf_hdr_init(mode.as_ptr());
+        if rust_htslib::htslib::bcf_hdr_parse(h, text.as_mut_ptr() as *mut libc::c_char) != 0 {
+            rust_htslib::htslib::bcf_hdr_destroy(h);
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "error parsing header",
+            ));
+        }
+        rust_htslib::htslib::bcf_hdr_sync(h);
+        Ok(HeaderView::new(h))
+    }
+}
+
+/// The (name, length) of each contig in header order. length is None if it is not in the header.
+pub(crate) fn contigs(hv: &HeaderView) -> Vec<(String, Option<u64>)> {
+    hv.header_records()
+        .into_iter()
+        .filter_map(|rec| match rec {
+            HeaderRecord::Contig { values, .. } => Some((
+                values.get("ID")?.to_string(),
+                values.get("length").and_then(|l| l.parse().ok()),
+            )),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Append a `##contig` line. Existing contigs keep their ids.
+pub(crate) fn add_contig(
+    hv: &mut HeaderView,
+    id: &str,
+    length: Option<u64>,
+) -> std::io::Result<()> {
+    let mut line = format!("##contig=<ID={}", id);
+    if let Some(length) = length {
+        line.push_str(&format!(",length={}", length));
+    }
+    line.push('>');
+    let c_str = std::ffi::CString::new(line).expect("CString::new failed");
+    let ret = unsafe { rust_htslib::htslib::bcf_hdr_append(hv.inner, c_str.as_ptr()) };
+    if ret != 0 {
+        log::error!("Error adding contig {}: {}", id, ret);
+        return Err(std::io::Error::last_os_error());
+    }
+    _ = unsafe { rust_htslib::htslib::bcf_hdr_sync(hv.inner) };
+    Ok(())
+}
 
-    if let Some(hrec) = hrec {
-        return Ok(hrec);
+/// Add a `##FILTER` line. htslib keeps the existing line if the filter is already defined.
+pub(crate) fn add_filter(hv: &mut HeaderView, id: &str, description: &str) -> std::io::Result<()> {
+    let line = format!(
+        r#"##FILTER=<ID={},Description="{}">"#,
+        id,
+        description.replace('"', "'")
+    );
+    let c_str = std::ffi::CString::new(line)?;
+    let ret = unsafe { rust_htslib::htslib::bcf_hdr_append(hv.inner, c_str.as_ptr()) };
+    if ret != 0 {
+        log::error!("Error adding FILTER {}: {}", id, ret);
+        return Err(std::io::Error::last_os_error());
     }
-    Err(mlua::Error::ExternalError(Arc::new(std::io::Error::new(
-        std::io::ErrorKind::InvalidInput,
-        format!("key {}, hdr_type:{:?} not found in header", key, hdr_type),
-    ))))
+    _ = unsafe { rust_htslib::htslib::bcf_hdr_sync(hv.inner) };
+    Ok(())
+}
+
+/// Read only the header of `path` and run each of the (name, code) `scripts` with it
+/// available as `header` (and the file as `path`). With no scripts, the header is printed.
+/// The lua state must already have the vcfexpress types registered.
+pub fn run_header_scripts(
+    lua: &Lua,
+    path: &str,
+    scripts: &[(String, String)],
+) -> Result<(), Box<dyn std::error::Error>> {
+    use rust_htslib::bcf::Read;
+    let reader = match path {
+        "-" | "stdin" => rust_htslib::bcf::Reader::from_stdin()?,
+        _ => rust_htslib::bcf::Reader::from_path(path)?,
+    };
+    let mut hv =
+        HeaderView::new(unsafe { rust_htslib::htslib::bcf_hdr_dup(reader.header().inner) });
+    if scripts.is_empty() {
+        print!("{}", header_text(&hv)?);
+        return Ok(());
+    }
+    let globals = lua.globals();
+    globals.raw_set("path", path)?;
+    lua.scope(|scope| {
+        globals.raw_set("header", scope.create_any_userdata_ref_mut(&mut hv)?)?;
+        for (name, code) in scripts {
+            lua.load(code).set_name(name).exec()?;
+        }
+        Ok(())
+    })?;
+    Ok(())
 }
 
 pub(crate) fn register_header(lua: &Lua) -> mlua::Result<()> {
@@ -73,7 +691,7 @@ pub(crate) fn register_header(lua: &Lua) -> mlua::Result<()> {
             |_lua: &Lua, (ud, find_key): (AnyUserData, String)| {
                 // get the HREC
                 let this = ud.borrow_mut::<HeaderView>()?;
-                find_record(
+                get_record(
                     &this.header_records(),
                     &find_key,
                     rust_htslib::htslib::BCF_HL_INFO,
@@ -85,33 +703,32 @@ pub(crate) fn register_header(lua: &Lua) -> mlua::Result<()> {
             |_lua: &Lua, (ud, find_key): (AnyUserData, String)| {
                 // get the HREC
                 let this = ud.borrow_mut::<HeaderView>()?;
-                find_record(
+                get_record(
                     &this.header_records(),
                     &find_key,
                     rust_htslib::htslib::BCF_HL_FMT,
                 )
             },
         );
+        // like info_get and format_get but nil if the tag is not in the header.
+        reg.add_function("info", |_lua: &Lua, (ud, key): (AnyUserData, String)| {
+            let this = ud.borrow::<HeaderView>()?;
+            Ok(find_record(
+                &this.header_records(),
+                &key,
+                rust_htslib::htslib::BCF_HL_INFO,
+            ))
+        });
+        reg.add_function("format", |_lua: &Lua, (ud, key): (AnyUserData, String)| {
+            let this = ud.borrow::<HeaderView>()?;
+            Ok(find_record(
+                &this.header_records(),
+                &key,
+                rust_htslib::htslib::BCF_HL_FMT,
+            ))
+        });
         reg.add_meta_function(MetaMethod::ToString, |_lua, this: AnyUserData| {
-            let this = this.borrow::<HeaderView>()?;
-
-            let mut kstr = rust_htslib::htslib::kstring_t {
-                l: 0,
-                m: 0,
-                s: std::ptr::null_mut(),
-            };
-            if unsafe { rust_htslib::htslib::bcf_hdr_format(this.inner, 0, &mut kstr) } != 0 {
-                return Err(mlua::Error::ExternalError(Arc::new(
-                    std::io::Error::last_os_error(),
-                )));
-            }
-            let s = unsafe {
-                String::from_utf8_unchecked(
-                    std::slice::from_raw_parts(kstr.s as *const u8, kstr.l as usize).to_vec(),
-                )
-            };
-
-            Ok(s)
+            header_text(&*this.borrow::<HeaderView>()?)
         });
         reg.add_field_method_get("samples", |_lua, this: &HeaderView| {
             let samples = this
@@ -121,28 +738,159 @@ pub(crate) fn register_header(lua: &Lua) -> mlua::Result<()> {
                 .collect::<Vec<_>>();
             Ok(samples)
         });
+        // keep only these samples (in VCF order). in filter, records are read with only these samples.
         reg.add_field_method_set(
             "samples",
             |_lua, this: &mut HeaderView, samples: Vec<String>| {
-                let sample_bytes = samples.iter().map(|x| x.as_bytes()).collect::<Vec<_>>();
-                match Header::from_template_subset(this, &sample_bytes) {
-                    Ok(h) => {
-                        //_ = unsafe { rust_htslib::htslib::bcf_hdr_sync(h.inner) };
-                        let header_t = unsafe { rust_htslib::htslib::bcf_hdr_dup(h.inner) };
-                        *this = HeaderView::new(header_t);
-                        eprintln!(
-                            "samples from c directly: {:?}",
-                            this.samples()
-                                .iter()
-                                .map(|&x| String::from_utf8_lossy(x).to_string())
-                                .collect::<Vec<_>>()
-                        );
-                        Ok(())
-                    }
-                    Err(e) => Err(mlua::Error::ExternalError(Arc::new(e))),
+                subset_samples(this, &samples).map_err(|e| mlua::Error::ExternalError(Arc::new(e)))
+            },
+        );
+        reg.add_field_method_get("version", |_lua, this: &HeaderView| Ok(version(this)));
+        reg.add_field_method_set("version", |_lua, this: &mut HeaderView, v: String| {
+            set_version(this, &v).map_err(|e| mlua::Error::ExternalError(Arc::new(e)))
+        });
+        // ##META lines (VCF 4.3+) as a list of tables of key = value.
+        reg.add_method("meta", |lua, this: &HeaderView, ()| {
+            lua.create_sequence_from(
+                meta_records(this)
+                    .into_iter()
+                    .map(|kvs| lua.create_table_from(kvs))
+                    .collect::<mlua::Result<Vec<_>>>()?,
+            )
+        });
+        // the values are also removed from each record written.
+        reg.add_function_mut("remove_info", |_lua, (ud, id): (AnyUserData, String)| {
+            remove_tag(
+                &mut *ud.borrow_mut::<HeaderView>()?,
+                rust_htslib::htslib::BCF_HL_INFO,
+                &id,
+            )
+        });
+        reg.add_function_mut(
+            "update_info",
+            |_lua, (ud, id, tbl): (AnyUserData, String, HashMap<String, String>)| {
+                update_tag(
+                    &mut *ud.borrow_mut::<HeaderView>()?,
+                    rust_htslib::htslib::BCF_HL_INFO,
+                    &id,
+                    &tbl,
+                )
+            },
+        );
+        reg.add_function_mut(
+            "update_format",
+            |_lua, (ud, id, tbl): (AnyUserData, String, HashMap<String, String>)| {
+                update_tag(
+                    &mut *ud.borrow_mut::<HeaderView>()?,
+                    rust_htslib::htslib::BCF_HL_FMT,
+                    &id,
+                    &tbl,
+                )
+            },
+        );
+        reg.add_function_mut("remove_format", |_lua, (ud, id): (AnyUserData, String)| {
+            remove_tag(
+                &mut *ud.borrow_mut::<HeaderView>()?,
+                rust_htslib::htslib::BCF_HL_FMT,
+                &id,
+            )
+        });
+        reg.add_function_mut(
+            "rename_sample",
+            |_lua, (ud, old, new): (AnyUserData, String, String)| {
+                rename_samples(
+                    &mut *ud.borrow_mut::<HeaderView>()?,
+                    &HashMap::from([(old, new)]),
+                )
+                .map_err(|e| mlua::Error::ExternalError(Arc::new(e)))
+            },
+        );
+        // takes a table of old = new.
+        reg.add_function_mut(
+            "rename_samples",
+            |_lua, (ud, renames): (AnyUserData, HashMap<String, String>)| {
+                rename_samples(&mut *ud.borrow_mut::<HeaderView>()?, &renames)
+                    .map_err(|e| mlua::Error::ExternalError(Arc::new(e)))
+            },
+        );
+        // contigs in header order as a list of {name=, length=}.
+        reg.add_method("contigs", |lua, this: &HeaderView, ()| {
+            lua.create_sequence_from(
+                contigs(this)
+                    .into_iter()
+                    .map(|(name, length)| {
+                        let t = lua.create_table()?;
+                        t.raw_set("name", name)?;
+                        t.raw_set("length", length)?;
+                        Ok(t)
+                    })
+                    .collect::<mlua::Result<Vec<_>>>()?,
+            )
+        });
+        reg.add_function_mut(
+            "add_contig",
+            |_lua, (ud, tbl): (AnyUserData, HashMap<String, String>)| {
+                let id = handle_hash_get(&tbl, "ID", "contig")?;
+                let length = match tbl.get("length") {
+                    Some(l) => Some(l.parse::<u64>().map_err(|_| {
+                        mlua::Error::runtime(format!("invalid contig length: {}", l))
+                    })?),
+                    None => None,
+                };
+                add_contig(&mut *ud.borrow_mut::<HeaderView>()?, id, length)
+                    .map_err(|e| mlua::Error::ExternalError(Arc::new(e)))
+            },
+        );
+        reg.add_function_mut(
+            "add_meta",
+            |_lua, (ud, tbl): (AnyUserData, HashMap<String, String>)| {
+                let this = ud.borrow_mut::<HeaderView>()?;
+                let id = handle_hash_get(&tbl, "ID", "meta")?;
+                let mut keys = tbl.keys().filter(|k| *k != "ID").collect::<Vec<_>>();
+                keys.sort();
+                let mut line = format!("##META=<ID={}", id);
+                for k in keys {
+                    line.push_str(&format!(",{}={}", k, tbl[k]));
+                }
+                line.push('>');
+                let c_str = std::ffi::CString::new(line).expect("CString::new failed");
+                if unsafe { rust_htslib::htslib::bcf_hdr_append(this.inner, c_str.as_ptr()) } != 0 {
+                    return Err(mlua::Error::ExternalError(Arc::new(
+                        std::io::Error::last_os_error(),
+                    )));
                 }
+                _ = unsafe { rust_htslib::htslib::bcf_hdr_sync(this.inner) };
+                Ok(())
             },
         );
+        reg.add_function_mut("add_line", |_lua, (ud, line): (AnyUserData, String)| {
+            let this = ud.borrow_mut::<HeaderView>()?;
+            let line = line.trim_end_matches(['\r', '\n']);
+            if !line.starts_with("##") || line.contains('\n') {
+                return Err(mlua::Error::runtime(format!(
+                    "add_line expects a single line starting with ##, got '{}'",
+                    line
+                )));
+            }
+            let c_str = std::ffi::CString::new(line)
+                .map_err(|e| mlua::Error::ExternalError(Arc::new(e)))?;
+            if unsafe { rust_htslib::htslib::bcf_hdr_append(this.inner, c_str.as_ptr()) } != 0 {
+                return Err(mlua::Error::runtime(format!(
+                    "error adding header line '{}'",
+                    line
+                )));
+            }
+            _ = unsafe { rust_htslib::htslib::bcf_hdr_sync(this.inner) };
+            Ok(())
+        });
+        // every ## line of the header as text, in order.
+        reg.add_method("lines", |_lua, this: &HeaderView, ()| {
+            Ok(header_text(this)?
+                .lines()
+                .filter(|l| l.starts_with("##"))
+                .map(|l| l.to_string())
+                .collect::<Vec<_>>())
+        });
         reg.add_function_mut(
             "add_info",
             |_lua, (ud, tbl): (AnyUserData, HashMap<String, String>)| {
@@ -198,6 +946,28 @@ pub(crate) fn register_header(lua: &Lua) -> mlua::Result<()> {
                 Ok(())
             },
         );
+        reg.add_function_mut(
+            "add_filter",
+            |_lua, (ud, tbl): (AnyUserData, HashMap<String, String>)| {
+                let this = ud.borrow_mut::<HeaderView>()?;
+                let c_str = std::ffi::CString::new(format!(
+                    r#"##FILTER=<ID={},Description="{}">"#,
+                    handle_hash_get(&tbl, "ID", "filter")?,
+                    handle_hash_get(&tbl, "Description", "filter")?,
+                ))
+                .expect("CString::new failed");
+                let ret =
+                    unsafe { rust_htslib::htslib::bcf_hdr_append(this.inner, c_str.as_ptr()) };
+                if ret != 0 {
+                    log::error!("Error adding FILTER for {:?}: {}", tbl, ret);
+                    return Err(mlua::Error::ExternalError(Arc::new(
+                        std::io::Error::last_os_error(),
+                    )));
+                }
+                _ = unsafe { rust_htslib::htslib::bcf_hdr_sync(this.inner) };
+                Ok(())
+            },
+        );
     })
 }
 
@@ -222,6 +992,28 @@ mod tests {
         (lua, header, header_view)
     }
 
+    #[test]
+    fn test_run_header_scripts() {
+        let (lua, header, _) = setup();
+        let vcf = rust_htslib::bcf::Writer::from_path(
+            "_test.header.vcf",
+            &header,
+            true,
+            rust_htslib::bcf::Format::Vcf,
+        )
+        .unwrap();
+        drop(vcf);
+        let scripts = [(
+            "script".to_string(),
+            "n = #header.samples; p = path".to_string(),
+        )];
+        run_header_scripts(&lua, "_test.header.vcf", &scripts).unwrap();
+        let (n, p): (i32, String) = lua.load("return n, p").eval().unwrap();
+        assert_eq!(n, 2);
+        assert_eq!(p, "_test.header.vcf");
+        _ = std::fs::remove_file("_test.header.vcf");
+    }
+
     #[test]
     fn test_lua_header_samples() {
         let (lua, _header, mut header_view) = setup();
@@ -230,7 +1022,8 @@ mod tests {
         let exp = lua
             .load(
                 r#"
-            -- TODO: this is broken
+            header.samples = {"Sample2", "Sample1"};
+            assert(table.concat(header.samples, ",") == "Sample1,Sample2")
             header.samples = {"Sample1"};
             return table.concat(header.samples, ",")
             "#,
@@ -285,6 +1078,369 @@ mod tests {
         .expect("error in test_add_info")
     }
 
+    #[test]
+    fn test_info_format_lookup() {
+        let (lua, _header, mut header_view) = setup();
+        lua.scope(|scope| {
+            lua.globals().set(
+                "header",
+                scope.create_any_userdata_ref_mut(&mut header_view)?,
+            )?;
+            lua.load(
+                r#"
+            header:add_info({ID="CSQ", Number=".", Type="String", Description='"Consequence. Format: Allele|Gene"'})
+            header:add_format({ID="AD", Number="R", Type="Integer", Description="Allele depth"})
+            "#,
+            )
+            .exec()?;
+            let (number, typ, fields, idx): (String, String, String, Option<String>) = lua
+                .load(
+                    r#"
+                local csq = header:info("CSQ")
+                return csq.Number, csq.Type, csq.Description:match("Format: (.*)"), csq.IDX
+                "#,
+                )
+                .eval()?;
+            assert_eq!((number.as_str(), typ.as_str()), (".", "String"));
+            assert_eq!(fields, "Allele|Gene");
+            assert!(idx.is_none());
+            let (ad, missing): (String, bool) = lua
+                .load(r#"return header:format_get("AD").Number, header:format("XX") == nil"#)
+                .eval()?;
+            assert_eq!(ad, "R");
+            assert!(missing);
+            assert!(lua.load(r#"header:info("AD")"#).exec().is_ok());
+            let err = lua
+                .load(r#"header:info_get("AD")"#)
+                .exec()
+                .unwrap_err()
+                .to_string();
+            assert!(err.contains("INFO AD not found in header"));
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_version_and_meta() {
+        let (lua, _header, mut header_view) = setup();
+        assert_eq!(version(&header_view), "4.2");
+        assert!(set_version(&header_view, "4.9").is_err());
+        lua.scope(|scope| {
+            lua.globals().set(
+                "header",
+                scope.create_any_userdata_ref_mut(&mut header_view)?,
+            )?;
+            let (v, n, t): (String, usize, String) = lua
+                .load(
+                    r#"
+            header.version = "VCFv4.4"
+            header:add_meta({ID="Assay", Type="String", Number=".", Values="[WholeGenome, Exome]"})
+            local m = header:meta()
+            return header.version, #m, m[1].Type
+            "#,
+                )
+                .eval()?;
+            assert_eq!((v.as_str(), n, t.as_str()), ("4.4", 1, "String"));
+            Ok(())
+        })
+        .unwrap();
+        let text = header_text(&header_view).unwrap();
+        assert!(text.starts_with("##fileformat=VCFv4.4\n"));
+        assert!(text.contains("##META=<ID=Assay,Number=.,Type=String,Values=[WholeGenome, Exome]>"));
+        assert!(version_problems(&header_view, "4.4").is_empty());
+        assert_eq!(version_problems(&header_view, "4.2").len(), 1);
+    }
+
+    #[test]
+    fn test_add_line_and_lines() {
+        let (lua, _header, mut header_view) = setup();
+        lua.scope(|scope| {
+            lua.globals().set(
+                "header",
+                scope.create_any_userdata_ref_mut(&mut header_view)?,
+            )?;
+            let (n, last): (usize, String) = lua
+                .load(
+                    r###"
+            header:add_line("##reference=GRCh38")
+            header:add_line('##source=<ID=vcfexpress,Version="1">\n')
+            local lines = header:lines()
+            return #lines, lines[#lines]
+            "###,
+                )
+                .eval()?;
+            assert_eq!(n, 5);
+            assert_eq!(last, r###"##source=<ID=vcfexpress,Version="1">"###);
+            assert!(lua
+                .load(r#"header:add_line("reference=GRCh38")"#)
+                .exec()
+                .is_err());
+            assert!(lua
+                .load(r###"header:add_line("##a=1\n##b=2")"###)
+                .exec()
+                .is_err());
+            Ok(())
+        })
+        .unwrap();
+        let text = header_text(&header_view).unwrap();
+        assert!(text.contains("##reference=GRCh38\n"));
+    }
+
+    #[test]
+    fn test_contigs() {
+        let (lua, _header, mut header_view) = setup();
+        lua.scope(|scope| {
+            lua.globals().set(
+                "header",
+                scope.create_any_userdata_ref_mut(&mut header_view)?,
+            )?;
+            let (n, name, length): (usize, String, u64) = lua
+                .load(
+                    r#"
+                header:add_contig({ID="chr2", length=2000})
+                header:add_contig({ID="chrM"})
+                local c = header:contigs()
+                assert(c[3].name == "chrM" and c[3].length == nil)
+                return #c, c[2].name, c[2].length
+                "#,
+                )
+                .eval()?;
+            assert_eq!((n, name.as_str(), length), (3, "chr2", 2000));
+            assert!(lua
+                .load(r#"header:add_contig({length=10})"#)
+                .exec()
+                .is_err());
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(header_view.name2rid(b"chr2").unwrap(), 1);
+    }
+
+    #[test]
+    fn test_remove_info_format() {
+        let (lua, _header, mut header_view) = setup();
+        let mut header = Header::from_template(&header_view);
+        header.push_record(br#"##INFO=<ID=DP,Number=1,Type=Integer,Description="Depth">"#);
+        header.push_record(br#"##INFO=<ID=AF,Number=A,Type=Float,Description="AF">"#);
+        header.push_record(br#"##FORMAT=<ID=GT,Number=1,Type=String,Description="GT">"#);
+        header.push_record(br#"##FORMAT=<ID=AD,Number=R,Type=Integer,Description="AD">"#);
+        let input = HeaderView::new(unsafe { rust_htslib::htslib::bcf_hdr_dup(header.inner) });
+        header_view = HeaderView::new(unsafe { rust_htslib::htslib::bcf_hdr_dup(header.inner) });
+        lua.scope(|scope| {
+            lua.globals().set(
+                "header",
+                scope.create_any_userdata_ref_mut(&mut header_view)?,
+            )?;
+            lua.load(r#"header:remove_info("DP"); header:remove_format("AD")"#)
+                .exec()?;
+            assert!(lua.load(r#"header:remove_info("XX")"#).exec().is_err());
+            Ok(())
+        })
+        .unwrap();
+        let removed = removed_tags(&input, &header_view);
+        assert_eq!(
+            removed,
+            vec![
+                (rust_htslib::htslib::BCF_HL_INFO, "DP".to_string()),
+                (rust_htslib::htslib::BCF_HL_FMT, "AD".to_string())
+            ]
+        );
+
+        let vcf = rust_htslib::bcf::Writer::from_path(
+            "_test.remove.vcf",
+            &header,
+            true,
+            rust_htslib::bcf::Format::Vcf,
+        )
+        .unwrap();
+        let mut record = vcf.empty_record();
+        record.set_rid(Some(0));
+        record.set_alleles(&[b"A", b"T"]).unwrap();
+        record.push_info_integer(b"DP", &[10]).unwrap();
+        record.push_info_float(b"AF", &[0.5]).unwrap();
+        record.push_format_integer(b"AD", &[1, 2, 3, 4]).unwrap();
+        strip_tags(&mut record, &removed);
+        assert!(record.info(b"DP").integer().unwrap().is_none());
+        assert!(record.info(b"AF").float().unwrap().is_some());
+        assert!(record.format(b"AD").integer().is_err());
+        drop(vcf);
+        _ = std::fs::remove_file("_test.remove.vcf");
+    }
+
+    #[test]
+    fn test_update_info_format() {
+        let (lua, _header, mut header_view) = setup();
+        let mut header = Header::from_template(&header_view);
+        header.push_record(br#"##INFO=<ID=AF,Number=1,Type=Float,Description="AF",Source="gnomAD, v4",Version="4">"#);
+        header.push_record(br#"##FORMAT=<ID=AD,Number=.,Type=Integer,Description="AD">"#);
+        header_view = HeaderView::new(unsafe { rust_htslib::htslib::bcf_hdr_dup(header.inner) });
+        let af_id = header_view.name_to_id(b"AF").unwrap();
+        lua.scope(|scope| {
+            lua.globals().set(
+                "header",
+                scope.create_any_userdata_ref_mut(&mut header_view)?,
+            )?;
+            lua.load(
+                r#"
+            header:update_info("AF", {Number="A", Description='Allele "alt" frequency'})
+            header:update_format("AD", {Number="R", Type="Integer"})
+            "#,
+            )
+            .exec()?;
+            let (n, d): (String, String) = lua
+                .load(r#"local af = header:info("AF"); return af.Number, af.Description"#)
+                .eval()?;
+            assert_eq!(
+                (n.as_str(), d.as_str()),
+                ("A", r#"Allele \"alt\" frequency"#)
+            );
+            assert!(lua
+                .load(r#"header:update_info("AF", {Type="Integer"})"#)
+                .exec()
+                .is_err());
+            assert!(lua
+                .load(r#"header:update_info("XX", {Number="1"})"#)
+                .exec()
+                .is_err());
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(header_view.name_to_id(b"AF").unwrap(), af_id);
+        let text = header_text(&header_view).unwrap();
+        assert_eq!(text.matches("##INFO=<ID=AF,").count(), 1);
+        assert!(text.contains(
+            r#"##INFO=<ID=AF,Number=A,Type=Float,Description="Allele \"alt\" frequency",Source="gnomAD, v4",Version="4">"#
+        ));
+        assert!(text.contains(r#"##FORMAT=<ID=AD,Number=R,Type=Integer,Description="AD">"#));
+        let (_, length) = header_view.info_type(b"AF").unwrap();
+        assert!(matches!(
+            length,
+            rust_htslib::bcf::header::TagLength::AltAlleles
+        ));
+    }
+
+    #[test]
+    fn test_rename_samples() {
+        let (lua, _header, mut header_view) = setup();
+        lua.scope(|scope| {
+            lua.globals().set(
+                "header",
+                scope.create_any_userdata_ref_mut(&mut header_view)?,
+            )?;
+            let samples: String = lua
+                .load(
+                    r#"
+                header:rename_sample("Sample1", "S1")
+                header:add_info({ID="X", Number="1", Type="Integer", Description="x"})
+                header:rename_samples({S1="A", Sample2="B"})
+                return table.concat(header.samples, ",")
+                "#,
+                )
+                .eval()?;
+            assert_eq!(samples, "A,B");
+            assert!(lua
+                .load(r#"header:rename_sample("Sample1", "C")"#)
+                .exec()
+                .is_err());
+            assert!(lua
+                .load(r#"header:rename_sample("A", "B")"#)
+                .exec()
+                .is_err());
+            Ok(())
+        })
+        .unwrap();
+        assert!(header_view.info_type(b"X").is_ok());
+        assert_eq!(header_view.sample_id(b"B"), Some(1));
+        assert_eq!(header_view.name2rid(b"chr1").unwrap(), 0);
+    }
+
+    #[test]
+    fn test_rename_contigs() {
+        let (_lua, _header, mut header_view) = setup();
+        add_contig(&mut header_view, "chr2", None).unwrap();
+        let renames = HashMap::from([
+            ("chr1".to_string(), "1".to_string()),
+            ("chrX".to_string(), "X".to_string()),
+        ]);
+        assert_eq!(rename_contigs(&mut header_view, &renames).unwrap(), 1);
+        assert_eq!(header_view.name2rid(b"1").unwrap(), 0);
+        assert!(header_view.name2rid(b"chr1").is_err());
+        assert_eq!(
+            contigs(&header_view),
+            [("1".to_string(), Some(10000)), ("chr2".to_string(), None)]
+        );
+
+        let renames = HashMap::from([("1".to_string(), "chr2".to_string())]);
+        let err = rename_contigs(&mut header_view, &renames).unwrap_err();
+        assert!(
+            err.to_string().contains("duplicate contig 'chr2'"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_add_filter() {
+        let (lua, _header, mut header_view) = setup();
+        lua.scope(|scope| {
+            lua.globals().set(
+                "header",
+                scope.create_any_userdata_ref_mut(&mut header_view)?,
+            )?;
+            lua.load(r#"header:add_filter({ID="LowDP", Description="Depth < 10"})"#)
+                .exec()?;
+            assert!(lua
+                .load(r#"header:add_filter({ID="NoDescription"})"#)
+                .exec()
+                .is_err());
+            Ok(())
+        })
+        .unwrap();
+        let text = header_text(&header_view).unwrap();
+        assert!(text.contains("##FILTER=<ID=LowDP,Description=\"Depth < 10\">\n"));
+        assert!(header_view.name_to_id(b"LowDP").is_ok());
+    }
+
+    #[test]
+    fn test_version_checks() {
+        assert_eq!(parse_version("VCFv4.10"), Some((4, 10)));
+        assert!(parse_version("4.10").unwrap() > parse_version("4.3").unwrap());
+        assert_eq!(parse_version("4"), None);
+
+        let mut header = Header::new();
+        header.push_record(br#"##contig=<ID=chr1,length=10000>"#);
+        header.push_record(br#"##INFO=<ID=AF,Number=1,Type=Float,Description="AF">"#);
+        header.push_record(br#"##FORMAT=<ID=GT,Number=1,Type=String,Description="GT">"#);
+        header.push_record(br#"##FORMAT=<ID=AD,Number=R,Type=Integer,Description="AD">"#);
+        header.push_sample(b"Sample1");
+        let vcf = rust_htslib::bcf::Writer::from_path(
+            "_test.version.vcf",
+            &header,
+            true,
+            rust_htslib::bcf::Format::Vcf,
+        )
+        .unwrap();
+        let problems = version_problems(vcf.header(), "4.4");
+        assert_eq!(
+            problems,
+            vec!["INFO AF is reserved in VCF 4.4 with Number=A,Type=Float but has Number=1,Type=Float"]
+        );
+        assert!(version_problems(vcf.header(), "4.2").is_empty());
+
+        let mut record = vcf.empty_record();
+        record.set_rid(Some(0));
+        record.set_pos(9);
+        record.set_alleles(&[b"A", b"G", b"<*>"]).unwrap();
+        record.push_format_integer(b"AD", &[3, 4]).unwrap();
+        assert_eq!(
+            non_ref_ad_problem(&record).unwrap(),
+            "chr1:10 has a <*> allele but AD of Sample1 has 2 values for 3 alleles"
+        );
+        record.push_format_integer(b"AD", &[3, 4, 0]).unwrap();
+        assert!(non_ref_ad_problem(&record).is_none());
+    }
+
     #[test]
     fn test_add_format() {
         let (lua, _header, mut header_view) = setup();
@@ -0,0 +1,213 @@
+//! Check that a set of VCF/BCF files have compatible headers before a merge or concat.
+//! Each file is compared to the first: samples must match (in order), and INFO/FORMAT/FILTER
+//! definitions and contigs that are present in both must agree.
+use rust_htslib::bcf::{self, header::HeaderView, HeaderRecord, Read};
+use std::collections::BTreeMap;
+
+/// A single difference between a file and the reference (first) file.
+#[derive(Debug, PartialEq)]
+pub struct Difference {
+    pub path: String,
+    /// e.g. "INFO", "FORMAT", "FILTER", "contig" or "samples".
+    pub kind: String,
+    pub id: String,
+    pub detail: String,
+}
+
+impl Difference {
+    /// True if the files can not be combined: the samples differ or a definition in both files
+    /// disagrees. Definitions in only one file can be added to a merged header.
+    pub fn is_conflict(&self) -> bool {
+        self.kind == "samples" || self.detail.contains(" but ")
+    }
+}
+
+impl std::fmt::Display for Difference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}\t{}\t{}\t{}",
+            self.path, self.kind, self.id, self.detail
+        )
+    }
+}
+
+/// The parts of a header that matter for compatibility.
+#[derive(Default)]
+struct Summary {
+    samples: Vec<String>,
+    /// (kind, ID) -> description of Number, Type (or length for contigs).
+    defs: BTreeMap<(String, String), String>,
+}
+
+impl Summary {
+    fn new(hv: &HeaderView) -> Self {
+        let mut s = Summary {
+            samples: hv
+                .samples()
+                .iter()
+                .map(|x| String::from_utf8_lossy(x).to_string())
+                .collect(),
+            ..Default::default()
+        };
+        for rec in hv.header_records() {
+            let (kind, values, fields): (_, _, &[&str]) = match &rec {
+                HeaderRecord::Info { values, .. } => ("INFO", values, &["Number", "Type"]),
+                HeaderRecord::Format { values, .. } => ("FORMAT", values, &["Number", "Type"]),
+                HeaderRecord::Filter { values, .. } => ("FILTER", values, &[]),
+                HeaderRecord::Contig { values, .. } => ("contig", values, &["length"]),
+                _ => continue,
+            };
+            let Some(id) = values.get("ID") else { continue };
+            let desc = fields
+                .iter()
+                .map(|f| format!("{}={}", f, values.get(*f).map_or(".", |v| v.as_str())))
+                .collect::<Vec<_>>()
+                .join(",");
+            s.defs.insert((kind.to_string(), id.clone()), desc);
+        }
+        s
+    }
+
+    fn compare(&self, other: &Summary, path: &str, reference: &str) -> Vec<Difference> {
+        let diff = |kind: &str, id: &str, detail: String| Difference {
+            path: path.to_string(),
+            kind: kind.to_string(),
+            id: id.to_string(),
+            detail,
+        };
+        let mut diffs = vec![];
+        if self.samples != other.samples {
+            for s in other.samples.iter().filter(|s| !self.samples.contains(s)) {
+                diffs.push(diff("samples", s, format!("not in {}", reference)));
+            }
+            for s in self.samples.iter().filter(|s| !other.samples.contains(s)) {
+                diffs.push(diff("samples", s, format!("only in {}", reference)));
+            }
+            if diffs.is_empty() {
+                diffs.push(diff(
+                    "samples",
+                    ".",
+                    format!("sample order differs from {}", reference),
+                ));
+            }
+        }
+        for ((kind, id), desc) in other.defs.iter() {
+            match self.defs.get(&(kind.clone(), id.clone())) {
+                None => diffs.push(diff(kind, id, format!("not in {}", reference))),
+                Some(d) if d != desc => diffs.push(diff(
+                    kind,
+                    id,
+                    format!("{} but {} in {}", desc, d, reference),
+                )),
+                _ => (),
+            }
+        }
+        for (kind, id) in self.defs.keys() {
+            if !other.defs.contains_key(&(kind.clone(), id.clone())) {
+                diffs.push(diff(kind, id, format!("only in {}", reference)));
+            }
+        }
+        diffs
+    }
+}
+
+/// Expand any glob patterns in `paths`. Paths without a match are kept as-is so that a
+/// missing file is reported when it is opened.
+pub fn expand_globs(paths: &[String]) -> Result<Vec<String>, glob::PatternError> {
+    let mut expanded = vec![];
+    for p in paths {
+        let mut matches = glob::glob(p)?
+            .filter_map(|m| m.ok())
+            .map(|m| m.to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+        if matches.is_empty() {
+            expanded.push(p.clone());
+        } else {
+            matches.sort();
+            expanded.extend(matches);
+        }
+    }
+    Ok(expanded)
+}
+
+/// Compare the headers of all `paths` to the first. Only the headers are read.
+pub fn check_headers(paths: &[String]) -> Result<Vec<Difference>, Box<dyn std::error::Error>> {
+    let mut reference: Option<(&str, Summary)> = None;
+    let mut diffs = vec![];
+    for path in paths {
+        let reader = bcf::Reader::from_path(path)?;
+        let summary = Summary::new(reader.header());
+        match &reference {
+            None => reference = Some((path, summary)),
+            Some((rpath, rsummary)) => diffs.extend(rsummary.compare(&summary, path, rpath)),
+        }
+    }
+    Ok(diffs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_vcf(path: &str, lines: &[&str], samples: &[&str]) {
+        let mut header = bcf::Header::new();
+        for l in lines {
+            header.push_record(l.as_bytes());
+        }
+        for s in samples {
+            header.push_sample(s.as_bytes());
+        }
+        bcf::Writer::from_path(path, &header, true, bcf::Format::Vcf).unwrap();
+    }
+
+    #[test]
+    fn test_check_headers() {
+        let contig = r#"##contig=<ID=chr1,length=10000>"#;
+        let dp = r#"##INFO=<ID=DP,Number=1,Type=Integer,Description="depth">"#;
+        write_vcf("_test.hc1.vcf", &[contig, dp], &["A", "B"]);
+        write_vcf("_test.hc2.vcf", &[contig, dp], &["A", "B"]);
+        write_vcf(
+            "_test.hc3.vcf",
+            &[
+                r#"##contig=<ID=chr1,length=20000>"#,
+                r#"##INFO=<ID=DP,Number=1,Type=Float,Description="depth">"#,
+            ],
+            &["B", "A"],
+        );
+        write_vcf("_test.hc4.vcf", &[contig], &["A", "C"]);
+
+        let paths = expand_globs(&["_test.hc[1-4].vcf".to_string()]).unwrap();
+        assert_eq!(paths.len(), 4);
+        let diffs = check_headers(&paths[..2]).unwrap();
+        assert!(diffs.is_empty());
+
+        let diffs = check_headers(&paths).unwrap();
+        let summary = diffs
+            .iter()
+            .map(|d| format!("{}:{}:{}", d.path, d.kind, d.id))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                "_test.hc3.vcf:samples:.",
+                "_test.hc3.vcf:INFO:DP",
+                "_test.hc3.vcf:contig:chr1",
+                "_test.hc4.vcf:samples:C",
+                "_test.hc4.vcf:samples:B",
+                "_test.hc4.vcf:INFO:DP",
+            ]
+        );
+        assert_eq!(
+            diffs.iter().map(|d| d.is_conflict()).collect::<Vec<_>>(),
+            [true, true, true, true, true, false]
+        );
+        assert_eq!(
+            diffs[1].detail,
+            "Number=1,Type=Float but Number=1,Type=Integer in _test.hc1.vcf"
+        );
+        for p in paths {
+            _ = std::fs::remove_file(p);
+        }
+    }
+}
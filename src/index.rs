@@ -0,0 +1,132 @@
+//! Index a bgzipped VCF or a BCF (`vcfexpress index`) so that filtered output can be queried by
+//! region without a separate `tabix` or `bcftools index`. A .vcf.gz gets a tabix (.tbi) index
+//! unless a CSI is requested or a contig is too long for tabix; a BCF always gets a CSI.
+use rust_htslib::bcf::{self, index::Type, Read};
+use std::io::Read as _;
+
+/// Tabix can not index positions beyond 2^29 - 1.
+const TBI_MAX_LENGTH: u64 = (1 << 29) - 1;
+
+/// True if `path` starts with a BGZF block header (gzip with the "BC" extra field).
+fn is_bgzf(path: &str) -> std::io::Result<bool> {
+    let mut buf = [0u8; 14];
+    let mut f = std::fs::File::open(path)?;
+    let mut n = 0;
+    while n < buf.len() {
+        match f.read(&mut buf[n..])? {
+            0 => break,
+            m => n += m,
+        }
+    }
+    Ok(n == buf.len() && buf[..4] == [0x1f, 0x8b, 8, 4] && buf[12..14] == *b"BC")
+}
+
+/// Write an index for `path` and return its path. `csi` gives a CSI index with `min_shift`
+/// (bins of 2^min_shift bases) for a .vcf.gz; an existing index is only replaced with `force`.
+pub fn index(
+    path: &str,
+    csi: bool,
+    min_shift: u32,
+    threads: u32,
+    force: bool,
+) -> Result<String, Box<dyn std::error::Error>> {
+    if !is_bgzf(path).map_err(|e| format!("error opening {}: {}", path, e))? {
+        return Err(format!(
+            "{} is not bgzip-compressed; write it with a .vcf.gz or .bcf.gz extension (or use bgzip) to index it",
+            path
+        )
+        .into());
+    }
+    let is_bcf = crate::vcfexpress::get_vcf_format(path) == bcf::Format::Bcf;
+    let mut csi = csi || is_bcf;
+    if !csi {
+        let reader =
+            bcf::Reader::from_path(path).map_err(|e| format!("error opening {}: {}", path, e))?;
+        if let Some((name, length)) = crate::header::contigs(reader.header())
+            .into_iter()
+            .find(|(_, length)| length.is_some_and(|l| l > TBI_MAX_LENGTH))
+        {
+            log::info!(
+                "{} (length {}) is too long for a tabix index; writing a CSI index",
+                name,
+                length.unwrap_or_default()
+            );
+            csi = true;
+        }
+    }
+    let index_path = format!("{}.{}", path, if csi { "csi" } else { "tbi" });
+    if !force && std::path::Path::new(&index_path).exists() {
+        return Err(format!("{} exists; use --force to replace it", index_path).into());
+    }
+    let index_type = if csi { Type::Csi(min_shift) } else { Type::Tbx };
+    bcf::index::build(path, Some(index_path.as_str()), threads, index_type)
+        .map_err(|e| format!("error indexing {}: {}", path, e.msg))?;
+    Ok(index_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_vcf(path: &str, format: bcf::Format, uncompressed: bool, length: u64) {
+        let mut header = bcf::Header::new();
+        header.push_record(format!("##contig=<ID=chr1,length={}>", length).as_bytes());
+        let mut vcf = bcf::Writer::from_path(path, &header, uncompressed, format).unwrap();
+        for pos in [9, 19] {
+            let mut record = vcf.empty_record();
+            record.set_rid(Some(0));
+            record.set_pos(pos);
+            record.set_alleles(&[b"A", b"T"]).unwrap();
+            vcf.write(&record).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_index() {
+        write_vcf("_test.index.vcf.gz", bcf::Format::Vcf, false, 10000);
+        write_vcf("_test.index.bcf", bcf::Format::Bcf, false, 10000);
+        write_vcf("_test.index.vcf", bcf::Format::Vcf, true, 10000);
+        write_vcf("_test.index.long.vcf.gz", bcf::Format::Vcf, false, 1 << 30);
+
+        assert_eq!(
+            index("_test.index.vcf.gz", false, 14, 1, false).unwrap(),
+            "_test.index.vcf.gz.tbi"
+        );
+        assert!(index("_test.index.vcf.gz", false, 14, 1, false).is_err());
+        assert!(index("_test.index.vcf.gz", false, 14, 1, true).is_ok());
+        assert_eq!(
+            index("_test.index.vcf.gz", true, 14, 1, false).unwrap(),
+            "_test.index.vcf.gz.csi"
+        );
+        assert_eq!(
+            index("_test.index.bcf", false, 14, 1, false).unwrap(),
+            "_test.index.bcf.csi"
+        );
+        assert_eq!(
+            index("_test.index.long.vcf.gz", false, 14, 1, false).unwrap(),
+            "_test.index.long.vcf.gz.csi"
+        );
+        let err = index("_test.index.vcf", false, 14, 1, false).unwrap_err();
+        assert!(err.to_string().contains("not bgzip-compressed"));
+        let err = index("_test.index.missing.vcf.gz", false, 14, 1, false).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("error opening _test.index.missing.vcf.gz: "));
+
+        let mut reader = bcf::IndexedReader::from_path("_test.index.bcf").unwrap();
+        reader.fetch(0, 15, None).unwrap();
+        assert_eq!(reader.records().count(), 1);
+        for f in [
+            "_test.index.vcf.gz",
+            "_test.index.vcf.gz.tbi",
+            "_test.index.vcf.gz.csi",
+            "_test.index.bcf",
+            "_test.index.bcf.csi",
+            "_test.index.vcf",
+            "_test.index.long.vcf.gz",
+            "_test.index.long.vcf.gz.csi",
+        ] {
+            _ = std::fs::remove_file(f);
+        }
+    }
+}
@@ -0,0 +1,226 @@
+//! Keep or drop records by their presence in other indexed callsets before they are evaluated:
+//! `--isec other.vcf` keeps records in every (or with `--isec-any`, any) of the files and
+//! `--exclude-vcf other.vcf` drops records in any of the files. A record is in a callset when one
+//! of its ALTs is at the same position with the same REF and ALT after
+//! [`crate::annotate::normalize`].
+use rust_htslib::bcf::{self, Read};
+use std::io;
+
+use crate::annotate::normalize;
+
+/// An indexed VCF/BCF to look up records in.
+pub struct Callset {
+    reader: bcf::IndexedReader,
+    record: bcf::Record,
+}
+
+impl Callset {
+    pub fn new(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        if !crate::regions::has_index(path) {
+            return Err(format!(
+                "no index (.csi or .tbi) found for {}; create one with `bcftools index`",
+                path
+            )
+            .into());
+        }
+        let mut reader = bcf::IndexedReader::from_path(path)?;
+        _ = reader.set_threads(2);
+        let record = reader.empty_record();
+        Ok(Callset { reader, record })
+    }
+
+    /// True if an ALT of `record` is in the callset.
+    pub fn contains(&mut self, record: &bcf::Record) -> io::Result<bool> {
+        let Some(rid) = record.rid() else {
+            return Ok(false);
+        };
+        let chrom = record.header().rid2name(rid).map_err(io::Error::other)?;
+        // a contig that is not in the callset.
+        let Ok(rid) = self.reader.header().name2rid(chrom) else {
+            return Ok(false);
+        };
+        let pos = record.pos();
+        let alleles = record.alleles();
+        let wanted = alleles[1..]
+            .iter()
+            .map(|alt| normalize(pos, alleles[0], alt))
+            .collect::<Vec<_>>();
+        let end = pos as u64 + alleles[0].len().max(1) as u64 - 1;
+        if self.reader.fetch(rid, pos as u64, Some(end)).is_err() {
+            return Ok(false);
+        }
+        while let Some(r) = self.reader.read(&mut self.record) {
+            r.map_err(io::Error::other)?;
+            let other = self.record.alleles();
+            if other[1..]
+                .iter()
+                .any(|alt| wanted.contains(&normalize(self.record.pos(), other[0], alt)))
+            {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+/// The `--isec` and `--exclude-vcf` callsets of a run.
+#[derive(Default)]
+pub struct Callsets {
+    isec: Vec<Callset>,
+    // keep records in any rather than every --isec callset.
+    any: bool,
+    exclude: Vec<Callset>,
+    dropped: usize,
+}
+
+impl Callsets {
+    pub fn new(
+        isec: &[String],
+        any: bool,
+        exclude: &[String],
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Callsets {
+            isec: isec
+                .iter()
+                .map(|p| Callset::new(p))
+                .collect::<Result<_, _>>()?,
+            any,
+            exclude: exclude
+                .iter()
+                .map(|p| Callset::new(p))
+                .collect::<Result<_, _>>()?,
+            dropped: 0,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.isec.is_empty() && self.exclude.is_empty()
+    }
+
+    /// False if `record` is not in the --isec callsets or is in an --exclude-vcf callset.
+    pub fn keep(&mut self, record: &bcf::Record) -> io::Result<bool> {
+        let mut keep = true;
+        if !self.isec.is_empty() {
+            let mut found = 0;
+            for c in self.isec.iter_mut() {
+                if c.contains(record)? {
+                    found += 1;
+                    if self.any {
+                        break;
+                    }
+                } else if !self.any {
+                    break;
+                }
+            }
+            keep = if self.any {
+                found > 0
+            } else {
+                found == self.isec.len()
+            };
+        }
+        if keep {
+            for c in self.exclude.iter_mut() {
+                if c.contains(record)? {
+                    keep = false;
+                    break;
+                }
+            }
+        }
+        if !keep {
+            self.dropped += 1;
+        }
+        Ok(keep)
+    }
+
+    /// The number of records dropped so far.
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_callset(path: &str, header: &bcf::Header, records: &[(i64, Vec<&str>)]) {
+        let mut vcf = bcf::Writer::from_path(path, header, false, bcf::Format::Bcf).unwrap();
+        for (pos, alleles) in records {
+            let mut record = vcf.empty_record();
+            record.set_rid(Some(0));
+            record.set_pos(*pos);
+            let alleles = alleles.iter().map(|a| a.as_bytes()).collect::<Vec<_>>();
+            record.set_alleles(&alleles).unwrap();
+            vcf.write(&record).unwrap();
+        }
+        drop(vcf);
+        bcf::index::build(path, None, 1, bcf::index::Type::Csi(14)).unwrap();
+    }
+
+    #[test]
+    fn test_callsets() {
+        let mut header = bcf::Header::new();
+        header.push_record(br#"##contig=<ID=chr1,length=10000>"#);
+        header.push_record(br#"##contig=<ID=chr2,length=10000>"#);
+        write_callset(
+            "_test.isec.a.bcf",
+            &header,
+            &[(9, vec!["A", "T"]), (19, vec!["CAT", "CT"])],
+        );
+        write_callset(
+            "_test.isec.b.bcf",
+            &header,
+            &[(9, vec!["A", "G"]), (29, vec!["G", "C"])],
+        );
+        let vcf =
+            bcf::Writer::from_path("_test.isec.vcf", &header, true, bcf::Format::Vcf).unwrap();
+        let records = [
+            (0, 9, vec!["A", "C", "T"]),
+            (0, 19, vec!["CA", "C"]),
+            (0, 29, vec!["G", "C"]),
+            (1, 9, vec!["A", "T"]),
+        ]
+        .iter()
+        .map(|(rid, pos, alleles)| {
+            let mut record = vcf.empty_record();
+            record.set_rid(Some(*rid));
+            record.set_pos(*pos);
+            let alleles = alleles.iter().map(|a| a.as_bytes()).collect::<Vec<_>>();
+            record.set_alleles(&alleles).unwrap();
+            record
+        })
+        .collect::<Vec<_>>();
+        let kept = |callsets: &mut Callsets| {
+            records
+                .iter()
+                .map(|r| callsets.keep(r).unwrap())
+                .collect::<Vec<_>>()
+        };
+        let a = ["_test.isec.a.bcf".to_string()];
+        let b = ["_test.isec.b.bcf".to_string()];
+        let both = [a[0].clone(), b[0].clone()];
+
+        let mut c = Callsets::new(&a, false, &[]).unwrap();
+        assert_eq!(kept(&mut c), [true, true, false, false]);
+        assert_eq!(c.dropped(), 2);
+        let mut c = Callsets::new(&both, false, &[]).unwrap();
+        assert_eq!(kept(&mut c), [false, false, false, false]);
+        let mut c = Callsets::new(&both, true, &[]).unwrap();
+        assert_eq!(kept(&mut c), [true, true, true, false]);
+        let mut c = Callsets::new(&[], false, &b).unwrap();
+        assert_eq!(kept(&mut c), [true, true, false, true]);
+        let mut c = Callsets::new(&a, false, &b).unwrap();
+        assert_eq!(kept(&mut c), [true, true, false, false]);
+
+        assert!(Callsets::new(&["_test.isec.vcf".to_string()], false, &[]).is_err());
+        drop(vcf);
+        for f in [
+            "_test.isec.a.bcf",
+            "_test.isec.a.bcf.csi",
+            "_test.isec.b.bcf",
+            "_test.isec.b.bcf.csi",
+            "_test.isec.vcf",
+        ] {
+            _ = std::fs::remove_file(f);
+        }
+    }
+}
@@ -1,14 +1,63 @@
 //! This crate supports applying user-defined lua expressions to each variant in a VCF File.
 //!
+pub mod acindex;
+pub mod alias;
+pub mod annotate;
+pub mod allele;
+pub mod altfilter;
+pub mod assertion;
+pub mod baseline;
+pub mod batch;
+pub mod bed;
+pub mod burden;
+pub mod check;
+pub mod compare;
+pub mod concat;
+pub mod coverage;
+pub mod density;
+pub mod describe;
+pub mod dryrun;
+pub mod exclude;
 pub mod genotypes;
-//pub mod sample;
+pub mod group;
 pub mod header;
+pub mod headercheck;
+pub mod index;
+pub mod isec;
+pub mod luau;
+pub mod manifest;
+pub mod merge;
+pub mod pack;
+pub mod pedigree;
+pub mod percent;
 pub mod pprint;
+pub mod prune;
+pub mod query;
+pub mod reference;
+pub mod reheader;
+pub mod regions;
+pub mod rename;
+pub mod report;
+pub mod retype;
+pub mod round;
+pub mod roh;
+pub mod sample;
+pub mod samplecache;
+pub mod snippets;
+pub mod sort;
+pub mod split;
+pub mod stats;
+pub mod subsample;
+pub mod targets;
+pub mod twopass;
 pub mod variant;
 pub mod vcfexpress;
 
 pub fn register(lua: &mlua::Lua) -> mlua::Result<()> {
     variant::register_variant(lua)?;
     genotypes::register_genotypes(lua)?;
+    acindex::register_acindex(lua)?;
+    coverage::register_coverage(lua)?;
+    assertion::register_assert(lua)?;
     header::register_header(lua)
 }
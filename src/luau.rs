@@ -0,0 +1,138 @@
+//! Luau compiler options for the expressions, templates and preludes: the optimization level
+//! (`--luau-O2`) and native code generation (`--luau-native`). Native code needs a build with the
+//! `luau-jit` feature and a supported CPU (x86-64 or arm64); otherwise the code is interpreted.
+use mlua::{Chunk, ChunkMode, Compiler, Lua};
+use rustc_hash::FxHashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LuauOptions {
+    /// 0 (none), 1 (the luau default) or 2 (inlining and loop unrolling).
+    pub optimization_level: u8,
+    pub native: bool,
+}
+
+impl Default for LuauOptions {
+    fn default() -> Self {
+        LuauOptions {
+            optimization_level: 1,
+            native: false,
+        }
+    }
+}
+
+/// Whether this build and CPU can compile lua functions to native code.
+pub fn native_supported() -> bool {
+    #[cfg(feature = "luau-jit")]
+    {
+        unsafe { mlua::ffi::luau_codegen_supported() != 0 }
+    }
+    #[cfg(not(feature = "luau-jit"))]
+    {
+        false
+    }
+}
+
+impl LuauOptions {
+    /// Set the options for code loaded into `lua` from now on, so call this before the preludes
+    /// and expressions are loaded.
+    pub fn apply(&self, lua: &Lua) {
+        lua.set_compiler(Compiler::new().set_optimization_level(self.optimization_level));
+        // mlua compiles natively by default when it is built with codegen.
+        #[cfg(feature = "luau-jit")]
+        lua.enable_jit(self.native);
+        if self.native && !native_supported() {
+            log::warn!("--luau-native: {}", self.native_status());
+        }
+    }
+
+    /// Whether the code is natively compiled and if not, why.
+    pub fn native_status(&self) -> &'static str {
+        if !self.native {
+            "no (use --luau-native)"
+        } else if native_supported() {
+            "yes"
+        } else if cfg!(feature = "luau-jit") {
+            "no (not supported on this CPU)"
+        } else {
+            "no (vcfexpress was built without the luau-jit feature)"
+        }
+    }
+}
+
+/// Expressions and preludes compiled once to bytecode, keyed by their source, so that each lua
+/// state (e.g. one per file in `batch`) loads them without compiling again. Set it as lua app
+/// data before [`crate::vcfexpress::VCFExpress::new`].
+#[derive(Debug, Default, Clone)]
+pub struct Bytecode(FxHashMap<String, Vec<u8>>);
+
+impl Bytecode {
+    /// Compile `code`, returning the syntax error (reported as from `name`) if it does not compile.
+    pub fn compile(&mut self, lua: &Lua, code: &str, name: &str) -> mlua::Result<()> {
+        let bytecode = Compiler::new().compile(code);
+        // errors are compiled to bytecode that fails to load with the message.
+        lua.load(&bytecode)
+            .set_name(name)
+            .set_mode(ChunkMode::Binary)
+            .into_function()?;
+        self.0.insert(code.to_string(), bytecode);
+        Ok(())
+    }
+}
+
+/// Load `code` from the [`Bytecode`] set on `lua` if it was compiled there; otherwise from source.
+pub(crate) fn load<'lua, 'a>(lua: &'lua Lua, code: &'a str) -> Chunk<'lua, 'a> {
+    match lua
+        .app_data_ref::<Bytecode>()
+        .and_then(|b| b.0.get(code).cloned())
+    {
+        Some(bytecode) => lua.load(bytecode).set_mode(ChunkMode::Binary),
+        None => lua.load(code),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_luau_options() {
+        let lua = Lua::new();
+        let options = LuauOptions {
+            optimization_level: 2,
+            native: true,
+        };
+        options.apply(&lua);
+        let v: f64 = lua
+            .load("local s = 0; for i = 1, 10 do s += i * 0.5 end; return s")
+            .eval()
+            .unwrap();
+        assert_eq!(v, 27.5);
+        assert_eq!(
+            options.native_status() == "yes",
+            cfg!(feature = "luau-jit") && native_supported()
+        );
+        assert_eq!(
+            LuauOptions::default().native_status(),
+            "no (use --luau-native)"
+        );
+    }
+
+    #[test]
+    fn test_bytecode() {
+        let lua = Lua::new();
+        let mut bytecode = Bytecode::default();
+        bytecode.compile(&lua, "return 1 + 1", "two").unwrap();
+        let err = bytecode.compile(&lua, "return 1 +", "bad").unwrap_err();
+        assert!(err.to_string().contains("bad"), "{}", err);
+        // source that was not compiled is loaded as is.
+        assert_eq!(load(&lua, "return 3").eval::<i32>().unwrap(), 3);
+        lua.set_app_data(bytecode.clone());
+        assert_eq!(load(&lua, "return 1 + 1").eval::<i32>().unwrap(), 2);
+        // the bytecode, not the source, is loaded.
+        bytecode
+            .0
+            .insert("return 3".to_string(), bytecode.0["return 1 + 1"].clone());
+        lua.set_app_data(bytecode);
+        assert_eq!(load(&lua, "return 3").eval::<i32>().unwrap(), 2);
+    }
+}
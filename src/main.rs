@@ -1,12 +1,29 @@
 #[global_allocator]
 static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
 
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
 
 use mlua::Lua;
 use rust_htslib::bcf::Read;
 
-use vcfexpress::{variant::HeaderMap, vcfexpress::VCFExpress};
+use vcfexpress::{
+    alias::SampleAliases,
+    altfilter::AltFilterTranslation,
+    group::parse_reducers,
+    luau::LuauOptions,
+    report::RunReport,
+    batch::{run_batch, BatchOptions},
+    dryrun::DryRun,
+    reference::Reference,
+    regions::RegionReader,
+    rename::{parse_pairs, Renames},
+    retype::parse_retypes,
+    pack::{packs_dir, Pack},
+    round::parse_round_format,
+    snippets::Snippets,
+    variant::HeaderMap,
+    vcfexpress::{BgzfOffset, TemplateNil, VCFExpress, VCFExpressOptions},
+};
 
 /// Args take the arguments for clap.
 /// Accept the path to VCF or BCF and the lua expressions
@@ -38,96 +55,1811 @@ pub enum Commands {
 
 {all-args}{after-help}
 ")]
-    Filter {
-        /// Path to input VCF or BCF file
+    Filter(Box<FilterArgs>),
+    /// Read only the header of each VCF/BCF and run lua code against it as `header`.
+    /// Without any code, the header is printed.
+    #[command(arg_required_else_help(true))]
+    Header(HeaderArgs),
+    /// Check that VCF/BCF files have compatible headers and samples, e.g. before a merge or concat.
+    /// Each difference from the first file is reported as: path, kind, ID, detail.
+    #[command(arg_required_else_help(true))]
+    CheckHeaders {
+        /// Paths or quoted glob patterns e.g. 'batch/*.vcf.gz'
+        #[arg(required = true)]
+        paths: Vec<String>,
+    },
+    /// Check expressions, templates and prelude code against the header of a VCF/BCF without
+    /// reading any records. Syntax errors, INFO/FORMAT tags that are not in the header, unknown
+    /// samples, type mismatches and likely mistakes (e.g. comparing a Number=A field to a number)
+    /// are printed and the exit code is non-zero if any are found.
+    #[command(arg_required_else_help(true))]
+    Check(CheckArgs),
+    /// Run the same expressions over many VCF/BCF files in parallel, writing each to --output-dir
+    /// with the same file name. A summary line is printed for each file:
+    /// path, output, evaluated, passing.
+    #[command(arg_required_else_help(true))]
+    Batch(BatchArgs),
+    /// Bundle prelude files, a snippets file and the paths of required annotation files into a
+    /// versioned archive for `install` and `filter --pack name@version`.
+    #[command(arg_required_else_help(true))]
+    Pack {
+        /// name@version of the pack e.g. rare-disease@1.2
+        reference: String,
+
+        /// prelude file(s), run in this order.
+        #[arg(short = 'p', long)]
+        lua_prelude: Vec<String>,
+
+        /// Snippets file as given to filter.
+        #[arg(long)]
+        snippets: Option<String>,
+
+        /// Annotation file(s) the pack uses, e.g. for --ac-index. They are not bundled but must exist
+        /// where the pack is used.
+        #[arg(long)]
+        requires: Vec<String>,
+
+        /// Output path. Default is name-version.vxpack
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Install a pack from `vcfexpress pack` into $VCFEXPRESS_PACKS (default ~/.vcfexpress/packs).
+    #[command(arg_required_else_help(true))]
+    Install {
+        /// Path to the .vxpack archive.
+        path: String,
+    },
+    /// Sort a VCF/BCF by position or by a numeric INFO field, e.g. the SORTKEY set with
+    /// `filter -s 'SORTKEY=<expr>'`. Files larger than --max-memory are sorted in runs written
+    /// to --tmp-dir and merged.
+    #[command(arg_required_else_help(true))]
+    Sort {
+        /// Path to input VCF or BCF ("-" for stdin)
+        path: String,
+
+        /// numeric INFO field to sort by. Records without it are written last.
+        /// Default is to sort by contig and position.
+        #[arg(short, long)]
+        key: Option<String>,
+
+        /// sort the key from high to low
+        #[arg(short, long)]
+        reverse: bool,
+
+        /// Approximate memory to use for records before writing a sorted run, e.g. 500M or 2G.
+        #[arg(short = 'm', long, default_value = "768M")]
+        max_memory: String,
+
+        /// Directory for the sorted runs. Default is the system temporary directory.
+        #[arg(short = 'T', long)]
+        tmp_dir: Option<String>,
+
+        /// Optional output file. Default is stdout.
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Split a VCF/BCF into one file per chromosome, per sample (with only the records where the
+    /// sample has an ALT allele) or per value of a lua expression, e.g. for scatter-gather.
+    #[command(arg_required_else_help(true))]
+    #[command(group(clap::ArgGroup::new("by").required(true).args(["chrom", "sample", "key"])))]
+    Split {
+        /// Path to input VCF or BCF ("-" for stdin)
+        path: String,
+
+        /// Write a file for each chromosome.
+        #[arg(long)]
+        chrom: bool,
+
+        /// Write a file for each sample with only that sample.
+        #[arg(long)]
+        sample: bool,
+
+        /// Write a file for each value of this lua expression e.g. 'return variant:info("GENE")'.
+        /// Records for which it returns nil are not written.
+        #[arg(short, long)]
+        key: Option<String>,
+
+        /// Put before each file name, e.g. out/ or out/cohort. (directories are created)
+        #[arg(long, default_value = "")]
+        prefix: String,
+
+        /// Put after each file name. The extension sets the format as for filter -o.
+        #[arg(long, default_value = ".vcf.gz")]
+        suffix: String,
+
+        /// The most files to have open at once.
+        #[arg(long, default_value_t = 256)]
+        max_open: usize,
+
+        /// Only write records that pass any of these lua expression(s).
+        #[arg(short, long)]
+        expression: Vec<String>,
+
+        /// File(s) containing lua(u) code as given to filter
+        #[arg(short = 'p', long)]
+        lua_prelude: Vec<String>,
+
+        /// Write the path, number of records and regions of each file as TSV (or JSON with a
+        /// .json path).
+        #[arg(long)]
+        manifest: Option<String>,
+    },
+    /// Replace or patch the header: new sample names, new contig lines from a FASTA index or a
+    /// whole new header. Records are copied without being unpacked when the fields and contigs
+    /// keep their ids.
+    #[command(arg_required_else_help(true))]
+    Reheader {
+        /// Path to input VCF or BCF ("-" for stdin)
+        path: String,
+
+        /// File with the new header lines. The #CHROM line of the input is used if it has none.
+        #[arg(long = "header")]
+        header: Option<String>,
+
+        /// File with the new sample names: one per line for every sample in order, or
+        /// `old new` pairs for the samples to rename.
+        #[arg(short, long)]
+        samples: Option<String>,
+
+        /// FASTA index (.fai, or the FASTA with a .fai next to it) whose sequences replace the
+        /// ##contig lines. The output is sorted if they are in a different order than the input's.
+        #[arg(short, long)]
+        fai: Option<String>,
+
+        /// Approximate memory to use for records if the output must be sorted, as for `sort`.
+        #[arg(short = 'm', long, default_value = "768M")]
+        max_memory: String,
+
+        /// Directory for sorted runs if the output must be sorted, as for `sort`.
+        #[arg(short = 'T', long)]
+        tmp_dir: Option<String>,
+
+        /// Optional output file. Default is stdout.
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Index a bgzipped VCF (.tbi, or .csi with --csi or for contigs too long for tabix) or a BCF
+    /// (.csi) e.g. after `filter -o out.vcf.gz`.
+    #[command(arg_required_else_help(true))]
+    Index {
+        /// Path to the .vcf.gz or .bcf
+        path: String,
+
+        /// Write a CSI index for a .vcf.gz rather than a tabix index.
+        #[arg(short, long)]
+        csi: bool,
+
+        /// Bins of 2^min-shift bases for a CSI index.
+        #[arg(short, long, default_value_t = 14)]
+        min_shift: u32,
+
+        /// Replace an existing index.
+        #[arg(short, long)]
+        force: bool,
+
+        /// Number of decompression threads.
+        #[arg(short, long, default_value_t = 2)]
+        threads: u32,
+    },
+    /// Add INFO fields to each record from other files: columns of a bgzipped, tabix-indexed TSV
+    /// (`--tsv anno.tsv.gz --columns 5:GENE,6:SCORE:Float`) from the lines at the position of
+    /// the variant, and INFO fields and ID of an indexed VCF/BCF (`--vcf gnomad.bcf --fields AF
+    /// --prefix gnomad_`) from the records with the same position and normalized alleles, and a
+    /// flag or the names of the overlapping regions of BED files (`--bed exons.bed:EXON`). The
+    /// fields are added to the header.
+    #[command(arg_required_else_help(true))]
+    Annotate {
+        /// Path to input VCF or BCF ("-" for stdin)
+        path: String,
+
+        /// bgzipped and tabix-indexed TSV with the chromosome and position columns in its index.
+        #[arg(long)]
+        tsv: Option<String>,
+
+        /// TSV columns to add as column:NAME or column:NAME:Type (1-based; Type is String,
+        /// Integer or Float; default String), e.g. 5:GENE,6:SCORE:Float.
+        #[arg(short, long, requires = "tsv")]
+        columns: Vec<String>,
+
+        /// 1-based REF and ALT columns of the TSV, e.g. 3:4. A line then must match the REF and
+        /// an ALT of the variant and the fields have a value for each ALT (Number=A).
+        /// Default is the first line at the position and Number=1.
+        #[arg(long, requires = "tsv")]
+        ref_alt_columns: Option<String>,
+
+        /// indexed VCF or BCF to take INFO fields and ID from. Records are matched by position and
+        /// alleles after trimming shared bases.
+        #[arg(long)]
+        vcf: Option<String>,
+
+        /// INFO fields of --vcf to add. Number=A and Number=R fields are matched to each ALT.
+        #[arg(short, long, requires = "vcf")]
+        fields: Vec<String>,
+
+        /// prefix for the names of the fields from --vcf, e.g. gnomad_.
+        #[arg(long, requires = "vcf", default_value = "")]
+        prefix: String,
+
+        /// set the ID from the matching --vcf records for variants without one.
+        #[arg(long, requires = "vcf")]
+        id: bool,
+
+        /// BED file (plain or gzipped) as path[:NAME] to set INFO NAME (default: the file name up
+        /// to the first '.') for variants overlapping its regions: the names of the regions if it
+        /// has a 4th column, otherwise a flag. May be given multiple times.
+        #[arg(long)]
+        bed: Vec<String>,
+
+        /// Optional output file. Default is stdout.
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Merge VCF/BCFs with different samples (e.g. single-sample calls), each sorted in the same
+    /// contig order, into one multi-sample file. Records at the same position with the same REF
+    /// are merged with the union of their ALTs; GT and Number=A/R/G FORMAT fields are remapped
+    /// and samples without a record get missing values.
+    #[command(arg_required_else_help(true))]
+    Merge {
+        /// Paths to the VCF or BCF files
+        #[arg(required = true, num_args = 2..)]
+        paths: Vec<String>,
+
+        /// How to combine an INFO field as TAG=rule: first (the default), sum, min, max, mean or
+        /// join (String fields: the distinct values separated by '|'), e.g. DP=sum,AF=max.
+        #[arg(long)]
+        info_rule: Vec<String>,
+
+        /// Optional output file. Default is stdout.
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Concatenate VCF/BCFs with the same samples (e.g. one file per chromosome or region) after
+    /// checking that their headers are compatible, in genomic order unless --naive. If the files
+    /// list their contigs in different orders, the output is sorted as with `sort`.
+    #[command(arg_required_else_help(true))]
+    Concat {
+        /// Paths to the VCF or BCF files. Glob patterns are expanded.
+        #[arg(required = true)]
+        paths: Vec<String>,
+
+        /// Write the files one after another in the order given rather than in genomic order.
+        /// The inputs then need not be sorted.
+        #[arg(long)]
+        naive: bool,
+
+        /// Only write records that pass any of these lua expression(s).
+        #[arg(short, long)]
+        expression: Vec<String>,
+
+        /// File(s) containing lua(u) code as given to filter
+        #[arg(short = 'p', long, requires = "expression")]
+        lua_prelude: Vec<String>,
+
+        /// Approximate memory to use for records if the output must be sorted, as for `sort`.
+        #[arg(short = 'm', long, default_value = "768M")]
+        max_memory: String,
+
+        /// Directory for sorted runs if the output must be sorted, as for `sort`.
+        #[arg(short = 'T', long)]
+        tmp_dir: Option<String>,
+
+        /// Optional output file. Default is stdout.
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Compare two VCF/BCFs: alleles shared and in only one file (matched after trimming shared
+    /// bases), alleles of A at a position of B with other alleles and the genotype concordance of
+    /// each sample in both, written as section, key, value lines. The alleles of B are held in
+    /// memory.
+    #[command(arg_required_else_help(true))]
+    Compare {
+        /// Path to VCF or BCF A ("-" for stdin)
+        a: String,
+
+        /// Path to VCF or BCF B
+        b: String,
+
+        /// Optional output file. Default is stdout.
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Summarize a VCF/BCF for QC: ts/tv, counts by variant type, FILTER and chromosome and the
+    /// fraction of multi-allelic sites, written as section, key, value lines.
+    #[command(arg_required_else_help(true))]
+    Stats {
+        /// Path to input VCF or BCF ("-" for stdin)
         path: String,
 
-        /// boolean Lua expression(s) to filter the VCF or BCF file
+        /// Only count records that pass any of these lua expression(s).
         #[arg(short, long)]
         expression: Vec<String>,
 
-        /// expression(s) to set existing INFO field(s) (new ones can be added in prelude)
-        /// e.g. --set-expression "AFmax=math.max(variant:info('AF'), variant:info('AFx'))"
-        #[arg(short = 's', long)]
-        set_expression: Vec<String>,
+        /// File(s) containing lua(u) code as given to filter
+        #[arg(short = 'p', long)]
+        lua_prelude: Vec<String>,
 
-        /// template expression in luau: https://luau-lang.org/syntax#string-interpolation. e.g. '{variant.chrom}:{variant.pos}'
+        /// Optional output file. Default is stdout.
         #[arg(short, long)]
-        template: Option<String>,
+        output: Option<String>,
+    },
+    /// Print fields of each record with a bcftools query format string, e.g.
+    /// -f '%CHROM\t%POS\t%INFO/AF[\t%SAMPLE=%GT]\n'. `[...]` is repeated for each sample.
+    #[command(arg_required_else_help(true))]
+    Query {
+        /// Path to input VCF or BCF ("-" for stdin)
+        path: String,
 
-        /// File(s) containing lua(u) code to run once before any variants are processed.
-        /// `header` is available here to access or modify the header.
+        /// bcftools query format: %CHROM, %POS, %ID, %REF, %ALT, %QUAL, %FILTER, %END, %LINE,
+        /// %INFO/TAG and, inside [...], %SAMPLE, %GT, %TGT and %FORMAT/TAG. %TAG{1} selects the
+        /// second value.
+        #[arg(short, long)]
+        format: String,
+
+        /// Only print records that pass any of these lua expression(s).
+        #[arg(short, long)]
+        expression: Vec<String>,
+
+        /// File(s) containing lua(u) code as given to filter
         #[arg(short = 'p', long)]
         lua_prelude: Vec<String>,
 
+        /// Print a header line with the name of each field first.
+        #[arg(short = 'H', long)]
+        print_header: bool,
+
         /// Optional output file. Default is stdout.
         #[arg(short, long)]
         output: Option<String>,
+    },
+    /// List every INFO and FORMAT tag with its Type, Number and Description and the lua to read
+    /// it, e.g. `variant:info("DP")  -- Integer, Number=1: Total depth`.
+    #[command(arg_required_else_help(true))]
+    Describe {
+        /// Path to VCF or BCF file ("-" for stdin)
+        path: String,
+    },
+    /// Build a compact index of per-allele AC/AN from a cohort VCF/BCF for use with `filter --ac-index`.
+    /// AC/AN are counted from GT, or taken from INFO for sites-only files.
+    #[command(arg_required_else_help(true))]
+    BuildAcIndex {
+        /// Path to cohort VCF or BCF file
+        path: String,
+
+        /// Output path for the index.
+        #[arg(short, long)]
+        output: String,
+    },
+    /// Transpose FORMAT fields to a sample-major cache for fast per-sample access with `filter --sample-cache`.
+    /// The cache is tied to the input by its size, modification time and header.
+    #[command(arg_required_else_help(true))]
+    BuildSampleCache {
+        /// Path to VCF or BCF file
+        path: String,
 
-        /// Run lua code in https://luau.org/sandbox.
-        #[arg(short = 'b', long)]
-        sandbox: bool,
+        /// Output path for the cache.
+        #[arg(short, long)]
+        output: String,
     },
 }
 
-fn filter_main(
+#[derive(Args)]
+pub struct FilterArgs {
+    /// Path to input VCF or BCF file
     path: String,
-    expressions: Vec<String>,
+
+    /// boolean Lua expression(s) to filter the VCF or BCF file
+    #[arg(short, long)]
+    expression: Vec<String>,
+
+    /// expression(s) to set existing INFO field(s) (new ones can be added in prelude)
+    /// e.g. --set-expression "AFmax=math.max(variant:info('AF'), variant:info('AFx'))"
+    #[arg(short = 's', long)]
     set_expression: Vec<String>,
+
+    /// expression(s) to set FORMAT field(s), evaluated once per sample with `sample` (as from
+    /// `variant:sample`) available. The field must be in the header (new ones can be added in prelude)
+    /// e.g. --set-format "AB=sample.AB"
+    #[arg(long)]
+    set_format: Vec<String>,
+
+    /// evaluate expressions and template once per sample with `sample` (as from `variant:sample`)
+    /// available. With a template, a line is printed for each passing sample (long format);
+    /// without one, a record is written if any sample passes.
+    #[arg(long, conflicts_with = "per_allele")]
+    per_sample: bool,
+
+    /// evaluate expressions and template once per ALT with `allele`, `allele_index` and `allele_info`
+    /// (the Number=A and Number=R INFO values for the allele) available. With a template, a line is
+    /// printed for each passing allele; without one, failing alleles are removed from the record.
+    #[arg(long)]
+    per_allele: bool,
+
+    /// set the FILTER of each written record from the allele-specific ALT_FILTER INFO field (set with
+    /// `variant:set_alt_filter(i, name)`): if every ALT failed, their filters are added to FILTER. Use
+    /// with --per-allele to drop the failing ALTs so that FILTER reflects those that remain.
+    #[arg(long, conflicts_with_all = ["template", "filter_to_alt_filter"])]
+    alt_filter_to_filter: bool,
+
+    /// add the FILTER of each written record to the ALT_FILTER INFO field of each of its ALTs, e.g. before
+    /// records are joined into a multi-allelic site. ALT_FILTER is added to the header if needed.
+    #[arg(long, conflicts_with = "template")]
+    filter_to_alt_filter: bool,
+
+    /// NAME[:description]: write failing records with FILTER set to NAME instead of dropping them
+    /// and set PASS on passing records without a FILTER, as `bcftools filter -s`. The FILTER is
+    /// added to the header.
+    #[arg(long, conflicts_with_all = ["template", "group_by", "window_density", "burden", "count"])]
+    soft_filter: Option<String>,
+
+    /// With --soft-filter, add NAME to the existing FILTER of failing records instead of
+    /// replacing it, as `bcftools filter -m+ -s`.
+    #[arg(long, requires = "soft_filter")]
+    soft_filter_append: bool,
+
+    /// Bucket passing variants by the value of this lua expression (e.g. `return variant.chrom`)
+    /// and write one row per group instead of the variants. A nil key skips the variant. If a
+    /// prelude defines `aggregate(key, variants)`, each row is its result.
+    #[arg(long, conflicts_with_all = ["template", "per_sample", "per_allele", "header_only"])]
+    group_by: Option<String>,
+
+    /// Columns for --group-by: count, sum:TAG, mean:TAG, min:TAG or max:TAG of numeric INFO
+    /// fields e.g. count,mean:DP. Default is count.
+    #[arg(long, requires = "group_by")]
+    group_reduce: Vec<String>,
+
+    /// Count passing variants in windows of this size (size:step for overlapping windows) and write
+    /// chrom, start, end, count for each window with variants instead of the variants.
+    /// The input must be sorted.
+    #[arg(long, conflicts_with_all = ["template", "per_sample", "per_allele", "header_only", "group_by"])]
+    window_density: Option<vcfexpress::density::WindowDensity>,
+
+    /// Write a gene x sample burden matrix instead of the variants: for each gene, the number of
+    /// passing variants, the number of carrier samples and, per sample, the passing variants where
+    /// it has an alternate allele. The lua expression returns the gene, a table of genes or nil
+    /// for the variant e.g. from the CSQ field.
+    #[arg(long, conflicts_with_all = ["template", "per_sample", "per_allele", "header_only", "group_by", "window_density"])]
+    burden: Option<String>,
+
+    /// Write only the number of evaluated and passing variants instead of the variants, e.g. to
+    /// explore thresholds on large files.
+    #[arg(long, conflicts_with_all = ["template", "header_only", "group_by", "window_density", "burden"])]
+    count: bool,
+
+    /// With --count, also write the number of variants passing each expression (as passing:1,
+    /// passing:2, ...). Every expression is then evaluated for each variant.
+    #[arg(long, requires = "count", conflicts_with_all = ["per_sample", "per_allele"])]
+    count_per_expression: bool,
+
+    /// do not count `*` (spanning deletion) and `<NON_REF>`/`<*>` alleles in `variant:aaf()` or in the
+    /// AN and AF set by `variant:update_allele_counts()`. With --per-allele, they are kept but not evaluated.
+    #[arg(long)]
+    skip_symbolic_alleles: bool,
+
+    /// template expression in luau: https://luau-lang.org/syntax#string-interpolation. e.g. '{variant.chrom}:{variant.pos}'
+    #[arg(short, long)]
     template: Option<String>,
+
+    /// print this (e.g. '.') for template values that are nil instead of "nil".
+    #[arg(long, conflicts_with = "strict_template")]
+    template_nil_as: Option<String>,
+
+    /// stop with an error when a template value is nil.
+    #[arg(long)]
+    strict_template: bool,
+
+    /// File(s) containing lua(u) code to run once before any variants are processed.
+    /// `header` is available here to access or modify the header.
+    #[arg(short = 'p', long)]
     lua_prelude: Vec<String>,
+
+    /// File of name = "lua fragment" snippets, one per line. Each `@name` in the expressions, set
+    /// expressions and --group-by is replaced by its fragment e.g. `-e '@rare and @high_impact'`.
+    #[arg(long)]
+    snippets: Option<String>,
+
+    /// Installed pack(s) (name@version, or name for the latest version) whose preludes run
+    /// before those given with -p and whose snippets are added to --snippets.
+    #[arg(long)]
+    pack: Vec<String>,
+
+    /// Optional output file. Default is stdout.
+    #[arg(short, long)]
     output: Option<String>,
+
+    /// Also stream the output (VCF or template) to stdout while it is written to --output, e.g.
+    /// to pipe into a viewer while keeping the results.
+    #[arg(long, requires = "output")]
+    tee: bool,
+
+    /// Write the lines from `emit(line)` (e.g. in `on_end()`) to this file. By default they are
+    /// written to the template output or, for VCF/BCF output, to stderr.
+    #[arg(long)]
+    summary_output: Option<String>,
+
+    /// Read the input twice. The first pass sets `globals_stats` (n, mean_depth[sample],
+    /// `globals_stats:qual_quantile(q)` and any fields set by a prelude `first_pass(variant)`)
+    /// for percentile-based thresholds in the expressions of the second. Both passes read the
+    /// same records (--region, --skip/--max-records and the --targets-file, --exclude-bed, --isec
+    /// and --sample-fraction filters).
+    #[arg(long)]
+    two_pass: bool,
+
+    /// Call `on_progress(n, locus)`, if it is defined in a prelude, every this many records
+    /// e.g. for heartbeat logging or checkpointing. locus is chrom:pos (1-based).
+    #[arg(long, default_value_t = 100_000)]
+    progress_every: usize,
+
+    /// Allow this many `assert_variant(cond, msg)` failures (each is logged with the locus)
+    /// before the run stops with an error.
+    #[arg(long, default_value_t = 0)]
+    max_assert_failures: usize,
+
+    /// Run lua code in https://luau.org/sandbox.
+    #[arg(short = 'b', long)]
     sandbox: bool,
-) -> Result<(), Box<dyn std::error::Error>> {
+
+    /// Allele-count index from `build-ac-index`. Available to expressions as `acindex`
+    /// e.g. `local ac, an = acindex:lookup(variant)`
+    #[arg(long)]
+    ac_index: Option<String>,
+
+    /// Sample-major cache from `build-sample-cache` used to speed up `variant:sample()`.
+    #[arg(long)]
+    sample_cache: Option<String>,
+
+    /// Per-sample depth (e.g. mosdepth NA12878.regions.bed.gz or SAMPLE=depth.bedgraph) for
+    /// `coverage:at(sample, variant)`. Without SAMPLE=, the sample is the file name up to the first '.'.
+    #[arg(long)]
+    coverage: Vec<String>,
+
+    /// TSV of alias<TAB>VCF sample name so that expressions, --samples and the --ped, --roh and
+    /// --coverage files can use other IDs (e.g. institutional IDs for sequencing barcodes).
+    #[arg(long)]
+    sample_alias: Option<String>,
+
+    /// Only read these samples (comma-separated names or aliases). Samples keep their VCF order.
+    #[arg(long, value_delimiter = ',')]
+    samples: Vec<String>,
+
+    /// `variant:sample(name)` returns nil for a name that is not in the VCF and `variant:samples(names)`
+    /// skips it. By default this is an error that lists the available samples.
+    #[arg(long)]
+    lenient_samples: bool,
+
+    /// A previous output of filter to compare against. Expressions and the template can use
+    /// `baseline.status` ("new" or "unchanged") and the number of new, unchanged and dropped
+    /// sites is reported at the end.
+    #[arg(long)]
+    baseline: Option<String>,
+
+    /// Only keep records that are in this indexed VCF/BCF: an ALT at the same position with the
+    /// same REF and ALT after trimming shared bases. May be given multiple times to keep records
+    /// in every file. Records are skipped before they are evaluated.
+    #[arg(long)]
+    isec: Vec<String>,
+
+    /// Keep records in any rather than every --isec file.
+    #[arg(long, requires = "isec")]
+    isec_any: bool,
+
+    /// Drop records that are in this indexed VCF/BCF (matched as for --isec). May be given
+    /// multiple times.
+    #[arg(long)]
+    exclude_vcf: Vec<String>,
+
+    /// Drop records that overlap an interval in this BED file (plain or gzipped, in any order),
+    /// e.g. the ENCODE blacklist, before they are evaluated. May be given multiple times.
+    #[arg(long)]
+    exclude_bed: Vec<String>,
+
+    /// PED file (family, sample, father, mother, ...) for `variant:transmitted_allele(child)`.
+    #[arg(long)]
+    ped: Option<String>,
+
+    /// BED file of runs of homozygosity for `sample:in_roh(variant)`. An optional 4th column
+    /// gives the sample; runs without it apply to all samples.
+    #[arg(long)]
+    roh: Option<String>,
+
+    /// Region(s) to read from an indexed VCF/BCF e.g. chr1:1000-2000. May be given multiple times.
+    #[arg(short = 'r', long)]
+    region: Vec<vcfexpress::regions::Region>,
+
+    /// Skip the first N records of the input (or of the --region(s)), e.g. to try expressions
+    /// on a slice of a large file.
+    #[arg(long, default_value_t = 0)]
+    skip: usize,
+
+    /// Stop after reading N records (after any --skip). The output and counts cover only those.
+    #[arg(long)]
+    max_records: Option<usize>,
+
+    /// Evaluate only a random fraction (e.g. 0.01) of the records, e.g. to estimate pass rates or
+    /// build test files. The same --seed and input give the same records.
+    #[arg(long)]
+    sample_fraction: Option<f64>,
+
+    /// Seed for --sample-fraction.
+    #[arg(long, default_value_t = 1, requires = "sample_fraction")]
+    seed: u64,
+
+    /// Rename INFO tags in the header and every record e.g. AF=VAF,DP=DEPTH.
+    /// Expressions use the new names.
+    #[arg(long)]
+    rename_info: Vec<String>,
+
+    /// Rename FORMAT tags in the header and every record e.g. AD=DEPTHS.
+    #[arg(long)]
+    rename_format: Vec<String>,
+
+    /// File of `old new` contig names, one pair per line (e.g. `1 chr1`), to rename CHROM and the
+    /// ##contig lines in the VCF/BCF output. Expressions see the new names.
+    #[arg(long)]
+    rename_chrs: Option<String>,
+
+    /// Change the Type of INFO fields in the header and every record e.g. DP=Float.
+    /// Conversions that would lose information (e.g. 0.5 to Integer) are an error.
+    #[arg(long)]
+    retype_info: Vec<String>,
+
+    /// Round Float FORMAT fields in the output to this many decimal places e.g. GL:2,AF:4
+    /// to shrink cohort files. Integer fields are left as-is.
+    #[arg(long)]
+    round_format: Vec<String>,
+
+    /// Remove INFO fields whose values are all missing and FORMAT fields (except GT) that are
+    /// missing for every sample from each record written, e.g. after subsetting samples.
+    #[arg(long)]
+    prune_empty: bool,
+
+    /// Coordinates of `variant.pos` in the template: 0 (0-based, the default) or 1 (1-based as
+    /// printed in the VCF). `variant.start` and `variant.POS` are always 0- and 1-based.
+    #[arg(long, default_value_t = 0, value_parser = clap::value_parser!(u8).range(0..=1))]
+    coords: u8,
+
+    /// Print the INFO/FORMAT tags, samples, annotation sources and helpers that each expression,
+    /// template and prelude uses and exit without reading any records.
+    #[arg(long)]
+    explain_plan: bool,
+
+    /// Compile the lua code with luau optimization level 2 (inlining and loop unrolling) instead
+    /// of 1.
+    #[arg(long = "luau-O2")]
+    luau_o2: bool,
+
+    /// Compile the lua code to native code where supported (requires a build with the luau-jit
+    /// feature), e.g. for arithmetic-heavy scoring expressions.
+    #[arg(long)]
+    luau_native: bool,
+
+    /// Write a JSON summary to this path at the end: the input, output and expressions, the
+    /// records evaluated, passing and failing (overall and for each expression, which are then all
+    /// evaluated), assertion failures and the wall time. A failed run writes it too, with the error.
+    #[arg(long)]
+    report: Option<String>,
+
+    /// Report, for each expression, the records for which it was evaluated, passed and was the
+    /// first to pass (decisive) to stderr at the end, e.g. to choose the order of the expressions.
+    /// An expression is not evaluated once an earlier one passes.
+    #[arg(long, conflicts_with_all = ["per_sample", "per_allele"])]
+    expression_summary: bool,
+
+    /// Report the run time, records per second and whether the expressions were natively
+    /// compiled to stderr at the end.
+    #[arg(long)]
+    profile: bool,
+
+    /// Run the prelude, write the resulting header (with any --samples, renames and retypes
+    /// applied) and exit without reading any records.
+    #[arg(long, conflicts_with = "template")]
+    header_only: bool,
+
+    /// Print the resolved configuration (the input, regions, samples, preludes and expressions
+    /// after snippet expansion, the outputs to be created, threads and luau options) as
+    /// key<TAB>value lines and exit. The code is compiled but not run and no records are read.
+    #[arg(long, conflicts_with_all = ["explain_plan", "header_only"])]
+    dry_run: bool,
+
+    /// Reference FASTA (with or without a .fai) to check the header contig lengths against.
+    /// A mismatch is an error.
+    #[arg(long)]
+    fasta: Option<String>,
+
+    /// With --fasta, add `##contig` lines for reference sequences that are not in the header.
+    #[arg(long, requires = "fasta")]
+    fasta_add_contigs: bool,
+
+    /// Do not decode percent-encoded INFO strings (e.g. %3B for ;) read into lua or encode
+    /// those set by expressions. Use for legacy files with a literal '%' in values.
+    #[arg(long)]
+    no_percent_encoding: bool,
+
+    /// VCF version of the output e.g. 4.4. Sets ##fileformat and warns about header lines that
+    /// the version does not support and reserved INFO/FORMAT tags with a non-standard Number or
+    /// Type. Versions before 4.3 do not use percent-encoding. With 4.4 or later, records with a
+    /// <*> or <NON_REF> allele must have an AD value for it.
+    #[arg(long)]
+    vcf_version: Option<String>,
+
+    /// Regions within this many bases of each other are read in a single ranged request.
+    /// Larger values mean fewer requests (useful for remote files) at the cost of reading more data.
+    #[arg(long, default_value_t = 0)]
+    region_slop: u64,
+
+    /// With --region, fail if the input has no index instead of scanning the whole file.
+    #[arg(long, requires = "region")]
+    require_index: bool,
+
+    /// Only evaluate records that overlap an interval in this BED file (plain or gzipped). It is
+    /// read alongside the input, so no index is needed and stdin works. The lines of each
+    /// chromosome must be together and sorted by start, as must the input records.
+    #[arg(long)]
+    targets_file: Option<String>,
+}
+
+#[derive(Args)]
+pub struct CheckArgs {
+    /// Path to input VCF or BCF file
+    path: String,
+
+    /// expression(s) as given to filter
+    #[arg(short, long)]
+    expression: Vec<String>,
+
+    /// set expression(s) as given to filter
+    #[arg(short = 's', long)]
+    set_expression: Vec<String>,
+
+    /// set-format expression(s) as given to filter
+    #[arg(long)]
+    set_format: Vec<String>,
+
+    /// template as given to filter
+    #[arg(short, long)]
+    template: Option<String>,
+
+    /// File(s) containing lua(u) code as given to filter
+    #[arg(short = 'p', long)]
+    lua_prelude: Vec<String>,
+
+    /// snippets as given to filter
+    #[arg(long)]
+    snippets: Option<String>,
+
+    /// pack(s) as given to filter
+    #[arg(long)]
+    pack: Vec<String>,
+
+    /// sample aliases as given to filter
+    #[arg(long)]
+    sample_alias: Option<String>,
+}
+
+#[derive(Args)]
+pub struct BatchArgs {
+    /// Paths or quoted glob patterns e.g. 'samples/*.vcf.gz'
+    #[arg(required = true)]
+    paths: Vec<String>,
+
+    /// expression(s) as given to filter
+    #[arg(short, long)]
+    expression: Vec<String>,
+
+    /// set expression(s) as given to filter
+    #[arg(short = 's', long)]
+    set_expression: Vec<String>,
+
+    /// template as given to filter. Each output is named as the input with .txt appended.
+    #[arg(short, long)]
+    template: Option<String>,
+
+    /// File(s) containing lua(u) code as given to filter
+    #[arg(short = 'p', long)]
+    lua_prelude: Vec<String>,
+
+    /// snippets as given to filter
+    #[arg(long)]
+    snippets: Option<String>,
+
+    /// pack(s) as given to filter
+    #[arg(long)]
+    pack: Vec<String>,
+
+    /// Directory for the output files.
+    #[arg(short, long, required = true)]
+    output_dir: String,
+
+    /// Number of files to process at once.
+    #[arg(long, default_value_t = 4)]
+    threads: usize,
+
+    /// Run lua code in https://luau.org/sandbox.
+    #[arg(long, default_value_t = false)]
+    sandbox: bool,
+}
+
+#[derive(Args)]
+pub struct HeaderArgs {
+    /// Path(s) to input VCF or BCF files
+    #[arg(required = true)]
+    paths: Vec<String>,
+
+    /// lua code to run for each file with `header` and `path` available
+    /// e.g. --lua 'print(path, #header.samples)'
+    #[arg(short, long)]
+    lua: Vec<String>,
+
+    /// File(s) containing lua(u) code to run for each file after any --lua code.
+    #[arg(short = 'p', long)]
+    lua_prelude: Vec<String>,
+}
+
+fn header_main(args: HeaderArgs) -> Result<(), Box<dyn std::error::Error>> {
     env_logger::init();
     let lua = Lua::new();
+    lua.load(vcfexpress::pprint::PPRINT)
+        .set_name("pprint")
+        .exec()?;
+    lua.load(vcfexpress::pprint::PRELUDE)
+        .set_name("prelude")
+        .exec()?;
+    vcfexpress::register(&lua)?;
 
-    let mut vcfexpr = VCFExpress::new(
+    let mut scripts = args
+        .lua
+        .into_iter()
+        .enumerate()
+        .map(|(i, code)| (format!("--lua {}", i + 1), code))
+        .collect::<Vec<_>>();
+    for path in args.lua_prelude {
+        let code = std::fs::read_to_string(&path)?;
+        scripts.push((path, code));
+    }
+    for path in &args.paths {
+        vcfexpress::header::run_header_scripts(&lua, path, &scripts)?;
+    }
+    Ok(())
+}
+
+/// Each expression, template and prelude named by its flag and position, e.g. "-e 2".
+fn named_codes(
+    expression: Vec<String>,
+    set_expression: Vec<String>,
+    set_format: Vec<String>,
+    template: Option<String>,
+    lua_prelude: Vec<String>,
+) -> std::io::Result<Vec<(String, String)>> {
+    let mut codes = vec![];
+    for (flag, values) in [
+        ("-e", expression),
+        ("-s", set_expression),
+        ("--set-format", set_format),
+        ("-t", template.into_iter().collect()),
+    ] {
+        for (i, code) in values.into_iter().enumerate() {
+            codes.push((format!("{} {}", flag, i + 1), code));
+        }
+    }
+    for path in lua_prelude {
+        let code = std::fs::read_to_string(&path)?;
+        codes.push((path, code));
+    }
+    Ok(codes)
+}
+
+/// The configuration of a filter run for --dry-run. The code is compiled with `lua`.
+fn dry_run(
+    args: &FilterArgs,
+    lua: &Lua,
+    luau: LuauOptions,
+    samples: &[String],
+) -> Result<DryRun, Box<dyn std::error::Error>> {
+    let mut dry = DryRun::default();
+    dry.add("input", &args.path);
+    dry.add_each("region", &args.region);
+    if !samples.is_empty() {
+        dry.add("samples", samples.join(","));
+    }
+    dry.add_each("pack", &args.pack);
+    if let Some(snippets) = &args.snippets {
+        dry.add("snippets", snippets);
+    }
+    for path in &args.lua_prelude {
+        dry.prelude(lua, path)?;
+    }
+    for e in &args.expression {
+        dry.expression(lua, "expression", e)?;
+    }
+    for e in &args.set_expression {
+        dry.set_expression(lua, "set_expression", e)?;
+    }
+    for e in &args.set_format {
+        dry.set_expression(lua, "set_format", e)?;
+    }
+    if let Some(t) = &args.template {
+        dry.template(lua, t)?;
+    }
+    if let Some(g) = &args.group_by {
+        dry.expression(lua, "group_by", g)?;
+    }
+    if let Some(b) = &args.burden {
+        dry.expression(lua, "burden", b)?;
+    }
+    dry.add_each("rename_info", &args.rename_info);
+    dry.add_each("rename_format", &args.rename_format);
+    dry.add_each("retype_info", &args.retype_info);
+    for (key, path) in [
+        ("ac_index", &args.ac_index),
+        ("sample_cache", &args.sample_cache),
+        ("sample_alias", &args.sample_alias),
+        ("baseline", &args.baseline),
+        ("ped", &args.ped),
+        ("roh", &args.roh),
+        ("fasta", &args.fasta),
+        ("rename_chrs", &args.rename_chrs),
+        ("targets_file", &args.targets_file),
+    ] {
+        if let Some(path) = path {
+            dry.add(key, path);
+        }
+    }
+    dry.add_each("coverage", &args.coverage);
+    dry.add_each(if args.isec_any { "isec_any" } else { "isec" }, &args.isec);
+    dry.add_each("exclude_vcf", &args.exclude_vcf);
+    dry.add_each("exclude_bed", &args.exclude_bed);
+    if let Some(soft_filter) = &args.soft_filter {
+        dry.add("soft_filter", soft_filter);
+        dry.add("soft_filter_append", args.soft_filter_append);
+    }
+    if args.skip > 0 {
+        dry.add("skip", args.skip);
+    }
+    if let Some(n) = args.max_records {
+        dry.add("max_records", n);
+    }
+    if let Some(fraction) = args.sample_fraction {
+        dry.add("sample_fraction", fraction);
+        dry.add("seed", args.seed);
+    }
+    dry.add(
+        "mode",
+        if args.per_sample {
+            "per-sample"
+        } else if args.per_allele {
+            "per-allele"
+        } else {
+            "per-variant"
+        },
+    );
+    let text = args.template.is_some()
+        || args.group_by.is_some()
+        || args.window_density.is_some()
+        || args.burden.is_some()
+        || args.count;
+    dry.output(args.output.as_deref(), text);
+    if args.tee {
+        dry.add("tee", "stdout");
+    }
+    for (key, path) in [
+        ("summary_output", &args.summary_output),
+        ("report", &args.report),
+    ] {
+        if let Some(path) = path {
+            dry.add(key, path);
+        }
+    }
+    // htslib threads for reading and, for a file, writing.
+    dry.add("threads", 2);
+    dry.add("two_pass", args.two_pass);
+    dry.add("sandbox", args.sandbox);
+    dry.add("luau_optimization_level", luau.optimization_level);
+    dry.add("luau_native", luau.native_status());
+    Ok(dry)
+}
+
+fn check_main(mut args: CheckArgs) -> Result<(), Box<dyn std::error::Error>> {
+    env_logger::init();
+    if let Some(snippets) = use_packs(&args.pack, &mut args.lua_prelude, args.snippets.as_ref())? {
+        (args.expression, args.set_expression, args.set_format) = expand_snippets(
+            &snippets,
+            args.expression,
+            args.set_expression,
+            args.set_format,
+        )?;
+    }
+    let codes = named_codes(
+        args.expression,
+        args.set_expression,
+        args.set_format,
+        args.template,
+        args.lua_prelude,
+    )?;
+    let aliases = args
+        .sample_alias
+        .as_deref()
+        .map(SampleAliases::from_path)
+        .transpose()?;
+    let problems = vcfexpress::check::check(&args.path, &codes, aliases.as_ref())?;
+    for p in &problems {
+        println!("{}", p);
+    }
+    if !problems.is_empty() {
+        return Err(format!("found {} problem(s)", problems.len()).into());
+    }
+    Ok(())
+}
+
+fn batch_main(mut args: BatchArgs) -> Result<(), Box<dyn std::error::Error>> {
+    env_logger::init();
+    if let Some(snippets) = use_packs(&args.pack, &mut args.lua_prelude, args.snippets.as_ref())? {
+        (args.expression, args.set_expression, _) = expand_snippets(
+            &snippets,
+            args.expression,
+            args.set_expression,
+            vec![],
+        )?;
+    }
+    let paths = vcfexpress::headercheck::expand_globs(&args.paths)?;
+    let opts = BatchOptions {
+        expressions: args.expression,
+        set_expressions: args.set_expression,
+        template: args.template,
+        lua_prelude: args.lua_prelude,
+        output_dir: args.output_dir,
+        threads: args.threads,
+        sandbox: args.sandbox,
+    };
+    let summaries = run_batch(&opts, &paths)?;
+    println!("#path\toutput\tevaluated\tpassing");
+    for s in &summaries {
+        println!("{}", s);
+    }
+    let failed = summaries.iter().filter(|s| s.result.is_err()).count();
+    if failed > 0 {
+        return Err(format!("{} of {} file(s) failed", failed, summaries.len()).into());
+    }
+    Ok(())
+}
+
+/// Load each installed `--pack`: its preludes are added before `lua_prelude` and its snippets are
+/// combined with those of `--snippets`.
+fn use_packs(
+    packs: &[String],
+    lua_prelude: &mut Vec<String>,
+    snippets: Option<&String>,
+) -> Result<Option<Snippets>, Box<dyn std::error::Error>> {
+    let mut preludes = vec![];
+    let mut snippet_paths = vec![];
+    for reference in packs {
+        let pack = Pack::load(reference, &packs_dir())?;
+        log::info!("using pack {}@{}", pack.name, pack.version);
+        preludes.extend(pack.preludes);
+        snippet_paths.extend(pack.snippets);
+    }
+    preludes.append(lua_prelude);
+    *lua_prelude = preludes;
+    snippet_paths.extend(snippets.cloned());
+    if snippet_paths.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(Snippets::from_paths(&snippet_paths)?))
+}
+
+/// Expand the `@name` snippets in the expressions, set expressions and set-format expressions.
+#[allow(clippy::type_complexity)]
+fn expand_snippets(
+    snippets: &Snippets,
+    expression: Vec<String>,
+    set_expression: Vec<String>,
+    set_format: Vec<String>,
+) -> std::io::Result<(Vec<String>, Vec<String>, Vec<String>)> {
+    Ok((
+        expression
+            .iter()
+            .map(|e| snippets.expand_expression(e))
+            .collect::<std::io::Result<_>>()?,
+        set_expression
+            .iter()
+            .map(|e| snippets.expand_set_expression(e))
+            .collect::<std::io::Result<_>>()?,
+        set_format
+            .iter()
+            .map(|e| snippets.expand_set_expression(e))
+            .collect::<std::io::Result<_>>()?,
+    ))
+}
+
+fn filter_main(args: FilterArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let started = std::time::Instant::now();
+    env_logger::init();
+    // with --report, a failed run writes the report too, with the error.
+    let mut run_report = args.report.clone().map(|path| {
+        (
+            path,
+            RunReport {
+                input: args.path.clone(),
+                output: args.output.clone(),
+                expressions: args.expression.clone(),
+                ..Default::default()
+            },
+        )
+    });
+    let result = filter_run(args, started, &mut run_report);
+    if let (Err(e), Some((path, mut run_report))) = (&result, run_report) {
+        run_report.error = Some(e.to_string());
+        run_report.wall_seconds = started.elapsed().as_secs_f64();
+        run_report.write(&path)?;
+    }
+    result
+}
+
+fn filter_run(
+    mut args: FilterArgs,
+    started: std::time::Instant,
+    run_report: &mut Option<(String, RunReport)>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(snippets) = use_packs(&args.pack, &mut args.lua_prelude, args.snippets.as_ref())? {
+        (args.expression, args.set_expression, args.set_format) = expand_snippets(
+            &snippets,
+            args.expression,
+            args.set_expression,
+            args.set_format,
+        )?;
+        args.group_by = args
+            .group_by
+            .map(|g| snippets.expand_expression(&g))
+            .transpose()?;
+        args.burden = args
+            .burden
+            .map(|g| snippets.expand_expression(&g))
+            .transpose()?;
+    }
+    if args.explain_plan {
+        let reader = vcfexpress::regions::open_reader(&args.path)?;
+        let codes = named_codes(
+            args.expression,
+            args.set_expression,
+            args.set_format,
+            args.template,
+            args.lua_prelude,
+        )?;
+        for (name, code) in &codes {
+            print!(
+                "{}",
+                vcfexpress::check::explain(reader.header(), name, code)
+            );
+        }
+        return Ok(());
+    }
+    // checked before the input is opened so that stdin is not read.
+    if args.two_pass && matches!(args.path.as_str(), "-" | "stdin") {
+        return Err("--two-pass requires an input file; stdin can not be read twice".into());
+    }
+    let lua = Lua::new();
+    let luau = LuauOptions {
+        optimization_level: if args.luau_o2 { 2 } else { 1 },
+        native: args.luau_native,
+    };
+    luau.apply(&lua);
+    let profile = args.profile.then_some(luau);
+    let expression_summary = args.expression_summary.then(|| args.expression.clone());
+    // the expressions after expanding snippets.
+    if let Some((_, r)) = run_report {
+        r.expressions = args.expression.clone();
+    }
+    let aliases = args
+        .sample_alias
+        .as_deref()
+        .map(SampleAliases::from_path)
+        .transpose()?;
+    let samples = args
+        .samples
+        .iter()
+        .map(|s| match &aliases {
+            Some(a) => a.resolve(s).to_string(),
+            None => s.clone(),
+        })
+        .collect::<Vec<_>>();
+    let renames = Renames {
+        info: parse_pairs(&args.rename_info)?,
+        format: parse_pairs(&args.rename_format)?,
+    };
+    if args.dry_run {
+        print!("{}", dry_run(&args, &lua, luau, &samples)?);
+        return Ok(());
+    }
+
+    let mut vcfexpr = VCFExpress::with_options(
         &lua,
-        path,
-        expressions,
-        set_expression,
-        template,
-        lua_prelude,
-        output,
-        sandbox,
+        args.path.clone(),
+        args.expression,
+        args.set_expression,
+        args.template,
+        args.lua_prelude,
+        args.output,
+        args.sandbox,
+        VCFExpressOptions {
+            renames,
+            retypes: parse_retypes(&args.retype_info)?,
+            template_nil: match (args.template_nil_as, args.strict_template) {
+                (_, true) => TemplateNil::Strict,
+                (Some(s), false) => TemplateNil::As(s),
+                (None, false) => TemplateNil::Default,
+            },
+            vcf_version: args.vcf_version,
+            set_format: args.set_format,
+            samples,
+            reference: args
+                .fasta
+                .as_deref()
+                .map(|f| Reference::from_path(f, args.fasta_add_contigs))
+                .transpose()?,
+            group_by: args.group_by,
+        },
     )?;
+    if let Some(path) = &args.rename_chrs {
+        vcfexpr.rename_contigs(&vcfexpress::header::read_contig_renames(path)?)?;
+    }
+    if let Some(soft_filter) = &args.soft_filter {
+        let (name, description) = soft_filter.split_once(':').unwrap_or((
+            soft_filter,
+            "Set if the record failed the vcfexpress expressions",
+        ));
+        vcfexpr.set_soft_filter(name, description, args.soft_filter_append)?;
+    }
+    if args.header_only {
+        // the header is written when the writer is created.
+        drop(vcfexpr.writer()?);
+        return Ok(());
+    }
+    if let Some(aliases) = aliases {
+        vcfexpr.set_sample_aliases(aliases);
+    }
+    if args.skip_symbolic_alleles {
+        vcfexpr.skip_symbolic_alleles();
+    }
+    if args.lenient_samples {
+        vcfexpr.lenient_samples();
+    }
+    vcfexpr.set_one_based_template_pos(args.coords == 1);
+    vcfexpr.set_record_slice(args.skip, args.max_records);
+    vcfexpr.set_progress_every(args.progress_every);
+    vcfexpr.set_summary_output(args.summary_output);
+    vcfexpr.set_tee(args.tee);
+    vcfexpr.set_max_assert_failures(args.max_assert_failures);
+    vcfexpr.set_per_sample(args.per_sample);
+    vcfexpr.set_per_allele(args.per_allele);
+    vcfexpr.set_alt_filter_translation(if args.alt_filter_to_filter {
+        Some(AltFilterTranslation::ToFilter)
+    } else if args.filter_to_alt_filter {
+        Some(AltFilterTranslation::FromFilter)
+    } else {
+        None
+    })?;
+    vcfexpr.set_round_format(parse_round_format(&args.round_format)?)?;
+    vcfexpr.set_prune_empty(args.prune_empty);
+    vcfexpr.set_group_reducers(parse_reducers(&args.group_reduce)?)?;
+    vcfexpr.set_window_density(args.window_density);
+    vcfexpr.set_burden(args.burden)?;
+    vcfexpr.set_count(args.count);
+    // per-expression counts are not collected with --per-sample or --per-allele.
+    let report_counts = args.report.is_some() && !args.per_sample && !args.per_allele;
+    if args.count_per_expression || report_counts {
+        vcfexpr.count_per_expression();
+    }
+    if args.no_percent_encoding {
+        vcfexpr.disable_percent_encoding();
+    }
+    if let Some(ac_index) = args.ac_index {
+        vcfexpr.load_ac_index(&ac_index)?;
+    }
+    if !args.coverage.is_empty() {
+        vcfexpr.load_coverage(&args.coverage)?;
+    }
+    if let Some(ped) = args.ped {
+        vcfexpr.load_pedigree(&ped)?;
+    }
+    if let Some(roh) = args.roh {
+        vcfexpr.load_roh(&roh)?;
+    }
+    if let Some(baseline) = args.baseline {
+        vcfexpr.load_baseline(&baseline)?;
+    }
+    if let Some(targets) = args.targets_file {
+        vcfexpr.load_targets(&targets)?;
+    }
+    vcfexpr.load_exclude_bed(&args.exclude_bed)?;
+    if let Some(fraction) = args.sample_fraction {
+        vcfexpr.set_sample_fraction(fraction, args.seed)?;
+    }
+    vcfexpr.load_callsets(&args.isec, args.isec_any, &args.exclude_vcf)?;
+    if let Some(sample_cache) = args.sample_cache {
+        vcfexpr.load_sample_cache(&sample_cache)?;
+    }
+
+    let result = filter_records(
+        &mut vcfexpr,
+        &args.path,
+        &args.region,
+        args.region_slop,
+        args.require_index,
+        args.two_pass,
+    );
+    // the counts so far are reported if reading or evaluating fails.
+    if let Some((_, r)) = run_report {
+        r.set_counts(&vcfexpr);
+    }
+    result?;
+    report(
+        &vcfexpr,
+        started,
+        profile,
+        expression_summary.as_deref(),
+        run_report.take(),
+    )?;
+    Ok(())
+}
 
+/// Read the records (of the --region(s)), evaluate them and write the output.
+fn filter_records(
+    vcfexpr: &mut VCFExpress,
+    path: &str,
+    region: &[vcfexpress::regions::Region],
+    region_slop: u64,
+    require_index: bool,
+    two_pass: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // region readers read the samples kept by --samples or header.samples in the prelude, as
+    // the reader of vcfexpr does, and find records in the sample cache by position.
+    let samples = vcfexpr
+        .header()
+        .samples()
+        .iter()
+        .map(|s| String::from_utf8_lossy(s).to_string())
+        .collect::<Vec<_>>();
+    let open_regions = || -> Result<RegionReader, Box<dyn std::error::Error>> {
+        let mut reader = RegionReader::new(path, region, region_slop, require_index)?;
+        if reader.header().sample_count() != samples.len() as u32 {
+            reader.subset_samples(&samples)?;
+        }
+        Ok(reader)
+    };
+    if !region.is_empty() {
+        vcfexpr.index_sample_cache();
+    }
+    if two_pass {
+        // the first pass reads the same --region(s) as the second.
+        if region.is_empty() {
+            let mut reader = vcfexpress::regions::open_reader(path)?;
+            _ = reader.set_threads(2);
+            let mut records = reader.records();
+            vcfexpr.first_pass(|| records.next())?;
+        } else {
+            let mut reader = open_regions()?;
+            vcfexpr.first_pass(|| {
+                let mut record = reader.empty_record();
+                reader.read(&mut record).map(|r| r.map(|_| record))
+            })?;
+        }
+    }
+    // opened before the writer so that a missing index with --require-index writes nothing.
+    let region_reader = if region.is_empty() {
+        None
+    } else {
+        Some(open_regions()?)
+    };
     let mut reader = vcfexpr.reader();
-    let mut writer = vcfexpr.writer();
+    let mut writer = vcfexpr.writer()?;
 
     let header_map = HeaderMap::new();
 
-    for record in reader.records() {
-        let mut record = record?;
-        writer.translate(&mut record);
+    if let Some(mut reader) = region_reader {
+        if !reader.is_indexed() {
+            eprintln!(
+                "[vcfexpress] warning: no index found for {}; scanning the whole file for the --region(s). create one with `vcfexpress index` or use --require-index to fail instead",
+                path
+            );
+        }
+        vcfexpr.renames().add_definitions(reader.header())?;
+        vcfexpr.start()?;
+        loop {
+            let mut record = reader.empty_record();
+            match reader.read(&mut record) {
+                Some(r) => r?,
+                None => break,
+            }
+            let Some(in_slice) = vcfexpr.in_slice() else {
+                break;
+            };
+            if !in_slice || !vcfexpr.keep_record(&record)? {
+                continue;
+            }
+            vcfexpr.translate(&mut writer, &mut record)?;
+            let mut sob = vcfexpr.evaluate(record, header_map.clone())?;
+            writer.write(&mut sob)?;
+        }
+        if reader.is_indexed() {
+            eprintln!(
+                "[vcfexpress] read {} region(s) with {} range request(s)",
+                region.len(),
+                reader.requests()
+            );
+        }
+        vcfexpr.finish(&mut writer)?;
+        return Ok(());
+    }
+
+    vcfexpr.start()?;
+    // the position of each record in a BCF or .vcf.gz for variant.file_offset.
+    let offset = BgzfOffset::new(&reader);
+    loop {
+        let file_offset = offset.as_ref().map(|o| o.tell());
+        let mut record = reader.empty_record();
+        match reader.read(&mut record) {
+            Some(r) => r?,
+            None => break,
+        }
+        let Some(in_slice) = vcfexpr.in_slice() else {
+            break;
+        };
+        if !in_slice || !vcfexpr.keep_record(&record)? {
+            continue;
+        }
+        vcfexpr.translate(&mut writer, &mut record)?;
+        vcfexpr.set_file_offset(file_offset);
         let mut sob = vcfexpr.evaluate(record, header_map.clone())?;
         writer.write(&mut sob)?;
     }
+    vcfexpr.finish(&mut writer)?;
+    Ok(())
+}
+
+// summaries printed to stderr at the end of filter.
+fn report(
+    vcfexpr: &VCFExpress,
+    started: std::time::Instant,
+    profile: Option<LuauOptions>,
+    expression_summary: Option<&[String]>,
+    run_report: Option<(String, RunReport)>,
+) -> std::io::Result<()> {
+    if vcfexpr.skipped_records() > 0 {
+        eprintln!(
+            "[vcfexpress] {} record(s) skipped before evaluation (--sample-fraction/--targets-file/--exclude-bed/--isec/--exclude-vcf)",
+            vcfexpr.skipped_records()
+        );
+    }
+    let (skipped, truncated) = vcfexpr.record_slice();
+    if skipped > 0 {
+        eprintln!(
+            "[vcfexpress] skipped the first {} record(s) (--skip)",
+            skipped
+        );
+    }
+    if truncated {
+        eprintln!(
+            "[vcfexpress] stopped reading at --max-records; the output and counts cover only part of the input"
+        );
+    }
+    if vcfexpr.counts().0 == 0 && vcfexpr.skipped_records() == 0 && skipped == 0 {
+        eprintln!(
+            "[vcfexpress] 0 records evaluated: the input (or the requested regions) has no records; the output is complete and has no variants"
+        );
+    }
+    if vcfexpr.assert_failures() > 0 {
+        eprintln!(
+            "[vcfexpress] {} assertion failure(s)",
+            vcfexpr.assert_failures()
+        );
+    }
+    if let Some((new, unchanged, dropped)) = vcfexpr.baseline_summary() {
+        eprintln!(
+            "[vcfexpress] compared to baseline: {} new, {} unchanged, {} dropped",
+            new, unchanged, dropped
+        );
+    }
+    if let Some(luau) = profile {
+        let (evaluated, passing) = vcfexpr.counts();
+        let secs = started.elapsed().as_secs_f64();
+        eprintln!(
+            "[vcfexpress] profile: {} records ({} passing) in {:.2}s, {:.0} records/s",
+            evaluated,
+            passing,
+            secs,
+            evaluated as f64 / secs.max(1e-9)
+        );
+        eprintln!(
+            "[vcfexpress] profile: luau optimization level {}, natively compiled: {}",
+            luau.optimization_level,
+            luau.native_status()
+        );
+    }
+    if let Some(expressions) = expression_summary {
+        eprintln!("[vcfexpress] expression\tevaluated\tpassing\tdecisive");
+        for (e, t) in expressions.iter().zip(vcfexpr.expression_tallies()) {
+            eprintln!(
+                "[vcfexpress] {}\t{}\t{}\t{}",
+                e, t.evaluated, t.passing, t.decisive
+            );
+        }
+    }
+    if let Some((path, mut run_report)) = run_report {
+        run_report.set_counts(vcfexpr);
+        run_report.wall_seconds = started.elapsed().as_secs_f64();
+        run_report.write(&path)?;
+    }
     Ok(())
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Cli::parse();
     match args.command {
-        Some(Commands::Filter {
+        Some(Commands::Filter(args)) => {
+            filter_main(*args)?;
+        }
+        Some(Commands::Header(args)) => {
+            header_main(args)?;
+        }
+        Some(Commands::CheckHeaders { paths }) => {
+            env_logger::init();
+            let paths = vcfexpress::headercheck::expand_globs(&paths)?;
+            let diffs = vcfexpress::headercheck::check_headers(&paths)?;
+            for d in &diffs {
+                println!("{}", d);
+            }
+            if !diffs.is_empty() {
+                return Err(format!(
+                    "found {} header difference(s) among {} files",
+                    diffs.len(),
+                    paths.len()
+                )
+                .into());
+            }
+        }
+        Some(Commands::Check(args)) => {
+            check_main(args)?;
+        }
+        Some(Commands::Batch(args)) => {
+            batch_main(args)?;
+        }
+        Some(Commands::Sort {
             path,
+            key,
+            reverse,
+            max_memory,
+            tmp_dir,
+            output,
+        }) => {
+            env_logger::init();
+            let options = vcfexpress::sort::SortOptions {
+                max_memory: vcfexpress::sort::parse_size(&max_memory)?,
+                tmp_dir,
+            };
+            vcfexpress::sort::sort(&path, output.as_deref(), key.as_deref(), reverse, options)?;
+        }
+        Some(Commands::Split {
+            path,
+            chrom,
+            sample,
+            key,
+            prefix,
+            suffix,
+            max_open,
             expression,
-            set_expression,
-            template,
             lua_prelude,
+            manifest,
+        }) => {
+            env_logger::init();
+            let by = match (chrom, sample, key) {
+                (true, _, _) => vcfexpress::split::SplitBy::Chrom,
+                (_, true, _) => vcfexpress::split::SplitBy::Sample,
+                (_, _, Some(key)) => vcfexpress::split::SplitBy::Key(key),
+                _ => unreachable!("clap requires one of --chrom, --sample or --key"),
+            };
+            let options = vcfexpress::split::SplitOptions {
+                prefix,
+                suffix,
+                max_open,
+            };
+            let m = vcfexpress::split::split(&path, &by, &options, expression, lua_prelude)?;
+            log::info!("wrote {} files", m.entries().len());
+            if let Some(manifest) = manifest {
+                m.write_path(&manifest)?;
+            }
+        }
+        Some(Commands::Index {
+            path,
+            csi,
+            min_shift,
+            force,
+            threads,
+        }) => {
+            env_logger::init();
+            let index = vcfexpress::index::index(&path, csi, min_shift, threads, force)?;
+            log::info!("wrote {}", index);
+        }
+        Some(Commands::Reheader {
+            path,
+            header,
+            samples,
+            fai,
+            max_memory,
+            tmp_dir,
             output,
-            sandbox,
         }) => {
-            filter_main(
-                path,
+            env_logger::init();
+            let options = vcfexpress::sort::SortOptions {
+                max_memory: vcfexpress::sort::parse_size(&max_memory)?,
+                tmp_dir,
+            };
+            vcfexpress::reheader::reheader(
+                &path,
+                output.as_deref(),
+                header.as_deref(),
+                samples.as_deref(),
+                fai.as_deref(),
+                options,
+            )?;
+        }
+        Some(Commands::Annotate {
+            path,
+            tsv,
+            columns,
+            ref_alt_columns,
+            vcf,
+            fields,
+            prefix,
+            id,
+            bed,
+            output,
+        }) => {
+            env_logger::init();
+            let mut annotators: Vec<Box<dyn vcfexpress::annotate::Annotator>> = vec![];
+            if let Some(tsv) = tsv {
+                let ref_alt = ref_alt_columns
+                    .as_deref()
+                    .map(vcfexpress::annotate::parse_ref_alt_columns)
+                    .transpose()?;
+                annotators.push(Box::new(vcfexpress::annotate::TsvAnnotator::new(
+                    &tsv,
+                    vcfexpress::annotate::parse_columns(&columns)?,
+                    ref_alt,
+                )?));
+            }
+            if let Some(vcf) = vcf {
+                annotators.push(Box::new(vcfexpress::annotate::VcfAnnotator::new(
+                    &vcf, &fields, &prefix, id,
+                )?));
+            }
+            for b in bed {
+                let (path, tag) = vcfexpress::annotate::parse_bed_arg(&b);
+                annotators.push(Box::new(vcfexpress::annotate::BedAnnotator::new(
+                    &path, &tag,
+                )?));
+            }
+            if annotators.is_empty() {
+                return Err("annotate needs a source of annotations: --tsv, --vcf or --bed".into());
+            }
+            vcfexpress::annotate::annotate(&path, output.as_deref(), &mut annotators)?;
+        }
+        Some(Commands::Merge {
+            paths,
+            info_rule,
+            output,
+        }) => {
+            env_logger::init();
+            let rules = vcfexpress::merge::parse_info_rules(&info_rule)?;
+            vcfexpress::merge::merge(&paths, output.as_deref(), &rules)?;
+        }
+        Some(Commands::Concat {
+            paths,
+            naive,
+            expression,
+            lua_prelude,
+            max_memory,
+            tmp_dir,
+            output,
+        }) => {
+            env_logger::init();
+            let paths = vcfexpress::headercheck::expand_globs(&paths)?;
+            let options = vcfexpress::sort::SortOptions {
+                max_memory: vcfexpress::sort::parse_size(&max_memory)?,
+                tmp_dir,
+            };
+            vcfexpress::concat::concat(
+                &paths,
+                output.as_deref(),
+                naive,
+                expression,
+                lua_prelude,
+                options,
+            )?;
+        }
+        Some(Commands::Compare { a, b, output }) => {
+            env_logger::init();
+            let cmp = vcfexpress::compare::compare(&a, &b)?;
+            match output {
+                Some(o) => cmp.write(&mut std::io::BufWriter::new(std::fs::File::create(o)?))?,
+                None => cmp.write(&mut std::io::stdout().lock())?,
+            }
+        }
+        Some(Commands::Stats {
+            path,
+            expression,
+            lua_prelude,
+            output,
+        }) => {
+            env_logger::init();
+            let stats = vcfexpress::stats::stats(&path, expression, lua_prelude)?;
+            match output {
+                Some(o) => stats.write(&mut std::io::BufWriter::new(std::fs::File::create(o)?))?,
+                None => stats.write(&mut std::io::stdout().lock())?,
+            }
+        }
+        Some(Commands::Query {
+            path,
+            format,
+            expression,
+            lua_prelude,
+            print_header,
+            output,
+        }) => {
+            env_logger::init();
+            vcfexpress::query::query(
+                &path,
+                &format,
                 expression,
-                set_expression,
-                template,
                 lua_prelude,
                 output,
-                sandbox,
+                print_header,
+            )?;
+        }
+        Some(Commands::Describe { path }) => {
+            let reader = match path.as_str() {
+                "-" | "stdin" => rust_htslib::bcf::Reader::from_stdin()?,
+                _ => rust_htslib::bcf::Reader::from_path(&path)?,
+            };
+            for line in vcfexpress::describe::describe(reader.header()) {
+                println!("{}", line);
+            }
+        }
+        Some(Commands::Pack {
+            reference,
+            lua_prelude,
+            snippets,
+            requires,
+            output,
+        }) => {
+            let output = match output {
+                Some(o) => o,
+                None => format!("{}.vxpack", reference.replace('@', "-")),
+            };
+            Pack::create(
+                &reference,
+                &lua_prelude,
+                snippets.as_deref(),
+                &requires,
+                &output,
             )?;
+            eprintln!("[vcfexpress] wrote {}", output);
+        }
+        Some(Commands::Install { path }) => {
+            let dir = packs_dir();
+            let pack = Pack::install(&path, &dir)?;
+            eprintln!(
+                "[vcfexpress] installed {}@{} in {}",
+                pack.name,
+                pack.version,
+                dir.display()
+            );
+        }
+        Some(Commands::BuildAcIndex { path, output }) => {
+            env_logger::init();
+            let idx = vcfexpress::acindex::AcIndex::build(&path)?;
+            idx.write(std::fs::File::create(output)?)?;
+        }
+        Some(Commands::BuildSampleCache { path, output }) => {
+            env_logger::init();
+            vcfexpress::samplecache::build(&path, &output)?;
         }
         None => {
             println!("No command provided");
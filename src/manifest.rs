@@ -0,0 +1,154 @@
+//! A manifest of the files written by a splitting/sharding mode: the path, number of records and
+//! the regions covered by each file, as TSV or (with a `.json` path) JSON, so that workflow
+//! engines can fan out over the outputs.
+use rustc_hash::FxHashMap;
+use std::io::Write;
+
+#[derive(Debug, Default, PartialEq)]
+pub struct ManifestEntry {
+    pub path: String,
+    pub records: u64,
+    /// (chrom, min 0-based start, max 0-based position) in the order the chromosomes were seen.
+    regions: Vec<(String, i64, i64)>,
+}
+
+impl ManifestEntry {
+    /// Regions as chrom:start-end with 1-based, inclusive coordinates.
+    pub fn regions(&self) -> Vec<String> {
+        self.regions
+            .iter()
+            .map(|(chrom, start, end)| format!("{}:{}-{}", chrom, start + 1, end + 1))
+            .collect()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Manifest {
+    entries: Vec<ManifestEntry>,
+    index: FxHashMap<String, usize>,
+}
+
+pub(crate) fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            // every other control character.
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+impl Manifest {
+    /// Count a record at `chrom`:`pos` (0-based) written to `path`.
+    pub fn add(&mut self, path: &str, chrom: &str, pos: i64) {
+        self.touch(path);
+        let entry = &mut self.entries[self.index[path]];
+        entry.records += 1;
+        match entry.regions.iter_mut().find(|(c, _, _)| c == chrom) {
+            Some((_, start, end)) => {
+                *start = (*start).min(pos);
+                *end = (*end).max(pos);
+            }
+            None => entry.regions.push((chrom.to_string(), pos, pos)),
+        }
+    }
+
+    /// Add `path` without any records, e.g. for a sample without variants.
+    pub fn touch(&mut self, path: &str) {
+        if !self.index.contains_key(path) {
+            self.index.insert(path.to_string(), self.entries.len());
+            self.entries.push(ManifestEntry {
+                path: path.to_string(),
+                ..Default::default()
+            });
+        }
+    }
+
+    /// The files in the order they were first written to.
+    pub fn entries(&self) -> &[ManifestEntry] {
+        &self.entries
+    }
+
+    pub fn write_tsv<W: Write>(&self, mut w: W) -> std::io::Result<()> {
+        writeln!(w, "#path\trecords\tregions")?;
+        for e in &self.entries {
+            writeln!(w, "{}\t{}\t{}", e.path, e.records, e.regions().join(","))?;
+        }
+        Ok(())
+    }
+
+    pub fn write_json<W: Write>(&self, mut w: W) -> std::io::Result<()> {
+        writeln!(w, "[")?;
+        for (i, e) in self.entries.iter().enumerate() {
+            let regions = e
+                .regions()
+                .iter()
+                .map(|r| json_string(r))
+                .collect::<Vec<_>>();
+            writeln!(
+                w,
+                "  {{\"path\": {}, \"records\": {}, \"regions\": [{}]}}{}",
+                json_string(&e.path),
+                e.records,
+                regions.join(", "),
+                if i + 1 < self.entries.len() { "," } else { "" }
+            )?;
+        }
+        writeln!(w, "]")
+    }
+
+    /// Write to `path` as JSON if it ends with .json, otherwise as TSV.
+    pub fn write_path(&self, path: &str) -> std::io::Result<()> {
+        let w = std::io::BufWriter::new(std::fs::File::create(path)?);
+        if path.ends_with(".json") {
+            self.write_json(w)
+        } else {
+            self.write_tsv(w)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest() {
+        let mut m = Manifest::default();
+        m.add("a.vcf.gz", "chr1", 99);
+        m.add("b.vcf.gz", "chr2", 9);
+        m.add("a.vcf.gz", "chr1", 9);
+        m.add("a.vcf.gz", "chrX", 0);
+        assert_eq!(m.entries().len(), 2);
+        assert_eq!(m.entries()[0].records, 3);
+        assert_eq!(m.entries()[0].regions(), ["chr1:10-100", "chrX:1-1"]);
+
+        let mut tsv = vec![];
+        m.write_tsv(&mut tsv).unwrap();
+        assert_eq!(
+            String::from_utf8(tsv).unwrap(),
+            "#path\trecords\tregions\na.vcf.gz\t3\tchr1:10-100,chrX:1-1\nb.vcf.gz\t1\tchr2:10-10\n"
+        );
+        let mut json = vec![];
+        m.write_json(&mut json).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        assert!(json.contains(
+            r#"{"path": "a.vcf.gz", "records": 3, "regions": ["chr1:10-100", "chrX:1-1"]},"#
+        ));
+        assert!(json.ends_with("\"regions\": [\"chr2:10-10\"]}\n]\n"));
+        assert_eq!(json_string("a\"b"), r#""a\"b""#);
+        assert_eq!(
+            json_string("a\r\n\u{1}\u{1f}b\\"),
+            r#""a\r\n\u0001\u001fb\\""#
+        );
+    }
+}
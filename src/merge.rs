@@ -0,0 +1,776 @@
+//! Merge VCF/BCFs with different samples (e.g. single-sample calls) over the same reference into
+//! one multi-sample file (`vcfexpress merge`). The inputs must be sorted in the same contig order;
+//! records at the same position with the same REF are merged into one with the union of their
+//! ALTs. GT and the Number=A, R and G FORMAT fields are remapped to the merged alleles and samples
+//! without a record at the site get missing values. INFO fields take the value of the first
+//! record unless a rule is given with `--info-rule DP=sum` (see [`InfoRule`]). QUAL is the
+//! highest and FILTER is PASS if any record passes, otherwise the union of the filters.
+use rust_htslib::bcf::{
+    self,
+    header::{HeaderRecord, HeaderView, TagLength, TagType},
+    Read,
+};
+use rust_htslib::htslib;
+use std::io;
+
+use crate::genotypes::allele_index;
+use crate::variant::Sentinel;
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+/// How the values of an INFO field from the merged records are combined. Number=A and Number=R
+/// fields are combined for each merged allele, other fields for each position of their values.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InfoRule {
+    /// the first record with a value.
+    First,
+    Sum,
+    Min,
+    Max,
+    Mean,
+    /// the distinct values separated by `|` (String fields).
+    Join,
+}
+
+/// Parse `TAG=rule,TAG2=rule` pairs. rule is one of first, sum, min, max, mean or join.
+pub fn parse_info_rules(args: &[String]) -> io::Result<Vec<(String, InfoRule)>> {
+    crate::rename::parse_pairs(args)?
+        .into_iter()
+        .map(|(tag, rule)| {
+            let rule = match rule.as_str() {
+                "first" => InfoRule::First,
+                "sum" => InfoRule::Sum,
+                "min" => InfoRule::Min,
+                "max" => InfoRule::Max,
+                "mean" => InfoRule::Mean,
+                "join" => InfoRule::Join,
+                r => {
+                    return Err(invalid(format!(
+                        "unknown rule '{}' for {}; expected first, sum, min, max, mean or join",
+                        r, tag
+                    )))
+                }
+            };
+            Ok((tag, rule))
+        })
+        .collect()
+}
+
+fn combine_numbers(values: &[f64], rule: InfoRule) -> Option<f64> {
+    let first = *values.first()?;
+    Some(match rule {
+        InfoRule::First | InfoRule::Join => first,
+        InfoRule::Sum => values.iter().sum(),
+        InfoRule::Min => values.iter().copied().fold(first, f64::min),
+        InfoRule::Max => values.iter().copied().fold(first, f64::max),
+        InfoRule::Mean => values.iter().sum::<f64>() / values.len() as f64,
+    })
+}
+
+fn combine_strings(values: &[String], rule: InfoRule) -> Option<String> {
+    let first = values.first()?;
+    match rule {
+        InfoRule::Join => {
+            let mut distinct: Vec<&str> = vec![];
+            for v in values {
+                if !distinct.contains(&v.as_str()) {
+                    distinct.push(v);
+                }
+            }
+            Some(distinct.join("|"))
+        }
+        _ => Some(first.clone()),
+    }
+}
+
+struct Input {
+    path: String,
+    reader: bcf::Reader,
+    record: bcf::Record,
+    // false once the file is read.
+    has_record: bool,
+    // (rid in the merged header, position) of the current record.
+    key: (u32, i64),
+    // the first sample of this file in the merged samples.
+    offset: usize,
+}
+
+impl Input {
+    fn advance(&mut self, hv: &HeaderView) -> Result<(), Box<dyn std::error::Error>> {
+        match self.reader.read(&mut self.record) {
+            None => self.has_record = false,
+            Some(r) => {
+                r?;
+                let chrom = self
+                    .record
+                    .rid()
+                    .and_then(|rid| self.record.header().rid2name(rid).ok())
+                    .ok_or_else(|| format!("record without a contig in {}", self.path))?;
+                let key = (hv.name2rid(chrom)?, self.record.pos());
+                if self.has_record && key < self.key {
+                    return Err(format!(
+                        "{} is not sorted (or not in the contig order of the other inputs) at {}:{}",
+                        self.path,
+                        String::from_utf8_lossy(chrom),
+                        self.record.pos() + 1
+                    )
+                    .into());
+                }
+                self.key = key;
+                self.has_record = true;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The IDs of the INFO or FORMAT fields of `hv` in header order.
+fn tags(hv: &HeaderView, format: bool) -> Vec<String> {
+    hv.header_records()
+        .into_iter()
+        .filter_map(|rec| match rec {
+            HeaderRecord::Info { values, .. } if !format => values.get("ID").cloned(),
+            HeaderRecord::Format { values, .. } if format => values.get("ID").cloned(),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Move the values of one sample from the alleles of its record to the merged alleles with
+/// `map` (record allele -> merged allele) for a Number=A, R or G field.
+fn remap<T: Sentinel>(values: &[T], length: &TagLength, map: &[usize], n: usize) -> Vec<T> {
+    let old = map.len();
+    match length {
+        TagLength::AltAlleles if values.len() == old - 1 => {
+            let mut out = vec![T::missing(); n - 1];
+            for (i, v) in values.iter().enumerate() {
+                out[map[i + 1] - 1] = *v;
+            }
+            out
+        }
+        TagLength::Alleles if values.len() == old => {
+            let mut out = vec![T::missing(); n];
+            for (i, v) in values.iter().enumerate() {
+                out[map[i]] = *v;
+            }
+            out
+        }
+        // diploid genotypes in the VCF order: (a, b) with a <= b at b * (b + 1) / 2 + a.
+        TagLength::Genotypes if values.len() == old * (old + 1) / 2 => {
+            let mut out = vec![T::missing(); n * (n + 1) / 2];
+            for b in 0..old {
+                for a in 0..=b {
+                    let (lo, hi) = (map[a].min(map[b]), map[a].max(map[b]));
+                    out[hi * (hi + 1) / 2 + lo] = values[b * (b + 1) / 2 + a];
+                }
+            }
+            out
+        }
+        // haploid
+        TagLength::Genotypes if values.len() == old => {
+            let mut out = vec![T::missing(); n];
+            for (i, v) in values.iter().enumerate() {
+                out[map[i]] = *v;
+            }
+            out
+        }
+        _ => values.to_vec(),
+    }
+}
+
+/// Pad the values of each sample with vector_end to the same length; None if all are missing.
+fn pad<T: Sentinel>(samples: Vec<Vec<T>>) -> Option<Vec<T>> {
+    if samples
+        .iter()
+        .all(|s| s.iter().all(|v| v.is_missing_value()))
+    {
+        return None;
+    }
+    let width = samples.iter().map(|s| s.len()).max().unwrap_or(1).max(1);
+    let mut out = Vec::with_capacity(width * samples.len());
+    for mut s in samples {
+        if s.is_empty() {
+            s.push(T::missing());
+        }
+        s.resize(width, T::vector_end());
+        out.extend(s);
+    }
+    Some(out)
+}
+
+/// The values of FORMAT `tag` for each sample of `record` without the vector_end padding.
+fn format_values<T: Sentinel>(samples: Vec<&[T]>) -> Vec<Vec<T>> {
+    samples
+        .iter()
+        .map(|s| {
+            s.iter()
+                .take_while(|v| !v.is_vector_end())
+                .copied()
+                .collect()
+        })
+        .collect()
+}
+
+/// Merge the records of `group` (indexes into `inputs`, all at the same position with the same
+/// REF) into `out`.
+fn merge_records(
+    hv: &HeaderView,
+    inputs: &[Input],
+    group: &[usize],
+    out: &mut bcf::Record,
+    n_samples: usize,
+    info_tags: &[(String, TagType, TagLength, InfoRule)],
+    format_tags: &[(String, TagType, TagLength)],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let records = group.iter().map(|&k| &inputs[k].record).collect::<Vec<_>>();
+    let first = records[0];
+    out.set_rid(Some(inputs[group[0]].key.0));
+    out.set_pos(first.pos());
+
+    // the merged alleles and, for each record, the index of each of its alleles in them.
+    let mut alleles: Vec<Vec<u8>> = vec![first.alleles()[0].to_vec()];
+    let mut maps: Vec<Vec<usize>> = vec![];
+    for r in &records {
+        let mut map = vec![0];
+        for alt in &r.alleles()[1..] {
+            let i = match alleles.iter().position(|a| a == alt) {
+                Some(i) => i,
+                None => {
+                    alleles.push(alt.to_vec());
+                    alleles.len() - 1
+                }
+            };
+            map.push(i);
+        }
+        maps.push(map);
+    }
+    let n = alleles.len();
+    out.set_alleles(&alleles.iter().map(|a| a.as_slice()).collect::<Vec<_>>())?;
+
+    let mut ids: Vec<Vec<u8>> = vec![];
+    for r in &records {
+        for id in r.id().split(|c| *c == b';') {
+            if id != b"." && !ids.iter().any(|i| i == id) {
+                ids.push(id.to_vec());
+            }
+        }
+    }
+    if !ids.is_empty() {
+        out.set_id(&ids.join(&b';'))?;
+    }
+    // empty records have QUAL 0; it is missing unless a record has one.
+    out.set_qual(f32::missing());
+    if let Some(q) = records
+        .iter()
+        .map(|r| r.qual())
+        .filter(|q| !q.is_missing_value())
+        .reduce(f32::max)
+    {
+        out.set_qual(q);
+    }
+
+    let mut filters: Vec<Vec<u8>> = vec![];
+    let mut pass = false;
+    for r in &records {
+        for id in r.filters() {
+            let name = r.header().id_to_name(id);
+            if name == b"PASS" {
+                pass = true;
+            } else if !filters.contains(&name) {
+                filters.push(name);
+            }
+        }
+    }
+    if pass {
+        out.push_filter(b"PASS".as_slice())?;
+    } else {
+        for f in &filters {
+            out.push_filter(&hv.name_to_id(f)?)?;
+        }
+    }
+
+    for (tag, typ, length, rule) in info_tags {
+        let t = tag.as_bytes();
+        // the values of each record go to a slot for each merged allele (Number=A and R) or for
+        // each position.
+        let expected = |map: &[usize]| match length {
+            TagLength::AltAlleles => Some(map.len() - 1),
+            TagLength::Alleles => Some(map.len()),
+            _ => None,
+        };
+        let slot = |map: &[usize], i: usize| match length {
+            TagLength::AltAlleles => map[i + 1] - 1,
+            TagLength::Alleles => map[i],
+            _ => i,
+        };
+        let width = match length {
+            TagLength::AltAlleles => n - 1,
+            TagLength::Alleles => n,
+            _ => 0,
+        };
+        match typ {
+            TagType::Flag => {
+                if records.iter().any(|r| r.info(t).flag().unwrap_or(false)) {
+                    out.push_info_flag(t)?;
+                }
+            }
+            TagType::Integer | TagType::Float => {
+                let mut slots: Vec<Vec<f64>> = vec![vec![]; width];
+                for (r, map) in records.iter().zip(&maps) {
+                    let values: Vec<Option<f64>> = if *typ == TagType::Integer {
+                        match r.info(t).integer() {
+                            Ok(Some(v)) => v
+                                .iter()
+                                .filter(|v| !v.is_vector_end())
+                                .map(|v| (!v.is_missing_value()).then_some(*v as f64))
+                                .collect(),
+                            _ => continue,
+                        }
+                    } else {
+                        match r.info(t).float() {
+                            Ok(Some(v)) => v
+                                .iter()
+                                .filter(|v| !v.is_vector_end())
+                                .map(|v| (!v.is_missing_value()).then_some(*v as f64))
+                                .collect(),
+                            _ => continue,
+                        }
+                    };
+                    if expected(map).is_some_and(|e| e != values.len()) {
+                        continue;
+                    }
+                    for (i, v) in values.into_iter().enumerate() {
+                        let s = slot(map, i);
+                        if s >= slots.len() {
+                            slots.resize(s + 1, vec![]);
+                        }
+                        slots[s].extend(v);
+                    }
+                }
+                let combined = slots
+                    .iter()
+                    .map(|s| combine_numbers(s, *rule))
+                    .collect::<Vec<_>>();
+                if combined.iter().all(|v| v.is_none()) {
+                    continue;
+                }
+                if *typ == TagType::Integer {
+                    let v = combined
+                        .iter()
+                        .map(|v| v.map_or(i32::missing(), |v| v.round() as i32))
+                        .collect::<Vec<_>>();
+                    out.push_info_integer(t, &v)?;
+                } else {
+                    let v = combined
+                        .iter()
+                        .map(|v| v.map_or(f32::missing(), |v| v as f32))
+                        .collect::<Vec<_>>();
+                    out.push_info_float(t, &v)?;
+                }
+            }
+            TagType::String => {
+                let mut slots: Vec<Vec<String>> = vec![vec![]; width];
+                for (r, map) in records.iter().zip(&maps) {
+                    let Ok(Some(values)) = r.info(t).string() else {
+                        continue;
+                    };
+                    if expected(map).is_some_and(|e| e != values.len()) {
+                        continue;
+                    }
+                    for (i, v) in values.iter().enumerate() {
+                        let s = slot(map, i);
+                        if s >= slots.len() {
+                            slots.resize(s + 1, vec![]);
+                        }
+                        if *v != b"." {
+                            slots[s].push(String::from_utf8_lossy(v).to_string());
+                        }
+                    }
+                }
+                let combined = slots
+                    .iter()
+                    .map(|s| combine_strings(s, *rule))
+                    .collect::<Vec<_>>();
+                if combined.iter().all(|v| v.is_none()) {
+                    continue;
+                }
+                let v = combined
+                    .iter()
+                    .map(|v| v.as_deref().unwrap_or(".").as_bytes())
+                    .collect::<Vec<_>>();
+                out.push_info_string(t, &v)?;
+            }
+        }
+    }
+
+    // the merged sample index of each sample of each record.
+    let offsets = group.iter().map(|&k| inputs[k].offset).collect::<Vec<_>>();
+    for (tag, typ, length) in format_tags {
+        let t = tag.as_bytes();
+        if tag == "GT" {
+            let mut samples: Vec<Vec<i32>> = vec![vec![]; n_samples];
+            let mut ploidy = 0;
+            let mut found = false;
+            for ((r, map), offset) in records.iter().zip(&maps).zip(&offsets) {
+                let Ok(gts) = r.format(t).integer() else {
+                    continue;
+                };
+                found = true;
+                for (i, gt) in format_values(gts.to_vec()).into_iter().enumerate() {
+                    ploidy = ploidy.max(gt.len());
+                    samples[offset + i] = gt
+                        .iter()
+                        .map(|&raw| match allele_index(raw) {
+                            a if a < 0 => raw,
+                            a => ((map[a as usize] as i32 + 1) << 1) | (raw & 1),
+                        })
+                        .collect();
+                }
+            }
+            if !found {
+                continue;
+            }
+            let mut v = Vec::with_capacity(n_samples * ploidy);
+            for mut s in samples {
+                if s.is_empty() {
+                    // missing genotype for a sample without a record at the site.
+                    s = vec![0; ploidy];
+                }
+                s.resize(ploidy, i32::vector_end());
+                v.extend(s);
+            }
+            out.push_format_integer(t, &v)?;
+            continue;
+        }
+        match typ {
+            TagType::Integer => {
+                let mut samples: Vec<Vec<i32>> = vec![vec![]; n_samples];
+                for ((r, map), offset) in records.iter().zip(&maps).zip(&offsets) {
+                    let Ok(values) = r.format(t).integer() else {
+                        continue;
+                    };
+                    for (i, v) in format_values(values.to_vec()).into_iter().enumerate() {
+                        samples[offset + i] = remap(&v, length, map, n);
+                    }
+                }
+                if let Some(v) = pad(samples) {
+                    out.push_format_integer(t, &v)?;
+                }
+            }
+            TagType::Float => {
+                let mut samples: Vec<Vec<f32>> = vec![vec![]; n_samples];
+                for ((r, map), offset) in records.iter().zip(&maps).zip(&offsets) {
+                    let Ok(values) = r.format(t).float() else {
+                        continue;
+                    };
+                    for (i, v) in format_values(values.to_vec()).into_iter().enumerate() {
+                        samples[offset + i] = remap(&v, length, map, n);
+                    }
+                }
+                if let Some(v) = pad(samples) {
+                    out.push_format_float(t, &v)?;
+                }
+            }
+            TagType::String | TagType::Flag => {
+                let mut samples: Vec<Vec<u8>> = vec![b".".to_vec(); n_samples];
+                let mut found = false;
+                for (r, offset) in records.iter().zip(&offsets) {
+                    let Ok(values) = r.format(t).string() else {
+                        continue;
+                    };
+                    found = true;
+                    for (i, v) in values.iter().enumerate() {
+                        samples[offset + i] = v.to_vec();
+                    }
+                }
+                if found {
+                    let v = samples.iter().map(|s| s.as_slice()).collect::<Vec<_>>();
+                    out.push_format_string(t, &v)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Merge the files in `paths` to `output` (stdout if None). `rules` set how INFO fields are
+/// combined; the default is [`InfoRule::First`].
+pub fn merge(
+    paths: &[String],
+    output: Option<&str>,
+    rules: &[(String, InfoRule)],
+) -> Result<(), Box<dyn std::error::Error>> {
+    if paths.len() < 2 {
+        return Err("merge needs at least 2 files".into());
+    }
+    let mut inputs = vec![];
+    let mut samples: Vec<Vec<u8>> = vec![];
+    for path in paths {
+        let mut reader = bcf::Reader::from_path(path)?;
+        _ = reader.set_threads(2);
+        let offset = samples.len();
+        for s in reader.header().samples() {
+            if samples.iter().any(|o| o == s) {
+                return Err(format!(
+                    "sample {} in {} is in another input",
+                    String::from_utf8_lossy(s),
+                    path
+                )
+                .into());
+            }
+            samples.push(s.to_vec());
+        }
+        let record = reader.empty_record();
+        inputs.push(Input {
+            path: path.clone(),
+            reader,
+            record,
+            has_record: false,
+            key: (0, 0),
+            offset,
+        });
+    }
+
+    // the header lines of all inputs (the first definition of a tag is kept), then the samples.
+    let mut header = bcf::Header::from_template_subset(inputs[0].reader.header(), &[])?;
+    for input in &inputs[1..] {
+        unsafe {
+            htslib::bcf_hdr_merge(header.inner, input.reader.header().inner);
+        }
+    }
+    for s in &samples {
+        header.push_sample(s);
+    }
+    let mut wtr = match output {
+        Some(output) if output != "-" => {
+            let format = crate::vcfexpress::get_vcf_format(output);
+            bcf::Writer::from_path(output, &header, !output.ends_with(".gz"), format)?
+        }
+        _ => bcf::Writer::from_stdout(&header, true, bcf::Format::Vcf)?,
+    };
+    let hv = wtr.header().clone();
+
+    for (tag, rule) in rules {
+        match hv.info_type(tag.as_bytes()) {
+            Err(_) => {
+                return Err(format!("--info-rule {}: no INFO {} in the inputs", tag, tag).into())
+            }
+            Ok((TagType::String, _)) if !matches!(rule, InfoRule::First | InfoRule::Join) => {
+                return Err(
+                    format!("--info-rule {}: String fields can use first or join", tag).into(),
+                )
+            }
+            Ok((TagType::Integer | TagType::Float, _)) if *rule == InfoRule::Join => {
+                return Err(format!("--info-rule {}: join is for String fields", tag).into())
+            }
+            _ => {}
+        }
+    }
+    let info_tags = tags(&hv, false)
+        .into_iter()
+        .filter_map(|tag| {
+            let (typ, length) = hv.info_type(tag.as_bytes()).ok()?;
+            let rule = rules
+                .iter()
+                .find(|(t, _)| *t == tag)
+                .map_or(InfoRule::First, |(_, r)| *r);
+            Some((tag, typ, length, rule))
+        })
+        .collect::<Vec<_>>();
+    let format_tags = tags(&hv, true)
+        .into_iter()
+        .filter_map(|tag| {
+            let (typ, length) = hv.format_type(tag.as_bytes()).ok()?;
+            Some((tag, typ, length))
+        })
+        .collect::<Vec<_>>();
+
+    for input in inputs.iter_mut() {
+        input.advance(&hv)?;
+    }
+    while let Some(key) = inputs.iter().filter(|i| i.has_record).map(|i| i.key).min() {
+        let at_key = |i: &Input| i.has_record && i.key == key;
+        let reference = inputs
+            .iter()
+            .find(|i| at_key(i))
+            .map(|i| i.record.alleles()[0].to_vec())
+            .expect("an input at the smallest position");
+        let group = (0..inputs.len())
+            .filter(|&k| at_key(&inputs[k]) && inputs[k].record.alleles()[0] == reference)
+            .collect::<Vec<_>>();
+        let mut out = wtr.empty_record();
+        merge_records(
+            &hv,
+            &inputs,
+            &group,
+            &mut out,
+            samples.len(),
+            &info_tags,
+            &format_tags,
+        )?;
+        wtr.write(&out)?;
+        for k in group {
+            inputs[k].advance(&hv)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_htslib::bcf::record::GenotypeAllele;
+
+    /// contig, position, alleles, DP, AF, GT and AD of the one sample.
+    type TestRecord<'a> = (u32, i64, Vec<&'a str>, i32, Vec<f32>, [i32; 2], Vec<i32>);
+
+    fn write_vcf(path: &str, sample: &str, records: &[TestRecord]) {
+        let mut header = bcf::Header::new();
+        header.push_record(br#"##contig=<ID=chr1,length=10000>"#);
+        header.push_record(br#"##contig=<ID=chr2,length=10000>"#);
+        header.push_record(br#"##FILTER=<ID=LowQual,Description="LowQual">"#);
+        header.push_record(br#"##INFO=<ID=DP,Number=1,Type=Integer,Description="Depth">"#);
+        header.push_record(br#"##INFO=<ID=AF,Number=A,Type=Float,Description="AF">"#);
+        header.push_record(br#"##FORMAT=<ID=GT,Number=1,Type=String,Description="Genotype">"#);
+        header.push_record(br#"##FORMAT=<ID=AD,Number=R,Type=Integer,Description="Depths">"#);
+        header.push_sample(sample.as_bytes());
+        let mut vcf = bcf::Writer::from_path(path, &header, true, bcf::Format::Vcf).unwrap();
+        for (rid, pos, alleles, dp, af, gt, ad) in records {
+            let mut record = vcf.empty_record();
+            record.set_rid(Some(*rid));
+            record.set_pos(*pos);
+            let alleles = alleles.iter().map(|a| a.as_bytes()).collect::<Vec<_>>();
+            record.set_alleles(&alleles).unwrap();
+            record.push_info_integer(b"DP", &[*dp]).unwrap();
+            record.push_info_float(b"AF", af).unwrap();
+            record
+                .push_genotypes(&[
+                    GenotypeAllele::Unphased(gt[0]),
+                    GenotypeAllele::Unphased(gt[1]),
+                ])
+                .unwrap();
+            record.push_format_integer(b"AD", ad).unwrap();
+            record.set_qual(f32::missing());
+            vcf.write(&record).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_merge_missing_qual() {
+        write_vcf(
+            "_test.merge.qa.vcf",
+            "S1",
+            &[(0, 299, vec!["A", "T"], 10, vec![0.5], [0, 1], vec![5, 5])],
+        );
+        write_vcf(
+            "_test.merge.qb.vcf",
+            "S2",
+            &[(0, 299, vec!["A", "T"], 7, vec![1.0], [1, 1], vec![0, 7])],
+        );
+        let paths = [
+            "_test.merge.qa.vcf".to_string(),
+            "_test.merge.qb.vcf".to_string(),
+        ];
+        merge(&paths, Some("_test.merge.qout.vcf"), &[]).unwrap();
+        let out = std::fs::read_to_string("_test.merge.qout.vcf").unwrap();
+        let quals = out
+            .lines()
+            .filter(|l| !l.starts_with('#'))
+            .map(|l| l.split('\t').nth(5).unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(quals, ["."]);
+        for f in [
+            "_test.merge.qa.vcf",
+            "_test.merge.qb.vcf",
+            "_test.merge.qout.vcf",
+        ] {
+            _ = std::fs::remove_file(f);
+        }
+    }
+
+    #[test]
+    fn test_merge() {
+        write_vcf(
+            "_test.merge.a.vcf",
+            "S1",
+            &[
+                (0, 9, vec!["A", "T"], 10, vec![0.5], [0, 1], vec![5, 5]),
+                (0, 19, vec!["G", "C"], 8, vec![1.0], [1, 1], vec![0, 8]),
+                (1, 4, vec!["T", "G"], 3, vec![0.5], [0, 1], vec![2, 1]),
+            ],
+        );
+        write_vcf(
+            "_test.merge.b.vcf",
+            "S2",
+            &[
+                (0, 9, vec!["A", "C"], 7, vec![1.0], [1, 1], vec![0, 7]),
+                (0, 29, vec!["C", "A"], 9, vec![0.5], [0, 1], vec![4, 5]),
+            ],
+        );
+        let paths = [
+            "_test.merge.a.vcf".to_string(),
+            "_test.merge.b.vcf".to_string(),
+        ];
+        let rules = parse_info_rules(&["DP=sum".to_string()]).unwrap();
+        merge(&paths, Some("_test.merge.out.vcf"), &rules).unwrap();
+        let out = std::fs::read_to_string("_test.merge.out.vcf").unwrap();
+        assert!(out.contains("#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\tS1\tS2\n"));
+        let records = out
+            .lines()
+            .filter(|l| !l.starts_with('#'))
+            .map(|l| {
+                let f = l.split('\t').collect::<Vec<_>>();
+                [f[0], f[1], f[4], f[7], f[9], f[10]].join(" ")
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            records,
+            [
+                "chr1 10 T,C DP=17;AF=0.5,1 0/1:5,5,. 2/2:0,.,7",
+                "chr1 20 C DP=8;AF=1 1/1:0,8 ./.:.",
+                "chr1 30 A DP=9;AF=0.5 ./.:. 0/1:4,5",
+                "chr2 5 G DP=3;AF=0.5 0/1:2,1 ./.:.",
+            ]
+        );
+
+        assert!(parse_info_rules(&["DP=median".to_string()]).is_err());
+        let rules = parse_info_rules(&["DP=join".to_string()]).unwrap();
+        assert!(merge(&paths, Some("_test.merge.out.vcf"), &rules).is_err());
+        assert!(merge(
+            &[paths[0].clone(), paths[0].clone()],
+            Some("_test.merge.out.vcf"),
+            &[]
+        )
+        .is_err());
+        for f in [
+            "_test.merge.a.vcf",
+            "_test.merge.b.vcf",
+            "_test.merge.out.vcf",
+        ] {
+            _ = std::fs::remove_file(f);
+        }
+    }
+
+    #[test]
+    fn test_remap() {
+        // A,T in a record merged to A,C,T.
+        let map = [0, 2];
+        assert_eq!(
+            remap(&[7], &TagLength::AltAlleles, &map, 3),
+            [i32::missing(), 7]
+        );
+        assert_eq!(
+            remap(&[3, 7], &TagLength::Alleles, &map, 3),
+            [3, i32::missing(), 7]
+        );
+        // PL of AA, AT, TT to AA, AC, CC, AT, CT, TT.
+        let m = i32::missing();
+        assert_eq!(
+            remap(&[0, 10, 20], &TagLength::Genotypes, &map, 3),
+            [0, m, m, 10, m, 20]
+        );
+        assert_eq!(remap(&[1, 2], &TagLength::Fixed(2), &map, 3), [1, 2]);
+    }
+}
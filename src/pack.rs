@@ -0,0 +1,317 @@
+//! Versioned filter packs: `vcfexpress pack mypack@1.2` bundles prelude files, a snippets file and
+//! the paths of required annotation files into a single `.vxpack` archive, `vcfexpress install`
+//! unpacks it into the packs directory and `filter --pack mypack@1.2` uses it.
+//!
+//! The archive is text: a `#vcfexpress-pack 1` line, then for each file a `#file <name> <bytes>`
+//! line followed by the content. The first file is `pack.txt` with `key=value` lines for the
+//! name, version, preludes (in order), snippets and required paths.
+//! Installed packs are in `$VCFEXPRESS_PACKS` (default `~/.vcfexpress/packs`) as `name/version/`.
+use std::io::{BufRead, Read, Write};
+use std::path::{Path, PathBuf};
+
+const MAGIC: &str = "#vcfexpress-pack 1";
+const MANIFEST: &str = "pack.txt";
+
+fn invalid(msg: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg)
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Pack {
+    pub name: String,
+    pub version: String,
+    /// prelude files, run in this order before those given with -p.
+    pub preludes: Vec<String>,
+    pub snippets: Option<String>,
+    /// annotation files (e.g. for --ac-index or --coverage) that must exist where the pack is used.
+    pub requires: Vec<String>,
+}
+
+/// Split `name@version`. The version is None if it is not given.
+pub fn parse_reference(reference: &str) -> std::io::Result<(String, Option<String>)> {
+    let (name, version) = match reference.split_once('@') {
+        Some((n, v)) => (n, Some(v.to_string())),
+        None => (reference, None),
+    };
+    let valid = |s: &str| {
+        !s.is_empty()
+            && !s.starts_with('.')
+            && s.chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+    };
+    if !valid(name) || version.as_deref().is_some_and(|v| !valid(v)) {
+        return Err(invalid(format!(
+            "expected name@version using letters, digits, '_', '-' and '.', got '{}'",
+            reference
+        )));
+    }
+    Ok((name.to_string(), version))
+}
+
+/// `$VCFEXPRESS_PACKS` or `~/.vcfexpress/packs`.
+pub fn packs_dir() -> PathBuf {
+    match std::env::var_os("VCFEXPRESS_PACKS") {
+        Some(d) => PathBuf::from(d),
+        None => PathBuf::from(std::env::var_os("HOME").unwrap_or_default())
+            .join(".vcfexpress")
+            .join("packs"),
+    }
+}
+
+fn file_name(path: &str) -> std::io::Result<String> {
+    Path::new(path)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .ok_or_else(|| invalid(format!("{} is not a file", path)))
+}
+
+/// Compare versions by their numeric components so that 1.10 is after 1.9.
+fn version_key(v: &str) -> Vec<(u64, String)> {
+    v.split(['.', '-'])
+        .map(|p| (p.parse::<u64>().unwrap_or(0), p.to_string()))
+        .collect()
+}
+
+impl Pack {
+    fn manifest(&self) -> String {
+        let mut m = format!("name={}\nversion={}\n", self.name, self.version);
+        for p in &self.preludes {
+            m.push_str(&format!("prelude={}\n", p));
+        }
+        if let Some(s) = &self.snippets {
+            m.push_str(&format!("snippets={}\n", s));
+        }
+        for r in &self.requires {
+            m.push_str(&format!("require={}\n", r));
+        }
+        m
+    }
+
+    fn parse_manifest(text: &str) -> std::io::Result<Self> {
+        let mut pack = Pack::default();
+        for line in text.lines().filter(|l| !l.is_empty()) {
+            match line.split_once('=') {
+                Some(("name", v)) => pack.name = v.to_string(),
+                Some(("version", v)) => pack.version = v.to_string(),
+                Some(("prelude", v)) => pack.preludes.push(v.to_string()),
+                Some(("snippets", v)) => pack.snippets = Some(v.to_string()),
+                Some(("require", v)) => pack.requires.push(v.to_string()),
+                _ => {
+                    return Err(invalid(format!(
+                        "unexpected line in {}: {}",
+                        MANIFEST, line
+                    )))
+                }
+            }
+        }
+        parse_reference(&format!("{}@{}", pack.name, pack.version))?;
+        Ok(pack)
+    }
+
+    /// Write an archive with the `preludes` and `snippets` files to `output`. `reference` is
+    /// `name@version`.
+    pub fn create(
+        reference: &str,
+        preludes: &[String],
+        snippets: Option<&str>,
+        requires: &[String],
+        output: &str,
+    ) -> std::io::Result<()> {
+        let (name, version) = parse_reference(reference)?;
+        let Some(version) = version else {
+            return Err(invalid(format!(
+                "a version is required to pack e.g. {}@1.0",
+                name
+            )));
+        };
+        let mut files = vec![];
+        for path in preludes.iter().map(|p| p.as_str()).chain(snippets) {
+            let name = file_name(path)?;
+            if name == MANIFEST || files.iter().any(|(n, _)| *n == name) {
+                return Err(invalid(format!(
+                    "more than one file in the pack is named {}",
+                    name
+                )));
+            }
+            files.push((name, std::fs::read(path)?));
+        }
+        if let Some(s) = snippets {
+            // fail now rather than where the pack is used.
+            crate::snippets::Snippets::parse(&std::fs::read_to_string(s)?)?;
+        }
+        let pack = Pack {
+            name,
+            version,
+            preludes: files[..preludes.len()]
+                .iter()
+                .map(|(n, _)| n.clone())
+                .collect(),
+            snippets: files.get(preludes.len()).map(|(n, _)| n.clone()),
+            requires: requires.to_vec(),
+        };
+        let mut w = std::io::BufWriter::new(std::fs::File::create(output)?);
+        writeln!(w, "{}", MAGIC)?;
+        let manifest = pack.manifest();
+        for (name, content) in
+            std::iter::once((MANIFEST.to_string(), manifest.into_bytes())).chain(files)
+        {
+            writeln!(w, "#file {} {}", name, content.len())?;
+            w.write_all(&content)?;
+            writeln!(w)?;
+        }
+        w.flush()
+    }
+
+    /// Unpack the archive at `path` into `dir/name/version/`, replacing an existing install of
+    /// the same version. Returns the installed pack.
+    pub fn install(path: &str, dir: &Path) -> std::io::Result<Pack> {
+        let mut r = std::io::BufReader::new(std::fs::File::open(path)?);
+        let mut line = String::new();
+        r.read_line(&mut line)?;
+        if line.trim_end() != MAGIC {
+            return Err(invalid(format!("{} is not a vcfexpress pack", path)));
+        }
+        let mut files = vec![];
+        loop {
+            line.clear();
+            if r.read_line(&mut line)? == 0 {
+                break;
+            }
+            let entry = line.trim_end().strip_prefix("#file ").and_then(|e| {
+                let (name, size) = e.rsplit_once(' ')?;
+                Some((name.to_string(), size.parse::<usize>().ok()?))
+            });
+            let Some((name, size)) = entry else {
+                return Err(invalid(format!(
+                    "{}: bad entry '{}'",
+                    path,
+                    line.trim_end()
+                )));
+            };
+            if name != file_name(&name)? {
+                return Err(invalid(format!("{}: bad file name '{}'", path, name)));
+            }
+            // the content and the newline after it.
+            let mut content = vec![0; size + 1];
+            r.read_exact(&mut content)?;
+            content.pop();
+            files.push((name, content));
+        }
+        let Some((_, manifest)) = files.iter().find(|(n, _)| n == MANIFEST) else {
+            return Err(invalid(format!("{} has no {}", path, MANIFEST)));
+        };
+        let pack = Pack::parse_manifest(&String::from_utf8_lossy(manifest))?;
+        let target = dir.join(&pack.name).join(&pack.version);
+        if target.exists() {
+            std::fs::remove_dir_all(&target)?;
+        }
+        std::fs::create_dir_all(&target)?;
+        for (name, content) in &files {
+            std::fs::write(target.join(name), content)?;
+        }
+        Ok(pack)
+    }
+
+    /// Load an installed pack from `dir` by `name@version`, or the latest installed version for
+    /// `name`. The prelude and snippets paths are made absolute and the required paths are checked.
+    pub fn load(reference: &str, dir: &Path) -> std::io::Result<Pack> {
+        let (name, version) = parse_reference(reference)?;
+        let version = match version {
+            Some(v) => v,
+            None => std::fs::read_dir(dir.join(&name))
+                .ok()
+                .into_iter()
+                .flatten()
+                .filter_map(|e| e.ok())
+                .map(|e| e.file_name().to_string_lossy().to_string())
+                .max_by_key(|v| version_key(v))
+                .ok_or_else(|| {
+                    invalid(format!(
+                        "pack {} is not installed in {}",
+                        name,
+                        dir.display()
+                    ))
+                })?,
+        };
+        let pack_dir = dir.join(&name).join(&version);
+        let manifest = std::fs::read_to_string(pack_dir.join(MANIFEST)).map_err(|_| {
+            invalid(format!(
+                "pack {}@{} is not installed in {}",
+                name,
+                version,
+                dir.display()
+            ))
+        })?;
+        let mut pack = Pack::parse_manifest(&manifest)?;
+        let resolve = |f: &String| pack_dir.join(f).to_string_lossy().to_string();
+        pack.preludes = pack.preludes.iter().map(resolve).collect();
+        pack.snippets = pack.snippets.as_ref().map(resolve);
+        let missing = pack
+            .requires
+            .iter()
+            .filter(|r| !Path::new(r).exists())
+            .cloned()
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            return Err(invalid(format!(
+                "pack {}@{} requires files that do not exist: {}",
+                pack.name,
+                pack.version,
+                missing.join(", ")
+            )));
+        }
+        Ok(pack)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pack_install_load() {
+        assert_eq!(
+            parse_reference("qc@1.2").unwrap(),
+            ("qc".to_string(), Some("1.2".to_string()))
+        );
+        assert!(parse_reference("../qc@1").is_err());
+        assert!(version_key("1.10") > version_key("1.9"));
+
+        std::fs::write("_test.pack.lua", "x = 1\n").unwrap();
+        std::fs::write("_test.pack.snippets", "rare = \"x < 1\"\n").unwrap();
+        let requires = vec!["_test.pack.lua".to_string()];
+        assert!(Pack::create("qc", &[], None, &[], "_test.pack.vxpack").is_err());
+        for version in ["1.9", "1.10"] {
+            Pack::create(
+                &format!("qc@{}", version),
+                &["_test.pack.lua".to_string()],
+                Some("_test.pack.snippets"),
+                &requires,
+                "_test.pack.vxpack",
+            )
+            .unwrap();
+            let pack = Pack::install("_test.pack.vxpack", Path::new("_test.packs")).unwrap();
+            assert_eq!(pack.preludes, ["_test.pack.lua"]);
+        }
+
+        let pack = Pack::load("qc", Path::new("_test.packs")).unwrap();
+        assert_eq!(pack.version, "1.10");
+        assert_eq!(
+            std::fs::read_to_string(&pack.preludes[0]).unwrap(),
+            "x = 1\n"
+        );
+        assert!(pack.snippets.unwrap().ends_with("qc/1.10/_test.pack.snippets"));
+        assert!(Pack::load("qc@2.0", Path::new("_test.packs")).is_err());
+
+        std::fs::remove_file("_test.pack.lua").unwrap();
+        let err = Pack::load("qc@1.9", Path::new("_test.packs"))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("requires files that do not exist: _test.pack.lua"));
+
+        for f in ["_test.pack.snippets", "_test.pack.vxpack"] {
+            _ = std::fs::remove_file(f);
+        }
+        _ = std::fs::remove_dir_all("_test.packs");
+    }
+}
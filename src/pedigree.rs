@@ -0,0 +1,149 @@
+//! Pedigree (PED) files for trio-aware expressions such as `variant:transmitted_allele(child)`.
+//! Only the first 4 columns (family, sample, father, mother) are used; `0` or `.` is an unknown parent.
+use std::collections::HashMap;
+use std::io::BufRead;
+
+#[derive(Debug, Default)]
+pub struct Pedigree {
+    /// sample -> (father, mother)
+    parents: HashMap<String, (Option<String>, Option<String>)>,
+}
+
+impl Pedigree {
+    pub fn from_path(path: &str) -> std::io::Result<Self> {
+        Self::from_reader(std::io::BufReader::new(std::fs::File::open(path)?))
+    }
+
+    pub fn from_reader<R: BufRead>(reader: R) -> std::io::Result<Self> {
+        let mut ped = Pedigree::default();
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            if fields.len() < 4 {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("expected at least 4 columns on line {} of pedigree", i + 1),
+                ));
+            }
+            let parent = |p: &str| match p {
+                "0" | "." | "" => None,
+                p => Some(p.to_string()),
+            };
+            ped.parents.insert(
+                fields[1].to_string(),
+                (parent(fields[2]), parent(fields[3])),
+            );
+        }
+        Ok(ped)
+    }
+
+    /// Replace sample aliases with the VCF sample names.
+    pub fn resolve_aliases(&mut self, aliases: &crate::alias::SampleAliases) {
+        let resolve = |s: &str| aliases.resolve(s).to_string();
+        self.parents = std::mem::take(&mut self.parents)
+            .into_iter()
+            .map(|(s, (f, m))| {
+                (
+                    resolve(&s),
+                    (f.as_deref().map(resolve), m.as_deref().map(resolve)),
+                )
+            })
+            .collect();
+    }
+
+    /// The (father, mother) of `sample` or None if the sample is not in the pedigree.
+    pub fn parents(&self, sample: &str) -> Option<(Option<&str>, Option<&str>)> {
+        self.parents
+            .get(sample)
+            .map(|(f, m)| (f.as_deref(), m.as_deref()))
+    }
+}
+
+/// The alleles transmitted from the father and the mother to a diploid child. Each is None unless
+/// it is the same in every assignment of the child's alleles to the parents that is consistent
+/// with their genotypes. A parent genotype of None (unknown or missing) allows any allele.
+/// Both are None for a Mendelian inconsistency, a missing or non-diploid child genotype.
+pub fn transmitted_alleles(
+    child: &[i32],
+    father: Option<&[i32]>,
+    mother: Option<&[i32]>,
+) -> (Option<i32>, Option<i32>) {
+    let [a, b] = *child else {
+        return (None, None);
+    };
+    if a < 0 || b < 0 {
+        return (None, None);
+    }
+    // a parent genotype with a missing allele could have given anything.
+    let known = |p: &&[i32]| !p.is_empty() && p.iter().all(|&x| x >= 0);
+    let (father, mother) = (father.filter(known), mother.filter(known));
+    let has = |p: Option<&[i32]>, x: i32| p.is_none_or(|p| p.contains(&x));
+    let options = [(a, b), (b, a)]
+        .into_iter()
+        .filter(|&(f, m)| has(father, f) && has(mother, m))
+        .collect::<Vec<_>>();
+    let Some(&(f, m)) = options.first() else {
+        return (None, None);
+    };
+    (
+        options.iter().all(|o| o.0 == f).then_some(f),
+        options.iter().all(|o| o.1 == m).then_some(m),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pedigree() {
+        let ped = Pedigree::from_reader(
+            "#fam\tid\tpa\tma\tsex\tpheno\nf1 kid dad mom 1 2\nf1 dad 0 0 1 1\n".as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(ped.parents("kid"), Some((Some("dad"), Some("mom"))));
+        assert_eq!(ped.parents("dad"), Some((None, None)));
+        assert_eq!(ped.parents("mom"), None);
+        assert!(Pedigree::from_reader("f1 kid dad\n".as_bytes()).is_err());
+
+        let mut ped = ped;
+        let aliases =
+            crate::alias::SampleAliases::from_reader("kid\tBC1\ndad\tBC2\n".as_bytes()).unwrap();
+        ped.resolve_aliases(&aliases);
+        assert_eq!(ped.parents("BC1"), Some((Some("BC2"), Some("mom"))));
+        assert_eq!(ped.parents("kid"), None);
+    }
+
+    #[test]
+    fn test_transmitted_alleles() {
+        // het child, hom-ref father: the alt came from the mother.
+        assert_eq!(
+            transmitted_alleles(&[0, 1], Some(&[0, 0]), Some(&[0, 1])),
+            (Some(0), Some(1))
+        );
+        // all het: ambiguous.
+        assert_eq!(
+            transmitted_alleles(&[0, 1], Some(&[0, 1]), Some(&[0, 1])),
+            (None, None)
+        );
+        // unknown mother.
+        assert_eq!(
+            transmitted_alleles(&[1, 0], Some(&[1, 1]), None),
+            (Some(1), Some(0))
+        );
+        // mendelian error and missing child.
+        assert_eq!(
+            transmitted_alleles(&[1, 1], Some(&[0, 0]), Some(&[0, 1])),
+            (None, None)
+        );
+        assert_eq!(transmitted_alleles(&[-1, 1], None, None), (None, None));
+        // hom-alt child: both parents gave the alt.
+        assert_eq!(
+            transmitted_alleles(&[1, 1], Some(&[0, 1]), Some(&[-1, -1])),
+            (Some(1), Some(1))
+        );
+    }
+}
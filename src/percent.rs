@@ -0,0 +1,79 @@
+//! Percent-encoding of INFO strings as in VCF 4.3 (e.g. `%3B` for `;`).
+//! Strings are decoded when read into lua and encoded when written back to a record.
+//! This can be turned off for legacy files with literal `%` in values.
+use std::borrow::Cow;
+
+/// Set as lua app data to turn off percent encoding and decoding.
+pub(crate) struct NoPercentEncoding;
+
+/// True unless percent encoding was turned off for this lua state.
+pub(crate) fn enabled(lua: &mlua::Lua) -> bool {
+    lua.app_data_ref::<NoPercentEncoding>().is_none()
+}
+
+fn hex(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Decode `%XX` sequences. A `%` that is not followed by two hex digits is kept as-is.
+pub fn decode(s: &[u8]) -> Cow<'_, [u8]> {
+    if !s.contains(&b'%') {
+        return Cow::Borrowed(s);
+    }
+    let mut out = Vec::with_capacity(s.len());
+    let mut i = 0;
+    while i < s.len() {
+        if s[i] == b'%' && i + 2 < s.len() {
+            if let (Some(h), Some(l)) = (hex(s[i + 1]), hex(s[i + 2])) {
+                out.push(h << 4 | l);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(s[i]);
+        i += 1;
+    }
+    Cow::Owned(out)
+}
+
+/// Encode the characters with special meaning in VCF: `%:;=,` tab, CR and LF.
+/// With `keep_commas`, commas are left as-is so they still separate values.
+pub fn encode(s: &[u8], keep_commas: bool) -> Cow<'_, [u8]> {
+    let special = |c: u8| {
+        matches!(c, b'%' | b':' | b';' | b'=' | b'\t' | b'\r' | b'\n')
+            || (c == b',' && !keep_commas)
+    };
+    if !s.iter().any(|&c| special(c)) {
+        return Cow::Borrowed(s);
+    }
+    let mut out = Vec::with_capacity(s.len() + 8);
+    for &c in s {
+        if special(c) {
+            out.extend_from_slice(format!("%{:02X}", c).as_bytes());
+        } else {
+            out.push(c);
+        }
+    }
+    Cow::Owned(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percent() {
+        assert_eq!(&*decode(b"a%3Bb%3dc%2C%"), b"a;b=c,%");
+        assert_eq!(&*decode(b"100%zz%2"), b"100%zz%2");
+        assert!(matches!(decode(b"plain"), Cow::Borrowed(_)));
+        assert_eq!(&*encode(b"a;b=c,d%", false), b"a%3Bb%3Dc%2Cd%25");
+        assert_eq!(&*encode(b"a,b", true), b"a,b");
+        let s = b"x:y;z=1,2\t%";
+        assert_eq!(&*decode(&encode(s, false)), s);
+    }
+}
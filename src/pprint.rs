@@ -9,12 +9,13 @@ function pprint(node)
 
     while true do
         local size = 0
-        for k,v in pairs(node) do
+        -- generalized iteration (not pairs) so that tables with __iter such as samples are printed.
+        for k,v in node do
             size = size + 1
         end
 
         local cur_index = 1
-        for k,v in pairs(node) do
+        for k,v in node do
             if (cache[node] == nil) or (cur_index >= cache[node]) then
 
                 if (string.find(output_str,"}",output_str:len())) then
@@ -0,0 +1,147 @@
+//! Remove INFO fields whose values are all missing and FORMAT fields that are missing for every
+//! sample (`--prune-empty`), e.g. after heavy redaction or sample subsetting. GT is always kept.
+use rust_htslib::bcf::header::{Id, TagType};
+use rust_htslib::bcf::{self};
+use rust_htslib::htslib;
+
+use crate::variant::Sentinel;
+
+fn all_missing<T: Sentinel>(values: &[T]) -> bool {
+    values
+        .iter()
+        .all(|v| v.is_missing_value() || v.is_vector_end())
+}
+
+fn all_missing_strings(values: &[&[u8]]) -> bool {
+    values.iter().all(|s| s.is_empty() || *s == b".")
+}
+
+/// The (`BCF_HL_INFO` or `BCF_HL_FMT`, ID) of each field in the record that has no values.
+fn empty_tags(record: &mut bcf::Record) -> rust_htslib::errors::Result<Vec<(u32, String)>> {
+    record.unpack();
+    let header = record.header();
+    let (info_ids, fmt_ids) = unsafe {
+        let d = &(*record.inner).d;
+        let info = std::slice::from_raw_parts(d.info, (*record.inner).n_info() as usize);
+        let fmt = std::slice::from_raw_parts(d.fmt, (*record.inner).n_fmt() as usize);
+        (
+            info.iter()
+                .filter(|i| !i.vptr.is_null())
+                .map(|i| i.key as u32)
+                .collect::<Vec<_>>(),
+            fmt.iter()
+                .filter(|f| !f.p.is_null())
+                .map(|f| f.id as u32)
+                .collect::<Vec<_>>(),
+        )
+    };
+    let mut empty = vec![];
+    for id in info_ids {
+        let tag = header.id_to_name(Id(id));
+        let info = record.info(&tag);
+        let missing = match header.info_type(&tag)?.0 {
+            TagType::Flag => false,
+            TagType::Integer => info.integer()?.is_some_and(|v| all_missing(&v)),
+            TagType::Float => info.float()?.is_some_and(|v| all_missing(&v)),
+            TagType::String => info.string()?.is_some_and(|v| all_missing_strings(&v)),
+        };
+        if missing {
+            empty.push((
+                htslib::BCF_HL_INFO,
+                String::from_utf8_lossy(&tag).to_string(),
+            ));
+        }
+    }
+    for id in fmt_ids {
+        let tag = header.id_to_name(Id(id));
+        if tag == b"GT" {
+            continue;
+        }
+        let fmt = record.format(&tag);
+        let missing = match header.format_type(&tag)?.0 {
+            TagType::Integer => fmt
+                .integer()
+                .is_ok_and(|v| v.iter().all(|s| all_missing(s))),
+            TagType::Float => fmt.float().is_ok_and(|v| v.iter().all(|s| all_missing(s))),
+            TagType::String => fmt.string().is_ok_and(|v| all_missing_strings(&v)),
+            TagType::Flag => false,
+        };
+        if missing {
+            empty.push((
+                htslib::BCF_HL_FMT,
+                String::from_utf8_lossy(&tag).to_string(),
+            ));
+        }
+    }
+    Ok(empty)
+}
+
+/// Remove the INFO and FORMAT (except GT) fields without any values from the record.
+pub fn prune_empty(record: &mut bcf::Record) -> rust_htslib::errors::Result<()> {
+    let empty = empty_tags(record)?;
+    crate::header::strip_tags(record, &empty);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prune_empty() {
+        let mut header = bcf::Header::new();
+        header.push_record(br#"##contig=<ID=chr1,length=10000>"#);
+        header.push_record(br#"##INFO=<ID=AF,Number=A,Type=Float,Description="AF">"#);
+        header.push_record(br#"##INFO=<ID=DP,Number=1,Type=Integer,Description="DP">"#);
+        header.push_record(br#"##INFO=<ID=CSQ,Number=.,Type=String,Description="CSQ">"#);
+        header.push_record(br#"##FORMAT=<ID=GT,Number=1,Type=String,Description="GT">"#);
+        header.push_record(br#"##FORMAT=<ID=AD,Number=R,Type=Integer,Description="AD">"#);
+        header.push_record(br#"##FORMAT=<ID=GQ,Number=1,Type=Integer,Description="GQ">"#);
+        header.push_record(br#"##FORMAT=<ID=FT,Number=1,Type=String,Description="FT">"#);
+        header.push_sample(b"S1");
+        header.push_sample(b"S2");
+        let vcf =
+            bcf::Writer::from_path("_test.prune.vcf", &header, true, bcf::Format::Vcf).unwrap();
+        let mut record = vcf.empty_record();
+        record.set_rid(Some(0));
+        record.set_alleles(&[b"A", b"T", b"C"]).unwrap();
+        record
+            .push_info_float(b"AF", &[f32::missing(), f32::missing()])
+            .unwrap();
+        record.push_info_integer(b"DP", &[10]).unwrap();
+        record.push_info_string(b"CSQ", &[b"."]).unwrap();
+        record
+            .push_genotypes(&[bcf::record::GenotypeAllele::UnphasedMissing; 4])
+            .unwrap();
+        record
+            .push_format_integer(
+                b"AD",
+                &[
+                    i32::missing(),
+                    i32::vector_end(),
+                    i32::vector_end(),
+                    3,
+                    4,
+                    5,
+                ],
+            )
+            .unwrap();
+        record
+            .push_format_integer(b"GQ", &[i32::missing(), i32::missing()])
+            .unwrap();
+        record
+            .push_format_string(b"FT", &[&b"."[..], &b"."[..]])
+            .unwrap();
+
+        prune_empty(&mut record).unwrap();
+        assert!(record.info(b"AF").float().unwrap().is_none());
+        assert!(record.info(b"CSQ").string().unwrap().is_none());
+        assert_eq!(*record.info(b"DP").integer().unwrap().unwrap(), [10]);
+        assert!(record.format(b"AD").integer().is_ok());
+        assert!(record.format(b"GQ").integer().is_err());
+        assert!(record.format(b"FT").string().is_err());
+        assert!(record.genotypes().is_ok());
+        drop(vcf);
+        _ = std::fs::remove_file("_test.prune.vcf");
+    }
+}
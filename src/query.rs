@@ -0,0 +1,516 @@
+//! `vcfexpress query -f '%CHROM\t%POS\t%INFO/AF[\t%GT]\n'`: bcftools query format strings
+//! translated to a template. Fields are `%CHROM`, `%POS`, `%ID`, `%REF`, `%ALT`, `%QUAL`,
+//! `%FILTER`, `%END`, `%LINE` and `%INFO/TAG` (or `%TAG`) and, inside `[...]` which is repeated
+//! for each sample, `%SAMPLE`, `%GT`, `%TGT` and `%FORMAT/TAG` (or `%TAG`). `{n}` after an INFO or
+//! FORMAT field selects the nth (0-based) value. Values are printed as in the VCF, from
+//! [`crate::vcfexpress::vcf_line`], with '.' for missing values and 0/1 for INFO flags. Each
+//! record is written as a line, so a trailing `\n` is not needed.
+use mlua::{AnyUserData, Lua, UserData, UserDataMethods};
+use rust_htslib::bcf::{
+    self,
+    header::{HeaderRecord, HeaderView},
+    Read,
+};
+use rustc_hash::FxHashSet;
+use std::sync::Arc;
+
+use crate::variant::{HeaderMap, Variant};
+use crate::vcfexpress::{StringOrVariant, VCFExpress};
+
+const SITE_FIELDS: [&str; 9] = [
+    "CHROM", "POS", "ID", "REF", "ALT", "QUAL", "FILTER", "END", "LINE",
+];
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Text(String),
+    /// the field as written without the % and the {n} subscript.
+    Field(String, Option<usize>),
+    /// a [...] block repeated for each sample.
+    Samples(Vec<Token>),
+}
+
+fn push_text(tokens: &mut Vec<Token>, s: &str) {
+    match tokens.last_mut() {
+        Some(Token::Text(t)) => t.push_str(s),
+        _ => tokens.push(Token::Text(s.to_string())),
+    }
+}
+
+/// Parse a bcftools query format string. A trailing `\n` is removed.
+fn parse(format: &str) -> Result<Vec<Token>, String> {
+    let format = format.strip_suffix("\\n").unwrap_or(format);
+    let mut tokens = vec![];
+    let mut samples: Option<Vec<Token>> = None;
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '%' => {
+                let mut name = String::new();
+                while let Some(&n) = chars.peek() {
+                    if !(n.is_ascii_alphanumeric() || n == '_' || n == '/') {
+                        break;
+                    }
+                    name.push(n);
+                    chars.next();
+                }
+                if name.is_empty() {
+                    return Err(format!("'%' without a field name in '{}'", format));
+                }
+                let mut index = None;
+                if chars.peek() == Some(&'{') {
+                    chars.next();
+                    let n = chars.by_ref().take_while(|&c| c != '}').collect::<String>();
+                    index = Some(n.parse::<usize>().map_err(|_| {
+                        format!("invalid subscript {{{}}} for %{} in '{}'", n, name, format)
+                    })?);
+                }
+                let current = samples.as_mut().unwrap_or(&mut tokens);
+                current.push(Token::Field(name, index));
+            }
+            '[' if samples.is_some() => {
+                return Err(format!("nested '[' in '{}'", format));
+            }
+            '[' => samples = Some(vec![]),
+            ']' => match samples.take() {
+                Some(s) => tokens.push(Token::Samples(s)),
+                None => return Err(format!("']' without '[' in '{}'", format)),
+            },
+            '\\' => {
+                let mut s = String::from('\\');
+                if let Some(n) = chars.next() {
+                    s.push(n);
+                }
+                push_text(samples.as_mut().unwrap_or(&mut tokens), &s);
+            }
+            c => push_text(
+                samples.as_mut().unwrap_or(&mut tokens),
+                c.encode_utf8(&mut [0; 4]),
+            ),
+        }
+    }
+    if samples.is_some() {
+        return Err(format!("'[' without ']' in '{}'", format));
+    }
+    Ok(tokens)
+}
+
+/// A field of the format string.
+enum Field<'a> {
+    Site(&'a str),
+    Info(&'a str),
+    Format(&'a str),
+}
+
+/// A bare tag is an INFO field outside of [...] and a FORMAT field inside.
+fn resolve(name: &str, in_samples: bool) -> Result<Field<'_>, String> {
+    if SITE_FIELDS.contains(&name) {
+        return Ok(Field::Site(name));
+    }
+    if let Some(tag) = name.strip_prefix("INFO/") {
+        return Ok(Field::Info(tag));
+    }
+    if in_samples {
+        return Ok(Field::Format(name.strip_prefix("FORMAT/").unwrap_or(name)));
+    }
+    match name {
+        "SAMPLE" | "GT" | "TGT" => Err(format!("%{} must be inside [...]", name)),
+        _ if name.starts_with("FORMAT/") => Err(format!("%{} must be inside [...]", name)),
+        _ => Ok(Field::Info(name)),
+    }
+}
+
+/// Check that the INFO and FORMAT fields of `tokens` are in the header.
+fn check(tokens: &[Token], in_samples: bool, hv: &HeaderView) -> Result<(), String> {
+    for t in tokens {
+        match t {
+            Token::Text(_) => {}
+            Token::Field(name, _) => match resolve(name, in_samples)? {
+                Field::Site(_) | Field::Format("SAMPLE" | "TGT") => {}
+                Field::Info(tag) if hv.info_type(tag.as_bytes()).is_err() => {
+                    return Err(format!("%{}: INFO {} is not in the header", name, tag));
+                }
+                Field::Format(tag) if hv.format_type(tag.as_bytes()).is_err() => {
+                    return Err(format!("%{}: FORMAT {} is not in the header", name, tag));
+                }
+                _ => {}
+            },
+            Token::Samples(inner) => check(inner, true, hv)?,
+        }
+    }
+    Ok(())
+}
+
+/// The luau template for `tokens`. Every value comes from the `QueryLine` `q`.
+fn template(tokens: &[Token]) -> Result<String, String> {
+    fn add(out: &mut String, tokens: &[Token], in_samples: bool) -> Result<(), String> {
+        for t in tokens {
+            match t {
+                Token::Text(s) => {
+                    for c in s.chars() {
+                        if c == '`' || c == '{' {
+                            out.push('\\');
+                        }
+                        out.push(c);
+                    }
+                }
+                Token::Field(name, index) => {
+                    let index = index.map_or(String::new(), |i| format!(", {}", i));
+                    match resolve(name, in_samples)? {
+                        Field::Site(f) => out.push_str(&format!("{{q:get(\"{}\")}}", f)),
+                        Field::Info(tag) => {
+                            out.push_str(&format!("{{q:info(\"{}\"{})}}", tag, index))
+                        }
+                        Field::Format(tag) => {
+                            out.push_str(&format!("{{q:fmt(i, \"{}\"{})}}", tag, index))
+                        }
+                    }
+                }
+                Token::Samples(inner) => {
+                    out.push_str("{q:samples(function(i) return `");
+                    add(out, inner, true)?;
+                    out.push_str("` end)}");
+                }
+            }
+        }
+        Ok(())
+    }
+    let mut out = String::from("local q = __query(variant)\nreturn `");
+    add(&mut out, tokens, false)?;
+    out.push('`');
+    Ok(out)
+}
+
+/// The names of the fields in place of their values, with `SAMPLE:` before the fields in [...].
+fn header_line(tokens: &[Token], samples: &[String]) -> String {
+    fn add(out: &mut String, tokens: &[Token], sample: Option<&str>, samples: &[String]) {
+        for t in tokens {
+            match t {
+                Token::Text(s) => {
+                    out.push_str(&s.replace("\\t", "\t").replace("\\n", "\n"));
+                }
+                Token::Field(name, index) => {
+                    if let Some(s) = sample {
+                        out.push_str(s);
+                        out.push(':');
+                    }
+                    out.push_str(name);
+                    if let Some(i) = index {
+                        out.push_str(&format!("{{{}}}", i));
+                    }
+                }
+                Token::Samples(inner) => {
+                    for s in samples {
+                        add(out, inner, Some(s), samples);
+                    }
+                }
+            }
+        }
+    }
+    let mut out = String::from("#");
+    add(&mut out, tokens, None, samples);
+    out
+}
+
+/// The columns of a record as VCF text.
+struct QueryLine {
+    columns: Vec<String>,
+    samples: Arc<Vec<String>>,
+    // INFO flags are printed as 0 when they are not set.
+    flags: Arc<FxHashSet<String>>,
+    end: i64,
+}
+
+impl UserData for QueryLine {}
+
+/// The `index`th of the comma-separated values or all of them.
+fn nth(value: &str, index: Option<usize>) -> String {
+    match index {
+        None => value.to_string(),
+        Some(i) => value.split(',').nth(i).unwrap_or(".").to_string(),
+    }
+}
+
+impl QueryLine {
+    fn new(
+        record: &bcf::Record,
+        samples: Arc<Vec<String>>,
+        flags: Arc<FxHashSet<String>>,
+    ) -> std::io::Result<Self> {
+        let line = crate::vcfexpress::vcf_line(record.header(), record)?;
+        let line = String::from_utf8_lossy(&line);
+        Ok(QueryLine {
+            columns: line.trim_end().split('\t').map(|c| c.to_string()).collect(),
+            samples,
+            flags,
+            // pos + rlen: the 1-based, inclusive end.
+            end: record.end(),
+        })
+    }
+
+    fn column(&self, i: usize) -> &str {
+        self.columns.get(i).map_or(".", |c| c.as_str())
+    }
+
+    fn site(&self, name: &str) -> String {
+        match name {
+            "END" => self.end.to_string(),
+            "LINE" => self.columns.join("\t"),
+            _ => match SITE_FIELDS.iter().position(|f| *f == name) {
+                Some(i) => self.column(i).to_string(),
+                None => ".".to_string(),
+            },
+        }
+    }
+
+    fn info(&self, tag: &str, index: Option<usize>) -> String {
+        for kv in self.column(7).split(';') {
+            let (k, v) = kv.split_once('=').unwrap_or((kv, ""));
+            if k == tag {
+                return if v.is_empty() {
+                    "1".to_string()
+                } else {
+                    nth(v, index)
+                };
+            }
+        }
+        if self.flags.contains(tag) {
+            "0".to_string()
+        } else {
+            ".".to_string()
+        }
+    }
+
+    /// The value of `tag` for sample `i` (0-based).
+    fn format(&self, i: usize, tag: &str, index: Option<usize>) -> String {
+        if tag == "SAMPLE" {
+            return self.samples.get(i).cloned().unwrap_or_default();
+        }
+        let key = if tag == "TGT" { "GT" } else { tag };
+        let value = self
+            .column(8)
+            .split(':')
+            .position(|k| k == key)
+            .and_then(|k| self.columns.get(9 + i)?.split(':').nth(k))
+            .unwrap_or(".");
+        if tag != "TGT" {
+            return nth(value, index);
+        }
+        // the alleles in place of their indexes.
+        let alleles = std::iter::once(self.column(3))
+            .chain(self.column(4).split(','))
+            .collect::<Vec<_>>();
+        let mut out = String::new();
+        let mut digits = String::new();
+        for c in value.chars().chain(std::iter::once('/')) {
+            if c.is_ascii_digit() {
+                digits.push(c);
+                continue;
+            }
+            if !digits.is_empty() {
+                let a = digits.parse::<usize>().ok();
+                out.push_str(a.and_then(|a| alleles.get(a)).copied().unwrap_or("."));
+                digits.clear();
+            }
+            out.push(c);
+        }
+        out.pop();
+        out
+    }
+}
+
+/// Add `__query(variant)` to get the `QueryLine` of a record with the samples and flags of `hv`.
+fn register_query(lua: &Lua, hv: &HeaderView) -> mlua::Result<()> {
+    lua.register_userdata_type::<QueryLine>(|reg| {
+        reg.add_method("get", |_, this: &QueryLine, name: String| {
+            Ok(this.site(&name))
+        });
+        reg.add_method(
+            "info",
+            |_, this: &QueryLine, (tag, index): (String, Option<usize>)| Ok(this.info(&tag, index)),
+        );
+        // i is 1-based as passed by samples.
+        reg.add_method(
+            "fmt",
+            |_, this: &QueryLine, (i, tag, index): (usize, String, Option<usize>)| {
+                Ok(this.format(i - 1, &tag, index))
+            },
+        );
+        reg.add_method("samples", |_, this: &QueryLine, f: mlua::Function| {
+            let mut out = String::new();
+            for i in 1..=this.samples.len() {
+                out.push_str(&f.call::<_, String>(i)?);
+            }
+            Ok(out)
+        });
+    })?;
+    let samples = Arc::new(
+        hv.samples()
+            .iter()
+            .map(|s| String::from_utf8_lossy(s).to_string())
+            .collect::<Vec<_>>(),
+    );
+    let flags = Arc::new(
+        hv.header_records()
+            .iter()
+            .filter_map(|r| match r {
+                HeaderRecord::Info { values, .. }
+                    if values.get("Type").map(|t| t.as_str()) == Some("Flag") =>
+                {
+                    values.get("ID").cloned()
+                }
+                _ => None,
+            })
+            .collect::<FxHashSet<_>>(),
+    );
+    lua.globals().raw_set(
+        "__query",
+        lua.create_function(move |_, variant: AnyUserData| {
+            let variant = variant.borrow::<Variant>()?;
+            QueryLine::new(variant.record(), samples.clone(), flags.clone())
+                .map_err(mlua::Error::external)
+        })?,
+    )
+}
+
+/// Write the records of `path` that pass `expressions` (all if there are none) to `output`
+/// (stdout if None) as given by the bcftools query `format`. `print_header` writes a line with
+/// the field names first.
+pub fn query(
+    path: &str,
+    format: &str,
+    expressions: Vec<String>,
+    lua_prelude: Vec<String>,
+    output: Option<String>,
+    print_header: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let tokens = parse(format)?;
+    let expressions = if expressions.is_empty() {
+        vec!["return true".to_string()]
+    } else {
+        expressions
+    };
+    let code = template(&tokens)?;
+    let lua = Lua::new();
+    let mut vcfexpr = VCFExpress::new(
+        &lua,
+        path.to_string(),
+        expressions,
+        vec![],
+        Some(code),
+        lua_prelude,
+        output,
+        false,
+    )?;
+    let hv = vcfexpr.header().clone();
+    check(&tokens, false, &hv)?;
+    register_query(&lua, &hv)?;
+    let mut reader = vcfexpr.reader();
+    let mut writer = vcfexpr.writer()?;
+    if print_header {
+        let samples = hv
+            .samples()
+            .iter()
+            .map(|s| String::from_utf8_lossy(s).to_string())
+            .collect::<Vec<_>>();
+        writer.write(&mut StringOrVariant::String(header_line(&tokens, &samples)))?;
+    }
+    let header_map = HeaderMap::new();
+    vcfexpr.start()?;
+    for r in reader.records() {
+        let mut record = r?;
+        vcfexpr.translate(&mut writer, &mut record)?;
+        let mut sob = vcfexpr.evaluate(record, header_map.clone())?;
+        writer.write(&mut sob)?;
+    }
+    vcfexpr.finish(&mut writer)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse(r"%CHROM\t%AF{1}[ %GT]\n").unwrap(),
+            vec![
+                Token::Field("CHROM".to_string(), None),
+                Token::Text(r"\t".to_string()),
+                Token::Field("AF".to_string(), Some(1)),
+                Token::Samples(vec![
+                    Token::Text(" ".to_string()),
+                    Token::Field("GT".to_string(), None)
+                ]),
+            ]
+        );
+        assert!(parse("[%GT").is_err());
+        assert!(parse("%GT]").is_err());
+        assert!(parse("[[%GT]]").is_err());
+        assert!(parse("%AF{x}").is_err());
+        assert!(parse("% ").is_err());
+        assert!(resolve("GT", false).is_err());
+        assert_eq!(
+            template(&parse("{%POS}`").unwrap()).unwrap(),
+            "local q = __query(variant)\nreturn `\\{{q:get(\"POS\")}}\\``"
+        );
+    }
+
+    #[test]
+    fn test_query() {
+        let vcf = "_test.query.vcf";
+        std::fs::write(
+            vcf,
+            [
+                "##fileformat=VCFv4.2",
+                "##contig=<ID=chr1,length=1000>",
+                r#"##INFO=<ID=AF,Number=A,Type=Float,Description="AF">"#,
+                r#"##INFO=<ID=DB,Number=0,Type=Flag,Description="dbSNP">"#,
+                r#"##FORMAT=<ID=GT,Number=1,Type=String,Description="Genotype">"#,
+                r#"##FORMAT=<ID=AD,Number=R,Type=Integer,Description="Depths">"#,
+                "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\tS1\tS2",
+                "chr1\t10\t.\tA\tG,T\t.\tPASS\tAF=0.25,0.5;DB\tGT:AD\t0/2:3,0,4\t1|1:0,5,0",
+                "chr1\t20\trs2\tC\tT\t.\tPASS\t.\tGT\t0/1\t./.",
+                "",
+            ]
+            .join("\n"),
+        )
+        .unwrap();
+        let out = "_test.query.txt";
+        let run = |format: &str, expressions: Vec<String>| {
+            query(
+                vcf,
+                format,
+                expressions,
+                vec![],
+                Some(out.to_string()),
+                true,
+            )?;
+            Ok::<_, Box<dyn std::error::Error>>(std::fs::read_to_string(out).unwrap())
+        };
+        assert_eq!(
+            run(r"%POS\t%ID\t%AF{1}\t%DB[\t%SAMPLE:%TGT:%AD]\n", vec![]).unwrap(),
+            [
+                "#POS\tID\tAF{1}\tDB\tS1:SAMPLE:S1:TGT:S1:AD\tS2:SAMPLE:S2:TGT:S2:AD",
+                "10\t.\t0.5\t1\tS1:A/T:3,0,4\tS2:G|G:0,5,0",
+                "20\trs2\t.\t0\tS1:C/T:.\tS2:./.:.",
+                "",
+            ]
+            .join("\n")
+        );
+        assert_eq!(
+            run(
+                "%CHROM:%POS-%END",
+                vec!["return variant.id == 'rs2'".to_string()]
+            )
+            .unwrap(),
+            "#CHROM:POS-END\nchr1:20-20\n"
+        );
+        let err = run("%POS[%DP]", vec![]).unwrap_err();
+        assert!(err.to_string().contains("FORMAT DP"), "{}", err);
+        for f in [vcf, out] {
+            _ = std::fs::remove_file(f);
+        }
+    }
+}
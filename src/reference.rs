@@ -0,0 +1,120 @@
+//! Check the `##contig` lengths in the output header against a reference FASTA (`--fasta`).
+//! Mismatched lengths are an error because indexing and most downstream tools fail confusingly on
+//! them. With `--fasta-add-contigs`, reference contigs that are not in the header are added.
+use rust_htslib::bcf::header::HeaderView;
+use rust_htslib::faidx;
+
+fn invalid(msg: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg)
+}
+
+#[derive(Debug, Default)]
+pub struct Reference {
+    /// (name, length) of each sequence in the order of the .fai.
+    contigs: Vec<(String, u64)>,
+    /// add `##contig` lines for reference sequences missing from the header.
+    add_missing: bool,
+}
+
+impl Reference {
+    /// Read the sequence names and lengths from the .fai of `path`. The index is created if needed.
+    pub fn from_path(path: &str, add_missing: bool) -> Result<Self, Box<dyn std::error::Error>> {
+        let fai = faidx::Reader::from_path(path)?;
+        let contigs = fai
+            .seq_names()?
+            .into_iter()
+            .map(|name| {
+                let length = fai.fetch_seq_len(&name);
+                (name, length)
+            })
+            .collect();
+        Ok(Reference {
+            contigs,
+            add_missing,
+        })
+    }
+
+    /// Report header contigs whose length does not match the reference and, if `add_missing`
+    /// is set, add the reference contigs that the header does not have.
+    pub fn check(&self, hv: &mut HeaderView) -> std::io::Result<()> {
+        let header_contigs = crate::header::contigs(hv);
+        let mut mismatches = vec![];
+        for (name, length) in &header_contigs {
+            match self.contigs.iter().find(|(n, _)| n == name) {
+                Some((_, ref_length)) => match length {
+                    Some(l) if l != ref_length => {
+                        mismatches.push(format!("{} (header: {}, fasta: {})", name, l, ref_length))
+                    }
+                    Some(_) => {}
+                    None => log::warn!("contig {} has no length in the header", name),
+                },
+                None => log::warn!("contig {} in the header is not in the --fasta", name),
+            }
+        }
+        if !mismatches.is_empty() {
+            return Err(invalid(format!(
+                "contig lengths in the header do not match --fasta: {}",
+                mismatches.join(", ")
+            )));
+        }
+        if self.add_missing {
+            let mut added = 0;
+            for (name, length) in &self.contigs {
+                if header_contigs.iter().any(|(n, _)| n == name) {
+                    continue;
+                }
+                crate::header::add_contig(hv, name, Some(*length))?;
+                added += 1;
+            }
+            if added > 0 {
+                log::info!("added {} contig(s) from --fasta to the header", added);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_htslib::bcf;
+
+    #[test]
+    fn test_reference_contigs() {
+        std::fs::write("_test.ref.fa", ">chr1\nACGTACGTAC\n>chr2\nACGT\n").unwrap();
+        _ = std::fs::remove_file("_test.ref.fa.fai");
+        let mut header = bcf::Header::new();
+        header.push_record(br#"##contig=<ID=chr1,length=10>"#);
+        let hv = bcf::Writer::from_path("_test.ref.vcf", &header, true, bcf::Format::Vcf)
+            .unwrap()
+            .header()
+            .clone();
+
+        let reference = Reference::from_path("_test.ref.fa", false).unwrap();
+        assert_eq!(reference.contigs[1], ("chr2".to_string(), 4));
+        let mut checked = hv.clone();
+        reference.check(&mut checked).unwrap();
+        assert_eq!(crate::header::contigs(&checked).len(), 1);
+
+        let reference = Reference::from_path("_test.ref.fa", true).unwrap();
+        let mut fixed = hv.clone();
+        reference.check(&mut fixed).unwrap();
+        assert_eq!(
+            crate::header::contigs(&fixed)[1],
+            ("chr2".to_string(), Some(4))
+        );
+
+        let mut header = bcf::Header::new();
+        header.push_record(br#"##contig=<ID=chr1,length=12>"#);
+        let mut bad = bcf::Writer::from_path("_test.ref.vcf", &header, true, bcf::Format::Vcf)
+            .unwrap()
+            .header()
+            .clone();
+        let err = reference.check(&mut bad).unwrap_err().to_string();
+        assert!(err.contains("chr1 (header: 12, fasta: 10)"));
+
+        for f in ["_test.ref.fa", "_test.ref.fa.fai", "_test.ref.vcf"] {
+            _ = std::fs::remove_file(f);
+        }
+    }
+}
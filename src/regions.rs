@@ -0,0 +1,536 @@
+//! Read only the requested regions from an indexed VCF/BCF.
+//! Nearby regions are coalesced into a single ranged read (with a configurable slop) so that
+//! many small regions on a remote file do not each cost a separate request.
+//! Without an index, the whole file is scanned for records in the regions instead.
+use rust_htslib::bcf::{self, Read};
+use rust_htslib::htslib;
+
+/// A 0-based, half-open genomic interval. `end` of None means to the end of the chromosome.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Region {
+    pub chrom: String,
+    pub start: u64,
+    pub end: Option<u64>,
+}
+
+impl std::fmt::Display for Region {
+    /// As parsed: 1-based and inclusive.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.end {
+            Some(end) => write!(f, "{}:{}-{}", self.chrom, self.start + 1, end),
+            None => write!(f, "{}:{}-", self.chrom, self.start + 1),
+        }
+    }
+}
+
+impl std::str::FromStr for Region {
+    type Err = String;
+
+    /// Parse `chrom`, `chrom:start` or `chrom:start-end` with 1-based, inclusive coordinates.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (chrom, range) = match s.rsplit_once(':') {
+            Some((chrom, range)) => (chrom, Some(range)),
+            None => (s, None),
+        };
+        let parse = |v: &str| {
+            v.replace(',', "")
+                .parse::<u64>()
+                .map_err(|_| format!("invalid region '{}'", s))
+        };
+        let (start, end) = match range {
+            None => (0, None),
+            Some(range) => match range.split_once('-') {
+                Some((start, "")) => (parse(start)?.saturating_sub(1), None),
+                Some((start, end)) => (parse(start)?.saturating_sub(1), Some(parse(end)?)),
+                None => (parse(range)?.saturating_sub(1), None),
+            },
+        };
+        if chrom.is_empty() || end.is_some_and(|e| e <= start) {
+            return Err(format!("invalid region '{}'", s));
+        }
+        Ok(Region {
+            chrom: chrom.to_string(),
+            start,
+            end,
+        })
+    }
+}
+
+/// A single ranged read covering one or more of the requested regions.
+#[derive(Debug, PartialEq)]
+pub struct Batch {
+    pub chrom: String,
+    pub start: u64,
+    pub end: Option<u64>,
+    /// the requested (start, end) intervals within this batch. Records are reported only if
+    /// they overlap one of these.
+    pub regions: Vec<(u64, Option<u64>)>,
+}
+
+impl Batch {
+    fn overlaps(&self, start: u64, end: u64) -> bool {
+        self.regions
+            .iter()
+            .any(|&(s, e)| start < e.unwrap_or(u64::MAX) && end > s)
+    }
+}
+
+/// Sort the regions and merge those on the same chromosome that are within `slop` bases of each other.
+/// Chromosomes are kept in the order they are first seen.
+pub fn coalesce(regions: &[Region], slop: u64) -> Vec<Batch> {
+    let mut chroms: Vec<&str> = vec![];
+    for r in regions {
+        if !chroms.contains(&r.chrom.as_str()) {
+            chroms.push(&r.chrom);
+        }
+    }
+    let mut batches = vec![];
+    for chrom in chroms {
+        let mut rs = regions
+            .iter()
+            .filter(|r| r.chrom == chrom)
+            .map(|r| (r.start, r.end))
+            .collect::<Vec<_>>();
+        rs.sort();
+        let mut current: Option<Batch> = None;
+        for (start, end) in rs {
+            if let Some(b) = current.as_mut() {
+                if let Some(bend) = b.end {
+                    if start <= bend.saturating_add(slop) {
+                        b.end = end.map(|e| e.max(bend));
+                        b.regions.push((start, end));
+                        continue;
+                    }
+                } else {
+                    // the batch already extends to the end of the chromosome.
+                    b.regions.push((start, end));
+                    continue;
+                }
+                batches.push(current.take().unwrap());
+            }
+            current = Some(Batch {
+                chrom: chrom.to_string(),
+                start,
+                end,
+                regions: vec![(start, end)],
+            });
+        }
+        batches.extend(current);
+    }
+    batches
+}
+
+/// True if `path` is a URL such as https://, s3:// or gs:// that htslib reads (with range
+/// requests when indexed) rather than a local path.
+pub fn is_url(path: &str) -> bool {
+    path.contains("://") && url::Url::parse(path).is_ok()
+}
+
+/// Open a VCF/BCF from a local path, a URL (see [`is_url`]) or stdin ("-" or "stdin").
+pub fn open_reader(path: &str) -> rust_htslib::errors::Result<bcf::Reader> {
+    match path {
+        "-" | "stdin" => bcf::Reader::from_stdin(),
+        p if is_url(p) => bcf::Reader::from_url(&url::Url::parse(p).expect("checked by is_url")),
+        p => bcf::Reader::from_path(p),
+    }
+}
+
+fn open_indexed(path: &str) -> rust_htslib::errors::Result<bcf::IndexedReader> {
+    if is_url(path) {
+        bcf::IndexedReader::from_url(&url::Url::parse(path).expect("checked by is_url"))
+    } else {
+        bcf::IndexedReader::from_path(path)
+    }
+}
+
+/// Whether `path` has a CSI or tabix index.
+pub(crate) fn has_index(path: &str) -> bool {
+    let Ok(p) = std::ffi::CString::new(path) else {
+        return false;
+    };
+    [htslib::HTS_FMT_CSI, htslib::HTS_FMT_TBI]
+        .iter()
+        .any(|&fmt| unsafe {
+            let idx = htslib::hts_idx_load3(
+                p.as_ptr(),
+                std::ptr::null(),
+                fmt as i32,
+                htslib::HTS_IDX_SILENT_FAIL as i32,
+            );
+            if idx.is_null() {
+                return false;
+            }
+            htslib::hts_idx_destroy(idx);
+            true
+        })
+}
+
+enum Source {
+    Indexed(bcf::IndexedReader),
+    // no index: every record is read and those outside the regions are skipped.
+    Scan(bcf::Reader),
+}
+
+/// RegionReader reads the records overlapping a set of regions with one fetch per [`Batch`].
+pub struct RegionReader {
+    reader: Source,
+    batches: Vec<Batch>,
+    current: Option<usize>,
+    requests: usize,
+    /// True if the records of the indexed reader must be subset to the samples of its header.
+    subset: bool,
+    /// (rid, pos, end, alleles) of the records reported that extend past the end of their
+    /// batch, so that they are not reported again when the next batch fetches them.
+    spanning: Vec<(u32, i64, i64, Vec<Vec<u8>>)>,
+}
+
+impl RegionReader {
+    /// Open `path` for the `regions`. If it has no index, the file is scanned from the start
+    /// (see [`RegionReader::is_indexed`]) unless `require_index` is set, which is an error.
+    pub fn new(
+        path: &str,
+        regions: &[Region],
+        slop: u64,
+        require_index: bool,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        if matches!(path, "-" | "stdin") {
+            return Err(
+                "--region requires an input file; stdin can not be indexed or read twice".into(),
+            );
+        }
+        // IndexedReader does not report a missing index as an error, so check for it first.
+        let reader = if has_index(path) {
+            let mut reader = open_indexed(path)?;
+            _ = reader.set_threads(2);
+            Source::Indexed(reader)
+        } else {
+            // opened first so that a missing file is reported as such.
+            let mut reader = open_reader(path)?;
+            if require_index {
+                return Err(format!(
+                        "no index (.csi or .tbi) found for {}; create one with `vcfexpress index {}` (the file must be a .vcf.gz or .bcf.gz) or remove --require-index to scan the whole file",
+                        path, path
+                    )
+                    .into());
+            }
+            _ = reader.set_threads(2);
+            Source::Scan(reader)
+        };
+        Ok(RegionReader {
+            reader,
+            batches: coalesce(regions, slop),
+            current: None,
+            requests: 0,
+            subset: false,
+            spanning: vec![],
+        })
+    }
+
+    /// False if the file has no index and is scanned for the regions. Records are then in the
+    /// order of the file rather than of the regions.
+    pub fn is_indexed(&self) -> bool {
+        matches!(self.reader, Source::Indexed(_))
+    }
+
+    /// The number of ranged reads issued so far.
+    pub fn requests(&self) -> usize {
+        self.requests
+    }
+
+    pub fn header(&self) -> &bcf::header::HeaderView {
+        match &self.reader {
+            Source::Indexed(r) => r.header(),
+            Source::Scan(r) => r.header(),
+        }
+    }
+
+    pub fn empty_record(&self) -> bcf::Record {
+        match &self.reader {
+            Source::Indexed(r) => r.empty_record(),
+            Source::Scan(r) => r.empty_record(),
+        }
+    }
+
+    /// Read only `samples` (VCF names), as [`crate::sample::subset_reader`] does for a reader of
+    /// the whole file. This must be called before any records are read.
+    pub fn subset_samples(&mut self, samples: &[String]) -> std::io::Result<()> {
+        match &self.reader {
+            Source::Indexed(r) => {
+                crate::sample::subset_reader(r, samples)?;
+                // the synced reader used by IndexedReader reads with its own copy of the header,
+                // so the records are subset as they are read.
+                self.subset = true;
+            }
+            Source::Scan(r) => crate::sample::subset_reader(r, samples)?,
+        }
+        Ok(())
+    }
+
+    /// Read the next record in any of the regions from the whole file.
+    fn scan(&mut self, record: &mut bcf::Record) -> Option<rust_htslib::errors::Result<()>> {
+        let Source::Scan(reader) = &mut self.reader else {
+            unreachable!("scan without Source::Scan");
+        };
+        loop {
+            match reader.read(record)? {
+                Ok(()) => {
+                    let Some(chrom) = record
+                        .rid()
+                        .and_then(|rid| record.header().rid2name(rid).ok())
+                    else {
+                        continue;
+                    };
+                    let (start, end) = (record.pos() as u64, record.end() as u64);
+                    if self
+                        .batches
+                        .iter()
+                        .any(|b| b.chrom.as_bytes() == chrom && b.overlaps(start, end))
+                    {
+                        return Some(Ok(()));
+                    }
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+
+    /// Advance to the next batch. Returns false when all batches are done.
+    fn next_batch(&mut self) -> rust_htslib::errors::Result<bool> {
+        let i = self.current.map_or(0, |i| i + 1);
+        self.current = Some(i);
+        let Some(b) = self.batches.get(i) else {
+            return Ok(false);
+        };
+        let Source::Indexed(reader) = &mut self.reader else {
+            unreachable!("fetch without an index");
+        };
+        let rid = match reader.header().name2rid(b.chrom.as_bytes()) {
+            Ok(rid) => rid,
+            Err(_) => {
+                log::warn!("chromosome {} not found in header; skipping", b.chrom);
+                return self.next_batch();
+            }
+        };
+        self.spanning.retain(|s| s.0 == rid && s.2 as u64 > b.start);
+        self.requests += 1;
+        // htslib treats end as inclusive.
+        reader.fetch(rid, b.start, b.end.map(|e| e.saturating_sub(1)))?;
+        Ok(true)
+    }
+
+    /// Read the next record that overlaps one of the regions. Same semantics as [`bcf::Read::read`].
+    pub fn read(&mut self, record: &mut bcf::Record) -> Option<rust_htslib::errors::Result<()>> {
+        if !self.is_indexed() {
+            return self.scan(record);
+        }
+        if self.current.is_none() {
+            match self.next_batch() {
+                Ok(true) => (),
+                Ok(false) => return None,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        loop {
+            let Source::Indexed(reader) = &mut self.reader else {
+                unreachable!("fetch without an index");
+            };
+            match reader.read(record) {
+                Some(Ok(())) => {
+                    let b = &self.batches[self.current.unwrap()];
+                    let (start, end) = (record.pos() as u64, record.end() as u64);
+                    if !b.overlaps(start, end) {
+                        continue;
+                    }
+                    // a record that starts before the batch may have been reported by the previous one.
+                    if start < b.start
+                        && self.spanning.iter().any(|(rid, pos, _, alleles)| {
+                            record.rid() == Some(*rid)
+                                && record.pos() == *pos
+                                && record.alleles() == *alleles
+                        })
+                    {
+                        continue;
+                    }
+                    if b.end.is_some_and(|e| end > e) {
+                        self.spanning.push((
+                            record.rid().unwrap_or_default(),
+                            record.pos(),
+                            record.end(),
+                            record.alleles().iter().map(|a| a.to_vec()).collect(),
+                        ));
+                    }
+                    if self.subset
+                        && unsafe { htslib::bcf_subset_format(record.header().inner, record.inner) }
+                            != 0
+                    {
+                        return Some(Err(rust_htslib::errors::Error::BcfInvalidRecord));
+                    }
+                    return Some(Ok(()));
+                }
+                Some(Err(e)) => return Some(Err(e)),
+                None => match self.next_batch() {
+                    Ok(true) => (),
+                    Ok(false) => return None,
+                    Err(e) => return Some(Err(e)),
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn region(s: &str) -> Region {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse_region() {
+        assert_eq!(
+            region("chr1:1,001-2000"),
+            Region {
+                chrom: "chr1".to_string(),
+                start: 1000,
+                end: Some(2000)
+            }
+        );
+        assert_eq!(region("chr1").end, None);
+        assert_eq!(region("HLA-A*01:01:1-").start, 0);
+        assert!("chr1:20-10".parse::<Region>().is_err());
+        assert!("chr1:a-10".parse::<Region>().is_err());
+    }
+
+    #[test]
+    fn test_coalesce() {
+        let regions = [
+            region("chr2:100-200"),
+            region("chr1:5000-6000"),
+            region("chr2:1-50"),
+            region("chr2:250-300"),
+            region("chr2:10000-10001"),
+        ];
+        let batches = coalesce(&regions, 100);
+        assert_eq!(batches.len(), 3);
+        assert_eq!((batches[0].start, batches[0].end), (0, Some(300)));
+        assert_eq!(batches[0].regions.len(), 3);
+        assert_eq!(batches[1].start, 9999);
+        assert_eq!(batches[2].chrom, "chr1");
+
+        assert_eq!(coalesce(&regions, 0).len(), 5);
+        assert!(batches[0].overlaps(120, 121));
+        assert!(!batches[0].overlaps(60, 90));
+
+        let batches = coalesce(&[region("chr1:10-"), region("chr1:500-600")], 0);
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].end, None);
+    }
+
+    #[test]
+    fn test_region_reader() {
+        let mut header = bcf::Header::new();
+        header.push_record(br#"##contig=<ID=chr1,length=100000>"#);
+        header.push_record(br#"##contig=<ID=chr2,length=100000>"#);
+        header.push_record(br#"##FORMAT=<ID=DP,Number=1,Type=Integer,Description="Depth">"#);
+        header.push_sample(b"S1");
+        header.push_sample(b"S2");
+        let mut vcf =
+            bcf::Writer::from_path("_test.regions.bcf", &header, false, bcf::Format::Bcf).unwrap();
+        // deletions at 95 and 140 start before a batch; the one at 140 overlaps two batches.
+        for (chrom, pos, reflen) in [
+            (b"chr1", 95, 10),
+            (b"chr1", 99, 1),
+            (b"chr1", 140, 70),
+            (b"chr1", 199, 1),
+            (b"chr1", 5000, 1),
+            (b"chr2", 10, 1),
+        ] {
+            let mut record = vcf.empty_record();
+            record.set_rid(Some(vcf.header().name2rid(chrom).unwrap()));
+            record.set_pos(pos);
+            record.set_alleles(&[&vec![b'A'; reflen], b"T"]).unwrap();
+            record
+                .push_format_integer(b"DP", &[pos as i32, pos as i32 + 1])
+                .unwrap();
+            vcf.write(&record).unwrap();
+        }
+        drop(vcf);
+        let regions = [
+            region("chr1:200"),
+            region("chr2:1-100"),
+            region("chr1:100-150"),
+            region("chr3:1-10"),
+        ];
+        let positions = |reader: &mut RegionReader| {
+            let mut positions = vec![];
+            loop {
+                let mut record = reader.empty_record();
+                match reader.read(&mut record) {
+                    Some(r) => r.unwrap(),
+                    None => break,
+                }
+                positions.push(record.pos());
+            }
+            positions
+        };
+
+        // without an index the file is scanned, or it is an error if an index is required.
+        let err = RegionReader::new("_test.regions.bcf", &regions, 1000, true)
+            .err()
+            .unwrap();
+        assert!(err
+            .to_string()
+            .contains("`vcfexpress index _test.regions.bcf`"));
+        assert!(RegionReader::new("_test.missing.bcf", &regions, 1000, false).is_err());
+        assert!(RegionReader::new("-", &regions, 1000, false).is_err());
+        let mut reader = RegionReader::new("_test.regions.bcf", &regions, 1000, false).unwrap();
+        assert!(!reader.is_indexed());
+        assert_eq!(positions(&mut reader), vec![95, 99, 140, 199, 5000, 10]);
+        assert_eq!(reader.requests(), 0);
+
+        bcf::index::build("_test.regions.bcf", None, 1, bcf::index::Type::Csi(14)).unwrap();
+        let mut reader = RegionReader::new("_test.regions.bcf", &regions, 1000, false).unwrap();
+        assert!(reader.is_indexed());
+        // chr1 regions are coalesced into one request. chr3 is not in the header.
+        assert_eq!(positions(&mut reader), vec![95, 99, 140, 199, 5000, 10]);
+        assert_eq!(reader.requests(), 2);
+        // in separate requests, the deletion at 140 is reported once.
+        let mut reader = RegionReader::new("_test.regions.bcf", &regions, 0, false).unwrap();
+        assert_eq!(positions(&mut reader), vec![95, 99, 140, 199, 5000, 10]);
+        assert_eq!(reader.requests(), 3);
+
+        // records are subset to the samples of the header.
+        for index in [false, true] {
+            if !index {
+                std::fs::rename("_test.regions.bcf.csi", "_test.regions.idx").unwrap();
+            }
+            let mut reader = RegionReader::new("_test.regions.bcf", &regions, 0, false).unwrap();
+            assert_eq!(reader.is_indexed(), index);
+            reader.subset_samples(&["S2".to_string()]).unwrap();
+            assert_eq!(reader.header().samples(), [b"S2"]);
+            let mut record = reader.empty_record();
+            reader.read(&mut record).unwrap().unwrap();
+            assert_eq!(record.sample_count(), 1);
+            assert_eq!(record.format(b"DP").integer().unwrap()[0], [96]);
+            if !index {
+                std::fs::rename("_test.regions.idx", "_test.regions.bcf.csi").unwrap();
+            }
+        }
+
+        // URLs are opened by htslib.
+        let url = format!(
+            "file://{}",
+            std::fs::canonicalize("_test.regions.bcf")
+                .unwrap()
+                .display()
+        );
+        assert!(is_url(&url) && !is_url("_test.regions.bcf"));
+        let mut reader = RegionReader::new(&url, &regions, 1000, false).unwrap();
+        assert!(reader.is_indexed());
+        assert_eq!(positions(&mut reader), vec![95, 99, 140, 199, 5000, 10]);
+        assert!(open_reader(&url).is_ok());
+        _ = std::fs::remove_file("_test.regions.bcf");
+        _ = std::fs::remove_file("_test.regions.bcf.csi");
+    }
+}
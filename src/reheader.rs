@@ -0,0 +1,344 @@
+//! Replace or patch the header of a VCF/BCF (`vcfexpress reheader`): a new header from a file,
+//! new sample names and/or new `##contig` lines from a FASTA index. When the fields and contigs
+//! keep their ids in the new header, as for renamed samples or an added header line, records
+//! are copied as read, without being unpacked; otherwise they are translated to the new ids and
+//! the values of INFO and FORMAT fields that are no longer defined are dropped. If the new
+//! contigs are in a different order, the output is sorted to match them.
+use crate::sort::{Order, SortOptions, Sorter};
+use rust_htslib::bcf::{self, header::HeaderView, Read};
+use rust_htslib::htslib;
+use std::collections::HashMap;
+
+fn invalid(msg: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, msg)
+}
+
+/// The new sample names from `path`: either one name per line, for each sample in order, or
+/// `old new` pairs for the samples to rename.
+pub fn read_sample_names(
+    path: &str,
+    samples: &[String],
+) -> std::io::Result<HashMap<String, String>> {
+    let text = std::fs::read_to_string(path)?;
+    let lines = text
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| l.split_whitespace().collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    if lines.iter().all(|l| l.len() == 2) {
+        return Ok(lines
+            .iter()
+            .map(|l| (l[0].to_string(), l[1].to_string()))
+            .collect());
+    }
+    if let Some(l) = lines.iter().find(|l| l.len() != 1) {
+        return Err(invalid(format!(
+            "expected a name or an 'old new' pair on each line of {}; got '{}'",
+            path,
+            l.join(" ")
+        )));
+    }
+    if lines.len() != samples.len() {
+        return Err(invalid(format!(
+            "{} has {} name(s) for {} sample(s)",
+            path,
+            lines.len(),
+            samples.len()
+        )));
+    }
+    Ok(samples
+        .iter()
+        .zip(lines)
+        .map(|(s, l)| (s.clone(), l[0].to_string()))
+        .collect())
+}
+
+/// The (name, length) of each sequence in a FASTA index. `path` is the .fai or the FASTA.
+pub fn read_fai(path: &str) -> std::io::Result<Vec<(String, u64)>> {
+    let fai = if path.ends_with(".fai") {
+        path.to_string()
+    } else {
+        format!("{}.fai", path)
+    };
+    let text = std::fs::read_to_string(&fai)?;
+    text.lines()
+        .filter(|l| !l.is_empty())
+        .map(|l| {
+            let mut fields = l.split('\t');
+            match (fields.next(), fields.next().and_then(|n| n.parse().ok())) {
+                (Some(name), Some(length)) => Ok((name.to_string(), length)),
+                _ => Err(invalid(format!("invalid line in {}: '{}'", fai, l))),
+            }
+        })
+        .collect()
+}
+
+/// `text` with its `##contig` lines replaced by `contigs`, placed where the first one was.
+fn replace_contigs(text: &str, contigs: &[(String, u64)]) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut added = false;
+    for line in text.lines() {
+        let is_contig = line.starts_with("##contig=");
+        if !added && (is_contig || line.starts_with("#CHROM")) {
+            for (name, length) in contigs {
+                out.push_str(&format!("##contig=<ID={},length={}>\n", name, length));
+            }
+            added = true;
+        }
+        if !is_contig {
+            out.push_str(line);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// True if every contig and tag of `old` has the same id in `new`, so that records can be
+/// written with `new` as they are.
+fn same_ids(old: &HeaderView, new: &HeaderView) -> bool {
+    [htslib::BCF_DT_ID, htslib::BCF_DT_CTG].iter().all(|&dt| {
+        let n = unsafe { (*old.inner).n[dt as usize] };
+        (0..n).all(|i| unsafe {
+            let key = (*(*old.inner).id[dt as usize].offset(i as isize)).key;
+            key.is_null() || htslib::bcf_hdr_id2int(new.inner, dt as i32, key) == i
+        })
+    })
+}
+
+/// Write `path` to `output` (stdout if None) with a new header: the text of `header` (a file of
+/// `##` lines and, optionally, a `#CHROM` line) or the original header, with the samples
+/// renamed as given in `samples` (see [`read_sample_names`]) and the contigs replaced by those
+/// of the FASTA index `fai`. `options` are used if the output must be sorted.
+pub fn reheader(
+    path: &str,
+    output: Option<&str>,
+    header: Option<&str>,
+    samples: Option<&str>,
+    fai: Option<&str>,
+    options: SortOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut reader = match path {
+        "-" | "stdin" => bcf::Reader::from_stdin()?,
+        _ => bcf::Reader::from_path(path)?,
+    };
+    _ = reader.set_threads(2);
+    let old = reader.header().clone();
+    let old_text = crate::header::header_text(&old)?;
+    let mut text = match header {
+        None => old_text.clone(),
+        Some(header) => {
+            let mut text = std::fs::read_to_string(header)?;
+            if !text.lines().any(|l| l.starts_with("#CHROM")) {
+                let chrom_line = old_text
+                    .lines()
+                    .rfind(|l| l.starts_with("#CHROM"))
+                    .unwrap_or_default();
+                if !text.is_empty() && !text.ends_with('\n') {
+                    text.push('\n');
+                }
+                text.push_str(chrom_line);
+                text.push('\n');
+            }
+            text
+        }
+    };
+    if let Some(fai) = fai {
+        text = replace_contigs(&text, &read_fai(fai)?);
+    }
+    let mut hv = crate::header::parse_header(&text)?;
+    if hv.sample_count() != old.sample_count() {
+        return Err(invalid(format!(
+            "the new header has {} sample(s) but {} has {}",
+            hv.sample_count(),
+            path,
+            old.sample_count()
+        ))
+        .into());
+    }
+    if let Some(samples) = samples {
+        let names = hv
+            .samples()
+            .iter()
+            .map(|s| String::from_utf8_lossy(s).to_string())
+            .collect::<Vec<_>>();
+        crate::header::rename_samples(&mut hv, &read_sample_names(samples, &names)?)?;
+    }
+
+    let header = bcf::Header::from_template(&hv);
+    let mut wtr = match output {
+        Some(output) if output != "-" => {
+            let format = crate::vcfexpress::get_vcf_format(output);
+            bcf::Writer::from_path(output, &header, !output.ends_with(".gz"), format)?
+        }
+        _ => bcf::Writer::from_stdout(&header, true, bcf::Format::Vcf)?,
+    };
+    let hv = wtr.header().clone();
+    if same_ids(&old, &hv) {
+        let mut record = reader.empty_record();
+        while let Some(r) = reader.read(&mut record) {
+            r?;
+            wtr.write(&record)?;
+        }
+        return Ok(());
+    }
+
+    let removed = crate::header::removed_tags(&old, &hv);
+    if !removed.is_empty() {
+        log::warn!(
+            "dropping the values of field(s) not in the new header: {}",
+            removed
+                .iter()
+                .map(|(_, id)| id.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    // whether each contig of the input is in the new header.
+    let contigs = (0..unsafe { (*old.inner).n[htslib::BCF_DT_CTG as usize] } as u32)
+        .map(|rid| {
+            old.rid2name(rid)
+                .is_ok_and(|name| hv.name2rid(name).is_ok())
+        })
+        .collect::<Vec<_>>();
+    // the input contigs in the new order; if it is not increasing the records must be sorted.
+    let rids = crate::header::contigs(&old)
+        .iter()
+        .filter_map(|(name, _)| hv.name2rid(name.as_bytes()).ok())
+        .collect::<Vec<_>>();
+    let mut sorter = None;
+    if !rids.windows(2).all(|w| w[0] < w[1]) {
+        log::info!(
+            "the new header has the contigs of {} in a different order; sorting the output",
+            path
+        );
+        sorter = Some(Sorter::new(&hv, Order::Position, options)?);
+    }
+    for r in reader.records() {
+        let mut record = r?;
+        if let Some(rid) = record.rid() {
+            if !contigs.get(rid as usize).copied().unwrap_or(false) {
+                return Err(invalid(format!(
+                    "contig {} of {} is not in the new header",
+                    String::from_utf8_lossy(old.rid2name(rid)?),
+                    path
+                ))
+                .into());
+            }
+        }
+        crate::header::strip_tags(&mut record, &removed);
+        wtr.translate(&mut record);
+        match &mut sorter {
+            Some(sorter) => sorter.push(record)?,
+            None => wtr.write(&record)?,
+        }
+    }
+    match sorter {
+        Some(sorter) => sorter.finish(&mut wtr),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VCF: &str = "##fileformat=VCFv4.2
+##contig=<ID=chr1,length=1000>
+##contig=<ID=chr2,length=1000>
+##INFO=<ID=DP,Number=1,Type=Integer,Description=\"Depth\">
+##INFO=<ID=AF,Number=A,Type=Float,Description=\"AF\">
+##FORMAT=<ID=GT,Number=1,Type=String,Description=\"Genotype\">
+#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\tS1\tS2
+chr1\t10\t.\tA\tG\t.\t.\tDP=3;AF=0.5\tGT\t0/1\t0/0
+chr2\t20\t.\tC\tT\t.\t.\tDP=4;AF=0.25\tGT\t1/1\t0/1
+";
+
+    fn body(path: &str) -> Vec<String> {
+        std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .filter(|l| !l.starts_with("##"))
+            .map(|l| l.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_reheader() {
+        std::fs::write("_test.reheader.vcf", VCF).unwrap();
+        std::fs::write("_test.reheader.names", "A\nB\n").unwrap();
+        std::fs::write("_test.reheader.pairs", "S2 B\n").unwrap();
+        std::fs::write(
+            "_test.reheader.fa.fai",
+            "chr2\t2000\t6\t60\t61\nchr1\t1000\t0\t60\t61\n",
+        )
+        .unwrap();
+        let input = "_test.reheader.vcf";
+        let out = "_test.reheader.out.vcf";
+        let run = |header, samples, fai| {
+            reheader(
+                input,
+                Some(out),
+                header,
+                samples,
+                fai,
+                SortOptions::default(),
+            )
+        };
+
+        run(None, Some("_test.reheader.names"), None).unwrap();
+        let lines = body(out);
+        assert!(lines[0].ends_with("FORMAT\tA\tB"));
+        assert_eq!(
+            lines[1],
+            "chr1\t10\t.\tA\tG\t.\t.\tDP=3;AF=0.5\tGT\t0/1\t0/0"
+        );
+
+        run(None, Some("_test.reheader.pairs"), None).unwrap();
+        assert!(body(out)[0].ends_with("FORMAT\tS1\tB"));
+
+        // the contigs are in a different order in the .fai so the records are translated and
+        // sorted.
+        run(None, None, Some("_test.reheader.fa")).unwrap();
+        let text = std::fs::read_to_string(out).unwrap();
+        assert!(text.contains("##contig=<ID=chr2,length=2000>\n##contig=<ID=chr1,length=1000>"));
+        assert_eq!(
+            body(out)[1..],
+            [
+                "chr2\t20\t.\tC\tT\t.\t.\tDP=4;AF=0.25\tGT\t1/1\t0/1",
+                "chr1\t10\t.\tA\tG\t.\t.\tDP=3;AF=0.5\tGT\t0/1\t0/0"
+            ]
+        );
+
+        // a header without AF and chr2 and without a #CHROM line.
+        let header = VCF
+            .lines()
+            .filter(|l| l.starts_with("##") && !l.contains("AF") && !l.contains("chr2"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        std::fs::write("_test.reheader.hdr", header).unwrap();
+        let err = run(Some("_test.reheader.hdr"), None, None).unwrap_err();
+        assert!(err.to_string().contains("contig chr2"), "{}", err);
+        std::fs::write("_test.reheader.vcf", &VCF[..VCF.rfind("chr2\t20").unwrap()]).unwrap();
+        run(Some("_test.reheader.hdr"), None, None).unwrap();
+        assert_eq!(
+            body(out),
+            [
+                "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\tS1\tS2",
+                "chr1\t10\t.\tA\tG\t.\t.\tDP=3\tGT\t0/1\t0/0"
+            ]
+        );
+
+        std::fs::write("_test.reheader.names", "A\n").unwrap();
+        assert!(run(None, Some("_test.reheader.names"), None).is_err());
+        for f in [
+            "_test.reheader.vcf",
+            "_test.reheader.names",
+            "_test.reheader.pairs",
+            "_test.reheader.fa.fai",
+            "_test.reheader.hdr",
+            "_test.reheader.out.vcf",
+        ] {
+            _ = std::fs::remove_file(f);
+        }
+    }
+}
@@ -0,0 +1,294 @@
+//! Rename INFO and FORMAT tags, e.g. `--rename-info AF=VAF`.
+//! The new definitions are added to the input header so that each record can move its values
+//! from the old to the new key before it is evaluated. The old definitions are then removed
+//! from the output header.
+use rust_htslib::bcf::header::{HeaderView, TagType};
+use rust_htslib::bcf::{self};
+use rust_htslib::htslib;
+use std::ffi::CString;
+
+use crate::variant::Sentinel;
+
+#[derive(Default, Debug, Clone)]
+pub struct Renames {
+    pub info: Vec<(String, String)>,
+    pub format: Vec<(String, String)>,
+}
+
+fn invalid(msg: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, msg)
+}
+
+/// Parse `OLD=NEW,OLD2=NEW2` pairs. May be given multiple times.
+pub fn parse_pairs(args: &[String]) -> std::io::Result<Vec<(String, String)>> {
+    args.iter()
+        .flat_map(|a| a.split(','))
+        .filter(|p| !p.is_empty())
+        .map(|p| match p.split_once('=') {
+            Some((old, new)) if !old.is_empty() && !new.is_empty() => {
+                Ok((old.to_string(), new.to_string()))
+            }
+            _ => Err(invalid(format!(
+                "expected OLD=NEW in tag rename, got '{}'",
+                p
+            ))),
+        })
+        .collect()
+}
+
+impl Renames {
+    pub fn is_empty(&self) -> bool {
+        self.info.is_empty() && self.format.is_empty()
+    }
+
+    fn all(&self) -> impl Iterator<Item = (u32, &String, &String)> {
+        self.info
+            .iter()
+            .map(|(o, n)| (htslib::BCF_HL_INFO, o, n))
+            .chain(self.format.iter().map(|(o, n)| (htslib::BCF_HL_FMT, o, n)))
+    }
+
+    /// Add a copy of each old definition under the new ID to a header that records will be read with.
+    pub fn add_definitions(&self, hv: &HeaderView) -> std::io::Result<()> {
+        let id = CString::new("ID").unwrap();
+        for (typ, old, new) in self.all() {
+            let kind = if typ == htslib::BCF_HL_INFO {
+                "INFO"
+            } else {
+                "FORMAT"
+            };
+            if old == "GT" || new == "GT" {
+                return Err(invalid("GT can not be renamed".to_string()));
+            }
+            let c_old = CString::new(old.as_str())?;
+            let c_new = CString::new(new.as_str())?;
+            unsafe {
+                if !htslib::bcf_hdr_get_hrec(
+                    hv.inner,
+                    typ as i32,
+                    id.as_ptr(),
+                    c_new.as_ptr(),
+                    std::ptr::null(),
+                )
+                .is_null()
+                {
+                    return Err(invalid(format!(
+                        "{} {} is already in the header; can not rename {} to it",
+                        kind, new, old
+                    )));
+                }
+                let hrec = htslib::bcf_hdr_get_hrec(
+                    hv.inner,
+                    typ as i32,
+                    id.as_ptr(),
+                    c_old.as_ptr(),
+                    std::ptr::null(),
+                );
+                if hrec.is_null() {
+                    return Err(invalid(format!(
+                        "{} {} not found in header for rename",
+                        kind, old
+                    )));
+                }
+                let hrec = htslib::bcf_hrec_dup(hrec);
+                let i = htslib::bcf_hrec_find_key(hrec, id.as_ptr());
+                // drop the IDX so htslib assigns a new one.
+                let idx = CString::new("IDX").unwrap();
+                let j = htslib::bcf_hrec_find_key(hrec, idx.as_ptr());
+                if j >= 0 {
+                    let (j, n) = (j as usize, (*hrec).nkeys as usize);
+                    libc::free(*(*hrec).keys.add(j) as *mut libc::c_void);
+                    libc::free(*(*hrec).vals.add(j) as *mut libc::c_void);
+                    for k in j..n - 1 {
+                        *(*hrec).keys.add(k) = *(*hrec).keys.add(k + 1);
+                        *(*hrec).vals.add(k) = *(*hrec).vals.add(k + 1);
+                    }
+                    (*hrec).nkeys -= 1;
+                }
+                if htslib::bcf_hrec_set_val(hrec, i, c_new.as_ptr(), new.len(), 0) != 0
+                    || htslib::bcf_hdr_add_hrec(hv.inner, hrec) < 0
+                    || htslib::bcf_hdr_sync(hv.inner) != 0
+                {
+                    return Err(invalid(format!("error renaming {} {}", kind, old)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove the old definitions from the output header.
+    pub fn remove_old_definitions(&self, hv: &mut HeaderView) {
+        for (typ, old, _) in self.all() {
+            let c_old = CString::new(old.as_str()).unwrap();
+            unsafe {
+                htslib::bcf_hdr_remove(hv.inner, typ as i32, c_old.as_ptr());
+                htslib::bcf_hdr_sync(hv.inner);
+            }
+        }
+    }
+
+    /// Move the values of each old tag in the record to the new tag.
+    pub fn apply(&self, record: &mut bcf::Record) -> rust_htslib::errors::Result<()> {
+        for (old, new) in &self.info {
+            let (old, new) = (old.as_bytes(), new.as_bytes());
+            match record.header().info_type(old)?.0 {
+                TagType::Flag => {
+                    if record.info(old).flag()? {
+                        record.push_info_flag(new)?;
+                        record.clear_info_flag(old)?;
+                    }
+                }
+                TagType::Integer => {
+                    if let Some(v) = record.info(old).integer()? {
+                        let v = v.to_vec();
+                        record.push_info_integer(new, &v)?;
+                        record.clear_info_integer(old)?;
+                    }
+                }
+                TagType::Float => {
+                    if let Some(v) = record.info(old).float()? {
+                        let v = v.to_vec();
+                        record.push_info_float(new, &v)?;
+                        record.clear_info_float(old)?;
+                    }
+                }
+                TagType::String => {
+                    if let Some(v) = record.info(old).string()? {
+                        let v = v.iter().map(|s| s.to_vec()).collect::<Vec<_>>();
+                        let v = v.iter().map(|s| s.as_slice()).collect::<Vec<_>>();
+                        record.push_info_string(new, &v)?;
+                        record.clear_info_string(old)?;
+                    }
+                }
+            }
+        }
+        for (old, new) in &self.format {
+            let (old, new) = (old.as_bytes(), new.as_bytes());
+            let ht = match record.header().format_type(old)?.0 {
+                TagType::Integer => {
+                    let Ok(v) = record.format(old).integer() else {
+                        continue;
+                    };
+                    let v = padded(&v);
+                    record.push_format_integer(new, &v)?;
+                    htslib::BCF_HT_INT
+                }
+                TagType::Float => {
+                    let Ok(v) = record.format(old).float() else {
+                        continue;
+                    };
+                    let v = padded(&v);
+                    record.push_format_float(new, &v)?;
+                    htslib::BCF_HT_REAL
+                }
+                TagType::String => {
+                    let Ok(v) = record.format(old).string() else {
+                        continue;
+                    };
+                    let v = v.iter().map(|s| s.to_vec()).collect::<Vec<_>>();
+                    record.push_format_string(new, &v)?;
+                    htslib::BCF_HT_STR
+                }
+                TagType::Flag => continue,
+            };
+            let c_old = CString::new(old).unwrap();
+            unsafe {
+                htslib::bcf_update_format(
+                    record.header().inner,
+                    record.inner,
+                    c_old.as_ptr(),
+                    std::ptr::null(),
+                    0,
+                    ht as i32,
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Flatten per-sample values, padding each sample with vector_end to the same length.
+pub(crate) fn padded<T: Sentinel>(values: &[&[T]]) -> Vec<T> {
+    let n = values.iter().map(|v| v.len()).max().unwrap_or(0);
+    let mut flat = Vec::with_capacity(n * values.len());
+    for v in values {
+        flat.extend_from_slice(v);
+        flat.resize(flat.len() + n - v.len(), T::vector_end());
+    }
+    flat
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_htslib::bcf::Read;
+
+    #[test]
+    fn test_parse_pairs() {
+        let pairs = parse_pairs(&["AF=VAF,DP=DEPTH".to_string(), "X=Y".to_string()]).unwrap();
+        assert_eq!(pairs.len(), 3);
+        assert_eq!(pairs[1], ("DP".to_string(), "DEPTH".to_string()));
+        assert!(parse_pairs(&["AF".to_string()]).is_err());
+        assert!(parse_pairs(&["AF=".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_rename() {
+        let mut header = bcf::Header::new();
+        header.push_record(br#"##contig=<ID=chr1,length=10000>"#);
+        header.push_record(br#"##INFO=<ID=AF,Number=A,Type=Float,Description="Allele Frequency">"#);
+        header.push_record(br#"##INFO=<ID=DB,Number=0,Type=Flag,Description="dbSNP">"#);
+        header.push_record(br#"##FORMAT=<ID=GT,Number=1,Type=String,Description="Genotype">"#);
+        header.push_record(br#"##FORMAT=<ID=AD,Number=R,Type=Integer,Description="Depths">"#);
+        header.push_sample(b"S1");
+        header.push_sample(b"S2");
+        let mut vcf =
+            bcf::Writer::from_path("_test.rename.vcf", &header, true, bcf::Format::Vcf).unwrap();
+        let mut record = vcf.empty_record();
+        record.set_rid(Some(0));
+        record.set_pos(9);
+        record.set_alleles(&[b"A", b"T"]).unwrap();
+        record.push_info_float(b"AF", &[0.25]).unwrap();
+        record.push_info_flag(b"DB").unwrap();
+        record
+            .push_genotypes(&[
+                bcf::record::GenotypeAllele::Unphased(0),
+                bcf::record::GenotypeAllele::Unphased(1),
+                bcf::record::GenotypeAllele::Unphased(1),
+                bcf::record::GenotypeAllele::Unphased(1),
+            ])
+            .unwrap();
+        record
+            .push_format_integer(b"AD", &[5, 4, i32::missing(), i32::vector_end()])
+            .unwrap();
+        vcf.write(&record).unwrap();
+        drop(vcf);
+
+        let renames = Renames {
+            info: parse_pairs(&["AF=VAF,DB=dbsnp".to_string()]).unwrap(),
+            format: parse_pairs(&["AD=DEPTHS".to_string()]).unwrap(),
+        };
+        let mut reader = bcf::Reader::from_path("_test.rename.vcf").unwrap();
+        renames.add_definitions(reader.header()).unwrap();
+        assert!(renames.add_definitions(reader.header()).is_err());
+        let mut hv = HeaderView::new(unsafe { htslib::bcf_hdr_dup(reader.header().inner) });
+        renames.remove_old_definitions(&mut hv);
+        let out = bcf::Header::from_template(&hv);
+        let mut wtr =
+            bcf::Writer::from_path("_test.rename.out.vcf", &out, true, bcf::Format::Vcf).unwrap();
+        let mut record = reader.records().next().unwrap().unwrap();
+        renames.apply(&mut record).unwrap();
+        assert_eq!(record.info(b"VAF").float().unwrap().unwrap()[0], 0.25);
+        assert!(record.info(b"AF").float().unwrap().is_none());
+        wtr.translate(&mut record);
+        wtr.write(&record).unwrap();
+        drop(wtr);
+
+        let text = std::fs::read_to_string("_test.rename.out.vcf").unwrap();
+        assert!(text.contains("##INFO=<ID=VAF,Number=A,Type=Float"));
+        assert!(!text.contains("ID=AF,"));
+        assert!(text.contains("\tVAF=0.25;dbsnp\tGT:DEPTHS\t0/1:5,4\t1/1:."));
+        _ = std::fs::remove_file("_test.rename.vcf");
+        _ = std::fs::remove_file("_test.rename.out.vcf");
+    }
+}
@@ -0,0 +1,174 @@
+//! A machine-readable summary of a filter run (`--report report.json`) for workflow managers to
+//! track provenance: the input, output and expressions, the number of records evaluated,
+//! passing and failing overall and for each expression, assertion failures and the wall time.
+//! A failed run also writes the report with the counts so far and the `error`.
+//! For each expression, `evaluated` and `decisive` show the effect of the expression order:
+//! expressions after the first that passes are not evaluated.
+use std::io::Write;
+
+use crate::manifest::json_string;
+use crate::vcfexpress::{ExpressionTally, VCFExpress};
+
+#[derive(Debug, Default)]
+pub struct RunReport {
+    pub input: String,
+    pub output: Option<String>,
+    pub expressions: Vec<String>,
+    /// the records passing each expression; None if they were not counted.
+    pub expression_passing: Option<Vec<usize>>,
+    /// the evaluated and decisive counts of each expression; empty with --per-sample or --per-allele.
+    pub expression_tallies: Vec<ExpressionTally>,
+    pub evaluated: usize,
+    pub passing: usize,
+    pub assert_failures: usize,
+    /// the records skipped with --skip and whether reading stopped at --max-records.
+    pub skipped: usize,
+    pub truncated: bool,
+    pub wall_seconds: f64,
+    /// the error that stopped the run; None if it finished.
+    pub error: Option<String>,
+}
+
+impl RunReport {
+    /// Take the counts from a finished run.
+    pub fn set_counts(&mut self, vcfexpr: &VCFExpress) {
+        (self.evaluated, self.passing) = vcfexpr.counts();
+        self.expression_passing = vcfexpr.expression_counts();
+        if vcfexpr.expression_tallies().iter().any(|t| t.evaluated > 0) {
+            self.expression_tallies = vcfexpr.expression_tallies().to_vec();
+        }
+        self.assert_failures = vcfexpr.assert_failures();
+        (self.skipped, self.truncated) = vcfexpr.record_slice();
+    }
+
+    pub fn write_json<W: Write>(&self, mut w: W) -> std::io::Result<()> {
+        let expressions = self
+            .expressions
+            .iter()
+            .enumerate()
+            .map(|(i, e)| {
+                let passing = match &self.expression_passing {
+                    Some(c) => c[i].to_string(),
+                    None => "null".to_string(),
+                };
+                match self.expression_tallies.get(i) {
+                    Some(t) => format!(
+                        "    {{\"expression\": {}, \"evaluated\": {}, \"passing\": {}, \"decisive\": {}}}",
+                        json_string(e),
+                        t.evaluated,
+                        passing,
+                        t.decisive
+                    ),
+                    None => format!(
+                        "    {{\"expression\": {}, \"passing\": {}}}",
+                        json_string(e),
+                        passing
+                    ),
+                }
+            })
+            .collect::<Vec<_>>();
+        writeln!(w, "{{")?;
+        writeln!(w, "  \"input\": {},", json_string(&self.input))?;
+        writeln!(
+            w,
+            "  \"output\": {},",
+            self.output
+                .as_deref()
+                .map_or("null".to_string(), json_string)
+        )?;
+        if expressions.is_empty() {
+            writeln!(w, "  \"expressions\": [],")?;
+        } else {
+            writeln!(w, "  \"expressions\": [\n{}\n  ],", expressions.join(",\n"))?;
+        }
+        writeln!(w, "  \"evaluated\": {},", self.evaluated)?;
+        writeln!(w, "  \"passing\": {},", self.passing)?;
+        writeln!(w, "  \"failing\": {},", self.evaluated - self.passing)?;
+        writeln!(w, "  \"assert_failures\": {},", self.assert_failures)?;
+        writeln!(w, "  \"skipped\": {},", self.skipped)?;
+        writeln!(w, "  \"truncated\": {},", self.truncated)?;
+        writeln!(w, "  \"wall_seconds\": {:.3},", self.wall_seconds)?;
+        writeln!(
+            w,
+            "  \"error\": {}",
+            self.error
+                .as_deref()
+                .map_or("null".to_string(), json_string)
+        )?;
+        writeln!(w, "}}")
+    }
+
+    pub fn write(&self, path: &str) -> std::io::Result<()> {
+        let mut w = std::io::BufWriter::new(std::fs::File::create(path)?);
+        self.write_json(&mut w)?;
+        w.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_report() {
+        let report = RunReport {
+            input: "in.vcf".to_string(),
+            output: None,
+            expressions: vec![
+                "return variant.id == \"rs1\"".to_string(),
+                "return true".to_string(),
+            ],
+            expression_passing: Some(vec![1, 3]),
+            expression_tallies: vec![
+                ExpressionTally {
+                    evaluated: 4,
+                    passing: 1,
+                    decisive: 1,
+                },
+                ExpressionTally {
+                    evaluated: 4,
+                    passing: 3,
+                    decisive: 2,
+                },
+            ],
+            evaluated: 4,
+            passing: 3,
+            assert_failures: 0,
+            skipped: 2,
+            truncated: true,
+            wall_seconds: 0.25,
+            error: None,
+        };
+        let mut json = vec![];
+        report.write_json(&mut json).unwrap();
+        assert_eq!(
+            String::from_utf8(json).unwrap(),
+            r#"{
+  "input": "in.vcf",
+  "output": null,
+  "expressions": [
+    {"expression": "return variant.id == \"rs1\"", "evaluated": 4, "passing": 1, "decisive": 1},
+    {"expression": "return true", "evaluated": 4, "passing": 3, "decisive": 2}
+  ],
+  "evaluated": 4,
+  "passing": 3,
+  "failing": 1,
+  "assert_failures": 0,
+  "skipped": 2,
+  "truncated": true,
+  "wall_seconds": 0.250,
+  "error": null
+}
+"#
+        );
+        let failed = RunReport {
+            error: Some("error evaluating record 3".to_string()),
+            ..report
+        };
+        let mut json = vec![];
+        failed.write_json(&mut json).unwrap();
+        assert!(String::from_utf8(json)
+            .unwrap()
+            .ends_with("  \"error\": \"error evaluating record 3\"\n}\n"));
+    }
+}
@@ -0,0 +1,323 @@
+//! Change the Type of INFO fields, e.g. `--retype-info AF=Float`.
+//! The output header gets the new Type and the values in each record are re-encoded after the
+//! record is translated to the output header. Conversions that would lose information
+//! (e.g. 0.5 to Integer) are errors.
+use rust_htslib::bcf::header::{HeaderView, TagType};
+use rust_htslib::bcf::{self};
+use rust_htslib::htslib;
+use std::ffi::CString;
+
+use crate::variant::Sentinel;
+
+fn invalid(msg: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, msg)
+}
+
+pub(crate) fn type_name(t: &TagType) -> &'static str {
+    match t {
+        TagType::Flag => "Flag",
+        TagType::Integer => "Integer",
+        TagType::Float => "Float",
+        TagType::String => "String",
+    }
+}
+
+/// Parse `TAG=Type,TAG2=Type` pairs. Type is one of Integer, Float or String.
+pub fn parse_retypes(args: &[String]) -> std::io::Result<Vec<(String, TagType)>> {
+    crate::rename::parse_pairs(args)?
+        .into_iter()
+        .map(|(tag, typ)| {
+            let typ = match typ.as_str() {
+                "Integer" => TagType::Integer,
+                "Float" => TagType::Float,
+                "String" => TagType::String,
+                t => {
+                    return Err(invalid(format!(
+                        "unsupported type '{}' for {}; expected Integer, Float or String",
+                        t, tag
+                    )))
+                }
+            };
+            Ok((tag, typ))
+        })
+        .collect()
+}
+
+/// Values of one INFO field in the input type.
+enum Values {
+    Integer(Vec<i32>),
+    Float(Vec<f32>),
+    String(Vec<Vec<u8>>),
+}
+
+#[derive(Default)]
+pub struct Retypes {
+    /// (tag, input type, output type)
+    info: Vec<(String, TagType, TagType)>,
+}
+
+impl Retypes {
+    /// Set the new types in the output header `hv`. `input` is the header that records are read with.
+    pub fn new(
+        retypes: Vec<(String, TagType)>,
+        input: &HeaderView,
+        hv: &mut HeaderView,
+    ) -> std::io::Result<Self> {
+        let mut info = vec![];
+        for (tag, to) in retypes {
+            let (from, _) = input
+                .info_type(tag.as_bytes())
+                .map_err(|_| invalid(format!("INFO {} not found in header for retype", tag)))?;
+            if from == TagType::Flag {
+                return Err(invalid(format!("can not retype Flag INFO field {}", tag)));
+            }
+            let line = header_line(hv, &tag)?;
+            let line = line.replacen(
+                &format!("Type={}", type_name(&from)),
+                &format!("Type={}", type_name(&to)),
+                1,
+            );
+            let c_tag = CString::new(tag.as_str())?;
+            let c_line = CString::new(line.trim_end())?;
+            unsafe {
+                htslib::bcf_hdr_remove(hv.inner, htslib::BCF_HL_INFO as i32, c_tag.as_ptr());
+                if htslib::bcf_hdr_append(hv.inner, c_line.as_ptr()) != 0
+                    || htslib::bcf_hdr_sync(hv.inner) != 0
+                {
+                    return Err(invalid(format!("error changing the type of INFO {}", tag)));
+                }
+            }
+            info.push((tag, from, to));
+        }
+        Ok(Retypes { info })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.info.is_empty()
+    }
+
+    /// Read the values to convert from a record that has not yet been translated.
+    fn read(&self, record: &bcf::Record) -> rust_htslib::errors::Result<Vec<Option<Values>>> {
+        self.info
+            .iter()
+            .map(|(tag, from, _)| {
+                let info = record.info(tag.as_bytes());
+                Ok(match from {
+                    TagType::Integer => info.integer()?.map(|v| Values::Integer(v.to_vec())),
+                    TagType::Float => info.float()?.map(|v| Values::Float(v.to_vec())),
+                    TagType::String => info
+                        .string()?
+                        .map(|v| Values::String(v.iter().map(|s| s.to_vec()).collect())),
+                    TagType::Flag => None,
+                })
+            })
+            .collect()
+    }
+
+    /// Translate the record to the output header with `translate` (e.g. that of the writer),
+    /// converting the retyped fields.
+    pub fn translate(
+        &self,
+        record: &mut bcf::Record,
+        translate: impl FnOnce(&mut bcf::Record),
+    ) -> std::io::Result<()> {
+        if self.is_empty() {
+            translate(record);
+            return Ok(());
+        }
+        let values = self.read(record).map_err(std::io::Error::other)?;
+        translate(record);
+        for ((tag, _, to), v) in self.info.iter().zip(values) {
+            let Some(v) = v else { continue };
+            let err = |v: String| {
+                let chrom = record
+                    .rid()
+                    .and_then(|rid| record.header().rid2name(rid).ok())
+                    .map(|c| String::from_utf8_lossy(c).to_string())
+                    .unwrap_or_default();
+                invalid(format!(
+                    "can not convert {} value '{}' to {} at {}:{} without losing information",
+                    tag,
+                    v,
+                    type_name(to),
+                    chrom,
+                    record.pos() + 1
+                ))
+            };
+            let tag = tag.as_bytes();
+            let result = match (to, v) {
+                (TagType::Float, Values::Integer(v)) => {
+                    let mut fs = Vec::with_capacity(v.len());
+                    for i in v {
+                        if i.is_missing_value() {
+                            fs.push(f32::missing());
+                        } else if (i as f32) as i32 != i {
+                            // f32 holds integers exactly only up to 2^24.
+                            return Err(err(i.to_string()));
+                        } else {
+                            fs.push(i as f32);
+                        }
+                    }
+                    record.push_info_float(tag, &fs)
+                }
+                (TagType::Integer, Values::Float(v)) => {
+                    let mut ints = Vec::with_capacity(v.len());
+                    for f in v {
+                        if f.is_missing_value() {
+                            ints.push(i32::missing());
+                        } else if f.fract() != 0.0 || f.abs() >= i32::MAX as f32 {
+                            return Err(err(f.to_string()));
+                        } else {
+                            ints.push(f as i32);
+                        }
+                    }
+                    record.push_info_integer(tag, &ints)
+                }
+                (TagType::Integer, Values::String(v)) => {
+                    let mut ints = Vec::with_capacity(v.len());
+                    for s in v {
+                        let s = String::from_utf8_lossy(&s).to_string();
+                        match s.as_str() {
+                            "." => ints.push(i32::missing()),
+                            _ => ints.push(s.parse::<i32>().map_err(|_| err(s))?),
+                        }
+                    }
+                    record.push_info_integer(tag, &ints)
+                }
+                (TagType::Float, Values::String(v)) => {
+                    let mut fs = Vec::with_capacity(v.len());
+                    for s in v {
+                        let s = String::from_utf8_lossy(&s).to_string();
+                        match s.as_str() {
+                            "." => fs.push(f32::missing()),
+                            _ => fs.push(s.parse::<f32>().map_err(|_| err(s))?),
+                        }
+                    }
+                    record.push_info_float(tag, &fs)
+                }
+                (TagType::String, v) => {
+                    let strs = match v {
+                        Values::Integer(v) => v
+                            .iter()
+                            .map(|i| match i.is_missing_value() {
+                                true => ".".to_string(),
+                                false => i.to_string(),
+                            })
+                            .collect::<Vec<_>>(),
+                        Values::Float(v) => v
+                            .iter()
+                            .map(|f| match f.is_missing_value() {
+                                true => ".".to_string(),
+                                false => f.to_string(),
+                            })
+                            .collect(),
+                        Values::String(v) => v
+                            .iter()
+                            .map(|s| String::from_utf8_lossy(s).to_string())
+                            .collect(),
+                    };
+                    record.push_info_string(tag, &[strs.join(",").as_bytes()])
+                }
+                (_, Values::Integer(v)) => record.push_info_integer(tag, &v),
+                (_, Values::Float(v)) => record.push_info_float(tag, &v),
+                (_, Values::String(_)) => Ok(()),
+            };
+            result.map_err(std::io::Error::other)?;
+        }
+        Ok(())
+    }
+}
+
+/// The header line for an INFO field.
+fn header_line(hv: &HeaderView, tag: &str) -> std::io::Result<String> {
+    let id = CString::new("ID").unwrap();
+    let c_tag = CString::new(tag)?;
+    unsafe {
+        let hrec = htslib::bcf_hdr_get_hrec(
+            hv.inner,
+            htslib::BCF_HL_INFO as i32,
+            id.as_ptr(),
+            c_tag.as_ptr(),
+            std::ptr::null(),
+        );
+        if hrec.is_null() {
+            return Err(invalid(format!("INFO {} not found in header", tag)));
+        }
+        let mut kstr = htslib::kstring_t {
+            l: 0,
+            m: 0,
+            s: std::ptr::null_mut(),
+        };
+        htslib::bcf_hrec_format(hrec, &mut kstr);
+        let line = String::from_utf8_lossy(std::slice::from_raw_parts(kstr.s as *const u8, kstr.l))
+            .to_string();
+        libc::free(kstr.s as *mut libc::c_void);
+        Ok(line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_htslib::bcf::Read;
+
+    fn write_input(dp: i32, af: f32) {
+        let mut header = bcf::Header::new();
+        header.push_record(br#"##contig=<ID=chr1,length=10000>"#);
+        header.push_record(br#"##INFO=<ID=DP,Number=1,Type=Integer,Description="Depth">"#);
+        header.push_record(br#"##INFO=<ID=AF,Number=A,Type=Float,Description="Frequency">"#);
+        let mut vcf =
+            bcf::Writer::from_path("_test.retype.bcf", &header, true, bcf::Format::Bcf).unwrap();
+        let mut record = vcf.empty_record();
+        record.set_rid(Some(0));
+        record.set_pos(9);
+        record.set_alleles(&[b"A", b"T"]).unwrap();
+        record.push_info_integer(b"DP", &[dp]).unwrap();
+        record.push_info_float(b"AF", &[af]).unwrap();
+        vcf.write(&record).unwrap();
+    }
+
+    fn run(retypes: &[&str]) -> std::io::Result<String> {
+        let mut reader = bcf::Reader::from_path("_test.retype.bcf").unwrap();
+        let mut hv = HeaderView::new(unsafe { htslib::bcf_hdr_dup(reader.header().inner) });
+        let retypes = parse_retypes(&retypes.iter().map(|s| s.to_string()).collect::<Vec<_>>())?;
+        let retypes = Retypes::new(retypes, reader.header(), &mut hv)?;
+        let mut wtr = bcf::Writer::from_path(
+            "_test.retype.vcf",
+            &bcf::Header::from_template(&hv),
+            true,
+            bcf::Format::Vcf,
+        )
+        .unwrap();
+        let mut record = reader.records().next().unwrap().unwrap();
+        retypes.translate(&mut record, |r| wtr.translate(r))?;
+        wtr.write(&record).unwrap();
+        drop(wtr);
+        Ok(std::fs::read_to_string("_test.retype.vcf").unwrap())
+    }
+
+    #[test]
+    fn test_retype() {
+        write_input(12, 2.0);
+        let text = run(&["DP=Float,AF=Integer"]).unwrap();
+        assert!(text.contains("##INFO=<ID=DP,Number=1,Type=Float,Description=\"Depth\">"));
+        assert!(text.contains("##INFO=<ID=AF,Number=A,Type=Integer"));
+        assert!(text.contains("\tDP=12;AF=2\n"));
+        let text = run(&["DP=String"]).unwrap();
+        assert!(text.contains("Type=String"));
+
+        write_input(16_777_217, 0.5);
+        let err = run(&["AF=Integer"]).unwrap_err().to_string();
+        assert!(err.contains("can not convert AF value '0.5' to Integer at chr1:10"));
+        let err = run(&["DP=Float"]).unwrap_err().to_string();
+        assert!(
+            err.contains("can not convert DP value '16777217' to Float"),
+            "{}",
+            err
+        );
+        assert!(run(&["XX=Float"]).is_err());
+        assert!(run(&["DP=Flag"]).is_err());
+        _ = std::fs::remove_file("_test.retype.bcf");
+        _ = std::fs::remove_file("_test.retype.vcf");
+    }
+}
@@ -0,0 +1,111 @@
+//! Runs of homozygosity from a BED file (`--roh`) for `sample:in_roh(variant)`.
+//! The optional 4th column names the sample the run belongs to; without it a run applies to all samples.
+use rustc_hash::FxHashMap;
+use std::io::BufRead;
+
+/// (start, end) of merged runs sorted by start. Coordinates are 0-based, half-open.
+type Runs = Vec<(u64, u64)>;
+
+#[derive(Debug, Default)]
+pub struct Roh {
+    /// chrom -> sample (None for runs of all samples) -> runs.
+    runs: FxHashMap<String, FxHashMap<Option<String>, Runs>>,
+}
+
+/// Sort `runs` and merge those that overlap or touch so that at most one can contain a position.
+fn merge(runs: &mut Runs) {
+    runs.sort_unstable();
+    let mut merged: Runs = Vec::with_capacity(runs.len());
+    for &(start, end) in runs.iter() {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    *runs = merged;
+}
+
+impl Roh {
+    pub fn from_path(path: &str) -> std::io::Result<Self> {
+        Self::from_reader(crate::bed::open(path)?)
+    }
+
+    pub fn from_reader<R: BufRead>(reader: R) -> std::io::Result<Self> {
+        let mut roh = Roh::default();
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            let Some(l) = crate::bed::parse("ROH file", i, &line)? else {
+                continue;
+            };
+            let sample = l
+                .fields
+                .get(3)
+                .map(|s| s.trim())
+                .filter(|s| !s.is_empty())
+                .map(|s| s.to_string());
+            roh.runs
+                .entry(l.chrom.to_string())
+                .or_default()
+                .entry(sample)
+                .or_default()
+                .push((l.start, l.end));
+        }
+        roh.runs
+            .values_mut()
+            .flat_map(|s| s.values_mut())
+            .for_each(merge);
+        Ok(roh)
+    }
+
+    /// Replace sample aliases with the VCF sample names.
+    pub fn resolve_aliases(&mut self, aliases: &crate::alias::SampleAliases) {
+        for samples in self.runs.values_mut() {
+            let mut resolved = FxHashMap::default();
+            for (sample, runs) in samples.drain() {
+                let sample = sample.map(|s| aliases.resolve(&s).to_string());
+                resolved.entry(sample).or_insert_with(Vec::new).extend(runs);
+            }
+            resolved.values_mut().for_each(merge);
+            *samples = resolved;
+        }
+    }
+
+    /// True if the 0-based position `pos` on `chrom` is in a run for `sample`.
+    pub fn contains(&self, sample: &str, chrom: &str, pos: u64) -> bool {
+        let Some(samples) = self.runs.get(chrom) else {
+            return false;
+        };
+        let in_run = |runs: Option<&Runs>| {
+            runs.is_some_and(|runs| {
+                // the runs are disjoint so only the last that starts at or before pos can contain it.
+                let n = runs.partition_point(|r| r.0 <= pos);
+                n > 0 && pos < runs[n - 1].1
+            })
+        };
+        in_run(samples.get(&None)) || in_run(samples.get(&Some(sample.to_string())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roh() {
+        let bed =
+            "#chrom\tstart\tend\tsample\nchr1\t100\t5000\tS1\nchr1\t0\t200\nchr2\t10\t20\tS2\n";
+        let roh = Roh::from_reader(bed.as_bytes()).unwrap();
+        assert!(roh.contains("S1", "chr1", 4999));
+        assert!(!roh.contains("S2", "chr1", 4999));
+        // the run without a sample applies to all.
+        assert!(roh.contains("S2", "chr1", 150));
+        assert!(!roh.contains("S1", "chr2", 15));
+        assert!(!roh.contains("S1", "chrX", 15));
+        // overlapping runs are merged.
+        let roh =
+            Roh::from_reader("chr1\t0\t100\nchr1\t10\t20\nchr1\t50\t300\n".as_bytes()).unwrap();
+        assert!(roh.contains("S1", "chr1", 250));
+        assert!(!roh.contains("S1", "chr1", 300));
+        assert!(Roh::from_reader("chr1\tx\t10\n".as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,151 @@
+//! Round Float FORMAT fields to a number of decimal places on write, e.g.
+//! `--round-format GL:2,AF:4`. Fewer distinct values compress much better in cohort BCFs.
+use rust_htslib::bcf::header::{HeaderView, TagType};
+use rust_htslib::bcf::{self};
+
+use crate::variant::Sentinel;
+
+fn invalid(msg: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, msg)
+}
+
+/// Parse `TAG:digits,TAG2:digits` pairs. May be given multiple times.
+pub fn parse_round_format(args: &[String]) -> std::io::Result<Vec<(String, i32)>> {
+    args.iter()
+        .flat_map(|a| a.split(','))
+        .filter(|p| !p.is_empty())
+        .map(|p| match p.split_once(':') {
+            Some((tag, digits)) if !tag.is_empty() => match digits.parse::<i32>() {
+                Ok(d) => Ok((tag.to_string(), d)),
+                Err(_) => Err(invalid(format!(
+                    "expected TAG:digits in --round-format, got '{}'",
+                    p
+                ))),
+            },
+            _ => Err(invalid(format!(
+                "expected TAG:digits in --round-format, got '{}'",
+                p
+            ))),
+        })
+        .collect()
+}
+
+/// Round `v` to `digits` decimal places. Negative digits round to tens, hundreds, ...
+fn round(v: f32, digits: i32) -> f32 {
+    if v.is_missing_value() || v.is_vector_end() {
+        return v;
+    }
+    let scale = 10f64.powi(digits);
+    ((v as f64 * scale).round() / scale) as f32
+}
+
+#[derive(Default, Debug)]
+pub struct Rounding {
+    /// Float FORMAT tags and the number of digits to keep.
+    tags: Vec<(String, i32)>,
+}
+
+impl Rounding {
+    /// Check the tags against the output header. Integer tags are already rounded so they are skipped.
+    pub fn new(tags: Vec<(String, i32)>, hv: &HeaderView) -> std::io::Result<Self> {
+        let mut keep = vec![];
+        for (tag, digits) in tags {
+            match hv.format_type(tag.as_bytes()) {
+                Ok((TagType::Float, _)) => keep.push((tag, digits)),
+                Ok((TagType::Integer, _)) => {
+                    log::info!("FORMAT {} is Integer; --round-format leaves it as-is", tag)
+                }
+                Ok(_) => {
+                    return Err(invalid(format!(
+                        "--round-format requires a numeric FORMAT field; {} is not",
+                        tag
+                    )))
+                }
+                Err(_) => {
+                    return Err(invalid(format!(
+                        "FORMAT {} given to --round-format not found in header",
+                        tag
+                    )))
+                }
+            }
+        }
+        Ok(Rounding { tags: keep })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty()
+    }
+
+    /// Round the values in a record that has been translated to the output header.
+    pub fn apply(&self, record: &mut bcf::Record) -> rust_htslib::errors::Result<()> {
+        for (tag, digits) in &self.tags {
+            let tag = tag.as_bytes();
+            let Ok(values) = record.format(tag).float() else {
+                continue;
+            };
+            let values = crate::rename::padded(&values)
+                .into_iter()
+                .map(|v| round(v, *digits))
+                .collect::<Vec<_>>();
+            record.push_format_float(tag, &values)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_format() {
+        let tags = parse_round_format(&["GL:2,AF:0".to_string(), "X:-1".to_string()]).unwrap();
+        assert_eq!(tags[2], ("X".to_string(), -1));
+        assert!(parse_round_format(&["GL".to_string()]).is_err());
+        assert!(parse_round_format(&["GL:x".to_string()]).is_err());
+        assert_eq!(round(0.123456, 2), 0.12);
+        assert_eq!(round(2.5, 0), 3.0);
+        assert_eq!(round(123.0, -1), 120.0);
+        assert!(round(f32::missing(), 2).is_missing_value());
+
+        let mut header = bcf::Header::new();
+        header.push_record(br#"##contig=<ID=chr1,length=10000>"#);
+        header.push_record(br#"##FORMAT=<ID=GL,Number=G,Type=Float,Description="GL">"#);
+        header.push_record(br#"##FORMAT=<ID=DP,Number=1,Type=Integer,Description="DP">"#);
+        header.push_record(br#"##FORMAT=<ID=FT,Number=1,Type=String,Description="FT">"#);
+        header.push_sample(b"S1");
+        header.push_sample(b"S2");
+        let vcf =
+            bcf::Writer::from_path("_test.round.vcf", &header, true, bcf::Format::Vcf).unwrap();
+        let hv = vcf.header().clone();
+        assert!(Rounding::new(vec![("FT".to_string(), 1)], &hv).is_err());
+        assert!(Rounding::new(vec![("XX".to_string(), 1)], &hv).is_err());
+        assert!(Rounding::new(vec![("DP".to_string(), 1)], &hv)
+            .unwrap()
+            .is_empty());
+
+        let rounding = Rounding::new(vec![("GL".to_string(), 1)], &hv).unwrap();
+        let mut record = vcf.empty_record();
+        record.set_rid(Some(0));
+        record.set_alleles(&[b"A", b"T"]).unwrap();
+        record
+            .push_format_float(
+                b"GL",
+                &[
+                    -0.04,
+                    -1.26,
+                    -2.0,
+                    f32::missing(),
+                    f32::vector_end(),
+                    f32::vector_end(),
+                ],
+            )
+            .unwrap();
+        rounding.apply(&mut record).unwrap();
+        let gl = record.format(b"GL").float().unwrap();
+        assert_eq!(gl[0], &[0.0, -1.3, -2.0]);
+        assert!(gl[1][0].is_missing_value());
+        drop(vcf);
+        _ = std::fs::remove_file("_test.round.vcf");
+    }
+}
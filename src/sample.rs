@@ -0,0 +1,472 @@
+//! Per-sample access to FORMAT fields. `variant:sample(name)` returns a lua table with
+//! every FORMAT field in the record for that sample. GT is given as allele indexes
+//! (-1 for missing) with the matching `phase` table. Assigning a FORMAT field, e.g.
+//! `sample.GT = '0/1'` or `sample.FT = 'LowGQ'`, writes it to the record.
+use log::info;
+use mlua::{AnyUserData, Lua, Table, Value};
+use rust_htslib::bcf::header::{Id, TagLength, TagType};
+use rust_htslib::bcf::{self};
+use rust_htslib::htslib;
+
+use crate::variant::values_table;
+
+/// FORMAT tags present in the record, in record order.
+pub(crate) fn format_tags(record: &bcf::Record) -> Vec<Vec<u8>> {
+    let inner = unsafe {
+        htslib::bcf_unpack(record.inner, htslib::BCF_UN_FMT as i32);
+        &*record.inner
+    };
+    (0..inner.n_fmt() as usize)
+        .map(|i| {
+            let id = unsafe { (*inner.d.fmt.add(i)).id };
+            record.header().id_to_name(Id(id as u32))
+        })
+        .collect()
+}
+
+/// Set as lua app data so that `variant:sample(name)` returns nil for a name that is not in the
+/// VCF and `variant:samples(names)` skips it, instead of raising an error.
+pub(crate) struct LenientSamples;
+
+/// True if unknown sample names are allowed for this lua state.
+pub(crate) fn lenient(lua: &Lua) -> bool {
+    lua.app_data_ref::<LenientSamples>().is_some()
+}
+
+/// An error message for a sample name that is not in the header, listing some that are.
+pub(crate) fn unknown_sample_message(header: &bcf::header::HeaderView, name: &str) -> String {
+    let samples = header.samples();
+    let mut available = samples
+        .iter()
+        .take(10)
+        .map(|s| String::from_utf8_lossy(s).to_string())
+        .collect::<Vec<_>>();
+    if samples.len() > available.len() {
+        available.push(format!("... ({} total)", samples.len()));
+    }
+    format!(
+        "sample '{}' not found in VCF. available samples: {}",
+        name,
+        available.join(", ")
+    )
+}
+
+/// Read only `samples` (VCF names) from `reader`. This must be called before any records are read.
+/// Samples keep their order in the VCF.
+pub(crate) fn subset_reader<R: bcf::Read>(reader: &R, samples: &[String]) -> std::io::Result<()> {
+    let hv = reader.header();
+    if let Some(s) = samples.iter().find(|s| hv.sample_id(s.as_bytes()).is_none()) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            unknown_sample_message(hv, s),
+        ));
+    }
+    let list = std::ffi::CString::new(samples.join(","))?;
+    if unsafe { htslib::bcf_hdr_set_samples(hv.inner, list.as_ptr(), 0) } != 0 {
+        return Err(std::io::Error::other("error subsetting samples"));
+    }
+    Ok(())
+}
+
+const SAMPLE_METATABLE: &str = "vcfexpress.sample";
+// the shared functions of the metatable of each sample table.
+const SAMPLE_NEWINDEX: &str = "vcfexpress.sample_newindex";
+const SAMPLE_ITER: &str = "vcfexpress.sample_iter";
+// weak-keyed table from sample table to sample name so that methods such as `in_roh`
+// know the sample without adding a key to the table.
+const SAMPLE_NAMES: &str = "vcfexpress.sample_names";
+
+fn sample_names<'lua>(lua: &'lua Lua) -> mlua::Result<Table<'lua>> {
+    if let Some(t) = lua.named_registry_value::<Option<Table>>(SAMPLE_NAMES)? {
+        return Ok(t);
+    }
+    let t = lua.create_table()?;
+    let mt = lua.create_table()?;
+    mt.set("__mode", "k")?;
+    t.set_metatable(Some(mt));
+    lua.set_named_registry_value(SAMPLE_NAMES, &t)?;
+    Ok(t)
+}
+
+/// The name of a sample table created by `new_sample_table` or None for any other table.
+pub(crate) fn sample_name(lua: &Lua, t: &Table) -> mlua::Result<Option<String>> {
+    Ok(sample_names(lua)?
+        .raw_get::<_, Option<mlua::String>>(t.clone())?
+        .map(|s| s.to_string_lossy().to_string()))
+}
+
+/// An empty table for sample `name` with the methods shared by all sample tables, e.g. `s:is_phased()`.
+pub(crate) fn new_sample_table<'lua>(lua: &'lua Lua, name: &[u8]) -> mlua::Result<Table<'lua>> {
+    let mt = match lua.named_registry_value::<Option<Table>>(SAMPLE_METATABLE)? {
+        Some(mt) => mt,
+        None => {
+            let methods = lua.create_table()?;
+            // true if every allele after the first is phased. haploid calls are phased.
+            methods.set(
+                "is_phased",
+                lua.create_function(|_lua, this: Table| {
+                    let phase = fields(&this)?.raw_get::<_, Option<Vec<bool>>>("phase")?;
+                    Ok(phase.is_some_and(|p| p.iter().skip(1).all(|&x| x)))
+                })?,
+            )?;
+            // normalized genotype probabilities from PL (or GL if there is no PL).
+            methods.set(
+                "genotype_probabilities",
+                lua.create_function(|_lua, this: Table| {
+                    let Some(l) = likelihoods(&this)? else {
+                        return Ok(None);
+                    };
+                    let total: f64 = l.iter().sum();
+                    Ok((total > 0.0).then(|| l.iter().map(|v| v / total).collect::<Vec<_>>()))
+                })?,
+            )?;
+            // the PL (or GL if there is no PL) value of the called genotype.
+            methods.set(
+                "called_likelihood",
+                lua.create_function(|_lua, this: Table| {
+                    let this = fields(&this)?;
+                    let Some(gt) = this.raw_get::<_, Option<Vec<i32>>>("GT")? else {
+                        return Ok(None);
+                    };
+                    let Some(i) = genotype_index(&gt) else {
+                        return Ok(None);
+                    };
+                    let pl = this.raw_get::<_, Option<Table>>("PL")?;
+                    let Some(l) = pl.or(this.raw_get::<_, Option<Table>>("GL")?) else {
+                        return Ok(None);
+                    };
+                    l.raw_get::<_, Option<f64>>(i + 1)
+                })?,
+            )?;
+            // true if the allele index (0 for REF, 1 for the first ALT) is in GT.
+            methods.set(
+                "has_allele",
+                lua.create_function(|_lua, (this, allele): (Table, i32)| {
+                    let gt = fields(&this)?.raw_get::<_, Option<Vec<i32>>>("GT")?;
+                    Ok(gt.is_some_and(|gt| gt.contains(&allele)))
+                })?,
+            )?;
+            methods.set(
+                "name",
+                lua.create_function(|lua, this: Table| {
+                    sample_names(lua)?.raw_get::<_, mlua::String>(this)
+                })?,
+            )?;
+            // true if the variant is in a run of homozygosity for this sample (--roh).
+            methods.set(
+                "in_roh",
+                lua.create_function(|lua, (this, variant): (Table, mlua::AnyUserData)| {
+                    let Some(roh) = lua.app_data_ref::<crate::roh::Roh>() else {
+                        return Err(mlua::Error::RuntimeError(
+                            "in_roh requires runs of homozygosity (--roh)".to_string(),
+                        ));
+                    };
+                    let name = sample_names(lua)?.raw_get::<_, mlua::String>(this)?;
+                    let variant = variant.borrow::<crate::variant::Variant>()?;
+                    let record = variant.record();
+                    let chrom = record
+                        .rid()
+                        .and_then(|rid| record.header().rid2name(rid).ok())
+                        .unwrap_or_default();
+                    Ok(roh.contains(
+                        &name.to_string_lossy(),
+                        &String::from_utf8_lossy(chrom),
+                        record.pos() as u64,
+                    ))
+                })?,
+            )?;
+            let mt = lua.create_table()?;
+            mt.set("__index", methods)?;
+            lua.set_named_registry_value(SAMPLE_METATABLE, &mt)?;
+            lua.set_named_registry_value(
+                SAMPLE_NEWINDEX,
+                lua.create_function(|lua, (this, key, value): (Table, String, Value)| {
+                    set_field(lua, &this, &key, value)
+                })?,
+            )?;
+            // `for k, v in sample do` iterates the fields.
+            lua.set_named_registry_value(
+                SAMPLE_ITER,
+                lua.create_function(|lua, this: Table| {
+                    let next = lua.globals().raw_get::<_, mlua::Function>("next")?;
+                    Ok((next, fields(&this)?))
+                })?,
+            )?;
+            mt
+        }
+    };
+    // the table is an empty proxy so that every assignment goes through __newindex. the values are
+    // in the __index table of its own metatable and methods are found from there.
+    let values = lua.create_table()?;
+    values.set_metatable(Some(mt));
+    let proxy_mt = lua.create_table()?;
+    proxy_mt.raw_set("__index", values)?;
+    proxy_mt.raw_set(
+        "__newindex",
+        lua.named_registry_value::<mlua::Function>(SAMPLE_NEWINDEX)?,
+    )?;
+    proxy_mt.raw_set(
+        "__iter",
+        lua.named_registry_value::<mlua::Function>(SAMPLE_ITER)?,
+    )?;
+    let t = lua.create_table()?;
+    t.set_metatable(Some(proxy_mt));
+    sample_names(lua)?.raw_set(t.clone(), lua.create_string(name)?)?;
+    Ok(t)
+}
+
+/// The table holding the values of a sample table from `new_sample_table`.
+pub(crate) fn fields<'lua>(sample: &Table<'lua>) -> mlua::Result<Table<'lua>> {
+    match sample.get_metatable() {
+        Some(mt) => mt.raw_get("__index"),
+        None => Err(mlua::Error::RuntimeError(
+            "expected a sample table from variant:sample".to_string(),
+        )),
+    }
+}
+
+/// Set the variant userdata and 0-based index of a sample table so that assignments to it are
+/// written to the record.
+pub(crate) fn attach(sample: &Table, variant: AnyUserData, sample_id: usize) -> mlua::Result<()> {
+    let mt = sample.get_metatable().ok_or_else(|| {
+        mlua::Error::RuntimeError("expected a sample table from variant:sample".to_string())
+    })?;
+    mt.raw_set("variant", variant)?;
+    mt.raw_set("sample_id", sample_id)
+}
+
+/// `sample[key] = value`: set FORMAT field `key` of the sample in the record and in the table.
+/// GT takes a genotype string such as "0|1", a Genotype or a table of allele indexes. Other fields
+/// must be in the header.
+fn set_field(lua: &Lua, sample: &Table, key: &str, value: Value) -> mlua::Result<()> {
+    let mt = sample
+        .get_metatable()
+        .expect("sample table without metatable");
+    let (Some(variant), Some(i)) = (
+        mt.raw_get::<_, Option<AnyUserData>>("variant")?,
+        mt.raw_get::<_, Option<usize>>("sample_id")?,
+    ) else {
+        return Err(mlua::Error::RuntimeError(format!(
+            "can not set sample.{}: only samples from variant:sample or variant:samples (or the \
+             sample global) can be assigned",
+            key
+        )));
+    };
+    let values = fields(sample)?;
+    let mut v = variant.borrow_mut::<crate::variant::Variant>()?;
+    if key == "GT" {
+        let gt = crate::genotypes::Genotype::from_lua_value(&value)?;
+        v.set_genotype(i, &gt)?;
+        let raw = gt.raw();
+        values.raw_set(
+            "phase",
+            lua.create_sequence_from(raw.iter().map(|&a| a & 1 == 1))?,
+        )?;
+        values.raw_set(
+            "GT",
+            lua.create_sequence_from(raw.iter().map(|&a| crate::genotypes::allele_index(a)))?,
+        )?;
+        return Ok(());
+    }
+    v.set_sample_format(lua, i, key, value.clone())?;
+    values.raw_set(key, value)
+}
+
+/// Linear-scale genotype likelihoods from PL (phred-scaled) or GL (log10-scaled).
+/// None if neither is present or any value is missing.
+fn likelihoods(sample: &Table) -> mlua::Result<Option<Vec<f64>>> {
+    let sample = fields(sample)?;
+    let (values, scale): (Table, fn(f64) -> f64) =
+        match sample.raw_get::<_, Option<Table>>("PL")? {
+            Some(pl) => (pl, |v| 10f64.powf(-v / 10.0)),
+            None => match sample.raw_get::<_, Option<Table>>("GL")? {
+                Some(gl) => (gl, |v| 10f64.powf(v)),
+                None => return Ok(None),
+            },
+        };
+    let n = values.raw_len();
+    let mut l = Vec::with_capacity(n);
+    for i in 1..=n {
+        match values.raw_get::<_, Option<f64>>(i)? {
+            Some(v) => l.push(scale(v)),
+            None => return Ok(None),
+        }
+    }
+    Ok((!l.is_empty()).then_some(l))
+}
+
+/// 0-based index of a haploid or diploid genotype in the VCF ordering of Number=G fields.
+fn genotype_index(gt: &[i32]) -> Option<usize> {
+    match *gt {
+        [a] if a >= 0 => Some(a as usize),
+        [a, b] if a >= 0 && b >= 0 => {
+            let (a, b) = (a.min(b) as usize, a.max(b) as usize);
+            Some(b * (b + 1) / 2 + a)
+        }
+        _ => None,
+    }
+}
+
+/// Build the table of FORMAT fields for the sample at 0-based `sample_id`.
+pub(crate) fn sample_table<'lua>(
+    lua: &'lua Lua,
+    record: &bcf::Record,
+    sample_id: usize,
+) -> mlua::Result<Table<'lua>> {
+    Ok(sample_tables(lua, record, &[sample_id])?.remove(0))
+}
+
+/// Build the tables of FORMAT fields for each of the 0-based `sample_ids`.
+/// Each FORMAT field is decoded only once.
+pub(crate) fn sample_tables<'lua>(
+    lua: &'lua Lua,
+    record: &bcf::Record,
+    sample_ids: &[usize],
+) -> mlua::Result<Vec<Table<'lua>>> {
+    let names = record.header().samples();
+    let tables = sample_ids
+        .iter()
+        .map(|&id| new_sample_table(lua, names[id]))
+        .collect::<mlua::Result<Vec<_>>>()?;
+    let samples = tables
+        .iter()
+        .map(fields)
+        .collect::<mlua::Result<Vec<_>>>()?;
+    for tag in format_tags(record) {
+        let (typ, num) = match record.header().format_type(&tag) {
+            Ok(t) => t,
+            Err(e) => {
+                info!("format tag {} not found. {}", String::from_utf8_lossy(&tag), e);
+                continue;
+            }
+        };
+        let fmt = record.format(&tag);
+        let name = lua.create_string(&tag)?;
+        let scalar = matches!(num, TagLength::Fixed(1));
+        match (typ, &tag[..]) {
+            (TagType::String, b"GT") => {
+                let v = match fmt.integer() {
+                    Ok(v) => v,
+                    Err(_) => continue,
+                };
+                for (sample, &id) in samples.iter().zip(sample_ids) {
+                    let raw = v[id]
+                        .iter()
+                        .take_while(|&&a| a != htslib::bcf_int32_vector_end)
+                        .collect::<Vec<_>>();
+                    sample.raw_set(
+                        "phase",
+                        lua.create_sequence_from(raw.iter().map(|&&a| a & 1 == 1))?,
+                    )?;
+                    sample.raw_set(
+                        name.clone(),
+                        lua.create_sequence_from(
+                            raw.iter().map(|&&a| crate::genotypes::allele_index(a)),
+                        )?,
+                    )?;
+                }
+            }
+            (TagType::Integer, _) => {
+                if let Ok(v) = fmt.integer() {
+                    for (sample, &id) in samples.iter().zip(sample_ids) {
+                        sample.raw_set(name.clone(), sample_value(lua, v[id], scalar)?)?;
+                    }
+                }
+            }
+            (TagType::Float, _) => {
+                if let Ok(v) = fmt.float() {
+                    for (sample, &id) in samples.iter().zip(sample_ids) {
+                        sample.raw_set(name.clone(), sample_value(lua, v[id], scalar)?)?;
+                    }
+                }
+            }
+            (TagType::String, _) => {
+                if let Ok(v) = fmt.string() {
+                    for (sample, &id) in samples.iter().zip(sample_ids) {
+                        let s = v[id];
+                        if !s.is_empty() && s != b"." {
+                            sample.raw_set(name.clone(), lua.create_string(s)?)?;
+                        }
+                    }
+                }
+            }
+            (TagType::Flag, _) => {}
+        }
+    }
+    for sample in &tables {
+        add_allele_fractions(sample)?;
+        add_dosage(sample)?;
+    }
+    Ok(tables)
+}
+
+/// Add `VAF` (alt depth / total depth) and `AB` (depth of the best-supported alt /
+/// (ref + that alt)) computed from AD. Both are nil without AD or when the depth is 0.
+/// Missing AD values count as 0. FORMAT fields of the same name are left as-is.
+pub(crate) fn add_allele_fractions(sample: &Table) -> mlua::Result<()> {
+    let sample = fields(sample)?;
+    let Some(ad) = sample.raw_get::<_, Option<Table>>("AD")? else {
+        return Ok(());
+    };
+    let ad = (1..=ad.raw_len())
+        .map(|i| ad.raw_get::<_, Option<i64>>(i).map(|v| v.unwrap_or(0).max(0)))
+        .collect::<mlua::Result<Vec<_>>>()?;
+    let Some((&ref_depth, alts)) = ad.split_first() else {
+        return Ok(());
+    };
+    let alt_depth: i64 = alts.iter().sum();
+    let max_alt = alts.iter().copied().max().unwrap_or(0);
+    if sample.raw_get::<_, Value>("VAF")?.is_nil() && ref_depth + alt_depth > 0 {
+        sample.raw_set("VAF", alt_depth as f64 / (ref_depth + alt_depth) as f64)?;
+    }
+    if sample.raw_get::<_, Value>("AB")?.is_nil() && ref_depth + max_alt > 0 {
+        sample.raw_set("AB", max_alt as f64 / (ref_depth + max_alt) as f64)?;
+    }
+    Ok(())
+}
+
+/// Add `dosage`: the alt-allele dosage from DS (summed over alts) when present, otherwise the
+/// number of non-reference alleles in GT. nil if DS is missing and GT is absent or has a missing allele.
+pub(crate) fn add_dosage(sample: &Table) -> mlua::Result<()> {
+    let sample = fields(sample)?;
+    if !sample.raw_get::<_, Value>("dosage")?.is_nil() {
+        return Ok(());
+    }
+    let dosage = match sample.raw_get::<_, Value>("DS")? {
+        Value::Number(n) => Some(n),
+        Value::Integer(n) => Some(n as f64),
+        Value::Table(t) if t.raw_len() > 0 => Some(
+            (1..=t.raw_len())
+                .map(|i| t.raw_get::<_, Option<f64>>(i).map(|v| v.unwrap_or(0.0)))
+                .sum::<mlua::Result<f64>>()?,
+        ),
+        _ => None,
+    };
+    let dosage = match dosage {
+        Some(d) => Some(d),
+        None => match sample.raw_get::<_, Option<Vec<i32>>>("GT")? {
+            Some(gt) if !gt.is_empty() && gt.iter().all(|&a| a >= 0) => {
+                Some(gt.iter().filter(|&&a| a > 0).count() as f64)
+            }
+            _ => None,
+        },
+    };
+    if let Some(d) = dosage {
+        sample.raw_set("dosage", d)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn sample_value<'lua, T: crate::variant::Sentinel>(
+    lua: &'lua Lua,
+    vals: &[T],
+    scalar: bool,
+) -> mlua::Result<Value<'lua>> {
+    if scalar {
+        return Ok(vals
+            .first()
+            .filter(|v| !v.is_missing_value())
+            .map_or(Value::Nil, |v| v.to_lua()));
+    }
+    Ok(Value::Table(values_table(lua, vals)?))
+}
@@ -0,0 +1,619 @@
+//! A sample-major cache of FORMAT values so that repeated per-sample analyses of the same
+//! file can get a single sample with `variant:sample(name)` without decoding every sample.
+//! Build with `vcfexpress build-sample-cache input.bcf -o input.vxsc` and use with
+//! `filter --sample-cache input.vxsc`. The cache stores the size and modification time of the
+//! input and a hash of its header and is ignored (with a warning) if they change. Records are matched by contig, position
+//! and alleles so that records read out of order, as with `--region`, can use the cache too.
+use mlua::{Lua, Table};
+use rust_htslib::bcf::header::{TagLength, TagType};
+use rust_htslib::bcf::{self, Read};
+use rustc_hash::FxHashMap;
+use std::cell::{Cell, RefCell};
+use std::hash::Hasher;
+use std::io::{BufReader, BufWriter, Read as IoRead, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+use crate::variant::Sentinel;
+
+const MAGIC: &[u8; 8] = b"VXSCACH3";
+
+/// The approximate number of bytes of encoded values that `build` holds in memory before they
+/// are written to a temporary file.
+const BLOCK_BYTES: usize = 256 << 20;
+
+const INT: u8 = 0;
+const FLOAT: u8 = 1;
+const STRING: u8 = 2;
+const GT: u8 = 3;
+const SCALAR: u8 = 0x80;
+
+/// The size, modification time (in nanoseconds) and a hash of the header of `path`, used to
+/// invalidate the cache without reading the whole file.
+pub fn fingerprint(path: &str) -> std::io::Result<[u64; 3]> {
+    let meta = std::fs::metadata(path)?;
+    let mtime = meta
+        .modified()?
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64);
+    let reader = bcf::Reader::from_path(path).map_err(std::io::Error::other)?;
+    let text = crate::header::header_text(reader.header()).map_err(std::io::Error::other)?;
+    let mut h = rustc_hash::FxHasher::default();
+    h.write(text.as_bytes());
+    Ok([meta.len(), mtime, h.finish()])
+}
+
+/// (contig, position, allele hash) of a record, with the contig as an index in `chroms`.
+type Key = (u32, i64, u64);
+
+fn allele_hash(record: &bcf::Record) -> u64 {
+    let mut h = rustc_hash::FxHasher::default();
+    for a in record.alleles() {
+        h.write(a);
+        h.write_u8(b',');
+    }
+    h.finish()
+}
+
+fn encode_values<T: Sentinel>(out: &mut Vec<u8>, vals: &[T], to_bytes: fn(T) -> [u8; 4]) {
+    let vals = vals
+        .iter()
+        .take_while(|v| !v.is_vector_end())
+        .collect::<Vec<_>>();
+    out.extend_from_slice(&(vals.len() as u32).to_le_bytes());
+    for v in vals {
+        out.extend_from_slice(&to_bytes(*v));
+    }
+}
+
+/// Append the encoded FORMAT values for every sample in the record to `per_sample`. Returns the
+/// number of bytes added.
+fn encode_record(
+    record: &bcf::Record,
+    tags: &mut Vec<Vec<u8>>,
+    per_sample: &mut [Vec<Vec<u8>>],
+) -> usize {
+    let mut entries = vec![vec![]; per_sample.len()];
+    for tag in crate::sample::format_tags(record) {
+        let (typ, num) = match record.header().format_type(&tag) {
+            Ok(t) => t,
+            Err(_) => continue,
+        };
+        let tag_idx = match tags.iter().position(|t| *t == tag) {
+            Some(i) => i,
+            None => {
+                tags.push(tag.clone());
+                tags.len() - 1
+            }
+        };
+        let scalar = if matches!(num, TagLength::Fixed(1)) {
+            SCALAR
+        } else {
+            0
+        };
+        let fmt = record.format(&tag);
+        match (typ, &tag[..]) {
+            (TagType::String, b"GT") | (TagType::Integer, _) => {
+                let code = if &tag[..] == b"GT" { GT } else { INT | scalar };
+                if let Ok(v) = fmt.integer() {
+                    for (e, vals) in entries.iter_mut().zip(v.iter()) {
+                        e.extend_from_slice(&(tag_idx as u16).to_le_bytes());
+                        e.push(code);
+                        encode_values(e, vals, i32::to_le_bytes);
+                    }
+                }
+            }
+            (TagType::Float, _) => {
+                if let Ok(v) = fmt.float() {
+                    for (e, vals) in entries.iter_mut().zip(v.iter()) {
+                        e.extend_from_slice(&(tag_idx as u16).to_le_bytes());
+                        e.push(FLOAT | scalar);
+                        encode_values(e, vals, f32::to_le_bytes);
+                    }
+                }
+            }
+            (TagType::String, _) => {
+                if let Ok(v) = fmt.string() {
+                    for (e, s) in entries.iter_mut().zip(v.iter()) {
+                        e.extend_from_slice(&(tag_idx as u16).to_le_bytes());
+                        e.push(STRING);
+                        e.extend_from_slice(&(s.len() as u32).to_le_bytes());
+                        e.extend_from_slice(s);
+                    }
+                }
+            }
+            (TagType::Flag, _) => {}
+        }
+    }
+    let bytes = entries.iter().map(|e| e.len()).sum();
+    for (s, e) in per_sample.iter_mut().zip(entries) {
+        s.push(e);
+    }
+    bytes
+}
+
+/// The entries of consecutive records, written to a temporary file by sample: for each sample,
+/// the length of each of its entries and then the entries.
+struct Block {
+    path: PathBuf,
+    n_records: usize,
+    /// the file offset of the lengths of each sample and the total length of its entries.
+    samples: Vec<(u64, u64)>,
+}
+
+/// The blocks written by `build`. Their files are removed on drop.
+#[derive(Default)]
+struct Blocks(Vec<Block>);
+
+impl Drop for Blocks {
+    fn drop(&mut self) {
+        for b in &self.0 {
+            _ = std::fs::remove_file(&b.path);
+        }
+    }
+}
+
+impl Blocks {
+    /// Write the entries in `per_sample` to a new block next to `output` and clear them.
+    fn write(&mut self, output: &str, per_sample: &mut [Vec<Vec<u8>>]) -> std::io::Result<()> {
+        let n_records = per_sample.first().map_or(0, |e| e.len());
+        if n_records == 0 {
+            return Ok(());
+        }
+        let path = PathBuf::from(format!("{}.{}.tmp", output, self.0.len()));
+        let mut w = BufWriter::new(std::fs::File::create(&path)?);
+        self.0.push(Block {
+            path,
+            n_records,
+            samples: vec![],
+        });
+        let block = self.0.last_mut().unwrap();
+        let mut offset = 0u64;
+        for entries in per_sample.iter_mut() {
+            let len = entries.iter().map(|e| e.len() as u64).sum::<u64>();
+            block.samples.push((offset, len));
+            for e in entries.iter() {
+                w.write_all(&(e.len() as u32).to_le_bytes())?;
+            }
+            for e in entries.drain(..) {
+                w.write_all(&e)?;
+            }
+            offset += 4 * n_records as u64 + len;
+        }
+        w.flush()
+    }
+
+    /// Write the offset of each (sample, record) entry relative to the start of the data and
+    /// then the entries, sample by sample, to `w`.
+    fn copy_to<W: Write>(&self, n_samples: usize, w: &mut W) -> std::io::Result<()> {
+        let mut files = self
+            .0
+            .iter()
+            .map(|b| std::fs::File::open(&b.path).map(BufReader::new))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        let mut offset = 0u64;
+        for s in 0..n_samples {
+            for (b, f) in self.0.iter().zip(files.iter_mut()) {
+                f.seek(SeekFrom::Start(b.samples[s].0))?;
+                for _ in 0..b.n_records {
+                    w.write_all(&offset.to_le_bytes())?;
+                    offset += read_u32(f)? as u64;
+                }
+            }
+        }
+        w.write_all(&offset.to_le_bytes())?;
+        for s in 0..n_samples {
+            for (b, f) in self.0.iter().zip(files.iter_mut()) {
+                let (start, len) = b.samples[s];
+                f.seek(SeekFrom::Start(start + 4 * b.n_records as u64))?;
+                std::io::copy(&mut f.by_ref().take(len), w)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Transpose the FORMAT data of `path` to sample-major order and write it to `output`.
+pub fn build(path: &str, output: &str) -> Result<(), Box<dyn std::error::Error>> {
+    build_in_blocks(path, output, BLOCK_BYTES)
+}
+
+/// [`build`], holding about `block_bytes` of values in memory at a time. The values of each block
+/// of records are written to a temporary file and copied to `output` at the end.
+fn build_in_blocks(
+    path: &str,
+    output: &str,
+    block_bytes: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let fingerprint = fingerprint(path)?;
+    let mut reader = bcf::Reader::from_path(path)?;
+    _ = reader.set_threads(2);
+    let samples = reader
+        .header()
+        .samples()
+        .iter()
+        .map(|s| s.to_vec())
+        .collect::<Vec<_>>();
+    let mut tags = vec![];
+    let mut chroms: Vec<Vec<u8>> = vec![];
+    let mut keys = vec![];
+    let mut per_sample: Vec<Vec<Vec<u8>>> = vec![vec![]; samples.len()];
+    let mut blocks = Blocks::default();
+    let mut bytes = 0;
+    for record in reader.records() {
+        let record = record?;
+        let chrom = record.header().rid2name(record.rid().unwrap_or_default())?;
+        let rid = match chroms.iter().position(|c| c == chrom) {
+            Some(rid) => rid,
+            None => {
+                chroms.push(chrom.to_vec());
+                chroms.len() - 1
+            }
+        };
+        keys.push((rid as u32, record.pos(), allele_hash(&record)));
+        bytes += encode_record(&record, &mut tags, &mut per_sample);
+        if bytes >= block_bytes {
+            blocks.write(output, &mut per_sample)?;
+            bytes = 0;
+        }
+    }
+    blocks.write(output, &mut per_sample)?;
+
+    let mut w = BufWriter::new(std::fs::File::create(output)?);
+    w.write_all(MAGIC)?;
+    for v in fingerprint {
+        w.write_all(&v.to_le_bytes())?;
+    }
+    w.write_all(&(samples.len() as u32).to_le_bytes())?;
+    w.write_all(&(keys.len() as u64).to_le_bytes())?;
+    w.write_all(&(tags.len() as u32).to_le_bytes())?;
+    w.write_all(&(chroms.len() as u32).to_le_bytes())?;
+    for t in tags.iter().chain(samples.iter()).chain(chroms.iter()) {
+        w.write_all(&(t.len() as u32).to_le_bytes())?;
+        w.write_all(t)?;
+    }
+    for (rid, pos, hash) in &keys {
+        w.write_all(&rid.to_le_bytes())?;
+        w.write_all(&pos.to_le_bytes())?;
+        w.write_all(&hash.to_le_bytes())?;
+    }
+    blocks.copy_to(samples.len(), &mut w)?;
+    w.flush()?;
+    Ok(())
+}
+
+/// SampleCache reads a single (sample, record) entry with two seeks.
+pub struct SampleCache {
+    file: RefCell<std::fs::File>,
+    tags: Vec<Vec<u8>>,
+    samples: Vec<Vec<u8>>,
+    /// cache index of each sample in the reader, which differs from the file order after
+    /// --samples or `header.samples = {...}` in the prelude.
+    ids: Vec<usize>,
+    /// the contigs of the records, in the order they were first seen.
+    chroms: Vec<Vec<u8>>,
+    keys: Vec<Key>,
+    /// the index of the first record with each key, to find records that are not read in file
+    /// order. Built by [`SampleCache::index_records`].
+    by_key: Option<FxHashMap<Key, usize>>,
+    offsets_start: u64,
+    data_start: u64,
+    /// 0-based index of the record currently being evaluated, if it is in the cache.
+    current: Cell<Option<usize>>,
+}
+
+fn read_u32<R: IoRead>(r: &mut R) -> std::io::Result<u32> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: IoRead>(r: &mut R) -> std::io::Result<u64> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_bytes<R: IoRead>(r: &mut R) -> std::io::Result<Vec<u8>> {
+    let mut v = vec![0u8; read_u32(r)? as usize];
+    r.read_exact(&mut v)?;
+    Ok(v)
+}
+
+impl SampleCache {
+    /// Open the cache for `vcf_path`. Returns None if the cache was built from a different file.
+    pub fn open(path: &str, vcf_path: &str) -> std::io::Result<Option<Self>> {
+        let mut r = std::io::BufReader::new(std::fs::File::open(path)?);
+        let mut magic = [0u8; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{} is not a sample cache built by vcfexpress", path),
+            ));
+        }
+        let mut built = [0u64; 3];
+        for v in built.iter_mut() {
+            *v = read_u64(&mut r)?;
+        }
+        if built != fingerprint(vcf_path)? {
+            log::warn!(
+                "sample cache {} does not match {}. ignoring the cache",
+                path,
+                vcf_path
+            );
+            return Ok(None);
+        }
+        let n_samples = read_u32(&mut r)? as usize;
+        let n_records = read_u64(&mut r)? as usize;
+        let n_tags = read_u32(&mut r)? as usize;
+        let n_chroms = read_u32(&mut r)? as usize;
+        let tags = (0..n_tags)
+            .map(|_| read_bytes(&mut r))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        let samples = (0..n_samples)
+            .map(|_| read_bytes(&mut r))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        let chroms = (0..n_chroms)
+            .map(|_| read_bytes(&mut r))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        let keys = (0..n_records)
+            .map(|_| {
+                Ok((
+                    read_u32(&mut r)?,
+                    read_u64(&mut r)? as i64,
+                    read_u64(&mut r)?,
+                ))
+            })
+            .collect::<std::io::Result<Vec<_>>>()?;
+        let offsets_start = r.stream_position()?;
+        let data_start = offsets_start + 8 * (n_samples * n_records + 1) as u64;
+        Ok(Some(SampleCache {
+            file: RefCell::new(r.into_inner()),
+            tags,
+            ids: (0..samples.len()).collect(),
+            samples,
+            chroms,
+            keys,
+            by_key: None,
+            offsets_start,
+            data_start,
+            current: Cell::new(None),
+        }))
+    }
+
+    /// Map the samples of the reader, in its order, to the cache by name.
+    pub fn map_samples(&mut self, names: &[&[u8]]) -> std::io::Result<()> {
+        self.ids = names
+            .iter()
+            .map(|name| {
+                self.samples.iter().position(|s| s == name).ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!(
+                            "sample {} is not in the sample cache",
+                            String::from_utf8_lossy(name)
+                        ),
+                    )
+                })
+            })
+            .collect::<std::io::Result<_>>()?;
+        Ok(())
+    }
+
+    /// Find records by contig, position and alleles rather than by their place in the input,
+    /// for records read out of order as with --region. Of identical records, the first is used.
+    pub fn index_records(&mut self) {
+        let mut by_key = FxHashMap::default();
+        for (i, key) in self.keys.iter().enumerate() {
+            by_key.entry(*key).or_insert(i);
+        }
+        self.by_key = Some(by_key);
+    }
+
+    /// Set the record being evaluated, the `index`th of the input (see
+    /// [`SampleCache::index_records`]). No record is current if `record` is not that one.
+    pub fn set_current(&self, index: usize, record: &bcf::Record) {
+        let key = record
+            .rid()
+            .and_then(|rid| record.header().rid2name(rid).ok())
+            .and_then(|chrom| self.chroms.iter().position(|c| c == chrom))
+            .map(|rid| (rid as u32, record.pos(), allele_hash(record)));
+        let index = match (&self.by_key, &key) {
+            (Some(by_key), Some(key)) => by_key.get(key).copied(),
+            _ => Some(index),
+        };
+        self.current
+            .set(index.filter(|&i| key.is_some() && self.keys.get(i) == key.as_ref()));
+    }
+
+    /// Read the encoded entry for the current record if the record at `pos` matches the cache.
+    /// `sample_id` is the index in the reader.
+    fn entry(&self, sample_id: usize, pos: i64) -> std::io::Result<Option<Vec<u8>>> {
+        let (Some(rec), Some(&sample_id)) = (self.current.get(), self.ids.get(sample_id)) else {
+            return Ok(None);
+        };
+        if self.keys[rec].1 != pos {
+            return Ok(None);
+        }
+        let mut f = self.file.borrow_mut();
+        let i = (sample_id * self.keys.len() + rec) as u64;
+        f.seek(SeekFrom::Start(self.offsets_start + 8 * i))?;
+        let start = read_u64(&mut *f)?;
+        let end = read_u64(&mut *f)?;
+        let mut buf = vec![0u8; (end - start) as usize];
+        f.seek(SeekFrom::Start(self.data_start + start))?;
+        f.read_exact(&mut buf)?;
+        Ok(Some(buf))
+    }
+
+    /// Build the same table as `variant:sample()` from the cache. Returns None if
+    /// the current record is not in the cache.
+    pub(crate) fn sample_table<'lua>(
+        &self,
+        lua: &'lua Lua,
+        sample_id: usize,
+        pos: i64,
+    ) -> mlua::Result<Option<Table<'lua>>> {
+        let buf = match self
+            .entry(sample_id, pos)
+            .map_err(|e| mlua::Error::ExternalError(std::sync::Arc::new(e)))?
+        {
+            Some(b) => b,
+            None => return Ok(None),
+        };
+        let table = crate::sample::new_sample_table(lua, &self.samples[self.ids[sample_id]])?;
+        let sample = crate::sample::fields(&table)?;
+        let mut i = 0;
+        while i + 7 <= buf.len() {
+            let tag = &self.tags[u16::from_le_bytes([buf[i], buf[i + 1]]) as usize];
+            let code = buf[i + 2];
+            let n = u32::from_le_bytes(buf[i + 3..i + 7].try_into().unwrap()) as usize;
+            i += 7;
+            let name = lua.create_string(tag)?;
+            if code == STRING {
+                let s = &buf[i..i + n];
+                if !s.is_empty() && s != b"." {
+                    sample.raw_set(name, lua.create_string(s)?)?;
+                }
+                i += n;
+                continue;
+            }
+            let words = buf[i..i + 4 * n]
+                .chunks_exact(4)
+                .map(|c| [c[0], c[1], c[2], c[3]])
+                .collect::<Vec<_>>();
+            i += 4 * n;
+            match code & !SCALAR {
+                GT => {
+                    let raw = words.iter().map(|w| i32::from_le_bytes(*w));
+                    sample.raw_set(
+                        "phase",
+                        lua.create_sequence_from(raw.clone().map(|a| a & 1 == 1))?,
+                    )?;
+                    sample.raw_set(
+                        name,
+                        lua.create_sequence_from(raw.map(crate::genotypes::allele_index))?,
+                    )?;
+                }
+                INT => {
+                    let vals = words.iter().map(|w| i32::from_le_bytes(*w)).collect::<Vec<_>>();
+                    set_value(lua, &sample, name, &vals, code & SCALAR != 0)?;
+                }
+                _ => {
+                    let vals = words.iter().map(|w| f32::from_le_bytes(*w)).collect::<Vec<_>>();
+                    set_value(lua, &sample, name, &vals, code & SCALAR != 0)?;
+                }
+            }
+        }
+        crate::sample::add_allele_fractions(&table)?;
+        crate::sample::add_dosage(&table)?;
+        Ok(Some(table))
+    }
+}
+
+fn set_value<'lua, T: Sentinel>(
+    lua: &'lua Lua,
+    sample: &Table<'lua>,
+    name: mlua::String<'lua>,
+    vals: &[T],
+    scalar: bool,
+) -> mlua::Result<()> {
+    if scalar {
+        if let Some(v) = vals.first().filter(|v| !v.is_missing_value()) {
+            sample.raw_set(name, v.to_lua())?;
+        }
+        return Ok(());
+    }
+    sample.raw_set(name, crate::variant::values_table(lua, vals)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_and_read() {
+        let mut header = bcf::Header::new();
+        header.push_record(r#"##contig=<ID=chr1,length=10000>"#.as_bytes());
+        header.push_record(
+            r#"##FORMAT=<ID=GT,Number=1,Type=String,Description="Genotype">"#.as_bytes(),
+        );
+        header.push_record(
+            r#"##FORMAT=<ID=AD,Number=R,Type=Integer,Description="Allelic Depths">"#.as_bytes(),
+        );
+        header.push_sample(b"S1");
+        header.push_sample(b"S2");
+        let mut vcf =
+            bcf::Writer::from_path("_test.sc.vcf", &header, true, bcf::Format::Vcf).unwrap();
+        for pos in [5, 9] {
+            let mut record = vcf.empty_record();
+            record.set_rid(Some(vcf.header().name2rid(b"chr1").unwrap()));
+            record.set_pos(pos);
+            record.set_alleles(&[b"A", b"T"]).unwrap();
+            record
+                .push_genotypes(&[
+                    bcf::record::GenotypeAllele::Unphased(0),
+                    bcf::record::GenotypeAllele::Phased(1),
+                    bcf::record::GenotypeAllele::Unphased(1),
+                    bcf::record::GenotypeAllele::Unphased(1),
+                ])
+                .unwrap();
+            record
+                .push_format_integer(b"AD", &[10, pos as i32, 0, 7])
+                .unwrap();
+            vcf.write(&record).unwrap();
+        }
+        drop(vcf);
+
+        build("_test.sc.vcf", "_test.vxsc").unwrap();
+        // a block per record gives the same cache.
+        build_in_blocks("_test.sc.vcf", "_test.blocks.vxsc", 1).unwrap();
+        assert_eq!(
+            std::fs::read("_test.vxsc").unwrap(),
+            std::fs::read("_test.blocks.vxsc").unwrap()
+        );
+        assert!(!std::path::Path::new("_test.blocks.vxsc.0.tmp").exists());
+        let mut cache = SampleCache::open("_test.vxsc", "_test.sc.vcf")
+            .unwrap()
+            .unwrap();
+        let records = bcf::Reader::from_path("_test.sc.vcf")
+            .unwrap()
+            .records()
+            .map(|r| r.unwrap())
+            .collect::<Vec<_>>();
+        let lua = Lua::new();
+        cache.set_current(1, &records[1]);
+        let s = cache.sample_table(&lua, 0, 9).unwrap().unwrap();
+        assert_eq!(s.get::<_, Vec<i32>>("AD").unwrap(), vec![10, 9]);
+        assert_eq!(s.get::<_, Vec<i32>>("GT").unwrap(), vec![0, 1]);
+        assert_eq!(s.get::<_, Vec<bool>>("phase").unwrap(), vec![false, true]);
+        // position mismatch means the record is not from the cache.
+        assert!(cache.sample_table(&lua, 0, 5).unwrap().is_none());
+        // the record is not the second of the input.
+        cache.set_current(1, &records[0]);
+        assert!(cache.sample_table(&lua, 0, 5).unwrap().is_none());
+        // unless records are found by position.
+        cache.index_records();
+        cache.set_current(1, &records[0]);
+        let s = cache.sample_table(&lua, 0, 5).unwrap().unwrap();
+        assert_eq!(s.get::<_, Vec<i32>>("AD").unwrap(), vec![10, 5]);
+        cache.set_current(0, &records[1]);
+
+        // after subsetting the reader to S2, its first sample is the second in the cache.
+        cache.map_samples(&[b"S2"]).unwrap();
+        let s = cache.sample_table(&lua, 0, 9).unwrap().unwrap();
+        assert_eq!(s.get::<_, Vec<i32>>("AD").unwrap(), vec![0, 7]);
+        assert!(cache.sample_table(&lua, 1, 9).unwrap().is_none());
+        assert!(cache.map_samples(&[b"S3"]).is_err());
+
+        // the cache is ignored once the input is changed.
+        let text = std::fs::read_to_string("_test.sc.vcf").unwrap();
+        std::fs::write("_test.sc.vcf", text.replace("\t5\t", "\t15\t")).unwrap();
+        assert!(SampleCache::open("_test.vxsc", "_test.sc.vcf")
+            .unwrap()
+            .is_none());
+        for f in ["_test.sc.vcf", "_test.vxsc", "_test.blocks.vxsc"] {
+            _ = std::fs::remove_file(f);
+        }
+    }
+}
@@ -0,0 +1,242 @@
+//! Named lua fragments shared across a team (`--snippets lib.snippets`) so that
+//! `-e '@rare and @high_impact'` expands each `@name` to its fragment before compilation. The file
+//! has one `name = "fragment"` entry per line, with `'''` or `"""` quotes for fragments over
+//! several lines:
+//! ```text
+//! # comments and blank lines are ignored
+//! rare = "variant:info('AF') < 0.01"
+//! high_impact = '''
+//! variant:info("IMPACT") == "HIGH"
+//! '''
+//! ```
+//! Fragments may use other snippets.
+use rustc_hash::FxHashMap;
+
+fn invalid(msg: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg)
+}
+
+#[derive(Debug, Default)]
+pub struct Snippets {
+    fragments: FxHashMap<String, String>,
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+impl Snippets {
+    pub fn from_path(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let text = std::fs::read_to_string(path)?;
+        Snippets::parse(&text).map_err(|e| format!("{}: {}", path, e).into())
+    }
+
+    /// Read and combine the snippets of each file. A name defined in more than one is an error.
+    pub fn from_paths(paths: &[String]) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut snippets = Snippets::default();
+        for path in paths {
+            for (name, fragment) in Snippets::from_path(path)?.fragments {
+                if snippets.fragments.contains_key(&name) {
+                    return Err(format!("snippet '{}' in {} is already defined", name, path).into());
+                }
+                snippets.fragments.insert(name, fragment);
+            }
+        }
+        Ok(snippets)
+    }
+
+    /// Parse `name = "..."`, `name = '...'` and multi-line `name = '''...'''` or `"""..."""` entries.
+    pub fn parse(text: &str) -> std::io::Result<Self> {
+        let mut fragments = FxHashMap::default();
+        let mut lines = text.lines().enumerate();
+        while let Some((i, line)) = lines.next() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((name, value)) = line.split_once('=') else {
+                return Err(invalid(format!(
+                    "line {}: expected name = \"fragment\"",
+                    i + 1
+                )));
+            };
+            let name = name.trim().trim_matches('"');
+            if name.is_empty() || !name.chars().all(is_name_char) {
+                return Err(invalid(format!(
+                    "line {}: snippet names may only have letters, digits and _, got '{}'",
+                    i + 1,
+                    name
+                )));
+            }
+            let value = value.trim();
+            let fragment = if let Some(delim) =
+                ["'''", "\"\"\""].into_iter().find(|d| value.starts_with(d))
+            {
+                let mut body = value[3..].to_string();
+                loop {
+                    if let Some(end) = body.find(delim) {
+                        body.truncate(end);
+                        break;
+                    }
+                    let Some((_, next)) = lines.next() else {
+                        return Err(invalid(format!(
+                            "line {}: {} for '{}' is not closed",
+                            i + 1,
+                            delim,
+                            name
+                        )));
+                    };
+                    body.push('\n');
+                    body.push_str(next);
+                }
+                body.trim().to_string()
+            } else if let Some(v) = value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')) {
+                v.to_string()
+            } else if let Some(v) = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+                v.replace("\\\"", "\"").replace("\\\\", "\\")
+            } else {
+                return Err(invalid(format!(
+                    "line {}: the fragment for '{}' must be a quoted string",
+                    i + 1,
+                    name
+                )));
+            };
+            if fragments.insert(name.to_string(), fragment).is_some() {
+                return Err(invalid(format!(
+                    "snippet '{}' is defined more than once",
+                    name
+                )));
+            }
+        }
+        Ok(Snippets { fragments })
+    }
+
+    /// Replace each `@name` outside of string literals with `(fragment)`.
+    pub fn expand(&self, code: &str) -> std::io::Result<String> {
+        self.expand_inner(code, &mut vec![])
+    }
+
+    fn expand_inner(&self, code: &str, seen: &mut Vec<String>) -> std::io::Result<String> {
+        let mut out = String::with_capacity(code.len());
+        let mut chars = code.chars().peekable();
+        let mut quote = None;
+        while let Some(c) = chars.next() {
+            match (quote, c) {
+                (Some(_), '\\') => {
+                    out.push(c);
+                    if let Some(next) = chars.next() {
+                        out.push(next);
+                    }
+                }
+                (Some(q), c) if c == q => {
+                    quote = None;
+                    out.push(c);
+                }
+                (Some(_), c) => out.push(c),
+                (None, '"' | '\'' | '`') => {
+                    quote = Some(c);
+                    out.push(c);
+                }
+                (None, '@') if chars.peek().is_some_and(|c| is_name_char(*c)) => {
+                    let mut name = String::new();
+                    while let Some(c) = chars.next_if(|c| is_name_char(*c)) {
+                        name.push(c);
+                    }
+                    let Some(fragment) = self.fragments.get(&name) else {
+                        let mut names = self.fragments.keys().cloned().collect::<Vec<_>>();
+                        names.sort();
+                        return Err(invalid(format!(
+                            "unknown snippet '@{}'. available snippets: {}",
+                            name,
+                            names.join(", ")
+                        )));
+                    };
+                    if seen.contains(&name) {
+                        return Err(invalid(format!(
+                            "snippet '@{}' refers to itself through {}",
+                            name,
+                            seen.join(" -> ")
+                        )));
+                    }
+                    seen.push(name);
+                    out.push('(');
+                    out.push_str(&self.expand_inner(fragment, seen)?);
+                    out.push(')');
+                    seen.pop();
+                }
+                (None, c) => out.push(c),
+            }
+        }
+        Ok(out)
+    }
+
+    /// Expand a filter expression. One that starts with a snippet, e.g. `@rare and @high_impact`,
+    /// is returned.
+    pub fn expand_expression(&self, code: &str) -> std::io::Result<String> {
+        let expanded = self.expand(code)?;
+        if code.trim_start().starts_with('@') {
+            Ok(format!("return {}", expanded))
+        } else {
+            Ok(expanded)
+        }
+    }
+
+    /// Expand the code of a `TAG=expression` set expression.
+    pub fn expand_set_expression(&self, exp: &str) -> std::io::Result<String> {
+        match exp.split_once('=') {
+            Some((tag, code)) => Ok(format!("{}={}", tag, self.expand_expression(code)?)),
+            None => Ok(exp.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snippets() {
+        let snippets = Snippets::parse(
+            r#"
+# team filters
+rare = "variant:info('AF') < 0.01"
+high_impact = '''
+variant:info("IMPACT") == "HIGH"
+'''
+rare_high = "@rare and @high_impact"
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            snippets
+                .expand_expression("@rare and @high_impact")
+                .unwrap(),
+            r#"return (variant:info('AF') < 0.01) and (variant:info("IMPACT") == "HIGH")"#
+        );
+        assert_eq!(
+            snippets.expand("return @rare_high").unwrap(),
+            r#"return ((variant:info('AF') < 0.01) and (variant:info("IMPACT") == "HIGH"))"#
+        );
+        // not expanded in strings.
+        assert_eq!(
+            snippets.expand(r#"return variant.id == "@rare""#).unwrap(),
+            r#"return variant.id == "@rare""#
+        );
+        assert_eq!(
+            snippets.expand_set_expression("RARE=@rare").unwrap(),
+            "RARE=return (variant:info('AF') < 0.01)"
+        );
+        let err = snippets.expand("@common").unwrap_err().to_string();
+        assert!(err.contains("available snippets: high_impact, rare, rare_high"));
+
+        let cycle = Snippets::parse("a = \"@b\"\nb = \"@a\"").unwrap();
+        assert!(cycle
+            .expand("@a")
+            .unwrap_err()
+            .to_string()
+            .contains("a -> b"));
+        assert!(Snippets::parse("a = x").is_err());
+        assert!(Snippets::parse("a = '''x").is_err());
+        assert!(Snippets::parse("a = 'x'\na = 'y'").is_err());
+    }
+}
@@ -0,0 +1,448 @@
+//! Sort the records of a VCF/BCF by position or by a numeric INFO field such as the
+//! `SORTKEY` set by `filter -s 'SORTKEY=<expr>'`. Records are buffered up to a memory budget;
+//! larger inputs are written as sorted runs to a temporary directory and merged.
+use rust_htslib::bcf::{self, header::HeaderView, Read};
+use std::cmp::Ordering;
+use std::path::PathBuf;
+
+use crate::variant::Sentinel;
+
+/// The INFO field that `filter -s` adds to the header if needed so that expressions can set a sort key.
+pub const SORTKEY: &str = "SORTKEY";
+pub(crate) const SORTKEY_HEADER: &str =
+    r#"##INFO=<ID=SORTKEY,Number=1,Type=Float,Description="Sort key set by vcfexpress">"#;
+
+/// The first value of INFO `key` as f64 or None if it is missing.
+fn info_key(record: &bcf::Record, key: &[u8]) -> rust_htslib::errors::Result<Option<f64>> {
+    let info = record.info(key);
+    Ok(match record.header().info_type(key)?.0 {
+        bcf::header::TagType::Integer => info
+            .integer()?
+            .and_then(|v| v.first().copied())
+            .filter(|v| !v.is_missing_value())
+            .map(|v| v as f64),
+        _ => info
+            .float()?
+            .and_then(|v| v.first().copied())
+            .filter(|v| !v.is_missing_value())
+            .map(|v| v as f64),
+    })
+}
+
+/// Parse a memory size such as `768M`, `2G`, `500K` or a number of bytes.
+pub fn parse_size(size: &str) -> Result<usize, String> {
+    let s = size.trim();
+    let (n, mult) = match s.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => {
+            let mult = match c.to_ascii_uppercase() {
+                'K' => 1 << 10,
+                'M' => 1 << 20,
+                'G' => 1 << 30,
+                _ => {
+                    return Err(format!(
+                        "invalid size: {} (use e.g. 500K, 768M or 2G)",
+                        size
+                    ))
+                }
+            };
+            (&s[..i], mult)
+        }
+        _ => (s, 1),
+    };
+    n.parse::<f64>()
+        .ok()
+        .filter(|n| *n > 0.0)
+        .map(|n| (n * mult as f64) as usize)
+        .ok_or_else(|| format!("invalid size: {} (use e.g. 500K, 768M or 2G)", size))
+}
+
+/// How a [`Sorter`] orders records.
+#[derive(Clone, Debug)]
+pub enum Order {
+    /// By contig (in header order) and position.
+    Position,
+    /// By a numeric INFO field, from high to low if `reverse`. Records without it are last.
+    Info { key: String, reverse: bool },
+}
+
+/// The memory budget and temporary directory of a [`Sorter`].
+#[derive(Clone, Debug)]
+pub struct SortOptions {
+    /// The approximate number of bytes of records to hold in memory before a run is written.
+    pub max_memory: usize,
+    /// Where runs are written. Default is the system temporary directory.
+    pub tmp_dir: Option<String>,
+}
+
+impl Default for SortOptions {
+    fn default() -> Self {
+        SortOptions {
+            max_memory: 768 << 20,
+            tmp_dir: None,
+        }
+    }
+}
+
+/// An external merge sort of records with a shared header. Records are buffered until the memory
+/// budget is reached and then written, sorted, as an uncompressed BCF run; [`Sorter::finish`]
+/// merges the runs (or sorts the buffer if nothing was written). Ties keep their input order.
+pub struct Sorter {
+    order: Order,
+    header: bcf::Header,
+    options: SortOptions,
+    buffer: Vec<(Option<f64>, bcf::Record)>,
+    bytes: usize,
+    // created when the first run is written and removed on drop.
+    dir: Option<PathBuf>,
+    runs: Vec<PathBuf>,
+}
+
+/// An approximation of the memory used by a record.
+fn record_size(record: &bcf::Record) -> usize {
+    let inner = record.inner();
+    std::mem::size_of::<rust_htslib::htslib::bcf1_t>() + inner.shared.m + inner.indiv.m
+}
+
+impl Sorter {
+    /// A sorter for records of `hv`. An error if `order` is by an INFO field that is not in it.
+    pub fn new(
+        hv: &HeaderView,
+        order: Order,
+        options: SortOptions,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        if let Order::Info { key, .. } = &order {
+            if hv.info_type(key.as_bytes()).is_err() {
+                return Err(format!("INFO {} not found in header", key).into());
+            }
+        }
+        Ok(Sorter {
+            order,
+            header: bcf::Header::from_template(hv),
+            options,
+            buffer: vec![],
+            bytes: 0,
+            dir: None,
+            runs: vec![],
+        })
+    }
+
+    fn key(&self, record: &bcf::Record) -> rust_htslib::errors::Result<Option<f64>> {
+        match &self.order {
+            Order::Position => Ok(None),
+            Order::Info { key, .. } => info_key(record, key.as_bytes()),
+        }
+    }
+
+    fn compare(&self, a: &(Option<f64>, bcf::Record), b: &(Option<f64>, bcf::Record)) -> Ordering {
+        match &self.order {
+            Order::Position => (a.1.rid(), a.1.pos()).cmp(&(b.1.rid(), b.1.pos())),
+            Order::Info { reverse, .. } => match (a.0, b.0) {
+                (Some(a), Some(b)) => {
+                    let o = a.partial_cmp(&b).unwrap_or(Ordering::Equal);
+                    if *reverse {
+                        o.reverse()
+                    } else {
+                        o
+                    }
+                }
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            },
+        }
+    }
+
+    fn sort_buffer(&mut self) {
+        let mut buffer = std::mem::take(&mut self.buffer);
+        buffer.sort_by(|a, b| self.compare(a, b));
+        self.buffer = buffer;
+    }
+
+    /// Add a record. It must have the header given to [`Sorter::new`].
+    pub fn push(&mut self, record: bcf::Record) -> Result<(), Box<dyn std::error::Error>> {
+        self.bytes += record_size(&record);
+        self.buffer.push((self.key(&record)?, record));
+        if self.bytes >= self.options.max_memory {
+            self.write_run()?;
+        }
+        Ok(())
+    }
+
+    fn write_run(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let dir = match &self.dir {
+            Some(dir) => dir.clone(),
+            None => {
+                let tmp = match &self.options.tmp_dir {
+                    Some(t) => PathBuf::from(t),
+                    None => std::env::temp_dir(),
+                };
+                let nanos = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map_or(0, |d| d.subsec_nanos());
+                let dir = tmp.join(format!("vcfexpress-sort.{}.{}", std::process::id(), nanos));
+                std::fs::create_dir(&dir)
+                    .map_err(|e| format!("error creating {}: {}", dir.display(), e))?;
+                self.dir = Some(dir.clone());
+                dir
+            }
+        };
+        self.sort_buffer();
+        let path = dir.join(format!("run.{}.bcf", self.runs.len()));
+        log::info!(
+            "writing {} sorted records to {}",
+            self.buffer.len(),
+            path.display()
+        );
+        let mut wtr = bcf::Writer::from_path(&path, &self.header, true, bcf::Format::Bcf)?;
+        for (_, r) in self.buffer.drain(..) {
+            wtr.write(&r)?;
+        }
+        self.bytes = 0;
+        self.runs.push(path);
+        Ok(())
+    }
+
+    /// Write all records, sorted, to `wtr`.
+    pub fn finish(mut self, wtr: &mut bcf::Writer) -> Result<(), Box<dyn std::error::Error>> {
+        if self.runs.is_empty() {
+            self.sort_buffer();
+            for (_, mut r) in std::mem::take(&mut self.buffer) {
+                wtr.translate(&mut r);
+                wtr.write(&r)?;
+            }
+            return Ok(());
+        }
+        self.write_run()?;
+        let mut readers = vec![];
+        let mut heads = vec![];
+        for path in &self.runs {
+            let mut reader = bcf::Reader::from_path(path)?;
+            let mut record = reader.empty_record();
+            heads.push(match reader.read(&mut record) {
+                Some(r) => {
+                    r?;
+                    Some((self.key(&record)?, record))
+                }
+                None => None,
+            });
+            readers.push(reader);
+        }
+        // the smallest head; the earliest run for ties.
+        while let Some(k) = (0..heads.len())
+            .filter(|&k| heads[k].is_some())
+            .min_by(|&a, &b| self.compare(heads[a].as_ref().unwrap(), heads[b].as_ref().unwrap()))
+        {
+            let (_, record) = heads[k].as_mut().unwrap();
+            let mut out = record.clone();
+            wtr.translate(&mut out);
+            wtr.write(&out)?;
+            match readers[k].read(record) {
+                Some(r) => {
+                    r?;
+                    let key = self.key(record)?;
+                    heads[k].as_mut().unwrap().0 = key;
+                }
+                None => heads[k] = None,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Sorter {
+    fn drop(&mut self) {
+        if let Some(dir) = &self.dir {
+            _ = std::fs::remove_dir_all(dir);
+        }
+    }
+}
+
+/// Read all records from `path` and write them to `output` (stdout if None) sorted by INFO `key`
+/// or, without a key, by contig (in header order) and position. Records with a missing key are
+/// written last and ties keep their input order. `reverse` sorts keys from high to low.
+pub fn sort(
+    path: &str,
+    output: Option<&str>,
+    key: Option<&str>,
+    reverse: bool,
+    options: SortOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut reader = match path {
+        "-" | "stdin" => bcf::Reader::from_stdin()?,
+        _ => bcf::Reader::from_path(path)?,
+    };
+    _ = reader.set_threads(2);
+    let order = match key {
+        Some(key) => Order::Info {
+            key: key.to_string(),
+            reverse,
+        },
+        None => Order::Position,
+    };
+    let mut sorter =
+        Sorter::new(reader.header(), order, options).map_err(|e| format!("{} in {}", e, path))?;
+    for r in reader.records() {
+        sorter.push(r?)?;
+    }
+
+    let header = bcf::Header::from_template(reader.header());
+    let mut wtr = match output {
+        Some(output) if output != "-" => {
+            let format = crate::vcfexpress::get_vcf_format(output);
+            bcf::Writer::from_path(output, &header, !output.ends_with(".gz"), format)?
+        }
+        _ => bcf::Writer::from_stdout(&header, true, bcf::Format::Vcf)?,
+    };
+    sorter.finish(&mut wtr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sort() {
+        let mut header = bcf::Header::new();
+        header.push_record(br#"##contig=<ID=chr1,length=10000>"#);
+        header.push_record(br#"##contig=<ID=chr2,length=10000>"#);
+        header.push_record(SORTKEY_HEADER.as_bytes());
+        let mut vcf =
+            bcf::Writer::from_path("_test.sort.vcf", &header, true, bcf::Format::Vcf).unwrap();
+        for (rid, pos, key) in [(1, 5, Some(0.5)), (0, 9, None), (0, 3, Some(2.0))] {
+            let mut record = vcf.empty_record();
+            record.set_rid(Some(rid));
+            record.set_pos(pos);
+            record.set_alleles(&[b"A", b"T"]).unwrap();
+            if let Some(k) = key {
+                record.push_info_float(SORTKEY.as_bytes(), &[k]).unwrap();
+            }
+            vcf.write(&record).unwrap();
+        }
+        drop(vcf);
+
+        let positions = |path: &str| {
+            let mut reader = bcf::Reader::from_path(path).unwrap();
+            reader
+                .records()
+                .map(|r| r.unwrap().pos())
+                .collect::<Vec<_>>()
+        };
+        sort(
+            "_test.sort.vcf",
+            Some("_test.sort.out.vcf"),
+            Some(SORTKEY),
+            true,
+            Default::default(),
+        )
+        .unwrap();
+        assert_eq!(positions("_test.sort.out.vcf"), vec![3, 5, 9]);
+        sort(
+            "_test.sort.vcf",
+            Some("_test.sort.out.vcf"),
+            Some(SORTKEY),
+            false,
+            Default::default(),
+        )
+        .unwrap();
+        assert_eq!(positions("_test.sort.out.vcf"), vec![5, 3, 9]);
+        sort(
+            "_test.sort.vcf",
+            Some("_test.sort.out.vcf"),
+            None,
+            false,
+            Default::default(),
+        )
+        .unwrap();
+        assert_eq!(positions("_test.sort.out.vcf"), vec![3, 9, 5]);
+        assert!(sort(
+            "_test.sort.vcf",
+            None,
+            Some("XX"),
+            false,
+            Default::default()
+        )
+        .is_err());
+        _ = std::fs::remove_file("_test.sort.vcf");
+        _ = std::fs::remove_file("_test.sort.out.vcf");
+    }
+
+    #[test]
+    fn test_external_sort() {
+        let mut header = bcf::Header::new();
+        header.push_record(br#"##contig=<ID=chr1,length=100000>"#);
+        header.push_record(br#"##contig=<ID=chr2,length=100000>"#);
+        header.push_record(SORTKEY_HEADER.as_bytes());
+        let mut vcf =
+            bcf::Writer::from_path("_test.xsort.vcf", &header, true, bcf::Format::Vcf).unwrap();
+        // positions that are out of order in every run, with ties across runs.
+        for i in 0..500i64 {
+            let mut record = vcf.empty_record();
+            record.set_rid(Some((i % 2) as u32));
+            record.set_pos((i * 7919) % 1000);
+            record.set_alleles(&[b"A", b"T"]).unwrap();
+            record
+                .push_info_float(SORTKEY.as_bytes(), &[(i % 10) as f32])
+                .unwrap();
+            vcf.write(&record).unwrap();
+        }
+        drop(vcf);
+        std::fs::create_dir_all("_test.xsort.tmp").unwrap();
+        let options = SortOptions {
+            max_memory: 4096,
+            tmp_dir: Some("_test.xsort.tmp".to_string()),
+        };
+
+        let read = |path: &str| {
+            let mut reader = bcf::Reader::from_path(path).unwrap();
+            reader
+                .records()
+                .map(|r| {
+                    let r = r.unwrap();
+                    (r.rid().unwrap(), r.pos(), info_key(&r, b"SORTKEY").unwrap())
+                })
+                .collect::<Vec<_>>()
+        };
+        let input = read("_test.xsort.vcf");
+        sort(
+            "_test.xsort.vcf",
+            Some("_test.xsort.out.vcf"),
+            None,
+            false,
+            options.clone(),
+        )
+        .unwrap();
+        let mut expected = input.clone();
+        expected.sort_by_key(|(rid, pos, _)| (*rid, *pos));
+        assert_eq!(read("_test.xsort.out.vcf"), expected);
+
+        sort(
+            "_test.xsort.vcf",
+            Some("_test.xsort.out.vcf"),
+            Some(SORTKEY),
+            true,
+            options,
+        )
+        .unwrap();
+        let mut expected = input;
+        expected.sort_by(|a, b| b.2.partial_cmp(&a.2).unwrap());
+        assert_eq!(
+            read("_test.xsort.out.vcf"),
+            expected,
+            "ties keep input order"
+        );
+        // the runs are removed.
+        assert_eq!(std::fs::read_dir("_test.xsort.tmp").unwrap().count(), 0);
+
+        assert_eq!(parse_size("768M"), Ok(768 << 20));
+        assert_eq!(parse_size("1.5k"), Ok(1536));
+        assert_eq!(parse_size("100"), Ok(100));
+        assert!(parse_size("2T").is_err());
+        assert!(parse_size("M").is_err());
+        _ = std::fs::remove_dir_all("_test.xsort.tmp");
+        _ = std::fs::remove_file("_test.xsort.vcf");
+        _ = std::fs::remove_file("_test.xsort.out.vcf");
+    }
+}
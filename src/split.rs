@@ -0,0 +1,545 @@
+//! Split a VCF/BCF into one file per chromosome, per sample or per value of a lua expression such
+//! as a gene (`vcfexpress split`) for scatter-gather workflows. Each file is `prefix + name +
+//! suffix` with the name made safe for a path and the format set by the suffix as for `filter -o`.
+//! Per-sample files have only that sample and the records where it has an ALT allele. At most
+//! `max_open` files are open at once: for keys, the least recently used file is closed and its
+//! records are copied back if it is written again; for samples, the input is read once for each
+//! `max_open` samples. The files written are recorded in a [`Manifest`].
+use mlua::Lua;
+use rust_htslib::bcf::{self, header::HeaderView, Read};
+use rustc_hash::FxHashMap;
+
+use crate::genotypes::allele_index;
+use crate::manifest::Manifest;
+use crate::variant::{HeaderMap, Variant};
+use crate::vcfexpress::{StringOrVariant, VCFExpress};
+
+/// What to split a file by.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SplitBy {
+    Chrom,
+    Sample,
+    /// A lua expression that returns the name of the file for `variant`. Records for which it
+    /// returns nil are not written.
+    Key(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct SplitOptions {
+    /// Put before each name, e.g. `out/cohort.` for out/cohort.chr1.vcf.gz.
+    pub prefix: String,
+    /// Put after each name. It sets the format, e.g. `.vcf.gz` or `.bcf`.
+    pub suffix: String,
+    /// The most output files to have open at once.
+    pub max_open: usize,
+}
+
+/// `name` with characters other than letters, digits, '.', '-' and '_' replaced by '_'.
+fn file_name(name: &str) -> String {
+    let name = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    match name.as_str() {
+        "" | "." | ".." => name.replace('.', "_") + "_",
+        _ => name,
+    }
+}
+
+fn open_writer(
+    path: &str,
+    header: &bcf::Header,
+) -> Result<bcf::Writer, Box<dyn std::error::Error>> {
+    let format = crate::vcfexpress::get_vcf_format(path);
+    Ok(bcf::Writer::from_path(
+        path,
+        header,
+        !path.ends_with(".gz"),
+        format,
+    )?)
+}
+
+struct Output {
+    path: String,
+    header: bcf::Header,
+    // keep only the sample in `header`.
+    subset: bool,
+    writer: Option<bcf::Writer>,
+    // written to and then closed to stay under max_open.
+    closed: bool,
+    last_used: u64,
+}
+
+/// The output files by name with at most `max_open` of them open.
+struct Outputs {
+    outputs: Vec<Output>,
+    index: FxHashMap<String, usize>,
+    max_open: usize,
+    open: usize,
+    tick: u64,
+    reopened: usize,
+}
+
+impl Outputs {
+    fn new(max_open: usize) -> Self {
+        Outputs {
+            outputs: vec![],
+            index: FxHashMap::default(),
+            max_open: max_open.max(1),
+            open: 0,
+            tick: 0,
+            reopened: 0,
+        }
+    }
+
+    /// The index of the output for `name`, adding it with `header` if it is new.
+    fn get(
+        &mut self,
+        name: &str,
+        options: &SplitOptions,
+        header: impl FnOnce() -> Result<bcf::Header, Box<dyn std::error::Error>>,
+        subset: bool,
+    ) -> Result<usize, Box<dyn std::error::Error>> {
+        if let Some(i) = self.index.get(name) {
+            return Ok(*i);
+        }
+        let path = format!("{}{}{}", options.prefix, file_name(name), options.suffix);
+        if let Some(o) = self.outputs.iter().find(|o| o.path == path) {
+            return Err(format!("{} and another name are both written to {}", name, o.path).into());
+        }
+        self.outputs.push(Output {
+            path,
+            header: header()?,
+            subset,
+            writer: None,
+            closed: false,
+            last_used: 0,
+        });
+        self.index.insert(name.to_string(), self.outputs.len() - 1);
+        Ok(self.outputs.len() - 1)
+    }
+
+    /// Open output `i`, closing the least recently used one if `max_open` are open. A file that
+    /// was closed is rewritten from a copy of its records.
+    fn open(&mut self, i: usize) -> Result<(), Box<dyn std::error::Error>> {
+        if self.open >= self.max_open {
+            if let Some(lru) = self
+                .outputs
+                .iter_mut()
+                .filter(|o| o.writer.is_some())
+                .min_by_key(|o| o.last_used)
+            {
+                lru.writer = None;
+                lru.closed = true;
+                self.open -= 1;
+            }
+        }
+        let o = &mut self.outputs[i];
+        if !o.closed {
+            o.writer = Some(open_writer(&o.path, &o.header)?);
+        } else {
+            if self.reopened == 0 {
+                log::warn!(
+                    "more than {} files are written to at once; closed files are copied when they are written again. use a higher --max-open or sort the input by the key",
+                    self.max_open
+                );
+            }
+            self.reopened += 1;
+            // move the records aside as opening the writer truncates the file.
+            let part = format!("{}.part", o.path);
+            std::fs::rename(&o.path, &part)?;
+            let mut writer = open_writer(&o.path, &o.header)?;
+            let mut reader = bcf::Reader::from_path(&part)?;
+            for r in reader.records() {
+                let mut r = r?;
+                writer.translate(&mut r);
+                writer.write(&r)?;
+            }
+            std::fs::remove_file(&part)?;
+            o.writer = Some(writer);
+        }
+        self.open += 1;
+        Ok(())
+    }
+
+    fn write(
+        &mut self,
+        i: usize,
+        record: &bcf::Record,
+        manifest: &mut Manifest,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.tick += 1;
+        if self.outputs[i].writer.is_none() {
+            self.open(i)?;
+        }
+        let o = &mut self.outputs[i];
+        o.last_used = self.tick;
+        let writer = o.writer.as_mut().expect("output is open");
+        let mut record = record.clone();
+        writer.translate(&mut record);
+        if o.subset {
+            writer.subset(&mut record);
+        }
+        writer.write(&record)?;
+        let chrom = record
+            .rid()
+            .and_then(|rid| record.header().rid2name(rid).ok())
+            .unwrap_or_default();
+        manifest.add(&o.path, &String::from_utf8_lossy(chrom), record.pos());
+        Ok(())
+    }
+}
+
+/// Call `key` with `variant` set to `record`.
+fn evaluate_key(
+    lua: &Lua,
+    key: &mlua::Function,
+    hv: &HeaderView,
+    record: bcf::Record,
+    header_map: HeaderMap,
+) -> Result<(Option<String>, bcf::Record), Box<dyn std::error::Error>> {
+    let mut variant = Variant::new(record, header_map);
+    let globals = lua.globals();
+    let name = lua.scope(|scope| {
+        globals.raw_set("variant", scope.create_any_userdata_ref_mut(&mut variant)?)?;
+        globals.raw_set("header", scope.create_any_userdata_ref(hv)?)?;
+        key.call::<_, Option<String>>(())
+    });
+    let record = variant.take();
+    match name {
+        Ok(name) => Ok((name, record)),
+        Err(e) => {
+            let chrom = record
+                .rid()
+                .and_then(|rid| record.header().rid2name(rid).ok())
+                .unwrap_or_default();
+            Err(format!(
+                "error evaluating --key at {}:{}: {}",
+                String::from_utf8_lossy(chrom),
+                record.pos() + 1,
+                e
+            )
+            .into())
+        }
+    }
+}
+
+/// The indexes (into `samples` of the record) of the samples with an ALT allele in GT. All of
+/// them if the record has no GT.
+fn samples_with_alt(record: &bcf::Record, samples: &[usize]) -> Vec<usize> {
+    let Ok(gts) = record.format(b"GT").integer() else {
+        return (0..samples.len()).collect();
+    };
+    samples
+        .iter()
+        .enumerate()
+        .filter(|(_, &s)| {
+            gts[s]
+                .iter()
+                .take_while(|&&a| a != rust_htslib::htslib::bcf_int32_vector_end)
+                .any(|&a| allele_index(a) > 0)
+        })
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// Read `path` once and write the records that pass `expressions` (all records if there are none)
+/// to the outputs for `by`. For samples, only those in `samples` (all if None) are written.
+fn split_pass(
+    path: &str,
+    by: &SplitBy,
+    samples: Option<&[String]>,
+    options: &SplitOptions,
+    expressions: &[String],
+    lua_prelude: &[String],
+    manifest: &mut Manifest,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let lua = Lua::new();
+    let mut vcfexpr = VCFExpress::new(
+        &lua,
+        path.to_string(),
+        expressions.to_vec(),
+        vec![],
+        None,
+        lua_prelude.to_vec(),
+        None,
+        false,
+    )?;
+    let key = match by {
+        SplitBy::Key(k) => Some(lua.load(k).set_name("split-key").into_function()?),
+        _ => None,
+    };
+    let mut reader = vcfexpr.reader();
+    let hv = vcfexpr.header().clone();
+    let samples = match samples {
+        Some(s) => s.to_vec(),
+        None => hv
+            .samples()
+            .iter()
+            .map(|s| String::from_utf8_lossy(s).to_string())
+            .collect(),
+    };
+    if *by == SplitBy::Sample && samples.is_empty() {
+        return Err(format!("{} has no samples to split by", path).into());
+    }
+    // the index of each sample in the records.
+    let sample_idx = samples
+        .iter()
+        .map(|s| {
+            reader
+                .header()
+                .sample_id(s.as_bytes())
+                .ok_or_else(|| format!("sample {} not found in {}", s, path))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut outputs = Outputs::new(options.max_open);
+    // every sample gets a file even if it has no variants.
+    let mut sample_outputs = vec![];
+    if *by == SplitBy::Sample {
+        for s in &samples {
+            let i = outputs.get(
+                s,
+                options,
+                || Ok(bcf::Header::from_template_subset(&hv, &[s.as_bytes()])?),
+                true,
+            )?;
+            outputs.open(i)?;
+            manifest.touch(&outputs.outputs[i].path);
+            sample_outputs.push(i);
+        }
+    }
+
+    let header_map = HeaderMap::new();
+    vcfexpr.start()?;
+    for r in reader.records() {
+        let mut record = r?;
+        if !expressions.is_empty() {
+            match vcfexpr.evaluate(record, header_map.clone())? {
+                StringOrVariant::Variant(Some(r)) => record = r,
+                _ => continue,
+            }
+        }
+        match by {
+            SplitBy::Chrom => {
+                let chrom = record
+                    .rid()
+                    .and_then(|rid| record.header().rid2name(rid).ok())
+                    .map(|c| String::from_utf8_lossy(c).to_string())
+                    .ok_or("record without a contig")?;
+                let i = outputs.get(
+                    &chrom,
+                    options,
+                    || Ok(bcf::Header::from_template(&hv)),
+                    false,
+                )?;
+                outputs.write(i, &record, manifest)?;
+            }
+            SplitBy::Sample => {
+                for s in samples_with_alt(&record, &sample_idx) {
+                    outputs.write(sample_outputs[s], &record, manifest)?;
+                }
+            }
+            SplitBy::Key(_) => {
+                let (name, r) = evaluate_key(
+                    &lua,
+                    key.as_ref().expect("key is compiled"),
+                    &hv,
+                    record,
+                    header_map.clone(),
+                )?;
+                let Some(name) = name else { continue };
+                let i = outputs.get(
+                    &name,
+                    options,
+                    || Ok(bcf::Header::from_template(&hv)),
+                    false,
+                )?;
+                outputs.write(i, &r, manifest)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Split `path` by `by` into files named from `options` and return the manifest of the files
+/// written. With `expressions`, only records that pass any of them are written.
+pub fn split(
+    path: &str,
+    by: &SplitBy,
+    options: &SplitOptions,
+    expressions: Vec<String>,
+    lua_prelude: Vec<String>,
+) -> Result<Manifest, Box<dyn std::error::Error>> {
+    // the directory of e.g. out/ or out/cohort.
+    let first = format!("{}{}", options.prefix, options.suffix);
+    if let Some(dir) = std::path::Path::new(&first).parent() {
+        if !dir.as_os_str().is_empty() {
+            std::fs::create_dir_all(dir)?;
+        }
+    }
+    let mut manifest = Manifest::default();
+    let stdin = matches!(path, "-" | "stdin");
+    if *by != SplitBy::Sample || stdin {
+        split_pass(
+            path,
+            by,
+            None,
+            options,
+            &expressions,
+            &lua_prelude,
+            &mut manifest,
+        )?;
+        return Ok(manifest);
+    }
+    let reader = bcf::Reader::from_path(path)?;
+    let samples = reader
+        .header()
+        .samples()
+        .iter()
+        .map(|s| String::from_utf8_lossy(s).to_string())
+        .collect::<Vec<_>>();
+    drop(reader);
+    if samples.len() > options.max_open {
+        log::info!(
+            "reading {} {} times to write {} samples with at most {} open files",
+            path,
+            samples.len().div_ceil(options.max_open),
+            samples.len(),
+            options.max_open
+        );
+    }
+    for chunk in samples.chunks(options.max_open.max(1)) {
+        split_pass(
+            path,
+            by,
+            Some(chunk),
+            options,
+            &expressions,
+            &lua_prelude,
+            &mut manifest,
+        )?;
+    }
+    Ok(manifest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_htslib::bcf::record::GenotypeAllele;
+
+    fn write_vcf(path: &str) {
+        let mut header = bcf::Header::new();
+        header.push_record(br#"##contig=<ID=chr1,length=10000>"#);
+        header.push_record(br#"##contig=<ID=chr2,length=10000>"#);
+        header.push_record(br#"##INFO=<ID=GENE,Number=1,Type=String,Description="Gene">"#);
+        header.push_record(br#"##FORMAT=<ID=GT,Number=1,Type=String,Description="Genotype">"#);
+        for s in ["S1", "S2", "S3"] {
+            header.push_sample(s.as_bytes());
+        }
+        let mut vcf = bcf::Writer::from_path(path, &header, true, bcf::Format::Vcf).unwrap();
+        for (rid, pos, gene, gts) in [
+            (0, 9, Some("A/1"), [[0, 1], [0, 0], [0, 0]]),
+            (0, 19, Some("B"), [[0, 0], [1, 1], [0, 0]]),
+            (0, 29, Some("A/1"), [[0, 1], [0, 1], [0, 0]]),
+            (1, 4, None, [[0, 0], [0, 0], [-1, -1]]),
+            (1, 14, Some("B"), [[1, 1], [0, 0], [0, 0]]),
+        ] {
+            let mut record = vcf.empty_record();
+            record.set_rid(Some(rid));
+            record.set_pos(pos);
+            record.set_alleles(&[b"A", b"T"]).unwrap();
+            if let Some(g) = gene {
+                record.push_info_string(b"GENE", &[g.as_bytes()]).unwrap();
+            }
+            let gts = gts
+                .iter()
+                .flat_map(|gt| {
+                    gt.iter().map(|&a| match a {
+                        -1 => GenotypeAllele::UnphasedMissing,
+                        a => GenotypeAllele::Unphased(a),
+                    })
+                })
+                .collect::<Vec<_>>();
+            record.push_genotypes(&gts).unwrap();
+            vcf.write(&record).unwrap();
+        }
+    }
+
+    fn summary(manifest: &Manifest) -> Vec<String> {
+        manifest
+            .entries()
+            .iter()
+            .map(|e| format!("{} {} {}", e.path, e.records, e.regions().join(",")))
+            .collect()
+    }
+
+    #[test]
+    fn test_split() {
+        write_vcf("_test.split.vcf");
+        let options = SplitOptions {
+            prefix: "_test.split.out/".to_string(),
+            suffix: ".vcf".to_string(),
+            max_open: 1,
+        };
+        let m = split("_test.split.vcf", &SplitBy::Chrom, &options, vec![], vec![]).unwrap();
+        assert_eq!(
+            summary(&m),
+            [
+                "_test.split.out/chr1.vcf 3 chr1:10-30",
+                "_test.split.out/chr2.vcf 2 chr2:5-15"
+            ]
+        );
+
+        // with one open file, A_1 is closed for B and copied when it is written again.
+        let key = SplitBy::Key(r#"return variant:info("GENE")"#.to_string());
+        let m = split("_test.split.vcf", &key, &options, vec![], vec![]).unwrap();
+        assert_eq!(
+            summary(&m),
+            [
+                "_test.split.out/A_1.vcf 2 chr1:10-30",
+                "_test.split.out/B.vcf 2 chr1:20-20,chr2:15-15"
+            ]
+        );
+        let mut reader = bcf::Reader::from_path("_test.split.out/A_1.vcf").unwrap();
+        let positions = reader
+            .records()
+            .map(|r| r.unwrap().pos())
+            .collect::<Vec<_>>();
+        assert_eq!(positions, [9, 29]);
+
+        let m = split(
+            "_test.split.vcf",
+            &SplitBy::Sample,
+            &SplitOptions {
+                max_open: 2,
+                ..options.clone()
+            },
+            vec!["return variant.pos > 5".to_string()],
+            vec![],
+        )
+        .unwrap();
+        assert_eq!(
+            summary(&m),
+            [
+                "_test.split.out/S1.vcf 3 chr1:10-30,chr2:15-15",
+                "_test.split.out/S2.vcf 2 chr1:20-30",
+                "_test.split.out/S3.vcf 0 "
+            ]
+        );
+        let reader = bcf::Reader::from_path("_test.split.out/S2.vcf").unwrap();
+        assert_eq!(reader.header().samples(), [b"S2"]);
+
+        assert_eq!(file_name("chr1"), "chr1");
+        assert_eq!(file_name("a b/c"), "a_b_c");
+        assert_eq!(file_name(".."), "___");
+        _ = std::fs::remove_dir_all("_test.split.out");
+        _ = std::fs::remove_file("_test.split.vcf");
+    }
+}
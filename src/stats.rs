@@ -0,0 +1,229 @@
+//! A quick QC summary of a VCF/BCF (`vcfexpress stats`): ts/tv, counts by variant type, by FILTER
+//! and by chromosome and the fraction of multi-allelic sites, optionally for only the records that
+//! pass `-e` expressions. The output is `section key value` lines.
+use mlua::Lua;
+use rust_htslib::bcf::{self, Read};
+use std::io::Write;
+
+use crate::variant::HeaderMap;
+use crate::vcfexpress::{StringOrVariant, VCFExpress};
+
+#[derive(Debug, Default)]
+pub struct VcfStats {
+    records: usize,
+    multiallelic: usize,
+    transitions: usize,
+    transversions: usize,
+    // alternate alleles by type: SNP, MNP, INDEL, OTHER (symbolic, `*` and breakends).
+    types: [usize; 4],
+    // in the order first seen.
+    filters: Vec<(String, usize)>,
+    chroms: Vec<(String, usize)>,
+}
+
+const TYPES: [&str; 4] = ["SNP", "MNP", "INDEL", "OTHER"];
+
+fn incr(counts: &mut Vec<(String, usize)>, key: &[u8]) {
+    match counts.iter_mut().find(|(k, _)| k.as_bytes() == key) {
+        Some((_, n)) => *n += 1,
+        None => counts.push((String::from_utf8_lossy(key).to_string(), 1)),
+    }
+}
+
+fn is_sequence(allele: &[u8]) -> bool {
+    !allele.is_empty()
+        && allele
+            .iter()
+            .all(|b| matches!(b.to_ascii_uppercase(), b'A' | b'C' | b'G' | b'T' | b'N'))
+}
+
+fn is_transition(a: u8, b: u8) -> bool {
+    matches!(
+        (a.to_ascii_uppercase(), b.to_ascii_uppercase()),
+        (b'A', b'G') | (b'G', b'A') | (b'C', b'T') | (b'T', b'C')
+    )
+}
+
+impl VcfStats {
+    pub fn add(&mut self, record: &bcf::Record) {
+        self.records += 1;
+        let header = record.header();
+        let chrom = record
+            .rid()
+            .and_then(|rid| header.rid2name(rid).ok())
+            .unwrap_or(b".");
+        incr(&mut self.chroms, chrom);
+        let filters = record.filters().collect::<Vec<_>>();
+        if filters.is_empty() {
+            incr(&mut self.filters, b".");
+        }
+        for id in filters {
+            incr(&mut self.filters, &header.id_to_name(id));
+        }
+        let alleles = record.alleles();
+        if alleles.len() > 2 {
+            self.multiallelic += 1;
+        }
+        let reference = alleles[0];
+        for alt in &alleles[1..] {
+            let t = if !is_sequence(reference) || !is_sequence(alt) {
+                3
+            } else if reference.len() != alt.len() {
+                2
+            } else if reference.len() == 1 {
+                if is_transition(reference[0], alt[0]) {
+                    self.transitions += 1;
+                } else if !reference.eq_ignore_ascii_case(alt) {
+                    self.transversions += 1;
+                }
+                0
+            } else {
+                1
+            };
+            self.types[t] += 1;
+        }
+    }
+
+    pub fn write<W: Write>(&self, w: &mut W) -> std::io::Result<()> {
+        let ratio = |a: usize, b: usize| {
+            if b == 0 {
+                ".".to_string()
+            } else {
+                format!("{:.3}", a as f64 / b as f64)
+            }
+        };
+        writeln!(w, "#section\tkey\tvalue")?;
+        writeln!(w, "summary\trecords\t{}", self.records)?;
+        writeln!(w, "summary\tmultiallelic\t{}", self.multiallelic)?;
+        writeln!(
+            w,
+            "summary\tmultiallelic_fraction\t{}",
+            ratio(self.multiallelic, self.records)
+        )?;
+        writeln!(w, "summary\tts\t{}", self.transitions)?;
+        writeln!(w, "summary\ttv\t{}", self.transversions)?;
+        writeln!(
+            w,
+            "summary\tts_tv\t{}",
+            ratio(self.transitions, self.transversions)
+        )?;
+        for (name, n) in TYPES.iter().zip(self.types) {
+            writeln!(w, "type\t{}\t{}", name, n)?;
+        }
+        for (name, n) in &self.filters {
+            writeln!(w, "filter\t{}\t{}", name, n)?;
+        }
+        for (name, n) in &self.chroms {
+            writeln!(w, "chrom\t{}\t{}", name, n)?;
+        }
+        Ok(())
+    }
+}
+
+/// Summarize the records of `path` ("-" for stdin) or, with `expressions`, those that pass any
+/// of them after running the preludes.
+pub fn stats(
+    path: &str,
+    expressions: Vec<String>,
+    lua_prelude: Vec<String>,
+) -> Result<VcfStats, Box<dyn std::error::Error>> {
+    let mut stats = VcfStats::default();
+    if expressions.is_empty() {
+        let mut reader = match path {
+            "-" | "stdin" => bcf::Reader::from_stdin()?,
+            _ => bcf::Reader::from_path(path)?,
+        };
+        _ = reader.set_threads(2);
+        for r in reader.records() {
+            stats.add(&r?);
+        }
+        return Ok(stats);
+    }
+    let lua = Lua::new();
+    let mut vcfexpr = VCFExpress::new(
+        &lua,
+        path.to_string(),
+        expressions,
+        vec![],
+        None,
+        lua_prelude,
+        None,
+        false,
+    )?;
+    let mut reader = vcfexpr.reader();
+    let header_map = HeaderMap::new();
+    vcfexpr.start()?;
+    for r in reader.records() {
+        if let StringOrVariant::Variant(Some(record)) = vcfexpr.evaluate(r?, header_map.clone())? {
+            stats.add(&record);
+        }
+    }
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats() {
+        let mut header = bcf::Header::new();
+        header.push_record(br#"##contig=<ID=chr1,length=10000>"#);
+        header.push_record(br#"##contig=<ID=chr2,length=10000>"#);
+        header.push_record(br#"##FILTER=<ID=LowQual,Description="LowQual">"#);
+        let mut vcf =
+            bcf::Writer::from_path("_test.stats.vcf", &header, true, bcf::Format::Vcf).unwrap();
+        for (rid, alleles, filter) in [
+            (0, vec!["A", "G"], Some("PASS")),
+            (0, vec!["C", "A", "CT"], Some("LowQual")),
+            (0, vec!["AC", "GT"], None),
+            (1, vec!["T", "C", "<DEL>"], Some("PASS")),
+        ] {
+            let mut record = vcf.empty_record();
+            record.set_rid(Some(rid));
+            let alleles = alleles.iter().map(|a| a.as_bytes()).collect::<Vec<_>>();
+            record.set_alleles(&alleles).unwrap();
+            if let Some(f) = filter {
+                let id = vcf.header().name_to_id(f.as_bytes()).unwrap();
+                record.push_filter(&id).unwrap();
+            }
+            vcf.write(&record).unwrap();
+        }
+        drop(vcf);
+
+        let mut out = vec![];
+        stats("_test.stats.vcf", vec![], vec![])
+            .unwrap()
+            .write(&mut out)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "#section\tkey\tvalue
+summary\trecords\t4
+summary\tmultiallelic\t2
+summary\tmultiallelic_fraction\t0.500
+summary\tts\t2
+summary\ttv\t1
+summary\tts_tv\t2.000
+type\tSNP\t3
+type\tMNP\t1
+type\tINDEL\t1
+type\tOTHER\t1
+filter\tPASS\t2
+filter\tLowQual\t1
+filter\t.\t1
+chrom\tchr1\t3
+chrom\tchr2\t1
+"
+        );
+
+        let s = stats(
+            "_test.stats.vcf",
+            vec!["return variant.chrom == 'chr2'".to_string()],
+            vec![],
+        )
+        .unwrap();
+        assert_eq!((s.records, s.types), (1, [1, 0, 0, 1]));
+        _ = std::fs::remove_file("_test.stats.vcf");
+    }
+}
@@ -0,0 +1,77 @@
+//! Keep a random fraction of the records (`--sample-fraction`) before they are evaluated, e.g. to
+//! estimate pass rates or build small test files from large ones. The choice for each record
+//! depends only on the seed and the order in which records are read, so a run with the same seed
+//! and input keeps the same records.
+use std::io;
+
+pub struct Subsample {
+    /// records are kept when the next random number is below this.
+    threshold: u64,
+    state: u64,
+    dropped: usize,
+}
+
+impl Subsample {
+    pub fn new(fraction: f64, seed: u64) -> io::Result<Self> {
+        if !(fraction > 0.0 && fraction <= 1.0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("--sample-fraction must be in (0, 1]; got {}", fraction),
+            ));
+        }
+        Ok(Subsample {
+            threshold: (fraction * u64::MAX as f64) as u64,
+            state: seed,
+            dropped: 0,
+        })
+    }
+
+    /// splitmix64: https://prng.di.unimi.it/splitmix64.c
+    fn next(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// True if the next record is in the sample.
+    pub fn keep(&mut self) -> bool {
+        let keep = self.threshold == u64::MAX || self.next() < self.threshold;
+        if !keep {
+            self.dropped += 1;
+        }
+        keep
+    }
+
+    /// The number of records not sampled.
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subsample() {
+        let kept = |fraction: f64, seed: u64| {
+            let mut s = Subsample::new(fraction, seed).unwrap();
+            (0..10_000).map(|_| s.keep()).collect::<Vec<_>>()
+        };
+        let a = kept(0.1, 42);
+        assert_eq!(a, kept(0.1, 42));
+        assert_ne!(a, kept(0.1, 43));
+        let n = a.iter().filter(|&&k| k).count();
+        assert!((900..1100).contains(&n), "{}", n);
+        assert!(kept(1.0, 1).iter().all(|&k| k));
+
+        let mut s = Subsample::new(0.5, 7).unwrap();
+        let n = (0..100).filter(|_| s.keep()).count();
+        assert_eq!(s.dropped(), 100 - n);
+        for fraction in [0.0, -0.5, 1.5, f64::NAN] {
+            assert!(Subsample::new(fraction, 1).is_err());
+        }
+    }
+}
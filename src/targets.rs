@@ -0,0 +1,229 @@
+//! Restrict processing to the intervals of a BED file (`--targets-file`) by streaming through it
+//! alongside the records instead of fetching from an index, so it works for unindexed and stdin
+//! input and the BED is never held in memory. The BED is first read once to find where the lines
+//! of each chromosome start; they must be together and sorted by start. If the input lists its
+//! chromosomes in a different order than the BED, the BED is re-read from the start for a
+//! chromosome that is behind. The input must be sorted by position within each chromosome.
+use rust_htslib::bcf;
+use rustc_hash::{FxHashMap, FxHashSet};
+use std::collections::VecDeque;
+use std::io::{self, BufRead};
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// The (chrom, start, end) of a BED line or None for a header or empty line.
+fn parse_line<'a>(
+    path: &str,
+    line_number: usize,
+    line: &'a str,
+) -> io::Result<Option<(&'a str, u64, u64)>> {
+    Ok(crate::bed::parse(path, line_number, line)?.map(|l| (l.chrom, l.start, l.end)))
+}
+
+pub struct Targets {
+    path: String,
+    /// the number of the first line of each chromosome.
+    blocks: FxHashMap<String, usize>,
+    reader: crate::bed::Reader,
+    /// the number of lines read from `reader`.
+    line_number: usize,
+    buf: String,
+    /// the contig of the last record and the start of the last record on it.
+    rid: Option<u32>,
+    last_pos: i64,
+    /// the next interval on the contig that no record has reached yet.
+    pending: Option<(u64, u64)>,
+    /// intervals that started before the end of a record and may overlap later ones.
+    active: VecDeque<(u64, u64)>,
+    dropped: usize,
+}
+
+impl Targets {
+    /// Read `path` once to index its chromosomes and check that it is sorted.
+    pub fn new(path: &str) -> io::Result<Self> {
+        let mut blocks = FxHashMap::default();
+        let mut last: Option<(String, u64)> = None;
+        let mut done = FxHashSet::default();
+        for (i, line) in crate::bed::open(path)?.lines().enumerate() {
+            let line = line?;
+            let Some((chrom, start, _)) = parse_line(path, i, &line)? else {
+                continue;
+            };
+            match &mut last {
+                Some((c, s)) if c == chrom => {
+                    if start < *s {
+                        return Err(invalid(format!(
+                            "{} is not sorted by start at line {}; sort it with `sort -k1,1 -k2,2n`",
+                            path,
+                            i + 1
+                        )));
+                    }
+                    *s = start;
+                    continue;
+                }
+                Some((c, _)) => {
+                    done.insert(std::mem::take(c));
+                }
+                None => {}
+            }
+            if done.contains(chrom) {
+                return Err(invalid(format!(
+                    "the lines for {} in {} are not together (line {}); sort it with `sort -k1,1 -k2,2n`",
+                    chrom,
+                    path,
+                    i + 1
+                )));
+            }
+            blocks.insert(chrom.to_string(), i);
+            last = Some((chrom.to_string(), start));
+        }
+        Ok(Targets {
+            path: path.to_string(),
+            blocks,
+            reader: crate::bed::open(path)?,
+            line_number: 0,
+            buf: String::new(),
+            rid: None,
+            last_pos: -1,
+            pending: None,
+            active: VecDeque::new(),
+            dropped: 0,
+        })
+    }
+
+    /// The next interval on `chrom` or None at the end of its lines.
+    fn next_interval(&mut self, chrom: &[u8]) -> io::Result<Option<(u64, u64)>> {
+        loop {
+            self.buf.clear();
+            if self.reader.read_line(&mut self.buf)? == 0 {
+                return Ok(None);
+            }
+            self.line_number += 1;
+            if let Some((c, start, end)) = parse_line(&self.path, self.line_number - 1, &self.buf)?
+            {
+                return Ok((c.as_bytes() == chrom).then_some((start, end)));
+            }
+        }
+    }
+
+    /// Move to the first line of `chrom` and read its first interval.
+    fn seek(&mut self, chrom: &[u8]) -> io::Result<()> {
+        self.active.clear();
+        self.pending = None;
+        let Some(&first) = self.blocks.get(String::from_utf8_lossy(chrom).as_ref()) else {
+            return Ok(());
+        };
+        if first < self.line_number {
+            self.reader = crate::bed::open(&self.path)?;
+            self.line_number = 0;
+        }
+        while self.line_number < first {
+            self.buf.clear();
+            if self.reader.read_line(&mut self.buf)? == 0 {
+                break;
+            }
+            self.line_number += 1;
+        }
+        self.pending = self.next_interval(chrom)?;
+        Ok(())
+    }
+
+    /// True if `record` overlaps an interval. Records must be sorted within each contig.
+    pub fn keep(&mut self, record: &bcf::Record) -> io::Result<bool> {
+        let Some(rid) = record.rid() else {
+            self.dropped += 1;
+            return Ok(false);
+        };
+        let chrom = record.header().rid2name(rid).map_err(io::Error::other)?;
+        let (start, end) = (record.pos(), record.end());
+        if self.rid != Some(rid) {
+            self.seek(chrom)?;
+            self.rid = Some(rid);
+        } else if start < self.last_pos {
+            return Err(invalid(format!(
+                "--targets-file requires input sorted by position; {}:{} is after {}",
+                String::from_utf8_lossy(chrom),
+                start + 1,
+                self.last_pos + 1
+            )));
+        }
+        self.last_pos = start;
+        while let Some(interval) = self.pending.filter(|i| (i.0 as i64) < end) {
+            self.active.push_back(interval);
+            self.pending = self.next_interval(chrom)?;
+        }
+        // records start at or after this one so intervals that end before it are done.
+        self.active.retain(|i| i.1 as i64 > start);
+        let keep = self
+            .active
+            .iter()
+            .any(|&(s, e)| (s as i64) < end && (e as i64) > start);
+        if !keep {
+            self.dropped += 1;
+        }
+        Ok(keep)
+    }
+
+    /// The number of records outside the intervals.
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_targets() {
+        std::fs::write(
+            "_test.targets.bed",
+            "track name=t\nchr2\t100\t200\nchr2\t150\t160\nchr2\t500\t600\nchr1\t10\t20\n",
+        )
+        .unwrap();
+        let mut header = bcf::Header::new();
+        header.push_record(br#"##contig=<ID=chr1,length=10000>"#);
+        header.push_record(br#"##contig=<ID=chr2,length=10000>"#);
+        header.push_record(br#"##contig=<ID=chr3,length=10000>"#);
+        let wtr =
+            bcf::Writer::from_path("_test.targets.vcf", &header, true, bcf::Format::Vcf).unwrap();
+        let mut targets = Targets::new("_test.targets.bed").unwrap();
+        let keep = |targets: &mut Targets, rid: u32, pos: i64, reflen: usize| {
+            let mut record = wtr.empty_record();
+            record.set_rid(Some(rid));
+            record.set_pos(pos);
+            let r = vec![b'A'; reflen];
+            record.set_alleles(&[&r, b"T"]).unwrap();
+            targets.keep(&record)
+        };
+        // chr1 comes after chr2 in the BED so it is re-read from the start.
+        assert!(!keep(&mut targets, 1, 50, 1).unwrap());
+        assert!(!keep(&mut targets, 1, 98, 2).unwrap());
+        assert!(keep(&mut targets, 1, 98, 3).unwrap());
+        assert!(keep(&mut targets, 1, 199, 1).unwrap());
+        assert!(!keep(&mut targets, 1, 200, 1).unwrap());
+        assert!(keep(&mut targets, 1, 599, 1).unwrap());
+        assert!(keep(&mut targets, 0, 10, 1).unwrap());
+        assert!(!keep(&mut targets, 0, 20, 1).unwrap());
+        assert!(!keep(&mut targets, 2, 10, 1).unwrap());
+        assert_eq!(targets.dropped(), 5);
+        assert!(keep(&mut targets, 2, 5, 1).is_err());
+
+        std::fs::write(
+            "_test.targets.bed",
+            "chr1\t10\t20\nchr2\t1\t2\nchr1\t30\t40\n",
+        )
+        .unwrap();
+        let err = Targets::new("_test.targets.bed").err().unwrap();
+        assert!(err.to_string().contains("not together"), "{}", err);
+        std::fs::write("_test.targets.bed", "chr1\t30\t40\nchr1\t10\t20\n").unwrap();
+        assert!(Targets::new("_test.targets.bed").is_err());
+        std::fs::write("_test.targets.bed", "chr1\t30\n").unwrap();
+        assert!(Targets::new("_test.targets.bed").is_err());
+        for f in ["_test.targets.bed", "_test.targets.vcf"] {
+            _ = std::fs::remove_file(f);
+        }
+    }
+}
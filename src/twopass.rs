@@ -0,0 +1,111 @@
+//! Dataset-level statistics from a first pass over the input (`--two-pass`), available to the
+//! expressions of the second, filtering pass as the `globals_stats` table, e.g.
+//! `return variant.qual >= globals_stats:qual_quantile(0.99)`.
+use mlua::Lua;
+use rust_htslib::bcf::{self};
+
+use crate::variant::Sentinel;
+
+#[derive(Debug, Default)]
+pub struct GlobalStats {
+    records: usize,
+    quals: Vec<f64>,
+    /// (sum, n) of the non-missing FORMAT DP of each sample.
+    depth: Vec<(f64, usize)>,
+}
+
+/// The `q` quantile (0..=1) of sorted `values` by linear interpolation between the closest ranks.
+fn quantile(values: &[f64], q: f64) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    let rank = q * (values.len() - 1) as f64;
+    let (lo, hi) = (rank.floor() as usize, rank.ceil() as usize);
+    Some(values[lo] + (values[hi] - values[lo]) * (rank - lo as f64))
+}
+
+/// The first FORMAT DP value of each sample, if it is not missing.
+fn first_values<T: Sentinel + Into<f64>>(rows: &[&[T]]) -> Vec<Option<f64>> {
+    rows.iter()
+        .map(|r| {
+            r.first()
+                .filter(|v| !v.is_missing_value() && !v.is_vector_end())
+                .map(|v| (*v).into())
+        })
+        .collect()
+}
+
+fn sample_depths(record: &bcf::Record) -> Option<Vec<Option<f64>>> {
+    if let Ok(dp) = record.format(b"DP").integer() {
+        return Some(first_values(&dp));
+    }
+    record
+        .format(b"DP")
+        .float()
+        .ok()
+        .map(|dp| first_values(&dp))
+}
+
+impl GlobalStats {
+    /// Add the QUAL and the per-sample FORMAT DP of a record.
+    pub fn add(&mut self, record: &bcf::Record) {
+        self.records += 1;
+        let qual = record.qual();
+        if !qual.is_missing_value() && !qual.is_nan() {
+            self.quals.push(qual as f64);
+        }
+        if let Some(depths) = sample_depths(record) {
+            if self.depth.len() < depths.len() {
+                self.depth.resize(depths.len(), (0.0, 0));
+            }
+            for (acc, d) in self.depth.iter_mut().zip(depths) {
+                if let Some(d) = d {
+                    acc.0 += d;
+                    acc.1 += 1;
+                }
+            }
+        }
+    }
+
+    /// Set the built-in fields of the `globals_stats` table: `n` records, `mean_depth[sample]`
+    /// (nil for samples without DP) and `globals_stats:qual_quantile(q)`.
+    pub fn set_table(self, lua: &Lua, t: &mlua::Table, samples: &[String]) -> mlua::Result<()> {
+        t.raw_set("n", self.records)?;
+        let mean_depth = lua.create_table()?;
+        for (sample, (sum, n)) in samples.iter().zip(&self.depth) {
+            if *n > 0 {
+                mean_depth.raw_set(sample.as_str(), sum / *n as f64)?;
+            }
+        }
+        t.raw_set("mean_depth", mean_depth)?;
+        let mut quals = self.quals;
+        quals.sort_by(|a, b| a.total_cmp(b));
+        t.raw_set(
+            "qual_quantile",
+            lua.create_function(move |_lua, (_, q): (mlua::Value, f64)| {
+                if !(0.0..=1.0).contains(&q) {
+                    return Err(mlua::Error::runtime(format!(
+                        "qual_quantile expects a value between 0 and 1, got {}",
+                        q
+                    )));
+                }
+                Ok(quantile(&quals, q))
+            })?,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quantile() {
+        let values = [1.0, 2.0, 3.0, 4.0, 5.0];
+        assert_eq!(quantile(&values, 0.5), Some(3.0));
+        assert_eq!(quantile(&values, 0.0), Some(1.0));
+        assert_eq!(quantile(&values, 1.0), Some(5.0));
+        assert_eq!(quantile(&values, 0.9), Some(4.6));
+        assert_eq!(quantile(&[], 0.5), None);
+    }
+}
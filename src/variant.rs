@@ -1,4 +1,4 @@
-use log::{error, info, warn};
+use log::error;
 use mlua::prelude::LuaValue;
 use mlua::{AnyUserData, Lua, MetaMethod, UserDataFields, UserDataMethods, Value};
 use parking_lot::Mutex;
@@ -11,6 +11,10 @@ use std::cell::RefCell;
 use std::rc::Rc;
 use std::sync::Arc;
 
+/// Set as lua app data while a template is evaluated with `--coords 1` so that `variant.pos`
+/// is the 1-based position as printed in the VCF.
+pub(crate) struct OneBasedTemplatePos;
+
 /// Variant also keeps a cache of info tags to avoid repeated lookups.
 pub struct HeaderMap(Rc<RefCell<FxHashMap<String, (TagType, TagLength)>>>);
 
@@ -35,11 +39,32 @@ impl Default for HeaderMap {
 pub struct Variant {
     record: bcf::Record,
     header_map: HeaderMap,
+    // decoded on the first access to `variant.genotypes` and shared with the lua objects.
+    genotypes: Option<Arc<Mutex<crate::genotypes::GtData>>>,
+    // 0-based number of the record in the input.
+    index: usize,
+    // BGZF virtual offset of the record in the input, if known.
+    file_offset: Option<u64>,
 }
 
 impl Variant {
     pub fn new(record: bcf::Record, header_map: HeaderMap) -> Self {
-        Variant { record, header_map }
+        Variant {
+            record,
+            header_map,
+            genotypes: None,
+            index: 0,
+            file_offset: None,
+        }
+    }
+    /// Set the 0-based number of the record in the input, available to lua as `variant.index`.
+    pub fn set_index(&mut self, index: usize) {
+        self.index = index;
+    }
+    /// Set the BGZF virtual offset of the record in the input, available to lua as
+    /// `variant.file_offset`; nil if it is not set.
+    pub fn set_file_offset(&mut self, offset: Option<u64>) {
+        self.file_offset = offset;
     }
     pub fn record(&self) -> &bcf::Record {
         &self.record
@@ -51,6 +76,314 @@ impl Variant {
         self.record
     }
 
+    /// The shared genotypes, decoded from the record on first use.
+    fn gt_data(&mut self) -> mlua::Result<Arc<Mutex<crate::genotypes::GtData>>> {
+        if self.genotypes.is_none() {
+            let gts = crate::genotypes::GtData::from_record(&self.record)
+                .map_err(|e| mlua::Error::RuntimeError(e.to_string()))?;
+            self.genotypes = Some(Arc::new(Mutex::new(gts)));
+        }
+        Ok(Arc::clone(self.genotypes.as_ref().unwrap()))
+    }
+
+    /// Set the genotype of the sample at 0-based `i`. Like `variant.genotypes[i] = ...`, it is
+    /// written to the record by `flush_genotypes`.
+    pub(crate) fn set_genotype(
+        &mut self,
+        i: usize,
+        gt: &crate::genotypes::Genotype,
+    ) -> mlua::Result<()> {
+        self.gt_data()?.lock().set(i, gt);
+        Ok(())
+    }
+
+    /// Write any genotypes assigned from lua (`variant.genotypes[i] = ...`) back to the record.
+    pub fn flush_genotypes(&mut self) -> Result<()> {
+        match &self.genotypes {
+            Some(gts) => gts.lock().flush(&mut self.record),
+            None => Ok(()),
+        }
+    }
+
+    /// Indexes (1 for the first ALT) of `*` and symbolic `<NON_REF>` or `<*>` alleles.
+    pub(crate) fn symbolic_alleles(&self) -> Vec<i32> {
+        self.record
+            .alleles()
+            .iter()
+            .enumerate()
+            .skip(1)
+            .filter(|(_, a)| crate::allele::is_symbolic(a))
+            .map(|(i, _)| i as i32)
+            .collect()
+    }
+
+    /// Total alt depth (from FORMAT AD) / total depth across samples. The depth of a sample is its
+    /// FORMAT DP, or the sum of its AD if DP is missing. Samples with all AD missing are skipped.
+    /// None without AD or when the total depth is 0.
+    fn alt_read_fraction(&self) -> Option<f64> {
+        let ad = self.record.format(b"AD").integer().ok()?;
+        let dp = self.record.format(b"DP").integer().ok();
+        let (mut alt, mut total) = (0i64, 0i64);
+        for (i, sample_ad) in ad.iter().enumerate() {
+            let values = sample_ad.iter().take_while(|v| !v.is_vector_end());
+            if values.clone().all(|v| v.is_missing_value()) {
+                continue;
+            }
+            // missing values count as 0.
+            let values = values.map(|v| (*v as i64).max(0)).collect::<Vec<_>>();
+            alt += values.iter().skip(1).sum::<i64>();
+            total += match dp.as_ref().and_then(|dp| dp.get(i)).map(|d| d[0]) {
+                Some(d) if !d.is_missing_value() => d as i64,
+                _ => values.iter().sum::<i64>(),
+            };
+        }
+        (total > 0).then(|| alt as f64 / total as f64)
+    }
+
+    /// The non-missing values of a numeric FORMAT field across all samples (all values for
+    /// Number != 1) and the number of missing values. A field absent from the record is missing
+    /// for every sample.
+    fn format_values(&self, tag: &str) -> mlua::Result<(Vec<f64>, usize)> {
+        let typ = self
+            .record
+            .header()
+            .format_type(tag.as_bytes())
+            .map_err(|e| mlua::Error::ExternalError(Arc::new(e)))?
+            .0;
+        fn collect<T: Sentinel + Copy + Into<f64>>(rows: &[&[T]]) -> (Vec<f64>, usize) {
+            let mut values = vec![];
+            let mut missing = 0;
+            for v in rows
+                .iter()
+                .flat_map(|r| r.iter().take_while(|v| !v.is_vector_end()))
+            {
+                if v.is_missing_value() {
+                    missing += 1;
+                } else {
+                    values.push((*v).into());
+                }
+            }
+            (values, missing)
+        }
+        let fmt = self.record.format(tag.as_bytes());
+        let result = match typ {
+            TagType::Integer => fmt.integer().map(|v| collect(&v)),
+            TagType::Float => fmt.float().map(|v| collect(&v)),
+            _ => {
+                return Err(mlua::Error::RuntimeError(format!(
+                    "format_stats requires a numeric FORMAT field; {} is not",
+                    tag
+                )))
+            }
+        };
+        Ok(result.unwrap_or_else(|_| (vec![], self.record.sample_count() as usize)))
+    }
+
+    /// Set INFO AC, AN and AF (and MAF if it is in the header) from the current genotypes.
+    /// Calls of the alleles in `skip` are not counted in AN and their AF is missing.
+    fn update_allele_counts(&mut self, skip: &[i32]) -> mlua::Result<()> {
+        let n_alleles = self.record.allele_count() as usize;
+        let (mut counts, mut an) = self.gt_data()?.lock().allele_counts(n_alleles);
+        let ac = counts.iter().skip(1).map(|&c| c as i32).collect::<Vec<_>>();
+        for &i in skip {
+            an -= counts[i as usize];
+            counts[i as usize] = 0;
+        }
+        let freq = |c: usize| {
+            if an == 0 {
+                f32::missing()
+            } else {
+                c as f32 / an as f32
+            }
+        };
+        let af = counts
+            .iter()
+            .enumerate()
+            .skip(1)
+            .map(|(i, &c)| match skip.contains(&(i as i32)) {
+                true => f32::missing(),
+                false => freq(c),
+            })
+            .collect::<Vec<_>>();
+        let header = self.record.header();
+        for (tag, typ) in [
+            ("AC", TagType::Integer),
+            ("AN", TagType::Integer),
+            ("AF", TagType::Float),
+        ] {
+            match header.info_type(tag.as_bytes()) {
+                Ok((t, _)) if t == typ => (),
+                _ => {
+                    return Err(mlua::Error::RuntimeError(format!(
+                        "update_allele_counts requires INFO {} with Type={:?} in the header. Add it in prelude if needed.",
+                        tag, typ
+                    )))
+                }
+            }
+        }
+        let has_maf = matches!(header.info_type(b"MAF"), Ok((TagType::Float, _)));
+        let to_lua = |e| mlua::Error::ExternalError(Arc::new(e));
+        self.record.push_info_integer(b"AC", &ac).map_err(to_lua)?;
+        self.record
+            .push_info_integer(b"AN", &[an as i32])
+            .map_err(to_lua)?;
+        self.record.push_info_float(b"AF", &af).map_err(to_lua)?;
+        if has_maf {
+            // 1 - the frequency of the most common allele.
+            let maf = freq(an - counts.iter().copied().max().unwrap_or(0).min(an));
+            self.record.push_info_float(b"MAF", &[maf]).map_err(to_lua)?;
+        }
+        Ok(())
+    }
+
+    /// Set the genotype of each sample for which `f(sample)` is true to missing. With `filter`, FT
+    /// of masked samples is set to (or has appended) that value. Returns the number masked.
+    fn mask_genotypes(
+        &mut self,
+        lua: &Lua,
+        f: mlua::Function,
+        filter: Option<String>,
+    ) -> mlua::Result<usize> {
+        let ids = (0..self.record.sample_count() as usize).collect::<Vec<_>>();
+        let samples = crate::sample::sample_tables(lua, &self.record, &ids)?;
+        let mut masked = vec![];
+        for (i, s) in samples.into_iter().enumerate() {
+            if f.call::<_, bool>(s)? {
+                masked.push(i);
+            }
+        }
+        if masked.is_empty() {
+            return Ok(0);
+        }
+        if let Some(filter) = filter {
+            let mut ft = match self.record.format(b"FT").string() {
+                Ok(v) => v.iter().map(|s| s.to_vec()).collect::<Vec<_>>(),
+                Err(_) => vec![b".".to_vec(); ids.len()],
+            };
+            for &i in &masked {
+                ft[i] = match &ft[i][..] {
+                    b"" | b"." | b"PASS" => filter.as_bytes().to_vec(),
+                    old if old.split(|&c| c == b';').any(|f| f == filter.as_bytes()) => {
+                        old.to_vec()
+                    }
+                    old => [old, b";", filter.as_bytes()].concat(),
+                };
+            }
+            self.record.push_format_string(b"FT", &ft).map_err(|e| {
+                mlua::Error::RuntimeError(format!(
+                    "error setting FT: {}. Make sure it was added to the header in prelude if needed.",
+                    e
+                ))
+            })?;
+        }
+        let gts = self.gt_data()?;
+        for &i in &masked {
+            gts.lock().mask(i);
+        }
+        Ok(masked.len())
+    }
+
+    /// Set FORMAT `tag` of the sample at 0-based `i` to `value`, keeping the values of other samples.
+    pub(crate) fn set_sample_format(
+        &mut self,
+        lua: &Lua,
+        i: usize,
+        tag: &str,
+        value: Value,
+    ) -> mlua::Result<()> {
+        if tag == "GT" {
+            return Err(mlua::Error::RuntimeError(
+                "GT can not be set with set_sample_format; use variant.genotypes".to_string(),
+            ));
+        }
+        let (typ, num) = self
+            .record
+            .header()
+            .format_type(tag.as_bytes())
+            .map_err(|_| {
+                mlua::Error::RuntimeError(format!(
+                    "format field '{}' not found. Make sure it was added to the header in prelude if needed.",
+                    tag
+                ))
+            })?;
+        let scalar = matches!(num, TagLength::Fixed(1));
+        let n = self.record.sample_count() as usize;
+        let fmt = self.record.format(tag.as_bytes());
+        // a field that is not in the record yet is missing for all samples.
+        let mut values = match typ {
+            TagType::Integer => match fmt.integer() {
+                Ok(v) => v
+                    .iter()
+                    .map(|s| crate::sample::sample_value(lua, s, scalar))
+                    .collect::<mlua::Result<Vec<_>>>()?,
+                Err(_) => vec![Value::Nil; n],
+            },
+            TagType::Float => match fmt.float() {
+                Ok(v) => v
+                    .iter()
+                    .map(|s| crate::sample::sample_value(lua, s, scalar))
+                    .collect::<mlua::Result<Vec<_>>>()?,
+                Err(_) => vec![Value::Nil; n],
+            },
+            TagType::String => match fmt.string() {
+                Ok(v) => v
+                    .iter()
+                    .map(|s| match *s {
+                        b"" | b"." => Ok(Value::Nil),
+                        s => lua.create_string(s).map(Value::String),
+                    })
+                    .collect::<mlua::Result<Vec<_>>>()?,
+                Err(_) => vec![Value::Nil; n],
+            },
+            TagType::Flag => {
+                return Err(mlua::Error::RuntimeError(format!(
+                    "format tag '{}' has unsupported type Flag",
+                    tag
+                )))
+            }
+        };
+        values[i] = value;
+        set_format(&mut self.record, tag, &values)
+    }
+
+    /// Get the 0-based index of a sample given its name (or --sample-alias).
+    pub(crate) fn sample_id(&self, lua: &Lua, name: &[u8]) -> Option<usize> {
+        self.record
+            .header()
+            .sample_id(&crate::alias::resolve(lua, name))
+    }
+
+    /// Get the 0-based index of a sample given its name, its 1-based index or a table from `variant:sample`.
+    pub(crate) fn sample_index(&self, lua: &Lua, sample: &Value) -> mlua::Result<usize> {
+        let n = self.record.sample_count() as usize;
+        match sample {
+            Value::Integer(i) if *i >= 1 && (*i as usize) <= n => Ok(*i as usize - 1),
+            Value::Table(t) => match crate::sample::sample_name(lua, t)? {
+                Some(name) => self.sample_id(lua, name.as_bytes()).ok_or_else(|| {
+                    mlua::Error::RuntimeError(crate::sample::unknown_sample_message(
+                        self.header(),
+                        &name,
+                    ))
+                }),
+                None => Err(mlua::Error::RuntimeError(
+                    "expected a sample table from variant:sample".to_string(),
+                )),
+            },
+            Value::String(s) => self
+                .sample_id(lua, s.as_bytes())
+                .ok_or_else(|| {
+                    mlua::Error::RuntimeError(crate::sample::unknown_sample_message(
+                        self.header(),
+                        &s.to_string_lossy(),
+                    ))
+                }),
+            v => Err(mlua::Error::RuntimeError(format!(
+                "expected sample name or 1-based index <= {}, got {:?}",
+                n, v
+            ))),
+        }
+    }
+
     pub fn info_type(&self, key: &str) -> Result<(TagType, TagLength)> {
         let t = match self.header_map.0.borrow().get(key) {
             Some((typ, num)) => return Ok((*typ, *num)),
@@ -71,6 +404,209 @@ impl Variant {
     }
 }
 
+/// Numeric FORMAT values carry htslib sentinels for missing values and for the
+/// padding used when a sample has fewer values than the widest sample.
+pub(crate) trait Sentinel: Copy {
+    fn is_missing_value(&self) -> bool;
+    fn is_vector_end(&self) -> bool;
+    fn missing() -> Self;
+    fn vector_end() -> Self;
+    fn to_lua<'lua>(self) -> Value<'lua>;
+    /// Convert a lua value to Self. nil gives None.
+    fn from_lua(v: &Value) -> mlua::Result<Option<Self>>;
+}
+
+impl Sentinel for i32 {
+    fn is_missing_value(&self) -> bool {
+        *self == rust_htslib::htslib::bcf_int32_missing
+    }
+    fn is_vector_end(&self) -> bool {
+        *self == rust_htslib::htslib::bcf_int32_vector_end
+    }
+    fn missing() -> Self {
+        rust_htslib::htslib::bcf_int32_missing
+    }
+    fn vector_end() -> Self {
+        rust_htslib::htslib::bcf_int32_vector_end
+    }
+    fn to_lua<'lua>(self) -> Value<'lua> {
+        Value::Integer(self as _)
+    }
+    fn from_lua(v: &Value) -> mlua::Result<Option<Self>> {
+        match v {
+            Value::Nil => Ok(None),
+            Value::Integer(i) => Ok(Some(*i)),
+            Value::Number(f) if f.fract() == 0.0 => Ok(Some(*f as i32)),
+            _ => Err(mlua::Error::RuntimeError(format!(
+                "expected integer value, got {:?}",
+                v
+            ))),
+        }
+    }
+}
+
+impl Sentinel for f32 {
+    fn is_missing_value(&self) -> bool {
+        self.to_bits() == 0x7F80_0001
+    }
+    fn is_vector_end(&self) -> bool {
+        self.to_bits() == 0x7F80_0002
+    }
+    fn missing() -> Self {
+        f32::from_bits(0x7F80_0001)
+    }
+    fn vector_end() -> Self {
+        f32::from_bits(0x7F80_0002)
+    }
+    fn to_lua<'lua>(self) -> Value<'lua> {
+        Value::Number(self as f64)
+    }
+    fn from_lua(v: &Value) -> mlua::Result<Option<Self>> {
+        match v {
+            Value::Nil => Ok(None),
+            Value::Integer(i) => Ok(Some(*i as f32)),
+            Value::Number(f) => Ok(Some(*f as f32)),
+            _ => Err(mlua::Error::RuntimeError(format!(
+                "expected numeric value, got {:?}",
+                v
+            ))),
+        }
+    }
+}
+
+/// Flatten one lua value per sample (a scalar, nil, or a table) into the
+/// padded layout htslib expects for FORMAT fields.
+fn flatten_format<T: Sentinel>(values: &[Value], max_len: Option<usize>) -> mlua::Result<Vec<T>> {
+    let mut rows = Vec::with_capacity(values.len());
+    for v in values {
+        let row = match v {
+            Value::Table(t) => {
+                let mut row = vec![];
+                for i in 1..=t.raw_len() {
+                    row.push(T::from_lua(&t.raw_get::<_, Value>(i)?)?);
+                }
+                row
+            }
+            v => vec![T::from_lua(v)?],
+        };
+        if let Some(n) = max_len {
+            if row.len() > n {
+                return Err(mlua::Error::RuntimeError(format!(
+                    "got {} values for a sample, but at most {} are allowed",
+                    row.len(),
+                    n
+                )));
+            }
+        }
+        rows.push(row);
+    }
+    let width = rows.iter().map(|r| r.len()).max().unwrap_or(1).max(1);
+    let mut flat = Vec::with_capacity(width * rows.len());
+    for row in rows {
+        let n = row.len();
+        if n == 0 {
+            flat.push(T::missing());
+        }
+        flat.extend(row.into_iter().map(|v| v.unwrap_or_else(T::missing)));
+        flat.extend((n.max(1)..width).map(|_| T::vector_end()));
+    }
+    Ok(flat)
+}
+
+/// Set a FORMAT field from one lua value per sample, respecting the declared Type and Number.
+pub(crate) fn set_format(
+    record: &mut bcf::Record,
+    tag: &str,
+    values: &[Value],
+) -> mlua::Result<()> {
+    let (typ, num) = record
+        .header()
+        .format_type(tag.as_bytes())
+        .map_err(|e| mlua::Error::ExternalError(Arc::new(e)))?;
+    let n_samples = record.sample_count() as usize;
+    if values.len() != n_samples {
+        return Err(mlua::Error::RuntimeError(format!(
+            "format_set for '{}' got {} values but there are {} samples",
+            tag,
+            values.len(),
+            n_samples
+        )));
+    }
+    let n_alleles = record.allele_count() as usize;
+    let max_len = match num {
+        TagLength::Fixed(n) => Some(n as usize),
+        TagLength::AltAlleles => Some(n_alleles.saturating_sub(1)),
+        TagLength::Alleles => Some(n_alleles),
+        _ => None,
+    };
+    let result = match typ {
+        TagType::Integer => {
+            record.push_format_integer(tag.as_bytes(), &flatten_format::<i32>(values, max_len)?)
+        }
+        TagType::Float => {
+            record.push_format_float(tag.as_bytes(), &flatten_format::<f32>(values, max_len)?)
+        }
+        TagType::String => {
+            let strings = values
+                .iter()
+                .map(|v| match v {
+                    Value::Nil => Ok(b".".to_vec()),
+                    Value::String(s) => Ok(s.as_bytes().to_vec()),
+                    Value::Integer(i) => Ok(i.to_string().into_bytes()),
+                    Value::Number(f) => Ok(f.to_string().into_bytes()),
+                    v => Err(mlua::Error::RuntimeError(format!(
+                        "expected string value for '{}', got {:?}",
+                        tag, v
+                    ))),
+                })
+                .collect::<mlua::Result<Vec<_>>>()?;
+            record.push_format_string(tag.as_bytes(), &strings)
+        }
+        TagType::Flag => {
+            return Err(mlua::Error::RuntimeError(format!(
+                "format tag '{}' has unsupported type Flag",
+                tag
+            )))
+        }
+    };
+    result.map_err(|e| mlua::Error::ExternalError(Arc::new(e)))
+}
+
+/// Convert per-sample FORMAT values into a lua table with one entry per sample.
+/// Scalar fields give a value per sample, others give a table per sample.
+/// Missing values become nil and vector-end padding is dropped.
+pub(crate) fn format_matrix<'lua, T: Sentinel>(
+    lua: &'lua Lua,
+    rows: &[&[T]],
+    scalar: bool,
+) -> mlua::Result<Value<'lua>> {
+    let t = lua.create_table_with_capacity(rows.len(), 0)?;
+    for (i, vals) in rows.iter().enumerate() {
+        if scalar {
+            if let Some(v) = vals.first().filter(|v| !v.is_missing_value()) {
+                t.raw_set(i + 1, v.to_lua())?;
+            }
+            continue;
+        }
+        t.raw_set(i + 1, values_table(lua, vals)?)?;
+    }
+    Ok(Value::Table(t))
+}
+
+/// Values for a single sample up to the vector-end padding. Missing values are nil.
+pub(crate) fn values_table<'lua, T: Sentinel>(
+    lua: &'lua Lua,
+    vals: &[T],
+) -> mlua::Result<mlua::Table<'lua>> {
+    let t = lua.create_table_with_capacity(vals.len(), 0)?;
+    for (j, v) in vals.iter().take_while(|v| !v.is_vector_end()).enumerate() {
+        if !v.is_missing_value() {
+            t.raw_set(j + 1, v.to_lua())?;
+        }
+    }
+    Ok(t)
+}
+
 use log::{debug, log_enabled, Level};
 
 pub fn register_variant(lua: &Lua) -> mlua::Result<()> {
@@ -115,13 +651,24 @@ pub fn register_variant(lua: &Lua) -> mlua::Result<()> {
             Ok(())
         });
 
+        reg.add_field_method_get("index", |_, this: &Variant| Ok(this.index));
+        reg.add_field_method_get("file_offset", |_, this: &Variant| Ok(this.file_offset));
         reg.add_field_method_get("start", |_, this: &Variant| Ok(this.record.pos()));
         reg.add_field_method_get("stop", |_, this: &Variant| Ok(this.record.end()));
-        reg.add_field_method_get("pos", |_, this: &Variant| Ok(this.record.pos()));
+        reg.add_field_method_get("pos", |lua, this: &Variant| {
+            let one_based = lua.app_data_ref::<OneBasedTemplatePos>().is_some();
+            Ok(this.record.pos() + one_based as i64)
+        });
         reg.add_field_method_set("pos", |_, this: &mut Variant, val: i64| {
             this.record.set_pos(val);
             Ok(())
         });
+        // the 1-based position as printed in the VCF.
+        reg.add_field_method_get("POS", |_, this: &Variant| Ok(this.record.pos() + 1));
+        reg.add_field_method_set("POS", |_, this: &mut Variant, val: i64| {
+            this.record.set_pos(val - 1);
+            Ok(())
+        });
         reg.add_field_method_get("filters", |lua: &Lua, this: &Variant| {
             let f = this.record.filters();
             let t = lua.create_table().expect("error creating table");
@@ -142,6 +689,26 @@ pub fn register_variant(lua: &Lua) -> mlua::Result<()> {
                 Ok(_) => Ok(()),
             },
         );
+        // add a FILTER (which replaces PASS). The FILTER must be in the header; use header:add_filter in the prelude.
+        reg.add_method_mut("add_filter", |_lua, this: &mut Variant, filter: String| {
+            this.record.push_filter(filter.as_bytes()).map_err(|e| {
+                mlua::Error::RuntimeError(format!(
+                    "error adding FILTER {}: {}. Make sure it was added to the header in prelude if needed.",
+                    filter, e
+                ))
+            })
+        });
+        // add a FILTER to ALT i (1-based) in the ALT_FILTER INFO field. "PASS" clears the ALT's filters.
+        reg.add_method_mut(
+            "set_alt_filter",
+            |_lua, this: &mut Variant, (i, filter): (usize, String)| {
+                crate::altfilter::set_alt_filter(&mut this.record, i, &filter)
+                    .map_err(|e| mlua::Error::RuntimeError(e.to_string()))
+            },
+        );
+        reg.add_method("alt_filter", |_lua, this: &Variant, i: usize| {
+            Ok(crate::altfilter::alt_filter(&this.record, i))
+        });
         reg.add_field_method_get("id", |lua: &Lua, this: &Variant| {
             let id = this.record.id();
             Ok(Value::String(unsafe {
@@ -226,93 +793,255 @@ pub fn register_variant(lua: &Lua) -> mlua::Result<()> {
                 Ok(_) => Ok(()),
             },
         );
-        reg.add_field_method_get("genotypes", |_lua: &Lua, this: &Variant| {
-            let genotypes = this.record.format(b"GT");
-            match genotypes.integer() {
-                Ok(genotypes) => {
-                    let sb = crate::genotypes::Genotypes(Arc::new(Mutex::new(
-                        crate::genotypes::I32Buffer(genotypes),
-                    )));
-                    Ok(sb)
-                }
-                Err(e) => Err(mlua::Error::RuntimeError(e.to_string())),
-            }
+        reg.add_field_function_get("genotypes", |_lua: &Lua, ud: AnyUserData| {
+            let mut this = ud.borrow_mut::<Variant>()?;
+            Ok(crate::genotypes::Genotypes(this.gt_data()?))
         });
-
-        reg.add_method("format", |lua: &Lua, this: &Variant, format: String| {
-            let fmt = this.record.format(format.as_bytes());
-            let typ = this.record.header().format_type(format.as_bytes());
-            let (typ, num) = match typ {
-                Err(e) => return Err(mlua::Error::ExternalError(Arc::new(e))),
-                Ok(typ) => typ,
-            };
-            let n_samples = this.record.sample_count() as usize;
-            let t = lua
-                .create_table_with_capacity(n_samples, 0)
-                .expect("error creating table");
-            return match typ {
-                bcf::header::TagType::Integer => fmt
-                    .integer()
-                    .map(|v| {
-                        if matches!(num, bcf::header::TagLength::Fixed(1)) {
-                            for (i, vals) in v.iter().enumerate() {
-                                t.raw_set(i + 1, vals[0]).expect("error setting value");
-                            }
-                        } else {
-                            for (i, vals) in v.iter().enumerate() {
-                                let ti = lua
-                                    .create_table_with_capacity(vals.len(), 0)
-                                    .expect("error creating table");
-                                for (j, val) in vals.iter().enumerate() {
-                                    ti.raw_set(j + 1, *val).expect("error setting value");
-                                }
-                                t.raw_set(i + 1, ti).expect("error setting value");
-                            }
-                        }
-                        Ok::<LuaValue<'_>, mlua::Error>(Value::Table(t))
-                    })
-                    .map_err(|e| mlua::Error::ExternalError(Arc::new(e))),
-                bcf::header::TagType::Float => fmt
-                    .float()
-                    .map(|v| {
-                        if matches!(num, bcf::header::TagLength::Fixed(1)) {
-                            for (i, vals) in v.iter().enumerate() {
-                                t.raw_set(i + 1, vals[0]).expect("error setting value");
-                            }
-                        } else {
-                            for (i, vals) in v.iter().enumerate() {
-                                let ti = lua
-                                    .create_table_with_capacity(vals.len(), 0)
-                                    .expect("error creating table");
-                                for (j, val) in vals.iter().enumerate() {
-                                    ti.raw_set(j + 1, *val).expect("error setting value");
-                                }
-                                t.raw_set(i + 1, ti).expect("error setting value");
-                            }
-                        }
-                        Ok::<LuaValue<'_>, mlua::Error>(Value::Table(t))
+        // set (or clear) the phase of all alleles for a sample given by name or 1-based index.
+        reg.add_method_mut(
+            "set_phased",
+            |lua: &Lua, this: &mut Variant, (sample, phased): (Value, bool)| {
+                let i = this.sample_index(lua, &sample)?;
+                this.gt_data()?.lock().set_phased(i, phased);
+                Ok(())
+            },
+        );
+        // set the PS (phase set) FORMAT field for a single sample. nil sets it to missing.
+        reg.add_method_mut(
+            "set_phase_set",
+            |lua: &Lua, this: &mut Variant, (sample, ps): (Value, Option<i32>)| {
+                let i = this.sample_index(lua, &sample)?;
+                let n = this.record.sample_count() as usize;
+                let mut values = match this.record.format(b"PS").integer() {
+                    Ok(v) => v
+                        .iter()
+                        .map(|s| s.first().copied().unwrap_or(i32::missing()))
+                        .collect::<Vec<_>>(),
+                    Err(_) => vec![i32::missing(); n],
+                };
+                values[i] = ps.unwrap_or(i32::missing());
+                this.record
+                    .push_format_integer(b"PS", &values)
+                    .map_err(|e| {
+                        mlua::Error::RuntimeError(format!(
+                            "error setting PS: {}. Make sure it was added to the header in prelude if needed.",
+                            e
+                        ))
                     })
-                    .map_err(|e| mlua::Error::ExternalError(Arc::new(e))),
+            },
+        );
 
-                bcf::header::TagType::String => Ok(fmt.string().map_or_else(
-                    |_e| {
+        reg.add_method("format", |lua: &Lua, this: &Variant, format: String| {
+            let tag = format.as_bytes();
+            let (typ, num) = this
+                .record
+                .header()
+                .format_type(tag)
+                .map_err(|e| mlua::Error::ExternalError(Arc::new(e)))?;
+            let fmt = this.record.format(tag);
+            match (typ, tag) {
+                (TagType::String, b"GT") => {
+                    let v = fmt
+                        .integer()
+                        .map_err(|e| mlua::Error::ExternalError(Arc::new(e)))?;
+                    let rows = v
+                        .iter()
+                        .map(|vals| {
+                            vals.iter()
+                                .take_while(|a| !a.is_vector_end())
+                                .map(|a| (a >> 1) - 1)
+                                .collect::<Vec<i32>>()
+                        })
+                        .collect::<Vec<_>>();
+                    let rows = rows.iter().map(|r| &r[..]).collect::<Vec<_>>();
+                    format_matrix(lua, &rows, false)
+                }
+                (TagType::Integer, _) => {
+                    let v = fmt
+                        .integer()
+                        .map_err(|e| mlua::Error::ExternalError(Arc::new(e)))?;
+                    format_matrix(lua, &v, matches!(num, TagLength::Fixed(1)))
+                }
+                (TagType::Float, _) => {
+                    let v = fmt
+                        .float()
+                        .map_err(|e| mlua::Error::ExternalError(Arc::new(e)))?;
+                    format_matrix(lua, &v, matches!(num, TagLength::Fixed(1)))
+                }
+                (TagType::String, _) => match fmt.string() {
+                    Err(_e) => {
                         if log_enabled!(Level::Debug) {
                             debug!("format tag {} not found", format);
                         }
                         Ok(Value::Nil)
-                    },
-                    |v| {
+                    }
+                    Ok(v) => {
+                        let t = lua.create_table_with_capacity(v.len(), 0)?;
                         for (i, vals) in v.iter().enumerate() {
-                            t.raw_set(i + 1, unsafe { String::from_utf8_unchecked(vals.to_vec()) })
-                                .expect("error setting value");
+                            if vals.is_empty() || *vals == b"." {
+                                continue;
+                            }
+                            t.raw_set(i + 1, lua.create_string(vals)?)?;
                         }
-                        Ok::<LuaValue<'_>, mlua::Error>(Value::Table(t))
-                    },
-                )),
+                        Ok(Value::Table(t))
+                    }
+                },
+                (TagType::Flag, _) => Err(mlua::Error::RuntimeError(format!(
+                    "format tag '{}' has unsupported type Flag",
+                    format
+                ))),
+            }
+        });
+
+        reg.add_method_mut(
+            "format_set",
+            |_lua: &Lua, this: &mut Variant, (tag, values): (String, mlua::Table)| {
+                let n_samples = this.record.sample_count() as usize;
+                let values = (1..=n_samples)
+                    .map(|i| values.raw_get::<_, Value>(i))
+                    .collect::<mlua::Result<Vec<_>>>()?;
+                set_format(&mut this.record, &tag, &values)
+            },
+        );
+
+        // set a FORMAT field (of any type but GT) for one sample. If `sample` is a table from
+        // variant:sample, the table is updated too.
+        reg.add_method_mut(
+            "set_sample_format",
+            |lua: &Lua, this: &mut Variant, (sample, tag, value): (Value, String, Value)| {
+                let i = this.sample_index(lua, &sample)?;
+                this.set_sample_format(lua, i, &tag, value.clone())?;
+                if let Value::Table(t) = sample {
+                    crate::sample::fields(&t)?.raw_set(tag, value)?;
+                }
+                Ok(())
+            },
+        );
 
-                _ => unimplemented!("format type {:?}", typ),
+        // genotype counts across samples. These use any genotypes assigned via variant.genotypes.
+        reg.add_method_mut("num_het", |_lua, this: &mut Variant, ()| {
+            Ok(this.gt_data()?.lock().counts().het)
+        });
+        reg.add_method_mut("num_hom_alt", |_lua, this: &mut Variant, ()| {
+            Ok(this.gt_data()?.lock().counts().hom_alt)
+        });
+        reg.add_method_mut("num_hom_ref", |_lua, this: &mut Variant, ()| {
+            Ok(this.gt_data()?.lock().counts().hom_ref)
+        });
+        reg.add_method_mut("num_missing", |_lua, this: &mut Variant, ()| {
+            Ok(this.gt_data()?.lock().counts().missing)
+        });
+        reg.add_method_mut("call_rate", |_lua, this: &mut Variant, ()| {
+            Ok(this.gt_data()?.lock().counts().call_rate())
+        });
+        reg.add_method_mut("aaf", |lua, this: &mut Variant, alt: Option<i32>| {
+            let skip = match crate::allele::skip_symbolic(lua) {
+                true => this.symbolic_alleles(),
+                false => vec![],
             };
+            Ok(this.gt_data()?.lock().alt_frequency(alt, &skip))
         });
+        reg.add_method("alt_read_fraction", |_lua, this: &Variant, ()| {
+            Ok(this.alt_read_fraction())
+        });
+        reg.add_method("format_stats", |lua, this: &Variant, tag: String| {
+            let (mut values, n_missing) = this.format_values(&tag)?;
+            let t = lua.create_table()?;
+            t.raw_set("n_missing", n_missing)?;
+            if values.is_empty() {
+                return Ok(t);
+            }
+            values.sort_by(|a, b| a.total_cmp(b));
+            let n = values.len();
+            let median = if n % 2 == 1 {
+                values[n / 2]
+            } else {
+                (values[n / 2 - 1] + values[n / 2]) / 2.0
+            };
+            t.raw_set("min", values[0])?;
+            t.raw_set("max", values[n - 1])?;
+            t.raw_set("mean", values.iter().sum::<f64>() / n as f64)?;
+            t.raw_set("median", median)?;
+            Ok(t)
+        });
+        reg.add_method("has_star_allele", |_lua, this: &Variant, ()| {
+            Ok(this.record.alleles().iter().skip(1).any(|a| *a == b"*"))
+        });
+        reg.add_method("has_non_ref_allele", |_lua, this: &Variant, ()| {
+            Ok(this
+                .record
+                .alleles()
+                .iter()
+                .skip(1)
+                .any(|a| *a == b"<NON_REF>" || *a == b"<*>"))
+        });
+        // the ALT indexes (1 for the first ALT, as in GT) carried by at least one sample.
+        reg.add_method_mut("alt_indices_in_genotypes", |_lua, this: &mut Variant, ()| {
+            let n_alleles = this.record.allele_count() as usize;
+            let (counts, _) = this.gt_data()?.lock().allele_counts(n_alleles);
+            Ok(counts
+                .iter()
+                .enumerate()
+                .skip(1)
+                .filter(|(_, &c)| c > 0)
+                .map(|(i, _)| i)
+                .collect::<Vec<_>>())
+        });
+        reg.add_method_mut(
+            "transmitted_allele",
+            |lua, this: &mut Variant, child: Value| {
+                let Some(ped) = lua.app_data_ref::<crate::pedigree::Pedigree>() else {
+                    return Err(mlua::Error::RuntimeError(
+                        "transmitted_allele requires a pedigree (--ped)".to_string(),
+                    ));
+                };
+                let i = this.sample_index(lua, &child)?;
+                let name = String::from_utf8_lossy(this.header().samples()[i]).to_string();
+                let (father, mother) = ped.parents(&name).ok_or_else(|| {
+                    mlua::Error::RuntimeError(format!("sample '{}' not found in pedigree", name))
+                })?;
+                let parent = |p: Option<&str>| p.and_then(|p| this.header().sample_id(p.as_bytes()));
+                let (father, mother) = (parent(father), parent(mother));
+                let gts = this.gt_data()?;
+                let gts = gts.lock();
+                let (f, m) = crate::pedigree::transmitted_alleles(
+                    &gts.alleles(i).unwrap_or_default(),
+                    father.and_then(|f| gts.alleles(f)).as_deref(),
+                    mother.and_then(|m| gts.alleles(m)).as_deref(),
+                );
+                Ok((f, m))
+            },
+        );
+        reg.add_method_mut(
+            "mask_genotypes",
+            |lua, this: &mut Variant, (f, filter): (mlua::Function, Option<String>)| {
+                this.mask_genotypes(lua, f, filter)
+            },
+        );
+        reg.add_method_mut("update_allele_counts", |lua, this: &mut Variant, ()| {
+            let skip = match crate::allele::skip_symbolic(lua) {
+                true => this.symbolic_alleles(),
+                false => vec![],
+            };
+            this.update_allele_counts(&skip)
+        });
+
+        reg.add_method("carriers", |lua: &Lua, this: &Variant, alt: Option<i32>| {
+            let bits = crate::genotypes::carrier_bits(&this.record, alt)?;
+            lua.create_sequence_from(crate::genotypes::bit_indexes(&bits).map(|i| i + 1))
+        });
+        reg.add_method(
+            "is_carrier",
+            |lua: &Lua, this: &Variant, (sample, alt): (Value, Option<i32>)| {
+                let i = this.sample_index(lua, &sample)?;
+                let gts = this
+                    .record
+                    .format(b"GT")
+                    .integer()
+                    .map_err(|e| mlua::Error::ExternalError(Arc::new(e)))?;
+                Ok(crate::genotypes::is_carrier(gts[i], alt))
+            },
+        );
 
         reg.add_method(
             "info",
@@ -328,7 +1057,7 @@ pub fn register_variant(lua: &Lua) -> mlua::Result<()> {
                     }
                     Ok(typ) => typ,
                 };
-                return match typ {
+                match typ {
                     bcf::header::TagType::Integer => info
                         .integer()
                         .map(|v| match v {
@@ -372,128 +1101,94 @@ pub fn register_variant(lua: &Lua) -> mlua::Result<()> {
                             None => Ok(Value::Nil),
                         })
                         .map_err(|e| mlua::Error::ExternalError(Arc::new(e))),
-                    bcf::header::TagType::String => info
-                        .string()
-                        .map(|v| match v {
-                            Some(v) => match (num, index) {
-                                (bcf::header::TagLength::Fixed(1), None) => {
-                                    Ok::<LuaValue<'_>, mlua::Error>(Value::String(
-                                        lua.create_string(unsafe {
-                                            String::from_utf8_unchecked(v[0].to_vec())
-                                        })?,
-                                    ))
-                                }
-                                (_, Some(i)) => Ok::<LuaValue<'_>, mlua::Error>(Value::String(
-                                    lua.create_string(unsafe {
-                                        String::from_utf8_unchecked(v[i].to_vec())
-                                    })?,
-                                )),
-                                _ => {
-                                    let t = lua.create_table().expect("error creating table");
-                                    for (i, s) in v.iter().enumerate() {
-                                        t.raw_set(i + 1, unsafe {
-                                            String::from_utf8_unchecked(s.to_vec())
-                                        })
-                                        .expect("error setting value");
+                    bcf::header::TagType::String => {
+                        let decode = crate::percent::enabled(lua);
+                        let to_lua = |s: &[u8]| {
+                            if decode {
+                                lua.create_string(crate::percent::decode(s))
+                            } else {
+                                lua.create_string(s)
+                            }
+                        };
+                        info.string()
+                            .map(|v| match v {
+                                Some(v) => match (num, index) {
+                                    (bcf::header::TagLength::Fixed(1), None) => {
+                                        Ok::<LuaValue<'_>, mlua::Error>(Value::String(to_lua(
+                                            v[0],
+                                        )?))
                                     }
-                                    Ok::<LuaValue<'_>, mlua::Error>(Value::Table(t))
-                                }
-                            },
-                            None => Ok(Value::Nil),
-                        })
-                        .map_err(|e| mlua::Error::ExternalError(Arc::new(e))),
+                                    (_, Some(i)) => Ok::<LuaValue<'_>, mlua::Error>(
+                                        Value::String(to_lua(v[i])?),
+                                    ),
+                                    _ => {
+                                        let t = lua.create_table()?;
+                                        for (i, s) in v.iter().enumerate() {
+                                            t.raw_set(i + 1, to_lua(s)?)?;
+                                        }
+                                        Ok::<LuaValue<'_>, mlua::Error>(Value::Table(t))
+                                    }
+                                },
+                                None => Ok(Value::Nil),
+                            })
+                            .map_err(|e| mlua::Error::ExternalError(Arc::new(e)))
+                    }
                     bcf::header::TagType::Flag => info
                         .flag()
                         .map(|v| Ok::<LuaValue<'_>, mlua::Error>(Value::Boolean(v)))
                         .map_err(|e| mlua::Error::ExternalError(Arc::new(e))),
-                };
+                }
             },
         );
-        reg.add_method(
-            "sample",
-            |lua: &Lua, this: &Variant, sample_name: String| {
-                let sample_id = match this.record.header().sample_id(sample_name.as_bytes()) {
-                    Some(id) => id,
-                    None => {
-                        let msg = format!("sample '{}' not found in VCF", sample_name);
-                        return Err(mlua::Error::RuntimeError(msg));
-                    }
+        // the tables are attached to the variant so that assigning a field writes to the record.
+        reg.add_function("sample", |lua: &Lua, (ud, sample): (AnyUserData, Value)| {
+            let this = ud.borrow::<Variant>()?;
+            let sample_id = match (&sample, crate::sample::lenient(lua)) {
+                (Value::String(s), true) => match this.sample_id(lua, s.as_bytes()) {
+                    Some(i) => i,
+                    None => return Ok(None),
+                },
+                _ => this.sample_index(lua, &sample)?,
+            };
+            let cached = match lua.app_data_ref::<crate::samplecache::SampleCache>() {
+                Some(cache) => cache.sample_table(lua, sample_id, this.record.pos())?,
+                None => None,
+            };
+            let t = match cached {
+                Some(t) => t,
+                None => crate::sample::sample_table(lua, &this.record, sample_id)?,
+            };
+            drop(this);
+            crate::sample::attach(&t, ud, sample_id)?;
+            Ok(Some(t))
+        });
+        // all samples (or the given names/1-based indexes) in VCF order.
+        reg.add_function(
+            "samples",
+            |lua: &Lua, (ud, which): (AnyUserData, Option<Vec<Value>>)| {
+                let this = ud.borrow::<Variant>()?;
+                let ids = match which {
+                    // unknown names are skipped with --lenient-samples.
+                    Some(which) if crate::sample::lenient(lua) => which
+                        .iter()
+                        .filter(|s| match s {
+                            Value::String(s) => this.sample_id(lua, s.as_bytes()).is_some(),
+                            _ => true,
+                        })
+                        .map(|s| this.sample_index(lua, s))
+                        .collect::<mlua::Result<Vec<_>>>()?,
+                    Some(which) => which
+                        .iter()
+                        .map(|s| this.sample_index(lua, s))
+                        .collect::<mlua::Result<Vec<_>>>()?,
+                    None => (0..this.record.sample_count() as usize).collect(),
                 };
-                // get all format fields for this sample.
-                let sample = lua.create_table().expect("error creating table");
-
-                this.record.header().header_records().iter().for_each(|r| {
-                    if let bcf::header::HeaderRecord::Format { key: _, values } = r {
-                        let tag = &values["ID"];
-                        let tag_bytes = tag.as_bytes();
-                        let fmt = this.record.format(tag_bytes);
-                        let typ = this.record.header().format_type(tag_bytes);
-                        let (typ, num) = match typ {
-                            Err(e) => {
-                                error!("format tag '{}' error: {:?}", tag, e);
-                                return;
-                            }
-                            Ok(typ) => typ,
-                        };
-                        let value = match (typ, tag_bytes) {
-                            (bcf::header::TagType::Integer, _)
-                            | (bcf::header::TagType::String, b"GT") => fmt
-                                .integer()
-                                .map(|v| match num {
-                                    bcf::header::TagLength::Fixed(1) if tag_bytes != b"GT" => {
-                                        Value::Integer(v[sample_id][0])
-                                    }
-                                    _ => {
-                                        let t = lua.create_table().expect("error creating table");
-                                        for (i, val) in v[sample_id].iter().enumerate() {
-                                            t.raw_set(i + 1, *val).expect("error setting value");
-                                        }
-                                        Value::Table(t)
-                                    }
-                                })
-                                .map_err(|e| mlua::Error::ExternalError(Arc::new(e))),
-                            (bcf::header::TagType::String, _) => fmt
-                                .string()
-                                .map(|v| match num {
-                                    bcf::header::TagLength::Fixed(1) => Value::String(
-                                        lua.create_string(unsafe {
-                                            String::from_utf8_unchecked(v[sample_id].to_vec())
-                                        })
-                                        .expect("error creating string"),
-                                    ),
-                                    _ => {
-                                        warn!("string format tag {} is not fixed length", tag);
-                                        Value::Nil
-                                    }
-                                })
-                                .map_err(|e| mlua::Error::ExternalError(Arc::new(e))),
-                            _ => Ok(Value::Nil),
-                        };
-                        if tag_bytes == b"GT" {
-                            let gt = match value {
-                                Ok(Value::Table(ref t)) => t,
-                                _ => return,
-                            };
-                            let mut phases = vec![];
-                            for i in 1..=gt.len().expect("error getting GT length") {
-                                let allele = gt.get::<_, i64>(i).expect("error getting allele");
-                                phases.push(allele & 1 == 1);
-                                gt.raw_set(i, (allele >> 1) - 1)
-                                    .expect("error setting value in GT table");
-                            }
-                            sample
-                                .raw_set("phase", phases)
-                                .expect("error setting genotype phases");
-                        }
-                        match value {
-                            Ok(val) => sample
-                                .raw_set(tag.to_string(), val)
-                                .expect("error setting value"),
-                            Err(e) => info!("format tag {} not found. {}", tag, e),
-                        }
-                    }
-                });
-                Ok(sample)
+                let samples = crate::sample::sample_tables(lua, &this.record, &ids)?;
+                drop(this);
+                for (s, &i) in samples.iter().zip(&ids) {
+                    crate::sample::attach(s, ud.clone(), i)?;
+                }
+                lua.create_sequence_from(samples)
             },
         );
     })
@@ -513,15 +1208,30 @@ mod tests {
         header.push_record(
             r#"##FORMAT=<ID=GT,Number=1,Type=String,Description="Genotype">"#.as_bytes(),
         );
+        header.push_record(
+            r#"##FORMAT=<ID=AD,Number=R,Type=Integer,Description="Allelic Depths">"#.as_bytes(),
+        );
+        header.push_record(
+            r#"##FORMAT=<ID=DP,Number=1,Type=Integer,Description="Sample Depth">"#.as_bytes(),
+        );
+        header.push_record(
+            r#"##FORMAT=<ID=PS,Number=1,Type=Integer,Description="Phase Set">"#.as_bytes(),
+        );
+        header.push_record(
+            r#"##FORMAT=<ID=PL,Number=G,Type=Integer,Description="Likelihoods">"#.as_bytes(),
+        );
         header.push_record(r#"##FILTER=<ID=PASS,Description="All filters passed">"#.as_bytes());
         header.push_record(
             r#"##INFO=<ID=DP,Number=1,Type=Integer,Description="Total Depth">"#.as_bytes(),
         );
+        header.push_record(br#"##INFO=<ID=AC,Number=A,Type=Integer,Description="Allele count">"#);
+        header.push_record(br#"##INFO=<ID=AN,Number=1,Type=Integer,Description="Allele number">"#);
+        header.push_record(br#"##INFO=<ID=AF,Number=A,Type=Float,Description="Allele frequency">"#);
         header.push_sample("NA12878".as_bytes());
         header.push_sample("NA12879".as_bytes());
         let vcf = bcf::Writer::from_path("_test.vcf", &header, true, bcf::Format::Vcf).unwrap();
         let mut record = vcf.empty_record();
-        let _ = record.set_rid(Some(vcf.header().name2rid(b"chr1").unwrap()));
+        record.set_rid(Some(vcf.header().name2rid(b"chr1").unwrap()));
         record.set_pos(6);
         record.set_alleles(&[b"A", b"AT"]).unwrap();
         record.set_id(b"rs1234").unwrap();
@@ -534,10 +1244,233 @@ mod tests {
             bcf::record::GenotypeAllele::Unphased(1),
         ];
         record.push_genotypes(alleles).unwrap();
+        record.push_format_integer(b"AD", &[10, 3, 0, 8]).unwrap();
+        record.push_format_integer(b"DP", &[13, i32::MIN]).unwrap();
+        record
+            .push_format_integer(b"PL", &[30, 0, 300, 200, 20, 0])
+            .unwrap();
 
         (lua, Variant::new(record, HeaderMap::new()))
     }
 
+    #[test]
+    fn test_update_allele_counts() {
+        let (lua, mut variant) = setup();
+        crate::genotypes::register_genotypes(&lua).unwrap();
+        lua.scope(|scope| {
+            lua.globals()
+                .raw_set("variant", scope.create_any_userdata_ref_mut(&mut variant)?)?;
+            lua.load(r#"variant.genotypes[2] = "./."; variant:update_allele_counts()"#)
+                .exec()?;
+            Ok(())
+        })
+        .unwrap();
+        let record = variant.record();
+        assert_eq!(*record.info(b"AC").integer().unwrap().unwrap(), [1]);
+        assert_eq!(*record.info(b"AN").integer().unwrap().unwrap(), [2]);
+        assert_eq!(*record.info(b"AF").float().unwrap().unwrap(), [0.5]);
+    }
+
+    #[test]
+    fn test_symbolic_alleles() {
+        let (lua, mut variant) = setup();
+        crate::genotypes::register_genotypes(&lua).unwrap();
+        variant
+            .record
+            .set_alleles(&[b"A", b"AT", b"*"])
+            .unwrap();
+        lua.scope(|scope| {
+            lua.globals()
+                .raw_set("variant", scope.create_any_userdata_ref_mut(&mut variant)?)?;
+            let (star, non_ref): (bool, bool) = lua
+                .load(r#"variant.genotypes[1] = "0/2"; return variant:has_star_allele(), variant:has_non_ref_allele()"#)
+                .eval()?;
+            assert!(star);
+            assert!(!non_ref);
+            assert_eq!(lua.load("return variant:aaf()").eval::<f64>()?, 0.75);
+            lua.set_app_data(crate::allele::SkipSymbolicAlleles);
+            assert_eq!(lua.load("return variant:aaf()").eval::<f64>()?, 2.0 / 3.0);
+            lua.load("variant:update_allele_counts()").exec()?;
+            Ok(())
+        })
+        .unwrap();
+        let record = variant.record();
+        assert_eq!(*record.info(b"AC").integer().unwrap().unwrap(), [2, 1]);
+        assert_eq!(*record.info(b"AN").integer().unwrap().unwrap(), [3]);
+        let af = record.info(b"AF").float().unwrap().unwrap();
+        assert!(af[1].is_missing_value());
+    }
+
+    #[test]
+    fn test_set_sample_format() {
+        let (lua, mut variant) = setup();
+        lua.scope(|scope| {
+            lua.globals()
+                .raw_set("variant", scope.create_any_userdata_ref_mut(&mut variant)?)?;
+            // FT is not in this header.
+            assert!(lua
+                .load(r#"variant:set_sample_format(1, "FT", "LowGQ")"#)
+                .exec()
+                .is_err());
+            let (dp, ad): (i32, Vec<i32>) = lua
+                .load(
+                    r#"local s = variant:sample("NA12879")
+                    variant:set_sample_format(s, "DP", 7)
+                    variant:set_sample_format(1, "AD", {4, 5})
+                    return s.DP, variant:sample(1).AD"#,
+                )
+                .eval()?;
+            assert_eq!(dp, 7);
+            assert_eq!(ad, vec![4, 5]);
+            Ok(())
+        })
+        .unwrap();
+        let dp = variant.record().format(b"DP").integer().unwrap();
+        assert_eq!((dp[0][0], dp[1][0]), (13, 7));
+    }
+
+    #[test]
+    fn test_assign_sample_fields() {
+        let (lua, mut variant) = setup();
+        crate::genotypes::register_genotypes(&lua).unwrap();
+        lua.scope(|scope| {
+            lua.globals()
+                .raw_set("variant", scope.create_any_userdata_ref_mut(&mut variant)?)?;
+            let (gt, phased, dp, n): (Vec<i32>, bool, i32, usize) = lua
+                .load(
+                    r#"local s = variant:sample("NA12879")
+                    s.GT = "0|1"
+                    s.DP = 7
+                    local n = 0
+                    for k, v in s do n = n + 1 end
+                    return s.GT, s:is_phased(), s.DP, n"#,
+                )
+                .eval()?;
+            assert_eq!((gt, phased, dp), (vec![0, 1], true, 7));
+            assert!(n > 0);
+            lua.load("variant:samples()[1].GT = {-1, -1}").exec()?;
+            // FT is not in this header.
+            assert!(lua
+                .load(r#"variant:sample(1).FT = "LowGQ""#)
+                .exec()
+                .is_err());
+            assert!(lua.load("variant:sample(1).GT = 3").exec().is_err());
+            Ok(())
+        })
+        .unwrap();
+        variant.flush_genotypes().unwrap();
+        let gts = variant.record().genotypes().unwrap();
+        assert_eq!(gts.get(0).to_string(), "./.");
+        assert_eq!(gts.get(1).to_string(), "0|1");
+        let dp = variant.record().format(b"DP").integer().unwrap();
+        assert_eq!(dp[1][0], 7);
+    }
+
+    #[test]
+    fn test_mask_genotypes() {
+        let (lua, mut variant) = setup();
+        crate::genotypes::register_genotypes(&lua).unwrap();
+        lua.scope(|scope| {
+            lua.globals()
+                .raw_set("variant", scope.create_any_userdata_ref_mut(&mut variant)?)?;
+            let n: usize = lua
+                .load("return variant:mask_genotypes(function(s) return s.AD[1] > 5 end)")
+                .eval()?;
+            assert_eq!(n, 1);
+            // FT is not in this header.
+            assert!(lua
+                .load("variant:mask_genotypes(function(s) return true end, 'LowQ')")
+                .exec()
+                .is_err());
+            Ok(())
+        })
+        .unwrap();
+        variant.flush_genotypes().unwrap();
+        let gts = variant.record().genotypes().unwrap();
+        assert_eq!(gts.get(0).to_string(), "./.");
+        assert_eq!(gts.get(1).to_string(), "1/1");
+    }
+
+    #[test]
+    fn test_alt_indices_in_genotypes() {
+        let (lua, mut variant) = setup();
+        crate::genotypes::register_genotypes(&lua).unwrap();
+        lua.scope(|scope| {
+            lua.globals()
+                .raw_set("variant", scope.create_any_userdata_ref_mut(&mut variant)?)?;
+            let (alts, has0, has1): (Vec<usize>, bool, bool) = lua
+                .load("return variant:alt_indices_in_genotypes(), variant:sample(1):has_allele(1), variant:sample(1):has_allele(2)")
+                .eval()?;
+            assert_eq!(alts, vec![1]);
+            assert!(has0);
+            assert!(!has1);
+            let alts: Vec<usize> = lua
+                .load(r#"variant.genotypes[1] = "0/0"; variant.genotypes[2] = "./."; return variant:alt_indices_in_genotypes()"#)
+                .eval()?;
+            assert!(alts.is_empty());
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_unknown_sample() {
+        let (lua, mut variant) = setup();
+        lua.scope(|scope| {
+            lua.globals()
+                .raw_set("variant", scope.create_any_userdata_ref_mut(&mut variant)?)?;
+            let err = lua
+                .load(r#"return variant:sample("NOPE")"#)
+                .exec()
+                .unwrap_err()
+                .to_string();
+            assert!(err.contains("sample 'NOPE' not found in VCF. available samples: NA12878, NA12879"));
+            lua.set_app_data(crate::sample::LenientSamples);
+            let (missing, n): (Option<mlua::Table>, usize) = lua
+                .load(r#"return variant:sample("NOPE"), #variant:samples({"NOPE", "NA12879"})"#)
+                .eval()?;
+            assert!(missing.is_none());
+            assert_eq!(n, 1);
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_phasing() {
+        let (lua, mut variant) = setup();
+        crate::genotypes::register_genotypes(&lua).unwrap();
+        lua.scope(|scope| {
+            lua.globals()
+                .raw_set("variant", scope.create_any_userdata_ref_mut(&mut variant)?)?;
+            let phased: (bool, bool, bool) = lua
+                .load("return variant:sample(1):is_phased(), variant:sample(2):is_phased(), variant.genotypes[1]:is_phased()")
+                .eval()?;
+            assert_eq!(phased, (true, false, true));
+            lua.load(
+                r#"
+                variant:set_phased("NA12878", false)
+                variant:set_phased(2, true)
+                variant:set_phase_set(2, 1234)
+                "#,
+            )
+            .exec()?;
+            let s: String = lua
+                .load("return tostring(variant.genotypes[1]) .. ',' .. tostring(variant.genotypes[2])")
+                .eval()?;
+            assert_eq!(s, "0/1,1|1");
+            assert!(lua.load("variant:set_phased(3, true)").exec().is_err());
+            Ok(())
+        })
+        .unwrap();
+        variant.flush_genotypes().unwrap();
+        let record = variant.take();
+        assert_eq!(record.genotypes().unwrap().get(1).to_string(), "1|1");
+        let ps = record.format(b"PS").integer().unwrap();
+        assert!(ps[0][0].is_missing_value());
+        assert_eq!(ps[1][0], 1234);
+    }
+
     #[test]
     fn test_lua_expressions() {
         let (lua, mut record) = setup();
@@ -552,6 +1485,19 @@ mod tests {
             (r#"return variant.ALT[1]"#, "A"),
             (r#"return variant.ALT[2]"#, "G"),
             (r#"return variant.FILTER"#, "PASS"),
+            (r#"return variant.index"#, "0"),
+            (
+                r#"return variant.start .. " " .. variant.pos .. " " .. variant.POS"#,
+                "6 6 7",
+            ),
+            (
+                r#"variant.POS = 100; local p = variant.pos; variant.POS = 7; return p"#,
+                "99",
+            ),
+            (
+                r#"variant:add_filter("PASS"); return table.concat(variant.filters, ",")"#,
+                "PASS",
+            ),
             // NOTE that we can get an integer, with 10, but we're testing
             // all strings here and verifying that the auto conversion works.
             (r#"return variant:info("DP")"#, "10"),
@@ -563,6 +1509,62 @@ mod tests {
                 r#"s=variant:sample('NA12878'); return tostring(s.phase[2])"#,
                 "true",
             ),
+            (r#"s=variant:sample('NA12878'); return s.AD[2]"#, "3"),
+            (r#"s=variant:sample('NA12878'); return s.DP"#, "13"),
+            // missing values are nil and samples can be accessed by 1-based index.
+            (r#"return tostring(variant:sample('NA12879').DP)"#, "nil"),
+            (r#"return variant:sample(2).GT[1]"#, "1"),
+            (r#"return #variant:samples()"#, "2"),
+            (
+                r#"return string.format("%.3f", variant:sample(1).VAF)"#,
+                "0.231",
+            ),
+            (r#"return variant:sample(2).AB"#, "1"),
+            // (3 + 8) / (13 + 8): the DP of the 2nd sample is missing so its AD sum is used.
+            (
+                r#"return string.format("%.3f", variant:alt_read_fraction())"#,
+                "0.524",
+            ),
+            // AD is 10,3 and 0,8; DP is 13 and missing.
+            (
+                r#"local s = variant:format_stats('AD'); return `{s.min} {s.max} {s.mean} {s.median} {s.n_missing}`"#,
+                "0 10 5.25 5.5 0",
+            ),
+            (
+                r#"local s = variant:format_stats('DP'); return `{s.median} {s.n_missing}`"#,
+                "13 1",
+            ),
+            // dosage is computed from GT when there is no DS.
+            (r#"return variant:sample(1).dosage"#, "1"),
+            (r#"return variant:sample(2):called_likelihood()"#, "0"),
+            (
+                r#"return string.format("%.4f", variant:sample(1):genotype_probabilities()[1])"#,
+                "0.0010",
+            ),
+            (
+                r#"local n = 0; for _, s in variant:samples() do n += s.AD[2] end; return n"#,
+                "11",
+            ),
+            (r#"return variant:samples({'NA12879'})[1].AD[1]"#, "0"),
+            // format gives one entry per sample.
+            (r#"return variant:format('AD')[1][2]"#, "3"),
+            (r#"return variant:format('AD')[2][2]"#, "8"),
+            (r#"return #variant:format('AD')"#, "2"),
+            (r#"return variant:format('DP')[1]"#, "13"),
+            // missing values are nil.
+            (r#"return tostring(variant:format('DP')[2])"#, "nil"),
+            (r#"return variant:format('GT')[1][2]"#, "1"),
+            // format_set takes one value (or table for Number != 1) per sample.
+            (
+                r#"variant:format_set('DP', {7, nil}); return variant:format('DP')[1]"#,
+                "7",
+            ),
+            (r#"return tostring(variant:format('DP')[2])"#, "nil"),
+            (
+                r#"variant:format_set('AD', {{1, 2}, {3}}); return variant:format('AD')[2][1]"#,
+                "3",
+            ),
+            (r#"return #variant:format('AD')[2]"#, "1"),
             // Add more expressions and expected results here...
         ];
 
@@ -4,21 +4,122 @@ use rust_htslib::bcf::{
     header::{TagLength, TagType},
     Read,
 };
-use std::{collections::HashMap, hash::Hash, io::Write};
+use std::{borrow::Cow, collections::HashMap, hash::Hash, io::Write};
 
 use crate::variant::{HeaderMap, Variant};
 
 /// VCFExpress is the only entry-point for this library.
 pub struct VCFExpress<'lua> {
     lua: &'lua Lua,
+    vcf_path: String,
     vcf_reader: Option<bcf::Reader>,
     template: Option<mlua::Function<'lua>>,
-    writer: Option<EitherWriter>,
+    // the output path. The writer is created by `writer()` so that the output can be text or VCF.
+    output: Option<String>,
+    writer_taken: bool,
     expressions: Vec<mlua::Function<'lua>>,
     set_expressions: HashMap<InfoFormat, ((TagType, TagLength), mlua::Function<'lua>)>,
     globals: mlua::Table<'lua>,
+    // the output header. available (read-only) to expressions as `header`.
+    header: bcf::header::HeaderView,
+    renames: crate::rename::Renames,
+    retypes: crate::retype::Retypes,
     variants_evaluated: usize,
     variants_passing: usize,
+    // evaluate expressions and template once per sample with `sample` set.
+    per_sample: bool,
+    // evaluate expressions and template once per ALT allele with `allele` set.
+    per_allele: bool,
+    // `on_progress(n, locus)` from the prelude, called every `progress_every` records.
+    on_progress: Option<mlua::Function<'lua>>,
+    // `on_start(header)` and `on_end()` from the prelude, called before the first and after the last record.
+    on_start: Option<mlua::Function<'lua>>,
+    on_end: Option<mlua::Function<'lua>>,
+    progress_every: usize,
+    // the `stats` global with the running counts and user counters.
+    stats: mlua::Table<'lua>,
+    // sites from a previous output for `baseline.status` and the summary.
+    baseline: Option<crate::baseline::Baseline>,
+    // --isec and --exclude-vcf callsets that records are looked up in before they are evaluated.
+    callsets: Option<crate::isec::Callsets>,
+    // --targets-file intervals that records must overlap to be evaluated.
+    targets: Option<crate::targets::Targets>,
+    // --exclude-bed intervals; overlapping records are skipped.
+    exclude_bed: crate::exclude::ExcludeBed,
+    // --sample-fraction: the random subset of records to evaluate.
+    subsample: Option<crate::subsample::Subsample>,
+    // for --two-pass, whether the first pass kept each record read so that the second pass
+    // evaluates the same records without running the filters again.
+    first_pass_kept: Option<Vec<bool>>,
+    // INFO and FORMAT tags removed from the header in the prelude, to strip from each record.
+    removed_tags: Vec<(u32, String)>,
+    // the output rid for each input rid with --rename-chrs.
+    contig_rids: Vec<Option<u32>>,
+    // Float FORMAT fields to round before a record is written.
+    rounding: crate::round::Rounding,
+    // remove INFO and FORMAT fields without values before a record is written.
+    prune_empty: bool,
+    // `variant.pos` is 1-based in templates (--coords 1).
+    one_based_template_pos: bool,
+    // write lines from `emit(line)` here instead of the main output.
+    summary_output: Option<String>,
+    // passing variants bucketed by the `--group-by` expression. one row per group is written at the end.
+    groups: Option<crate::group::Groups<'lua>>,
+    // counts of passing variants in genomic windows, written instead of the variants.
+    density: Option<crate::density::WindowDensity>,
+    // per-gene counts of passing variants and carriers, written instead of the variants.
+    burden: Option<crate::burden::Burden<'lua>>,
+    // also write the output to stdout when it goes to a file.
+    tee: bool,
+    // how often each expression was evaluated, passed and was the first to pass.
+    expression_tallies: Vec<ExpressionTally>,
+    // evaluate every expression for each variant rather than stopping at the first that passes.
+    evaluate_all_expressions: bool,
+    // write only the counts of evaluated and passing variants.
+    count_only: bool,
+    // translate ALT_FILTER to or from the record FILTER before a record is written.
+    alt_filter: Option<crate::altfilter::AltFilterTranslation>,
+    // with --soft-filter, failing records are written with this FILTER instead of dropped. It
+    // replaces their FILTER or, with --soft-filter-append, is added to it.
+    soft_filter: Option<(String, bool)>,
+    // --skip and --max-records: the input records to skip, the number to read after those, the
+    // number read so far and whether there were more.
+    skip: usize,
+    max_records: Option<usize>,
+    records_read: usize,
+    truncated: bool,
+    // with --vcf-version 4.4 or later, the number of records with a `<*>` or `<NON_REF>` ALT but
+    // too few AD values. only the first is reported as it is read.
+    non_ref_ad_problems: Option<usize>,
+    // the BGZF virtual offset of the next record to evaluate, from `set_file_offset`.
+    file_offset: Option<u64>,
+}
+
+/// The position of a BGZF-compressed (BCF or .vcf.gz) reader in its file.
+pub struct BgzfOffset(*mut rust_htslib::htslib::BGZF);
+
+impl BgzfOffset {
+    /// None if the input of `reader` is not BGZF-compressed. It must be used only while
+    /// `reader` is open.
+    pub fn new(reader: &bcf::Reader) -> Option<Self> {
+        // rust-htslib keeps the htsFile of a reader private; its Debug output is the only place
+        // it is exposed, as `Reader { inner: 0x..., header: ... }`.
+        let debug = format!("{:?}", reader);
+        let hex = debug.split("inner: 0x").nth(1)?;
+        let end = hex
+            .find(|c: char| !c.is_ascii_hexdigit())
+            .unwrap_or(hex.len());
+        let fp = usize::from_str_radix(&hex[..end], 16).ok()? as *mut rust_htslib::htslib::htsFile;
+        let bgzf = unsafe { rust_htslib::htslib::hts_get_bgzfp(fp) };
+        (!bgzf.is_null()).then_some(BgzfOffset(bgzf))
+    }
+
+    /// The virtual offset of the next record, as from htslib's `bgzf_tell`: the file offset of
+    /// its BGZF block shifted left 16 bits, plus its offset in the uncompressed block.
+    pub fn tell(&self) -> u64 {
+        let bgzf = unsafe { &*self.0 };
+        ((bgzf.block_address as u64) << 16) | (bgzf.block_offset as u64 & 0xFFFF)
+    }
 }
 
 /// `StringOrVariant` allows `evaluate` to return either a string, an owned VCF record, or nothing.
@@ -33,53 +134,147 @@ pub enum StringOrVariant {
 
 /// `EitherWriter` encapsulates the different types of writers we can use.
 /// `File` and `Stdout` are for template output and `Vcf` is for VCF records.
+/// `VcfTee` and `FileTee` (for `--tee`) also write each record or line to stdout.
 pub enum EitherWriter {
     Vcf(bcf::Writer),
     File(std::io::BufWriter<std::fs::File>),
     Stdout(std::io::BufWriter<std::io::Stdout>),
+    VcfTee(VcfTee),
+    FileTee(
+        std::io::BufWriter<std::fs::File>,
+        std::io::BufWriter<std::io::Stdout>,
+    ),
+}
+
+/// VCF records written to a file and to stdout (`--tee`). Each record is formatted once and the
+/// same line is written to stdout and to a VCF file; only a BCF file encodes the record again.
+pub struct VcfTee {
+    // translates records to the output header and writes BCF output. for VCF output it writes
+    // only the header, to /dev/null.
+    wtr: bcf::Writer,
+    // plain or bgzipped VCF output.
+    file: Option<Box<dyn Write>>,
+    out: std::io::BufWriter<std::io::Stdout>,
+}
+
+impl VcfTee {
+    fn new(output: &str, header: &bcf::header::Header) -> std::io::Result<Self> {
+        let format = get_vcf_format(output);
+        let wtr = match format {
+            bcf::Format::Bcf => {
+                let mut wtr =
+                    bcf::Writer::from_path(output, header, !output.ends_with(".gz"), format)
+                        .map_err(std::io::Error::other)?;
+                _ = wtr.set_threads(2);
+                wtr
+            }
+            bcf::Format::Vcf => bcf::Writer::from_path("/dev/null", header, true, format)
+                .map_err(std::io::Error::other)?,
+        };
+        let text = crate::header::header_text(wtr.header()).map_err(std::io::Error::other)?;
+        let file = match format {
+            bcf::Format::Bcf => None,
+            bcf::Format::Vcf => {
+                let mut f: Box<dyn Write> = if output.ends_with(".gz") {
+                    Box::new(
+                        rust_htslib::bgzf::Writer::from_path(output)
+                            .map_err(std::io::Error::other)?,
+                    )
+                } else {
+                    Box::new(std::io::BufWriter::new(std::fs::File::create(output)?))
+                };
+                f.write_all(text.as_bytes())?;
+                Some(f)
+            }
+        };
+        let mut out = std::io::BufWriter::new(std::io::stdout());
+        out.write_all(text.as_bytes())?;
+        Ok(VcfTee { wtr, file, out })
+    }
+
+    fn write(&mut self, record: &bcf::Record) -> std::io::Result<()> {
+        let line = vcf_line(self.wtr.header(), record)?;
+        match &mut self.file {
+            Some(f) => f.write_all(&line)?,
+            None => self.wtr.write(record).map_err(std::io::Error::other)?,
+        }
+        self.out.write_all(&line)
+    }
+}
+
+/// The record as a VCF line (with the newline) using `hv`, e.g. the header of the writer it was
+/// translated to.
+pub(crate) fn vcf_line(
+    hv: &bcf::header::HeaderView,
+    record: &bcf::Record,
+) -> std::io::Result<Vec<u8>> {
+    let mut kstr = rust_htslib::htslib::kstring_t {
+        l: 0,
+        m: 0,
+        s: std::ptr::null_mut(),
+    };
+    let ret = unsafe {
+        rust_htslib::htslib::vcf_format(hv.inner, record.inner(), &mut kstr)
+    };
+    let line = unsafe {
+        let line = if kstr.s.is_null() {
+            vec![]
+        } else {
+            std::slice::from_raw_parts(kstr.s as *const u8, kstr.l).to_vec()
+        };
+        libc::free(kstr.s as *mut libc::c_void);
+        line
+    };
+    if ret != 0 {
+        return Err(std::io::Error::other("error formatting VCF record"));
+    }
+    Ok(line)
 }
 
 impl EitherWriter {
     pub fn translate(&mut self, record: &mut bcf::Record) {
-        if let EitherWriter::Vcf(ref mut w) = self {
-            w.translate(record);
+        match self {
+            EitherWriter::Vcf(ref mut w) => w.translate(record),
+            EitherWriter::VcfTee(ref mut tee) => tee.wtr.translate(record),
+            _ => {}
         }
     }
 
     pub fn write(&mut self, sob: &mut StringOrVariant) -> std::io::Result<()> {
         match sob {
             StringOrVariant::None => Ok(()),
-            StringOrVariant::Variant(None) => Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "expected VCF record got None",
-            )),
+            StringOrVariant::Variant(None) => {
+                Err(std::io::Error::other("expected VCF record got None"))
+            }
             StringOrVariant::Variant(Some(ref mut record)) => {
-                if let EitherWriter::Vcf(ref mut wtr) = self {
+                if let EitherWriter::VcfTee(ref mut tee) = self {
+                    tee.write(record)
+                } else if let EitherWriter::Vcf(ref mut wtr) = self {
                     match wtr.write(record) {
                         Ok(_) => Ok(()),
-                        Err(e) => Err(std::io::Error::new(std::io::ErrorKind::Other, e)),
+                        Err(e) => Err(std::io::Error::other(e)),
                     }
                 } else {
                     // error because we should not be writing a record to a file or stdout
-                    Err(std::io::Error::new(
-                        std::io::ErrorKind::Other,
-                        "expected VCF writer without template",
-                    ))
+                    Err(std::io::Error::other("expected VCF writer without template"))
                 }
             }
             StringOrVariant::String(s) => match self {
-                EitherWriter::Vcf(ref mut _wtr) => Err(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    "did not VCF writer with template",
-                )),
+                EitherWriter::Vcf(_) | EitherWriter::VcfTee(_) => {
+                    Err(std::io::Error::other("did not VCF writer with template"))
+                }
                 EitherWriter::File(ref mut f) => writeln!(f, "{}", s),
                 EitherWriter::Stdout(ref mut f) => writeln!(f, "{}", s),
+                EitherWriter::FileTee(ref mut f, ref mut out) => {
+                    writeln!(f, "{}", s)?;
+                    writeln!(out, "{}", s)
+                }
             },
         }
     }
 }
 
-fn get_vcf_format(path: &str) -> bcf::Format {
+pub(crate) fn get_vcf_format(path: &str) -> bcf::Format {
     if path.ends_with(".bcf") || path.ends_with(".bcf.gz") {
         bcf::Format::Bcf
     } else {
@@ -87,30 +282,194 @@ fn get_vcf_format(path: &str) -> bcf::Format {
     }
 }
 
+/// The lua code for a template: the template in backticks with a return if needed.
+pub(crate) fn template_code(template: &str) -> String {
+    // check if template contains backticks
+    let return_pre = if template.contains("return ") {
+        ""
+    } else {
+        "return "
+    };
+    // add the backticks and return if needed.
+    if template.contains('`') {
+        format!("{}{}", return_pre, template)
+    } else {
+        format!("{} `{}`", return_pre, template)
+    }
+}
+
 fn process_template(template: Option<String>, lua: &Lua) -> Option<mlua::Function<'_>> {
-    if let Some(template) = template.as_ref() {
-        // check if template contains backticks
-        let return_pre = if template.contains("return ") {
-            ""
-        } else {
-            "return "
-        };
-        // add the backticks and return if needed.
-        let expr = if template.contains('`') {
-            format!("{}{}", return_pre, template)
-        } else {
-            format!("{} `{}`", return_pre, template)
+    template.map(|template| {
+        lua.load(template_code(&template))
+            .into_function()
+            .expect("error in template")
+    })
+}
+
+/// The counts for one `-e` expression. Expressions are evaluated in order until one passes, so
+/// `decisive` is the number of variants for which it was the first to pass and later expressions
+/// are `evaluated` only for the variants that the earlier ones failed (unless every expression is
+/// evaluated, see `VCFExpress::count_per_expression`). Not counted with --per-sample or --per-allele.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ExpressionTally {
+    pub evaluated: usize,
+    pub passing: usize,
+    pub decisive: usize,
+}
+
+/// How a template renders an interpolated value that is nil.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum TemplateNil {
+    /// print "nil" (the luau default).
+    #[default]
+    Default,
+    /// print the given string instead, e.g. "." for VCF-style missing values.
+    As(String),
+    /// stop with an error naming the expression.
+    Strict,
+}
+
+/// The `stats` table: `evaluated` and `passing` counts of variants and `counters` that are
+/// incremented from lua with `stats:incr(name, n?)`.
+fn new_stats_table(lua: &Lua) -> mlua::Result<mlua::Table<'_>> {
+    let stats = lua.create_table()?;
+    stats.raw_set("evaluated", 0)?;
+    stats.raw_set("passing", 0)?;
+    stats.raw_set("counters", lua.create_table()?)?;
+    let methods = lua.create_table()?;
+    methods.raw_set(
+        "incr",
+        lua.create_function(|_lua, (this, name, n): (mlua::Table, String, Option<f64>)| {
+            let counters = this.raw_get::<_, mlua::Table>("counters")?;
+            let v = counters.raw_get::<_, Option<f64>>(name.as_str())?.unwrap_or(0.0);
+            let v = v + n.unwrap_or(1.0);
+            counters.raw_set(name, v)?;
+            Ok(v)
+        })?,
+    )?;
+    let mt = lua.create_table()?;
+    mt.raw_set("__index", methods)?;
+    stats.set_metatable(Some(mt));
+    Ok(stats)
+}
+
+/// Lines from `emit(line)`, written after `on_end()` by `VCFExpress::finish`.
+#[derive(Default)]
+struct Emitted(Vec<String>);
+
+/// Add `emit(line)` to the globals, e.g. to write a summary of `state` from `on_end()`.
+fn register_emit(lua: &Lua) -> mlua::Result<()> {
+    lua.set_app_data(Emitted::default());
+    let f = lua.create_function(|lua, line: String| {
+        if let Some(mut emitted) = lua.app_data_mut::<Emitted>() {
+            emitted.0.push(line);
+        }
+        Ok(())
+    })?;
+    lua.globals().raw_set("emit", f)
+}
+
+/// Wrap each `{expr}` in the backtick strings of `template` as `{__template_value((expr), i)}`
+/// so that nil values can be replaced or reported. Returns the new template and the expressions,
+/// where `i` is the 1-based index of the expression.
+fn wrap_template_values(template: &str) -> (String, Vec<String>) {
+    let mut out = String::with_capacity(template.len());
+    let mut exprs = vec![];
+    let mut chars = template.chars();
+    let mut in_string = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '`' => in_string = !in_string,
+            '\\' if in_string => {
+                out.push(c);
+                if let Some(n) = chars.next() {
+                    out.push(n);
+                }
+                continue;
+            }
+            '{' if in_string => {
+                let mut depth = 1;
+                let mut expr = String::new();
+                for n in chars.by_ref() {
+                    match n {
+                        '{' => depth += 1,
+                        '}' => depth -= 1,
+                        _ => (),
+                    }
+                    if depth == 0 {
+                        break;
+                    }
+                    expr.push(n);
+                }
+                exprs.push(expr.trim().to_string());
+                out.push_str(&format!("{{__template_value(({}), {})}}", expr, exprs.len()));
+                continue;
+            }
+            _ => (),
+        }
+        out.push(c);
+    }
+    (out, exprs)
+}
+
+/// Find literal tags used in `format_set('TAG', ...)` calls so we can report
+/// a missing FORMAT header line before any variants are processed.
+fn format_set_tags(expression: &str) -> Vec<&str> {
+    let mut tags = vec![];
+    let mut rest = expression;
+    while let Some(i) = rest.find("format_set(") {
+        rest = rest[i + "format_set(".len()..].trim_start();
+        let quote = match rest.chars().next() {
+            Some(q) if q == '"' || q == '\'' => q,
+            _ => continue,
         };
-        Some(lua.load(expr).into_function().expect("error in template"))
-    } else {
-        None
+        if let Some(end) = rest[1..].find(quote) {
+            tags.push(&rest[1..end + 1]);
+        }
+    }
+    tags
+}
+
+fn check_format_set_tags<'a>(
+    hv: &bcf::header::HeaderView,
+    expressions: impl Iterator<Item = &'a String>,
+) -> std::io::Result<()> {
+    for exp in expressions {
+        for tag in format_set_tags(exp) {
+            if hv.format_type(tag.as_bytes()).is_err() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("format field '{}' used in format_set not found in header. Make sure it was added to the header in prelude if needed.", tag),
+                ));
+            }
+        }
     }
+    Ok(())
+}
+
+/// Options for [`VCFExpress::with_options`] that are applied while the header and the lua code
+/// are set up. The defaults are those of [`VCFExpress::new`].
+#[derive(Default)]
+pub struct VCFExpressOptions {
+    /// tags to rename (--rename-info/--rename-format).
+    pub renames: crate::rename::Renames,
+    /// INFO fields to convert to another type (--retype-info).
+    pub retypes: Vec<(String, TagType)>,
+    pub template_nil: TemplateNil,
+    /// the VCF version of the output header, e.g. "4.3".
+    pub vcf_version: Option<String>,
+    /// FORMAT=expression to set for each sample (--set-format).
+    pub set_format: Vec<String>,
+    /// only read these samples (--samples).
+    pub samples: Vec<String>,
+    pub reference: Option<crate::reference::Reference>,
+    /// the --group-by expression.
+    pub group_by: Option<String>,
 }
 
 #[derive(Debug, PartialEq, Eq, Hash)]
 enum InfoFormat {
     Info(String),
-    #[allow(dead_code)]
     Format(String),
 }
 
@@ -139,77 +498,209 @@ impl<'lua> VCFExpress<'lua> {
         template: Option<String>,
         lua_prelude: Vec<String>,
         output: Option<String>,
-        sandbox: bool
+        sandbox: bool,
     ) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::with_options(
+            lua,
+            vcf_path,
+            expression,
+            set_expression,
+            template,
+            lua_prelude,
+            output,
+            sandbox,
+            VCFExpressOptions::default(),
+        )
+    }
+
+    /// Create a new VCFExpress object as with [`VCFExpress::new`] and the given options.
+    #[allow(clippy::too_many_arguments)]
+    pub fn with_options(
+        lua: &'lua Lua,
+        vcf_path: String,
+        expression: Vec<String>,
+        set_expression: Vec<String>,
+        template: Option<String>,
+        lua_prelude: Vec<String>,
+        output: Option<String>,
+        sandbox: bool,
+        options: VCFExpressOptions,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let VCFExpressOptions {
+            renames,
+            retypes,
+            template_nil,
+            vcf_version,
+            set_format,
+            samples,
+            reference,
+            group_by,
+        } = options;
         lua.sandbox(sandbox)?;
         lua.load(crate::pprint::PPRINT).set_name("pprint").exec()?;
         lua.load(crate::pprint::PRELUDE)
             .set_name("prelude")
             .exec()?;
 
-        let mut reader = match vcf_path.as_str() {
-            "-" | "stdin" => bcf::Reader::from_stdin()?,
-            _ => bcf::Reader::from_path(&vcf_path)?,
-        };
+        let mut reader = crate::regions::open_reader(&vcf_path)?;
         _ = reader.set_threads(2);
+        renames.add_definitions(reader.header())?;
         crate::register(lua)?;
         let globals = lua.globals();
-        let template = process_template(template, lua);
+        // ALT_FILTER is added to the header when the code sets it.
+        let mut uses_alt_filter = expression
+            .iter()
+            .chain(set_expression.iter())
+            .chain(template.iter())
+            .any(|e| e.contains("set_alt_filter"));
+        let template = match (template, &template_nil) {
+            (Some(t), TemplateNil::As(_) | TemplateNil::Strict) => {
+                let t = if t.contains('`') { t } else { format!("`{}`", t) };
+                let (t, exprs) = wrap_template_values(&t);
+                globals.raw_set(
+                    "__template_value",
+                    lua.create_function(move |lua, (v, i): (mlua::Value, usize)| {
+                        if !v.is_nil() {
+                            return Ok(v);
+                        }
+                        match &template_nil {
+                            TemplateNil::As(s) => Ok(mlua::Value::String(lua.create_string(s)?)),
+                            _ => Err(mlua::Error::runtime(format!(
+                                "template expression '{}' is nil",
+                                exprs[i - 1]
+                            ))),
+                        }
+                    })?,
+                )?;
+                process_template(Some(t), lua)
+            }
+            (t, _) => process_template(t, lua),
+        };
 
-        let exps: Vec<_> = expression
+        let exps = expression
             .iter()
-            .map(|exp| {
-                lua.load(exp)
-                    .set_name(exp)
-                    .into_function()
-                    .expect("error in expression")
-            })
-            .collect();
+            .map(|exp| crate::luau::load(lua, exp).set_name(exp).into_function())
+            .collect::<mlua::Result<Vec<_>>>()?;
 
         let mut hv = bcf::header::HeaderView::new(unsafe {
             rust_htslib::htslib::bcf_hdr_dup(reader.header().inner)
         });
+        if !samples.is_empty() {
+            crate::header::subset_samples(&mut hv, &samples)?;
+        }
 
+        let stats = new_stats_table(lua)?;
+        globals.raw_set("stats", stats.clone())?;
+        // `state` persists across records for aggregations that are reported from `on_end()`.
+        globals.raw_set("state", lua.create_table()?)?;
+        register_emit(lua)?;
         lua.scope(|scope| {
             globals.raw_set("header", scope.create_any_userdata_ref_mut(&mut hv)?)?;
             for path in lua_prelude {
                 let code = std::fs::read_to_string(&path)?;
-                lua.load(&code).set_name(path).exec()?;
+                uses_alt_filter |= code.contains("set_alt_filter");
+                crate::luau::load(lua, &code).set_name(path).exec()?;
             }
             Ok(())
         })?;
 
-        let info_exps = VCFExpress::load_info_expressions(lua, &mut hv, set_expression)?;
-
-        let header = bcf::header::Header::from_template(&hv);
-
-        let writer = if template.is_none() {
-            EitherWriter::Vcf(if let Some(output) = output {
-                let format = get_vcf_format(&output);
-                let mut wtr =
-                    bcf::Writer::from_path(&output, &header, !output.ends_with(".gz"), format)?;
-                _ = wtr.set_threads(2);
-                wtr
-            } else {
-                bcf::Writer::from_stdout(&header, true, bcf::Format::Vcf)?
-            })
-        } else if output.is_none() || output.as_ref().unwrap() == "-" {
-            EitherWriter::Stdout(std::io::BufWriter::new(std::io::stdout()))
-        } else {
-            let file = std::fs::File::create(output.unwrap())?;
-            EitherWriter::File(std::io::BufWriter::new(file))
+        // --samples or `header.samples = {...}` in the prelude. the reader can only be subset once.
+        if hv.sample_count() != reader.header().sample_count() {
+            let samples = hv
+                .samples()
+                .iter()
+                .map(|s| String::from_utf8_lossy(s).to_string())
+                .collect::<Vec<_>>();
+            crate::sample::subset_reader(&reader, &samples)?;
+        }
+        let on_progress = globals.raw_get::<_, Option<mlua::Function>>("on_progress")?;
+        let on_start = globals.raw_get::<_, Option<mlua::Function>>("on_start")?;
+        let on_end = globals.raw_get::<_, Option<mlua::Function>>("on_end")?;
+        let groups = match &group_by {
+            Some(g) => Some(crate::group::Groups::new(
+                lua.load(g).set_name("group-by").into_function()?,
+                globals.raw_get::<_, Option<mlua::Function>>("aggregate")?,
+            )),
+            None => None,
         };
+        if uses_alt_filter {
+            crate::altfilter::add_header(&mut hv)?;
+        }
+        let removed_tags = crate::header::removed_tags(reader.header(), &hv);
+        check_format_set_tags(&hv, expression.iter().chain(set_expression.iter()))?;
+        let mut info_exps = VCFExpress::load_info_expressions(lua, &mut hv, set_expression)?;
+        info_exps.extend(VCFExpress::load_format_expressions(lua, &hv, set_format)?);
+        renames.remove_old_definitions(&mut hv);
+        let retypes = crate::retype::Retypes::new(retypes, reader.header(), &mut hv)?;
+        let mut non_ref_ad_problems = None;
+        if let Some(v) = vcf_version {
+            crate::header::set_version(&hv, &v)?;
+            for p in crate::header::version_problems(&hv, &v) {
+                log::warn!("{}", p);
+            }
+            let version = crate::header::parse_version(&v).unwrap_or((4, 2));
+            // percent-encoding of INFO strings was added in VCF 4.3.
+            if version < (4, 3) {
+                lua.set_app_data(crate::percent::NoPercentEncoding);
+            }
+            if version >= (4, 4) {
+                non_ref_ad_problems = Some(0);
+            }
+        }
+
+        if let Some(reference) = reference {
+            reference.check(&mut hv)?;
+        }
 
         Ok(VCFExpress {
             lua,
+            vcf_path,
             vcf_reader: Some(reader),
             template,
-            writer: Some(writer),
+            output,
+            writer_taken: false,
+            tee: false,
+            expression_tallies: vec![ExpressionTally::default(); exps.len()],
+            evaluate_all_expressions: false,
+            count_only: false,
+            alt_filter: None,
+            soft_filter: None,
+            skip: 0,
+            max_records: None,
+            records_read: 0,
+            truncated: false,
+            non_ref_ad_problems,
+            file_offset: None,
             expressions: exps,
             set_expressions: info_exps,
             globals,
+            header: hv,
+            renames,
+            retypes,
             variants_evaluated: 0,
             variants_passing: 0,
+            per_sample: false,
+            per_allele: false,
+            on_progress,
+            on_start,
+            on_end,
+            progress_every: 100_000,
+            stats,
+            baseline: None,
+            callsets: None,
+            targets: None,
+            exclude_bed: Default::default(),
+            subsample: None,
+            first_pass_kept: None,
+            removed_tags,
+            contig_rids: vec![],
+            rounding: crate::round::Rounding::default(),
+            prune_empty: false,
+            one_based_template_pos: false,
+            summary_output: None,
+            groups,
+            density: None,
+            burden: None,
         })
     }
 
@@ -228,6 +719,18 @@ impl<'lua> VCFExpress<'lua> {
         HashMap<InfoFormat, ((TagType, TagLength), mlua::Function<'lua>)>,
         Box<dyn std::error::Error>,
     > {
+        // SORTKEY is added to the header for use with the sort subcommand.
+        if info_expressions
+            .iter()
+            .any(|e| e.split_once('=').map(|e| e.0) == Some(crate::sort::SORTKEY))
+            && hv.info_type(crate::sort::SORTKEY.as_bytes()).is_err()
+        {
+            let line = std::ffi::CString::new(crate::sort::SORTKEY_HEADER)?;
+            unsafe {
+                rust_htslib::htslib::bcf_hdr_append(hv.inner, line.as_ptr());
+                rust_htslib::htslib::bcf_hdr_sync(hv.inner);
+            }
+        }
         let info_exps: HashMap<_, _> = info_expressions
             .iter()
             .map(|exp| {
@@ -241,7 +744,7 @@ impl<'lua> VCFExpress<'lua> {
                     InfoFormat::Info(name_exp.0.to_string()),
                     (
                         t,
-                        lua.load(name_exp.1)
+                        crate::luau::load(lua, name_exp.1)
                             .set_name(exp)
                             .into_function()
                             .unwrap_or_else(|_| panic!("error in expression: {}", exp)),
@@ -266,25 +769,592 @@ impl<'lua> VCFExpress<'lua> {
         Ok(())
     }
 
+    /// Load an allele-count index built with `build-ac-index`. It is available
+    /// to expressions and the template as `acindex`.
+    pub fn load_ac_index(&mut self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let idx = crate::acindex::AcIndex::from_path(path)?;
+        self.globals.raw_set("acindex", idx)?;
+        Ok(())
+    }
+
+    /// Load a sample-major cache built with `build-sample-cache` so that `variant:sample()`
+    /// reads only the requested sample. The cache is ignored if it was built from a different file.
+    pub fn load_sample_cache(&mut self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(mut cache) = crate::samplecache::SampleCache::open(path, &self.vcf_path)? {
+            cache.map_samples(&self.header.samples())?;
+            self.lua.set_app_data(cache);
+        }
+        Ok(())
+    }
+
+    /// Find records in the sample cache by position rather than by their place in the input,
+    /// for records read from --region(s).
+    pub fn index_sample_cache(&self) {
+        if let Some(mut cache) = self.lua.app_data_mut::<crate::samplecache::SampleCache>() {
+            cache.index_records();
+        }
+    }
+
+    /// Load per-sample depth files given as `SAMPLE=path` or a path whose name starts with the
+    /// sample (e.g. mosdepth `NA12878.regions.bed.gz`). Available to expressions as `coverage`.
+    pub fn load_coverage(&mut self, args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+        let mut coverage = crate::coverage::Coverage::default();
+        for arg in args {
+            let (mut sample, path) = crate::coverage::parse_coverage_arg(arg);
+            if let Some(aliases) = self.lua.app_data_ref::<crate::alias::SampleAliases>() {
+                sample = aliases.resolve(&sample).to_string();
+            }
+            if !self.header.samples().contains(&sample.as_bytes()) {
+                log::warn!("coverage sample {} from {} is not in the VCF", sample, path);
+            }
+            coverage.add_path(&sample, &path)?;
+        }
+        self.globals.raw_set("coverage", coverage)?;
+        Ok(())
+    }
+
+    /// Load a previous output of filter to compare against. Expressions and the template can use
+    /// `baseline.status` ("new" or "unchanged") and `baseline_summary` gives the counts at the end.
+    pub fn load_baseline(&mut self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.baseline = Some(crate::baseline::Baseline::from_path(path)?);
+        self.globals
+            .raw_set("baseline", self.lua.create_table()?)?;
+        Ok(())
+    }
+
+    /// Only evaluate records with an ALT in every (or with `any`, any) `isec` callset and in none of
+    /// the `exclude` callsets. See [`VCFExpress::keep_record`].
+    pub fn load_callsets(
+        &mut self,
+        isec: &[String],
+        any: bool,
+        exclude: &[String],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let callsets = crate::isec::Callsets::new(isec, any, exclude)?;
+        if !callsets.is_empty() {
+            self.callsets = Some(callsets);
+        }
+        Ok(())
+    }
+
+    /// Only evaluate records that overlap an interval of the sorted BED file `path`, which is
+    /// read alongside the input. See [`VCFExpress::keep_record`].
+    pub fn load_targets(&mut self, path: &str) -> std::io::Result<()> {
+        self.targets = Some(crate::targets::Targets::new(path)?);
+        Ok(())
+    }
+
+    /// Skip records that overlap an interval of any of the BED files in `paths`. See
+    /// [`VCFExpress::keep_record`].
+    pub fn load_exclude_bed(&mut self, paths: &[String]) -> std::io::Result<()> {
+        self.exclude_bed = crate::exclude::ExcludeBed::new(paths)?;
+        Ok(())
+    }
+
+    /// Evaluate only a random `fraction` of the records, chosen reproducibly from `seed`. See
+    /// [`VCFExpress::keep_record`].
+    pub fn set_sample_fraction(&mut self, fraction: f64, seed: u64) -> std::io::Result<()> {
+        self.subsample = Some(crate::subsample::Subsample::new(fraction, seed)?);
+        Ok(())
+    }
+
+    /// False for a record that should be skipped without being evaluated or written. After
+    /// [`VCFExpress::first_pass`], this keeps the records that the first pass kept.
+    pub fn keep_record(&mut self, record: &bcf::Record) -> std::io::Result<bool> {
+        if let Some(kept) = &self.first_pass_kept {
+            let i = self.records_read.checked_sub(1);
+            return Ok(i.and_then(|i| kept.get(i)).copied().unwrap_or(false));
+        }
+        // first so that the records sampled do not depend on the other filters.
+        if let Some(subsample) = &mut self.subsample {
+            if !subsample.keep() {
+                return Ok(false);
+            }
+        }
+        if let Some(targets) = &mut self.targets {
+            if !targets.keep(record)? {
+                return Ok(false);
+            }
+        }
+        if !self.exclude_bed.is_empty() && !self.exclude_bed.keep(record)? {
+            return Ok(false);
+        }
+        match &mut self.callsets {
+            Some(callsets) => callsets.keep(record),
+            None => Ok(true),
+        }
+    }
+
+    /// Read only a slice of the input: skip the first `skip` records and stop after `max_records`
+    /// more. The first pass of --two-pass reads the same slice.
+    pub fn set_record_slice(&mut self, skip: usize, max_records: Option<usize>) {
+        self.skip = skip;
+        self.max_records = max_records;
+    }
+
+    /// Set the BGZF virtual offset (see [`BgzfOffset`]) of the next record given to
+    /// [`VCFExpress::evaluate`], available to lua as `variant.file_offset`.
+    pub fn set_file_offset(&mut self, offset: Option<u64>) {
+        self.file_offset = offset;
+    }
+
+    /// Call for each record read: Some(true) if it is in the slice set with `set_record_slice`,
+    /// Some(false) if it is to be skipped and None once the slice is done to stop reading.
+    pub fn in_slice(&mut self) -> Option<bool> {
+        if self
+            .max_records
+            .is_some_and(|n| self.records_read >= self.skip.saturating_add(n))
+        {
+            self.truncated = true;
+            return None;
+        }
+        self.records_read += 1;
+        Some(self.records_read > self.skip)
+    }
+
+    /// The number of records skipped with --skip and whether records after the --max-records
+    /// were left unread.
+    pub fn record_slice(&self) -> (usize, bool) {
+        (self.records_read.min(self.skip), self.truncated)
+    }
+
+    /// The number of records skipped by [`VCFExpress::keep_record`].
+    pub fn skipped_records(&self) -> usize {
+        self.targets.as_ref().map_or(0, |t| t.dropped())
+            + self.exclude_bed.dropped()
+            + self.subsample.as_ref().map_or(0, |s| s.dropped())
+            + self.callsets.as_ref().map_or(0, |c| c.dropped())
+    }
+
+    /// The number of (new, unchanged, dropped) sites compared to the baseline, if one was loaded.
+    pub fn baseline_summary(&self) -> Option<(usize, usize, usize)> {
+        self.baseline.as_ref().map(|b| b.summary())
+    }
+
+    /// Round Float FORMAT fields to the given number of decimal places in each record written.
+    pub fn set_round_format(&mut self, tags: Vec<(String, i32)>) -> std::io::Result<()> {
+        self.rounding = crate::round::Rounding::new(tags, &self.header)?;
+        Ok(())
+    }
+
+    /// Remove INFO fields whose values are all missing and FORMAT fields (except GT) that are
+    /// missing for every sample from each record written.
+    pub fn set_prune_empty(&mut self, prune_empty: bool) {
+        self.prune_empty = prune_empty;
+    }
+
+    /// Allow this many `assert_variant` failures before the run stops with an error.
+    pub fn set_max_assert_failures(&mut self, n: usize) {
+        self.lua.set_app_data(crate::assertion::Assertions {
+            failures: 0,
+            max_failures: n,
+        });
+    }
+
+    /// The number of variants (evaluated, passing) so far.
+    pub fn counts(&self) -> (usize, usize) {
+        (self.variants_evaluated, self.variants_passing)
+    }
+
+    /// Count the variants passing each expression. Every expression is then evaluated for each
+    /// variant rather than stopping at the first that passes.
+    pub fn count_per_expression(&mut self) {
+        self.evaluate_all_expressions = true;
+    }
+
+    /// Write only the number of evaluated and passing variants (and those passing each
+    /// expression if counted) as `name count` lines instead of the variants.
+    pub fn set_count(&mut self, count: bool) {
+        self.count_only = count;
+    }
+
+    /// Translate the ALT_FILTER INFO field of each written record to the record FILTER (added when
+    /// every ALT failed, e.g. after --per-allele removed the others) or add the record FILTER to
+    /// ALT_FILTER. Call this before `writer` as ALT_FILTER may be added to the header.
+    pub fn set_alt_filter_translation(
+        &mut self,
+        direction: Option<crate::altfilter::AltFilterTranslation>,
+    ) -> std::io::Result<()> {
+        match direction {
+            Some(crate::altfilter::AltFilterTranslation::ToFilter)
+                if self
+                    .header
+                    .info_type(crate::altfilter::ALT_FILTER.as_bytes())
+                    .is_err() =>
+            {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "--alt-filter-to-filter: ALT_FILTER is not in the header",
+                ));
+            }
+            Some(crate::altfilter::AltFilterTranslation::FromFilter) => {
+                crate::altfilter::add_header(&mut self.header)?
+            }
+            _ => {}
+        }
+        self.alt_filter = direction;
+        Ok(())
+    }
+
+    /// Write failing records with their FILTER set to `name` instead of dropping them, and set
+    /// PASS on passing records without a FILTER, as `bcftools filter -s`. With `append`, `name`
+    /// is added to the FILTER of failing records instead (as `bcftools filter -m+ -s`). The
+    /// FILTER is added to the header with `description`, so call this before `writer`.
+    pub fn set_soft_filter(
+        &mut self,
+        name: &str,
+        description: &str,
+        append: bool,
+    ) -> std::io::Result<()> {
+        if name.is_empty()
+            || name == "PASS"
+            || name.contains(|c: char| c.is_whitespace() || c == ';' || c == ',')
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("--soft-filter: invalid FILTER name '{}'", name),
+            ));
+        }
+        if self.template.is_some() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "--soft-filter can not be used with a template",
+            ));
+        }
+        crate::header::add_filter(&mut self.header, name, description)?;
+        self.soft_filter = Some((name.to_string(), append));
+        Ok(())
+    }
+
+    /// The number of variants passing each expression, if every expression was evaluated.
+    pub fn expression_counts(&self) -> Option<Vec<usize>> {
+        self.evaluate_all_expressions
+            .then(|| self.expression_tallies.iter().map(|t| t.passing).collect())
+    }
+
+    /// The evaluated, passing and decisive counts of each expression.
+    pub fn expression_tallies(&self) -> &[ExpressionTally] {
+        &self.expression_tallies
+    }
+
+    /// The number of `assert_variant` failures so far.
+    pub fn assert_failures(&self) -> usize {
+        self.lua
+            .app_data_ref::<crate::assertion::Assertions>()
+            .map(|a| a.failures)
+            .unwrap_or(0)
+    }
+
+    /// Use sample aliases so that expressions can refer to samples by another name. This must be
+    /// called before `load_pedigree`, `load_roh` and `load_coverage` so that aliases in those files
+    /// are resolved too.
+    pub fn set_sample_aliases(&mut self, aliases: crate::alias::SampleAliases) {
+        self.lua.set_app_data(aliases);
+    }
+
+    /// Load a PED file for pedigree-aware functions such as `variant:transmitted_allele(child)`.
+    pub fn load_pedigree(&mut self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let mut ped = crate::pedigree::Pedigree::from_path(path)?;
+        if let Some(aliases) = self.lua.app_data_ref::<crate::alias::SampleAliases>() {
+            ped.resolve_aliases(&aliases);
+        }
+        self.lua.set_app_data(ped);
+        Ok(())
+    }
+
+    /// Load runs of homozygosity from a BED file (optional 4th column: sample) for `sample:in_roh(variant)`.
+    pub fn load_roh(&mut self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let mut roh = crate::roh::Roh::from_path(path)?;
+        if let Some(aliases) = self.lua.app_data_ref::<crate::alias::SampleAliases>() {
+            roh.resolve_aliases(&aliases);
+        }
+        self.lua.set_app_data(roh);
+        Ok(())
+    }
+
     /// Take ownership of the the bcf::Reader object.
     /// This must be called before using `evaluate`
     pub fn reader(&mut self) -> bcf::Reader {
         self.vcf_reader.take().expect("reader already taken")
     }
 
+    /// The output header, after any changes made in the prelude.
+    pub fn header(&self) -> &bcf::header::HeaderView {
+        &self.header
+    }
+
+    /// Tags to rename (from --rename-info/--rename-format). Any other reader must have
+    /// the new definitions added to its header with [`crate::rename::Renames::add_definitions`].
+    pub fn renames(&self) -> &crate::rename::Renames {
+        &self.renames
+    }
+
+    /// Move values from renamed tags to their new names. This must be called before the
+    /// record is translated to the output header.
+    pub fn rename_tags(&self, record: &mut bcf::Record) -> std::io::Result<()> {
+        self.renames.apply(record).map_err(std::io::Error::other)
+    }
+
+    /// Rename tags and translate the record to the output header of `writer`, converting
+    /// the values of any INFO fields given to --retype-info and dropping those of tags removed
+    /// from the header in the prelude. Call this before `evaluate`.
+    pub fn translate(
+        &self,
+        writer: &mut EitherWriter,
+        record: &mut bcf::Record,
+    ) -> std::io::Result<()> {
+        self.rename_tags(record)?;
+        if let EitherWriter::Vcf(_) | EitherWriter::VcfTee(_) = writer {
+            let rid = record.rid();
+            crate::header::strip_tags(record, &self.removed_tags);
+            self.retypes.translate(record, |r| writer.translate(r))?;
+            if let (Some(rid), false) = (rid, self.contig_rids.is_empty()) {
+                record.set_rid(self.contig_rids.get(rid as usize).copied().flatten());
+            }
+        }
+        Ok(())
+    }
+
+    /// Rename contigs (old -> new, as from `--rename-chrs`) in the output header and set CHROM
+    /// of each record to the new name when it is translated, so `variant.chrom` in expressions is
+    /// the new name. Call this before the reader is taken.
+    pub fn rename_contigs(&mut self, renames: &HashMap<String, String>) -> std::io::Result<()> {
+        if crate::header::rename_contigs(&mut self.header, renames)? == 0 {
+            log::warn!("none of the contigs to rename are in the header");
+        }
+        let input = self
+            .vcf_reader
+            .as_ref()
+            .expect("reader already taken")
+            .header();
+        for (name, _) in crate::header::contigs(input) {
+            let Ok(rid) = input.name2rid(name.as_bytes()) else {
+                continue;
+            };
+            let new = renames.get(&name).unwrap_or(&name);
+            if self.contig_rids.len() <= rid as usize {
+                self.contig_rids.resize(rid as usize + 1, None);
+            }
+            self.contig_rids[rid as usize] = self.header.name2rid(new.as_bytes()).ok();
+        }
+        Ok(())
+    }
+
+    /// Do not count `*` and symbolic `<NON_REF>`/`<*>` alleles in `variant:aaf()` or the AN and AF
+    /// from `variant:update_allele_counts()`, and do not evaluate them with --per-allele.
+    pub fn skip_symbolic_alleles(&mut self) {
+        self.lua.set_app_data(crate::allele::SkipSymbolicAlleles);
+    }
+
+    /// Return nil from `variant:sample(name)` (and skip the name in `variant:samples`) for
+    /// a sample that is not in the VCF instead of raising an error.
+    pub fn lenient_samples(&mut self) {
+        self.lua.set_app_data(crate::sample::LenientSamples);
+    }
+
+    /// Turn off percent decoding of INFO strings read into lua and encoding of those written
+    /// back, e.g. for legacy files with a literal `%` in values.
+    pub fn disable_percent_encoding(&mut self) {
+        self.lua.set_app_data(crate::percent::NoPercentEncoding);
+    }
+
+    /// Make `variant.pos` the 1-based position (as in the VCF) when a template is evaluated.
+    /// `variant.start` and `variant.POS` are always 0-based and 1-based.
+    pub fn set_one_based_template_pos(&mut self, one_based: bool) {
+        self.one_based_template_pos = one_based;
+    }
+
+    fn render(&self, template: &mlua::Function<'lua>) -> mlua::Result<String> {
+        if !self.one_based_template_pos {
+            return template.call(());
+        }
+        self.lua.set_app_data(crate::variant::OneBasedTemplatePos);
+        let result = template.call(());
+        self.lua
+            .remove_app_data::<crate::variant::OneBasedTemplatePos>();
+        result
+    }
+
+    /// Write the lines from `emit(line)` to `path` instead of the main output.
+    pub fn set_summary_output(&mut self, path: Option<String>) {
+        self.summary_output = path;
+    }
+
+    /// Columns for `--group-by` e.g. count and mean:DP. Ignored without `--group-by`.
+    pub fn set_group_reducers(
+        &mut self,
+        reducers: Vec<crate::group::Reducer>,
+    ) -> std::io::Result<()> {
+        match &mut self.groups {
+            Some(groups) => groups.set_reducers(reducers, &self.header),
+            None => Ok(()),
+        }
+    }
+
+    /// Evaluate the expressions and template once for each (variant, sample) pair with `sample`
+    /// (as from `variant:sample`) available. With a template, a line is written for each passing
+    /// sample; otherwise the record is written if any sample passes.
+    pub fn set_per_sample(&mut self, per_sample: bool) {
+        self.per_sample = per_sample;
+    }
+
+    /// Evaluate the expressions and template once for each ALT allele with `allele`, `allele_index`
+    /// and `allele_info` (Number=A and Number=R INFO values for the allele, and FORMAT values in
+    /// `allele_info.format`) available. With a template,
+    /// a line is written for each passing allele; otherwise alleles for which no expression passes
+    /// are removed from the record and it is written if any allele passes.
+    pub fn set_per_allele(&mut self, per_allele: bool) {
+        self.per_allele = per_allele;
+    }
+
+    /// Call `on_progress(n, locus)`, if it is defined in the prelude, every `n` records.
+    pub fn set_progress_every(&mut self, n: usize) {
+        self.progress_every = n.max(1);
+    }
+
+    /// Count passing variants in genomic windows and write a `chrom start end count` row for each
+    /// window instead of the variants.
+    pub fn set_window_density(&mut self, density: Option<crate::density::WindowDensity>) {
+        self.density = density;
+    }
+
+    /// Write a gene x sample burden matrix instead of the variants. `genes` is a lua expression
+    /// that returns the gene(s) of the current variant or nil; the expressions select the
+    /// qualifying variants.
+    pub fn set_burden(&mut self, genes: Option<String>) -> std::io::Result<()> {
+        let Some(genes) = genes else {
+            self.burden = None;
+            return Ok(());
+        };
+        if self.header.format_type(b"GT").is_err() {
+            return Err(std::io::Error::other(
+                "--burden requires the FORMAT GT field in the header",
+            ));
+        }
+        let f = self
+            .lua
+            .load(&genes)
+            .set_name("burden")
+            .into_function()
+            .map_err(std::io::Error::other)?;
+        let samples = self
+            .header
+            .samples()
+            .iter()
+            .map(|s| String::from_utf8_lossy(s).to_string())
+            .collect();
+        self.burden = Some(crate::burden::Burden::new(f, samples));
+        Ok(())
+    }
+
+    /// Also write the output to stdout when it is written to a file (`--tee`).
+    pub fn set_tee(&mut self, tee: bool) {
+        self.tee = tee;
+    }
+
     /// Take ownership of the the Writer enum.
     /// This must be called before using `evaluate`
-    pub fn writer(&mut self) -> EitherWriter {
-        self.writer.take().expect("writer already taken")
+    /// The writer is text with a template, `--group-by`, window density, `--burden` or `--count`,
+    /// otherwise VCF/BCF with the output header (which is written now).
+    pub fn writer(&mut self) -> std::io::Result<EitherWriter> {
+        assert!(!self.writer_taken, "writer already taken");
+        self.writer_taken = true;
+        let output = self.output.take();
+        let tee = self.tee && output.as_deref().is_some_and(|o| o != "-");
+        if self.template.is_some()
+            || self.groups.is_some()
+            || self.density.is_some()
+            || self.burden.is_some()
+            || self.count_only
+        {
+            return Ok(match output {
+                Some(o) if o != "-" => {
+                    let f = std::io::BufWriter::new(std::fs::File::create(o)?);
+                    if tee {
+                        EitherWriter::FileTee(f, std::io::BufWriter::new(std::io::stdout()))
+                    } else {
+                        EitherWriter::File(f)
+                    }
+                }
+                _ => EitherWriter::Stdout(std::io::BufWriter::new(std::io::stdout())),
+            });
+        }
+        let header = bcf::header::Header::from_template(&self.header);
+        if let (true, Some(output)) = (tee, &output) {
+            return Ok(EitherWriter::VcfTee(VcfTee::new(output, &header)?));
+        }
+        let writer = match output {
+            Some(output) => {
+                let format = get_vcf_format(&output);
+                let mut wtr =
+                    bcf::Writer::from_path(&output, &header, !output.ends_with(".gz"), format)
+                        .map_err(std::io::Error::other)?;
+                _ = wtr.set_threads(2);
+                wtr
+            }
+            None => bcf::Writer::from_stdout(&header, true, bcf::Format::Vcf)
+                .map_err(std::io::Error::other)?,
+        };
+        Ok(EitherWriter::Vcf(writer))
     }
 
     // this is called from in the scope and lets us evaluate the info expressions.
     // we collect the results to be used outside the scope where we can get a mutable variant.
+    /// Load `--set-format` expressions. The FORMAT field must be in the header.
+    #[allow(clippy::type_complexity)]
+    fn load_format_expressions(
+        lua: &'lua Lua,
+        hv: &bcf::header::HeaderView,
+        format_expressions: Vec<String>,
+    ) -> Result<
+        Vec<(InfoFormat, ((TagType, TagLength), mlua::Function<'lua>))>,
+        Box<dyn std::error::Error>,
+    > {
+        format_expressions
+            .iter()
+            .map(|exp| {
+                let (name, code) = exp.split_once('=').ok_or_else(|| {
+                    format!("invalid format expression '{}' should have name=$expression", exp)
+                })?;
+                let t = hv.format_type(name.as_bytes()).map_err(|_| {
+                    format!("format field '{}' not found. Make sure it was added to the header in prelude if needed.", name)
+                })?;
+                if name == "GT" {
+                    return Err("GT can not be set with --set-format; use variant.genotypes".into());
+                }
+                let f = lua.load(code).set_name(exp).into_function()?;
+                Ok((InfoFormat::Format(name.to_string()), (t, f)))
+            })
+            .collect()
+    }
+
+    // called in the scope with one sample table per sample. Each --set-format expression
+    // is called once per sample with that table as the `sample` global.
+    fn evaluate_format_expressions(
+        &self,
+        samples: &[mlua::Table<'lua>],
+        format_results: &mut Vec<(String, Vec<mlua::Value<'lua>>)>,
+    ) -> mlua::Result<()> {
+        for (inf, (_, expr)) in self.set_expressions.iter() {
+            if let InfoFormat::Format(tag) = inf {
+                let mut values = Vec::with_capacity(samples.len());
+                for sample in samples {
+                    self.globals.raw_set("sample", sample.clone())?;
+                    values.push(expr.call::<_, mlua::Value>(())?);
+                }
+                self.globals.raw_set("sample", mlua::Value::Nil)?;
+                format_results.push((tag.clone(), values));
+            }
+        }
+        Ok(())
+    }
+
     fn evaluate_info_expressions(
         &self,
         info_results: &mut HashMap<String, InfoFormatValue>,
     ) -> mlua::Result<()> {
-        for (inf, ((tagtyp, _taglen), expr)) in self.set_expressions.iter() {
+        let encode = crate::percent::enabled(self.lua);
+        for (inf, ((tagtyp, taglen), expr)) in self.set_expressions.iter() {
             if let InfoFormat::Info(tag) = inf {
                 let t = match tagtyp {
                     TagType::Flag => {
@@ -300,7 +1370,14 @@ impl<'lua> VCFExpress<'lua> {
                         InfoFormatValue::Integer(i)
                     }
                     TagType::String => {
-                        let s = expr.call::<_, String>(())?;
+                        let mut s = expr.call::<_, String>(())?;
+                        if encode {
+                            // commas separate values unless the field holds a single string.
+                            let keep_commas = !matches!(taglen, TagLength::Fixed(1));
+                            if let Cow::Owned(e) = crate::percent::encode(s.as_bytes(), keep_commas) {
+                                s = String::from_utf8(e).expect("encoded string is valid utf8");
+                            }
+                        }
                         InfoFormatValue::String(s)
                     }
                 };
@@ -310,51 +1387,391 @@ impl<'lua> VCFExpress<'lua> {
         Ok(())
     }
 
+    /// Read the input once before filtering (`--two-pass`) and set the `globals_stats` global with the
+    /// number of records, `mean_depth` per sample and `qual_quantile(q)`. If the prelude defines
+    /// `first_pass(variant)`, it is called for each record (as read, without renames or set
+    /// expressions) and can add its own fields to `globals_stats`. `next` reads the records as
+    /// the second pass will (e.g. only the --region(s)) and only the records in the slice and kept
+    /// by [`VCFExpress::keep_record`] are counted. Call this before `start`.
+    pub fn first_pass<F>(&mut self, mut next: F) -> std::io::Result<()>
+    where
+        F: FnMut() -> Option<rust_htslib::errors::Result<bcf::Record>>,
+    {
+        if matches!(self.vcf_path.as_str(), "-" | "stdin") {
+            return Err(std::io::Error::other(
+                "--two-pass requires an input file; stdin can not be read twice",
+            ));
+        }
+        let table = self.lua.create_table().map_err(std::io::Error::other)?;
+        self.globals
+            .raw_set("globals_stats", table.clone())
+            .map_err(std::io::Error::other)?;
+        let hook = self
+            .globals
+            .raw_get::<_, Option<mlua::Function>>("first_pass")
+            .map_err(std::io::Error::other)?;
+        let mut samples = vec![];
+        let mut stats = crate::twopass::GlobalStats::default();
+        let header_map = HeaderMap::new();
+        let mut kept = vec![];
+        while let Some(record) = next() {
+            let record = record.map_err(std::io::Error::other)?;
+            let Some(in_slice) = self.in_slice() else {
+                break;
+            };
+            let keep = in_slice && self.keep_record(&record)?;
+            kept.push(keep);
+            if !keep {
+                continue;
+            }
+            if samples.is_empty() {
+                samples = record
+                    .header()
+                    .samples()
+                    .iter()
+                    .map(|s| String::from_utf8_lossy(s).to_string())
+                    .collect();
+            }
+            stats.add(&record);
+            if let Some(f) = &hook {
+                let mut variant = Variant::new(record, header_map.clone());
+                self.lua
+                    .scope(|scope| {
+                        f.call::<_, ()>(scope.create_any_userdata_ref_mut(&mut variant)?)
+                    })
+                    .map_err(std::io::Error::other)?;
+            }
+        }
+        // the second pass reads the same records again.
+        self.records_read = 0;
+        self.truncated = false;
+        self.first_pass_kept = Some(kept);
+        stats
+            .set_table(self.lua, &table, &samples)
+            .map_err(std::io::Error::other)
+    }
+
+    /// Call `on_start(header)` if it is defined in the prelude. Call this before the first record.
+    pub fn start(&self) -> mlua::Result<()> {
+        let Some(f) = &self.on_start else {
+            return Ok(());
+        };
+        self.lua.scope(|scope| {
+            let header = scope.create_any_userdata_ref(&self.header)?;
+            f.call::<_, ()>(header)
+        })
+    }
+
+    /// Call `on_end()` if it is defined in the prelude, then write the lines from `emit(line)` to
+    /// the `--summary-output` path if set, otherwise to the template output. With VCF/BCF output
+    /// they go to stderr so the records are not corrupted. Call this after the last record.
+    pub fn finish(&mut self, writer: &mut EitherWriter) -> std::io::Result<()> {
+        if let Some(n) = self.non_ref_ad_problems.filter(|&n| n > 1) {
+            log::warn!(
+                "{} records have a <*> or <NON_REF> allele without an AD value for each allele",
+                n
+            );
+        }
+        if let Some(density) = &mut self.density {
+            for row in density.flush() {
+                writer.write(&mut StringOrVariant::String(row))?;
+            }
+        }
+        if let Some(groups) = &mut self.groups {
+            let rows = groups.rows(self.lua).map_err(std::io::Error::other)?;
+            for row in rows {
+                writer.write(&mut StringOrVariant::String(row))?;
+            }
+        }
+        if let Some(burden) = &self.burden {
+            for row in burden.rows() {
+                writer.write(&mut StringOrVariant::String(row))?;
+            }
+        }
+        if self.count_only {
+            let mut rows = vec![
+                format!("evaluated\t{}", self.variants_evaluated),
+                format!("passing\t{}", self.variants_passing),
+            ];
+            for (i, n) in self.expression_counts().into_iter().flatten().enumerate() {
+                rows.push(format!("passing:{}\t{}", i + 1, n));
+            }
+            for row in rows {
+                writer.write(&mut StringOrVariant::String(row))?;
+            }
+        }
+        if let Some(f) = &self.on_end {
+            f.call::<_, ()>(()).map_err(std::io::Error::other)?;
+        }
+        let lines = self
+            .lua
+            .app_data_mut::<Emitted>()
+            .map(|mut e| std::mem::take(&mut e.0))
+            .unwrap_or_default();
+        if let Some(path) = &self.summary_output {
+            let mut f = std::io::BufWriter::new(std::fs::File::create(path)?);
+            for line in &lines {
+                writeln!(f, "{}", line)?;
+            }
+            return f.flush();
+        }
+        for line in lines {
+            match writer {
+                EitherWriter::Vcf(_) | EitherWriter::VcfTee(..) => eprintln!("{}", line),
+                EitherWriter::File(f) => writeln!(f, "{}", line)?,
+                EitherWriter::Stdout(f) => writeln!(f, "{}", line)?,
+                EitherWriter::FileTee(f, out) => {
+                    writeln!(f, "{}", line)?;
+                    writeln!(out, "{}", line)?
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // call the on_progress hook with the number of records evaluated and the current locus as chrom:pos (1-based).
+    fn progress(&self, record: &bcf::Record) -> std::io::Result<()> {
+        let Some(f) = &self.on_progress else {
+            return Ok(());
+        };
+        let chrom = record
+            .rid()
+            .and_then(|rid| record.header().rid2name(rid).ok())
+            .unwrap_or_default();
+        let locus = format!("{}:{}", String::from_utf8_lossy(chrom), record.pos() + 1);
+        f.call::<_, ()>((self.variants_evaluated, locus))
+            .map_err(std::io::Error::other)
+    }
+
+    // called in the scope for --per-sample. The template output of all passing samples is
+    // returned as a single string with one line per sample.
+    fn evaluate_per_sample(
+        &self,
+        samples: &[mlua::Table<'lua>],
+    ) -> mlua::Result<StringOrVariant> {
+        let mut lines = vec![];
+        let mut passed = false;
+        for sample in samples {
+            self.globals.raw_set("sample", sample.clone())?;
+            let mut pass = false;
+            for exp in &self.expressions {
+                if exp.call::<_, bool>(())? {
+                    pass = true;
+                    break;
+                }
+            }
+            if !pass {
+                continue;
+            }
+            passed = true;
+            match &self.template {
+                Some(template) => lines.push(self.render(template).inspect_err(|e| {
+                    log::error!("Error in template: {}", e);
+                })?),
+                None => break,
+            }
+        }
+        self.globals.raw_set("sample", mlua::Value::Nil)?;
+        Ok(match (passed, &self.template) {
+            (false, _) => StringOrVariant::None,
+            (true, Some(_)) => StringOrVariant::String(lines.join("\n")),
+            (true, None) => StringOrVariant::Variant(None),
+        })
+    }
+
+    // called in the scope for --per-allele. `keep` is set to whether any expression passed
+    // for each ALT.
+    fn evaluate_per_allele(
+        &self,
+        alleles: &[(Vec<u8>, mlua::Table<'lua>)],
+        keep: &mut Vec<bool>,
+    ) -> mlua::Result<StringOrVariant> {
+        let mut lines = vec![];
+        let mut passed = false;
+        let skip_symbolic = crate::allele::skip_symbolic(self.lua);
+        for (i, (allele, info)) in alleles.iter().enumerate() {
+            // skipped alleles are kept in the record.
+            if skip_symbolic && crate::allele::is_symbolic(allele) {
+                keep.push(true);
+                continue;
+            }
+            self.globals
+                .raw_set("allele", self.lua.create_string(allele)?)?;
+            self.globals.raw_set("allele_index", i + 1)?;
+            self.globals.raw_set("allele_info", info.clone())?;
+            let mut pass = false;
+            for exp in &self.expressions {
+                if exp.call::<_, bool>(())? {
+                    pass = true;
+                    break;
+                }
+            }
+            keep.push(pass);
+            passed |= pass;
+            if let (true, Some(template)) = (pass, &self.template) {
+                lines.push(self.render(template).inspect_err(|e| {
+                    log::error!("Error in template: {}", e);
+                })?);
+            }
+        }
+        for name in ["allele", "allele_index", "allele_info"] {
+            self.globals.raw_set(name, mlua::Value::Nil)?;
+        }
+        Ok(match (passed, &self.template) {
+            (false, _) => StringOrVariant::None,
+            (true, Some(_)) => StringOrVariant::String(lines.join("\n")),
+            (true, None) => StringOrVariant::Variant(None),
+        })
+    }
+
     /// Evaluate the expressions and optional template for a single record.
     pub fn evaluate(
         &mut self,
         record: bcf::Record,
         header_map: HeaderMap,
     ) -> std::io::Result<StringOrVariant> {
-        let mut variant = Variant::new(record, header_map);
+        // the record's place in the input. records dropped by --skip or keep_record are counted
+        // by in_slice so this matches the sample cache; without in_slice every record is evaluated.
+        let index = self
+            .records_read
+            .checked_sub(1)
+            .unwrap_or(self.variants_evaluated);
+        if let Some(cache) = self.lua.app_data_ref::<crate::samplecache::SampleCache>() {
+            cache.set_current(index, &record);
+        }
+        let mut variant = Variant::new(record, header_map.clone());
+        variant.set_index(index);
+        variant.set_file_offset(self.file_offset.take());
         self.variants_evaluated += 1;
+        self.stats
+            .raw_set("evaluated", self.variants_evaluated)
+            .map_err(std::io::Error::other)?;
+        if self.variants_evaluated.is_multiple_of(self.progress_every) {
+            self.progress(variant.record())?;
+        }
+        if let Some(baseline) = &self.baseline {
+            let t = self.globals.raw_get::<_, mlua::Table>("baseline");
+            t.and_then(|t| t.raw_set("status", baseline.status(variant.record())))
+                .map_err(std::io::Error::other)?;
+        }
+        if let Some(n) = &mut self.non_ref_ad_problems {
+            if let Some(p) = crate::header::non_ref_ad_problem(variant.record()) {
+                if *n == 0 {
+                    log::warn!("{}", p);
+                }
+                *n += 1;
+            }
+        }
         let mut info_results = HashMap::new();
+        let mut format_results = vec![];
+        let samples = if self.per_sample
+            || self
+                .set_expressions
+                .keys()
+                .any(|k| matches!(k, InfoFormat::Format(_)))
+        {
+            let ids = (0..variant.record().sample_count() as usize).collect::<Vec<_>>();
+            crate::sample::sample_tables(self.lua, variant.record(), &ids)
+                .map_err(std::io::Error::other)?
+        } else {
+            vec![]
+        };
+        let alleles = if self.per_allele {
+            let alts = variant
+                .record()
+                .alleles()
+                .iter()
+                .skip(1)
+                .map(|a| a.to_vec())
+                .collect::<Vec<_>>();
+            let infos = crate::allele::allele_info_tables(self.lua, variant.record())
+                .map_err(std::io::Error::other)?;
+            alts.into_iter().zip(infos).collect::<Vec<_>>()
+        } else {
+            vec![]
+        };
+        let mut keep_alleles = vec![];
+        let mut group_key = None;
+        let mut burden_genes = vec![];
         let eval_result = self.lua.scope(|scope| {
             let ud = match scope.create_any_userdata_ref_mut(&mut variant) {
                 Ok(ud) => ud,
                 Err(e) => return Err(e),
             };
+            for (i, sample) in samples.iter().enumerate() {
+                crate::sample::attach(sample, ud.clone(), i)?;
+            }
             match self.globals.raw_set("variant", ud) {
                 Ok(_) => (),
                 Err(e) => return Err(e),
             }
+            self.globals
+                .raw_set("header", scope.create_any_userdata_ref(&self.header)?)?;
             self.evaluate_info_expressions(&mut info_results)?;
-            // we have many expressions, we stop on the first passing expression. The result of this scope
+            self.evaluate_format_expressions(&samples, &mut format_results)?;
+            if self.per_sample {
+                let result = self.evaluate_per_sample(&samples)?;
+                if !matches!(result, StringOrVariant::None) {
+                    self.variants_passing += 1;
+                    self.stats.raw_set("passing", self.variants_passing)?;
+                }
+                return Ok(result);
+            }
+            if self.per_allele {
+                let result = self.evaluate_per_allele(&alleles, &mut keep_alleles)?;
+                if !matches!(result, StringOrVariant::None) {
+                    self.variants_passing += 1;
+                    self.stats.raw_set("passing", self.variants_passing)?;
+                }
+                return Ok(result);
+            }
+            // we have many expressions, we stop on the first passing expression unless we count the
+            // variants passing each one. The result of this scope
             // can be either a bool, or a string (if we have a template).
-            for exp in &self.expressions {
-                match exp.call::<_, bool>(()) {
-                    Err(e) => return Err(e),
-                    Ok(true) => {
-                        self.variants_passing += 1;
-                        if let Some(template) = &self.template {
-                            // if we have a template, we want to evaluate it in this same scope.
-                            return match template.call::<_, String>(()) {
-                                Ok(res) => Ok(StringOrVariant::String(res)),
-                                Err(e) => {
-                                    log::error!("Error in template: {}", e);
-                                    return Err(e);
-                                }
-                            };
-                        }
-                        return Ok(StringOrVariant::Variant(None));
+            let mut passed = false;
+            for (exp, tally) in self.expressions.iter().zip(&mut self.expression_tallies) {
+                tally.evaluated += 1;
+                if exp.call::<_, bool>(())? {
+                    tally.passing += 1;
+                    if !passed {
+                        tally.decisive += 1;
+                        passed = true;
+                    }
+                    if !self.evaluate_all_expressions {
+                        break;
                     }
-                    Ok(false) => {}
                 }
             }
-
-            Ok(StringOrVariant::None)
+            if !passed {
+                return Ok(StringOrVariant::None);
+            }
+            self.variants_passing += 1;
+            self.stats.raw_set("passing", self.variants_passing)?;
+            if let Some(groups) = &self.groups {
+                group_key = groups.key()?;
+                return Ok(StringOrVariant::Variant(None));
+            }
+            if let Some(burden) = &self.burden {
+                burden_genes = burden.genes()?;
+                return Ok(StringOrVariant::Variant(None));
+            }
+            if let Some(template) = &self.template {
+                // if we have a template, we want to evaluate it in this same scope.
+                return match self.render(template) {
+                    Ok(res) => Ok(StringOrVariant::String(res)),
+                    Err(e) => {
+                        log::error!("Error in template: {}", e);
+                        return Err(e);
+                    }
+                };
+            }
+            Ok(StringOrVariant::Variant(None))
         });
 
+        if let Err(e) = variant.flush_genotypes() {
+            log::error!("Error setting genotypes: {}", e);
+            return Err(std::io::Error::other(e));
+        }
         let mut record = variant.take();
         for (stag, value) in info_results {
             let tag = stag.as_bytes();
@@ -375,14 +1792,94 @@ impl<'lua> VCFExpress<'lua> {
                 Ok(_) => (),
                 Err(e) => {
                     log::error!("Error setting info field: {}: {}", stag, e);
-                    return Err(std::io::Error::new(std::io::ErrorKind::Other, e));
+                    return Err(std::io::Error::other(e));
                 }
             }
         }
+        for (tag, values) in format_results {
+            if let Err(e) = crate::variant::set_format(&mut record, &tag, &values) {
+                log::error!("Error setting format field: {}: {}", tag, e);
+                return Err(std::io::Error::other(e));
+            }
+        }
+        // before any failing alleles are removed so the record matches the baseline site.
+        if let (Some(baseline), Ok(r)) = (&mut self.baseline, &eval_result) {
+            if !matches!(r, StringOrVariant::None) {
+                baseline.add_passing(&record);
+            }
+        }
+        if self.per_allele && matches!(eval_result, Ok(StringOrVariant::Variant(None))) {
+            crate::allele::remove_alleles(&mut record, &keep_alleles)?;
+        }
+        let eval_result = match (&self.soft_filter, eval_result) {
+            (Some((name, append)), Ok(StringOrVariant::None)) => {
+                if *append {
+                    record.push_filter(name.as_bytes())
+                } else {
+                    record.set_filters(&[name.as_bytes()])
+                }
+                .map_err(std::io::Error::other)?;
+                Ok(StringOrVariant::Variant(None))
+            }
+            (Some(_), Ok(r)) => {
+                if record.filters().next().is_none() {
+                    record
+                        .set_filters(&[b"PASS".as_slice()])
+                        .map_err(std::io::Error::other)?;
+                }
+                Ok(r)
+            }
+            (_, r) => r,
+        };
         match eval_result {
-            Ok(StringOrVariant::Variant(None)) => Ok(StringOrVariant::Variant(Some(record))),
+            Ok(_) if self.count_only => Ok(StringOrVariant::None),
+            Ok(StringOrVariant::Variant(None)) => {
+                if let Some(direction) = self.alt_filter {
+                    crate::altfilter::translate(&mut record, direction)?;
+                }
+                self.rounding
+                    .apply(&mut record)
+                    .map_err(std::io::Error::other)?;
+                if self.prune_empty {
+                    crate::prune::prune_empty(&mut record).map_err(std::io::Error::other)?;
+                }
+                if let Some(density) = &mut self.density {
+                    let chrom = record
+                        .rid()
+                        .and_then(|rid| record.header().rid2name(rid).ok())
+                        .unwrap_or_default();
+                    let rows = density.add(&String::from_utf8_lossy(chrom), record.pos())?;
+                    if rows.is_empty() {
+                        return Ok(StringOrVariant::None);
+                    }
+                    return Ok(StringOrVariant::String(rows.join("\n")));
+                }
+                if let Some(groups) = &mut self.groups {
+                    if let Some(key) = group_key {
+                        groups.add(key, &record, header_map);
+                    }
+                    return Ok(StringOrVariant::None);
+                }
+                if let Some(burden) = &mut self.burden {
+                    burden.add(burden_genes, &record)?;
+                    return Ok(StringOrVariant::None);
+                }
+                Ok(StringOrVariant::Variant(Some(record)))
+            }
             Ok(b) => Ok(b),
-            Err(e) => Err(std::io::Error::new(std::io::ErrorKind::Other, e)),
+            Err(e) => {
+                let chrom = record
+                    .rid()
+                    .and_then(|rid| record.header().rid2name(rid).ok())
+                    .unwrap_or_default();
+                Err(std::io::Error::other(format!(
+                    "error evaluating record {} (0-based) at {}:{}: {}",
+                    index,
+                    String::from_utf8_lossy(chrom),
+                    record.pos() + 1,
+                    e
+                )))
+            }
         }
     }
 }
@@ -390,8 +1887,700 @@ impl<'lua> VCFExpress<'lua> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::variant::Sentinel;
     use mlua::Lua;
 
+    #[test]
+    fn test_wrap_template_values() {
+        let (t, exprs) = wrap_template_values(r#"`{variant.id}\t{ {1}[1] } \{x}`"#);
+        assert_eq!(
+            t,
+            r#"`{__template_value((variant.id), 1)}\t{__template_value(( {1}[1] ), 2)} \{x}`"#
+        );
+        assert_eq!(exprs, vec!["variant.id", "{1}[1]"]);
+        // only backtick strings are interpolated.
+        let (t, _) = wrap_template_values(r#"return `{a}` .. "{b}""#);
+        assert_eq!(t, r#"return `{__template_value((a), 1)}` .. "{b}""#);
+    }
+
+    #[test]
+    fn test_set_format() {
+        let mut header = bcf::Header::new();
+        header.push_record(br#"##contig=<ID=chr1,length=10000>"#);
+        header.push_record(br#"##FORMAT=<ID=DP,Number=1,Type=Integer,Description="Depth">"#);
+        header.push_record(br#"##FORMAT=<ID=DP2,Number=1,Type=Integer,Description="Depth x2">"#);
+        header.push_sample(b"S1");
+        header.push_sample(b"S2");
+        let mut vcf =
+            bcf::Writer::from_path("_test.setformat.vcf", &header, true, bcf::Format::Vcf).unwrap();
+        let mut record = vcf.empty_record();
+        record.set_rid(Some(0));
+        record.set_pos(9);
+        record.set_alleles(&[b"A", b"T"]).unwrap();
+        record.push_format_integer(b"DP", &[5, i32::MIN]).unwrap();
+        vcf.write(&record).unwrap();
+        drop(vcf);
+
+        let lua = Lua::new();
+        let mut vcfexpr = VCFExpress::with_options(
+            &lua,
+            "_test.setformat.vcf".to_string(),
+            vec!["return true".to_string()],
+            vec![],
+            None,
+            vec![],
+            Some("_test.setformat.out.vcf".to_string()),
+            false,
+            VCFExpressOptions {
+                set_format: vec!["DP2=return sample.DP and sample.DP * 2".to_string()],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let mut reader = vcfexpr.reader();
+        let record = reader.records().next().unwrap().unwrap();
+        match vcfexpr.evaluate(record, HeaderMap::new()).unwrap() {
+            StringOrVariant::Variant(Some(record)) => {
+                let dp2 = record.format(b"DP2").integer().unwrap();
+                assert_eq!(dp2[0], [10]);
+                assert_eq!(dp2[1], [i32::MIN]);
+            }
+            _ => panic!("expected a variant"),
+        }
+        _ = std::fs::remove_file("_test.setformat.vcf");
+        _ = std::fs::remove_file("_test.setformat.out.vcf");
+    }
+
+    #[test]
+    fn test_on_progress() {
+        let mut header = bcf::Header::new();
+        header.push_record(br#"##contig=<ID=chr1,length=10000>"#);
+        let mut vcf =
+            bcf::Writer::from_path("_test.progress.vcf", &header, true, bcf::Format::Vcf).unwrap();
+        for pos in [9, 19, 29] {
+            let mut record = vcf.empty_record();
+            record.set_rid(Some(0));
+            record.set_pos(pos);
+            record.set_alleles(&[b"A", b"T"]).unwrap();
+            vcf.write(&record).unwrap();
+        }
+        drop(vcf);
+        std::fs::write(
+            "_test.progress.lua",
+            r#"calls = {}
+function on_progress(n, locus) table.insert(calls, `{n}@{locus}`) end
+function on_start(h) table.insert(calls, `start {#h:contigs()}`) end
+function on_end()
+    table.insert(calls, `end {stats.evaluated}`)
+    emit(`positions\t{table.concat(state.positions, ",")}`)
+end
+"#,
+        )
+        .unwrap();
+
+        let lua = Lua::new();
+        let mut vcfexpr = VCFExpress::new(
+            &lua,
+            "_test.progress.vcf".to_string(),
+            vec![
+                "state.positions = state.positions or {}; table.insert(state.positions, variant.pos); return true"
+                    .to_string(),
+            ],
+            vec![],
+            None,
+            vec!["_test.progress.lua".to_string()],
+            Some("_test.progress.out.vcf".to_string()),
+            false,
+        )
+        .unwrap();
+        vcfexpr.set_progress_every(2);
+        vcfexpr.set_summary_output(Some("_test.progress.summary.txt".to_string()));
+        let mut reader = vcfexpr.reader();
+        let mut writer = vcfexpr.writer().unwrap();
+        vcfexpr.start().unwrap();
+        for record in reader.records() {
+            vcfexpr.evaluate(record.unwrap(), HeaderMap::new()).unwrap();
+        }
+        vcfexpr.finish(&mut writer).unwrap();
+        let calls: Vec<String> = lua.load("return calls").eval().unwrap();
+        assert_eq!(calls, vec!["start 1", "2@chr1:20", "end 3"]);
+        assert_eq!(
+            std::fs::read_to_string("_test.progress.summary.txt").unwrap(),
+            "positions\t9,19,29\n"
+        );
+        let (evaluated, passing, n): (usize, usize, f64) = lua
+            .load("stats:incr('x'); stats:incr('x', 2); return stats.evaluated, stats.passing, stats.counters.x")
+            .eval()
+            .unwrap();
+        assert_eq!((evaluated, passing, n), (3, 3, 3.0));
+        for f in [
+            "_test.progress.vcf",
+            "_test.progress.lua",
+            "_test.progress.out.vcf",
+            "_test.progress.summary.txt",
+        ] {
+            _ = std::fs::remove_file(f);
+        }
+    }
+
+    #[test]
+    fn test_count() {
+        let mut header = bcf::Header::new();
+        header.push_record(br#"##contig=<ID=chr1,length=10000>"#);
+        let mut vcf =
+            bcf::Writer::from_path("_test.count.vcf", &header, true, bcf::Format::Vcf).unwrap();
+        for pos in [9, 19, 29] {
+            let mut record = vcf.empty_record();
+            record.set_rid(Some(0));
+            record.set_pos(pos);
+            record.set_alleles(&[b"A", b"T"]).unwrap();
+            vcf.write(&record).unwrap();
+        }
+        drop(vcf);
+
+        let lua = Lua::new();
+        let mut vcfexpr = VCFExpress::new(
+            &lua,
+            "_test.count.vcf".to_string(),
+            vec![
+                "return variant.pos > 10".to_string(),
+                "return variant.pos < 20".to_string(),
+            ],
+            vec![],
+            None,
+            vec![],
+            Some("_test.count.txt".to_string()),
+            false,
+        )
+        .unwrap();
+        vcfexpr.set_count(true);
+        vcfexpr.count_per_expression();
+        let mut reader = vcfexpr.reader();
+        let mut writer = vcfexpr.writer().unwrap();
+        for record in reader.records() {
+            let mut sob = vcfexpr.evaluate(record.unwrap(), HeaderMap::new()).unwrap();
+            assert!(matches!(sob, StringOrVariant::None));
+            writer.write(&mut sob).unwrap();
+        }
+        vcfexpr.finish(&mut writer).unwrap();
+        drop(writer);
+        assert_eq!(vcfexpr.expression_counts(), Some(vec![2, 2]));
+        // position 19 passes both but the first decides.
+        assert_eq!(
+            vcfexpr.expression_tallies(),
+            [
+                ExpressionTally {
+                    evaluated: 3,
+                    passing: 2,
+                    decisive: 2
+                },
+                ExpressionTally {
+                    evaluated: 3,
+                    passing: 2,
+                    decisive: 1
+                }
+            ]
+        );
+        assert_eq!(
+            std::fs::read_to_string("_test.count.txt").unwrap(),
+            "evaluated\t3\npassing\t3\npassing:1\t2\npassing:2\t2\n"
+        );
+        _ = std::fs::remove_file("_test.count.vcf");
+        _ = std::fs::remove_file("_test.count.txt");
+    }
+
+    #[test]
+    fn test_empty_input() {
+        let mut header = bcf::Header::new();
+        header.push_record(br#"##contig=<ID=chr1,length=10000>"#);
+        drop(bcf::Writer::from_path("_test.empty.vcf", &header, true, bcf::Format::Vcf).unwrap());
+
+        let lua = Lua::new();
+        let mut vcfexpr = VCFExpress::new(
+            &lua,
+            "_test.empty.vcf".to_string(),
+            vec!["return true".to_string()],
+            vec![],
+            None,
+            vec![],
+            Some("_test.empty.out.bcf".to_string()),
+            false,
+        )
+        .unwrap();
+        let mut reader = vcfexpr.reader();
+        let mut writer = vcfexpr.writer().unwrap();
+        vcfexpr.start().unwrap();
+        assert_eq!(reader.records().count(), 0);
+        vcfexpr.finish(&mut writer).unwrap();
+        drop(writer);
+        assert_eq!(vcfexpr.counts(), (0, 0));
+        // a valid, header-only output.
+        let mut out = bcf::Reader::from_path("_test.empty.out.bcf").unwrap();
+        assert_eq!(out.header().contig_count(), 1);
+        assert_eq!(out.records().count(), 0);
+        _ = std::fs::remove_file("_test.empty.vcf");
+        _ = std::fs::remove_file("_test.empty.out.bcf");
+    }
+
+    #[test]
+    fn test_vcf_line() {
+        let mut header = bcf::Header::new();
+        header.push_record(br#"##contig=<ID=chr1,length=10000>"#);
+        header.push_record(br#"##INFO=<ID=DP,Number=1,Type=Integer,Description="DP">"#);
+        let vcf = bcf::Writer::from_path("_test.tee.vcf", &header, true, bcf::Format::Vcf).unwrap();
+        let mut record = vcf.empty_record();
+        record.set_rid(Some(0));
+        record.set_pos(9);
+        record.set_alleles(&[b"A", b"T"]).unwrap();
+        record.push_info_integer(b"DP", &[12]).unwrap();
+        assert_eq!(
+            String::from_utf8(vcf_line(vcf.header(), &record).unwrap()).unwrap(),
+            "chr1\t10\t.\tA\tT\t0\t.\tDP=12\n"
+        );
+        drop(vcf);
+        _ = std::fs::remove_file("_test.tee.vcf");
+    }
+
+    #[test]
+    fn test_two_pass() {
+        let mut header = bcf::Header::new();
+        header.push_record(br#"##contig=<ID=chr1,length=10000>"#);
+        header.push_record(br#"##FORMAT=<ID=DP,Number=1,Type=Integer,Description="DP">"#);
+        header.push_sample(b"S1");
+        header.push_sample(b"S2");
+        let mut vcf =
+            bcf::Writer::from_path("_test.twopass.vcf", &header, true, bcf::Format::Vcf).unwrap();
+        for (pos, qual, dp) in [(9, 10.0, [10, 1]), (19, 50.0, [20, 1]), (29, 30.0, [30, 1])] {
+            let mut record = vcf.empty_record();
+            record.set_rid(Some(0));
+            record.set_pos(pos);
+            record.set_qual(qual);
+            record.set_alleles(&[b"A", b"T"]).unwrap();
+            record
+                .push_format_integer(b"DP", &[dp[0], i32::missing()])
+                .unwrap();
+            vcf.write(&record).unwrap();
+        }
+        drop(vcf);
+        std::fs::write(
+            "_test.twopass.lua",
+            "function first_pass(v) globals_stats.max_pos = math.max(globals_stats.max_pos or 0, v.pos) end\n",
+        )
+        .unwrap();
+
+        let lua = Lua::new();
+        let mut vcfexpr = VCFExpress::new(
+            &lua,
+            "_test.twopass.vcf".to_string(),
+            vec!["return variant.qual >= globals_stats:qual_quantile(0.5)".to_string()],
+            vec![],
+            None,
+            vec!["_test.twopass.lua".to_string()],
+            Some("_test.twopass.out.vcf".to_string()),
+            false,
+        )
+        .unwrap();
+        let mut first = bcf::Reader::from_path("_test.twopass.vcf").unwrap();
+        let mut records = first.records();
+        vcfexpr.first_pass(|| records.next()).unwrap();
+        let mut reader = vcfexpr.reader();
+        for record in reader.records() {
+            vcfexpr.evaluate(record.unwrap(), HeaderMap::new()).unwrap();
+        }
+        assert_eq!(vcfexpr.counts(), (3, 2));
+        let (n, depth, max_pos, p90): (usize, f64, i64, f64) = lua
+            .load("return globals_stats.n, globals_stats.mean_depth.S1, globals_stats.max_pos, globals_stats:qual_quantile(0.9)")
+            .eval()
+            .unwrap();
+        assert_eq!((n, depth, max_pos, p90), (3, 20.0, 29, 46.0));
+        assert!(lua
+            .load("return globals_stats.mean_depth.S2 == nil")
+            .eval::<bool>()
+            .unwrap());
+        assert!(lua
+            .load("return globals_stats:qual_quantile(2)")
+            .eval::<Option<f64>>()
+            .is_err());
+
+        // the first pass counts only the records in the slice and kept by the filters.
+        let lua = Lua::new();
+        let mut vcfexpr = VCFExpress::new(
+            &lua,
+            "_test.twopass.vcf".to_string(),
+            vec!["return variant.qual >= globals_stats:qual_quantile(0.5)".to_string()],
+            vec![],
+            None,
+            vec!["_test.twopass.lua".to_string()],
+            Some("_test.twopass.out.vcf".to_string()),
+            false,
+        )
+        .unwrap();
+        vcfexpr.set_record_slice(1, None);
+        let mut first = bcf::Reader::from_path("_test.twopass.vcf").unwrap();
+        let mut records = first.records();
+        vcfexpr.first_pass(|| records.next()).unwrap();
+        let mut reader = vcfexpr.reader();
+        for record in reader.records() {
+            let record = record.unwrap();
+            if vcfexpr.in_slice().unwrap() && vcfexpr.keep_record(&record).unwrap() {
+                vcfexpr.evaluate(record, HeaderMap::new()).unwrap();
+            }
+        }
+        assert_eq!(vcfexpr.counts(), (2, 1));
+        assert_eq!(vcfexpr.record_slice(), (1, false));
+        let (n, depth, p90): (usize, f64, f64) = lua
+            .load("return globals_stats.n, globals_stats.mean_depth.S1, globals_stats:qual_quantile(0.9)")
+            .eval()
+            .unwrap();
+        assert_eq!((n, depth, p90), (2, 25.0, 48.0));
+        for f in [
+            "_test.twopass.vcf",
+            "_test.twopass.lua",
+            "_test.twopass.out.vcf",
+        ] {
+            _ = std::fs::remove_file(f);
+        }
+    }
+
+    #[test]
+    fn test_per_sample() {
+        let mut header = bcf::Header::new();
+        header.push_record(br#"##contig=<ID=chr1,length=10000>"#);
+        header.push_record(br#"##FORMAT=<ID=DP,Number=1,Type=Integer,Description="Depth">"#);
+        header.push_sample(b"S1");
+        header.push_sample(b"S2");
+        header.push_sample(b"S3");
+        let mut vcf =
+            bcf::Writer::from_path("_test.persample.vcf", &header, true, bcf::Format::Vcf).unwrap();
+        let mut record = vcf.empty_record();
+        record.set_rid(Some(0));
+        record.set_pos(9);
+        record.set_alleles(&[b"A", b"T"]).unwrap();
+        record.push_format_integer(b"DP", &[5, 20, 30]).unwrap();
+        vcf.write(&record).unwrap();
+        drop(vcf);
+
+        let run = |template: Option<&str>, expression: &str, one_based: bool| {
+            let lua = Lua::new();
+            let mut vcfexpr = VCFExpress::new(
+                &lua,
+                "_test.persample.vcf".to_string(),
+                vec![expression.to_string()],
+                vec![],
+                template.map(|t| t.to_string()),
+                vec![],
+                Some("_test.persample.out".to_string()),
+                false,
+            )
+            .unwrap();
+            vcfexpr.set_per_sample(true);
+            vcfexpr.set_one_based_template_pos(one_based);
+            let mut reader = vcfexpr.reader();
+            let record = reader.records().next().unwrap().unwrap();
+            vcfexpr.evaluate(record, HeaderMap::new()).unwrap()
+        };
+        match run(
+            Some("{variant.pos}\t{sample:name()}"),
+            "return sample.DP > 10",
+            false,
+        ) {
+            StringOrVariant::String(s) => assert_eq!(s, "9\tS2\n9\tS3"),
+            _ => panic!("expected a string"),
+        }
+        assert!(matches!(
+            run(None, "return sample.DP > 25", false),
+            StringOrVariant::Variant(Some(_))
+        ));
+        assert!(matches!(
+            run(None, "return sample.DP > 50", false),
+            StringOrVariant::None
+        ));
+        // --coords 1 changes variant.pos in the template but not in the expression.
+        match run(
+            Some("{variant.pos}:{variant.POS}:{variant.start}"),
+            "return variant.pos == 9 and sample.DP > 25",
+            true,
+        ) {
+            StringOrVariant::String(s) => assert_eq!(s, "10:10:9"),
+            _ => panic!("expected a string"),
+        }
+        _ = std::fs::remove_file("_test.persample.vcf");
+        _ = std::fs::remove_file("_test.persample.out");
+    }
+
+    #[test]
+    fn test_prelude_subset_samples() {
+        let mut header = bcf::Header::new();
+        header.push_record(br#"##contig=<ID=chr1,length=10000>"#);
+        header.push_record(br#"##FORMAT=<ID=DP,Number=1,Type=Integer,Description="Depth">"#);
+        header.push_sample(b"S1");
+        header.push_sample(b"S2");
+        header.push_sample(b"S3");
+        let mut vcf =
+            bcf::Writer::from_path("_test.subset.vcf", &header, true, bcf::Format::Vcf).unwrap();
+        let mut record = vcf.empty_record();
+        record.set_rid(Some(0));
+        record.set_pos(9);
+        record.set_alleles(&[b"A", b"T"]).unwrap();
+        record.push_format_integer(b"DP", &[5, 20, 30]).unwrap();
+        vcf.write(&record).unwrap();
+        drop(vcf);
+        std::fs::write("_test.subset.lua", r#"header.samples = {"S3", "S1"}"#).unwrap();
+
+        let lua = Lua::new();
+        let mut vcfexpr = VCFExpress::new(
+            &lua,
+            "_test.subset.vcf".to_string(),
+            vec!["return variant:sample('S3').DP == 30".to_string()],
+            vec![],
+            None,
+            vec!["_test.subset.lua".to_string()],
+            Some("_test.subset.out.vcf".to_string()),
+            false,
+        )
+        .unwrap();
+        let mut reader = vcfexpr.reader();
+        let mut writer = vcfexpr.writer().unwrap();
+        let mut record = reader.records().next().unwrap().unwrap();
+        vcfexpr.translate(&mut writer, &mut record).unwrap();
+        let mut sob = vcfexpr.evaluate(record, HeaderMap::new()).unwrap();
+        writer.write(&mut sob).unwrap();
+        drop(writer);
+        let text = std::fs::read_to_string("_test.subset.out.vcf").unwrap();
+        assert!(text.contains("\tFORMAT\tS1\tS3\n"));
+        assert!(text.ends_with("\tDP\t5\t30\n"));
+        for f in ["_test.subset.vcf", "_test.subset.lua", "_test.subset.out.vcf"] {
+            _ = std::fs::remove_file(f);
+        }
+    }
+
+    #[test]
+    fn test_rename_contigs() {
+        let mut header = bcf::Header::new();
+        header.push_record(br#"##contig=<ID=chr1,length=10000>"#);
+        header.push_record(br#"##contig=<ID=chr2,length=20000>"#);
+        let mut vcf =
+            bcf::Writer::from_path("_test.renamechrs.vcf", &header, true, bcf::Format::Vcf)
+                .unwrap();
+        for rid in [0, 1] {
+            let mut record = vcf.empty_record();
+            record.set_rid(Some(rid));
+            record.set_pos(9);
+            record.set_alleles(&[b"A", b"T"]).unwrap();
+            vcf.write(&record).unwrap();
+        }
+        drop(vcf);
+
+        let lua = Lua::new();
+        let mut vcfexpr = VCFExpress::new(
+            &lua,
+            "_test.renamechrs.vcf".to_string(),
+            // expressions see the new name.
+            vec!["return variant.chrom == '1'".to_string()],
+            vec![],
+            None,
+            vec![],
+            Some("_test.renamechrs.out.vcf".to_string()),
+            false,
+        )
+        .unwrap();
+        vcfexpr
+            .rename_contigs(&HashMap::from([("chr1".to_string(), "1".to_string())]))
+            .unwrap();
+        let mut reader = vcfexpr.reader();
+        let mut writer = vcfexpr.writer().unwrap();
+        for r in reader.records() {
+            let mut record = r.unwrap();
+            vcfexpr.translate(&mut writer, &mut record).unwrap();
+            let mut sob = vcfexpr.evaluate(record, HeaderMap::new()).unwrap();
+            writer.write(&mut sob).unwrap();
+        }
+        drop(writer);
+        let text = std::fs::read_to_string("_test.renamechrs.out.vcf").unwrap();
+        assert!(text.contains("##contig=<ID=1,length=10000>\n##contig=<ID=chr2,length=20000>"));
+        assert!(text.ends_with("\n1\t10\t.\tA\tT\t0\t.\t.\n"), "{}", text);
+        for f in ["_test.renamechrs.vcf", "_test.renamechrs.out.vcf"] {
+            _ = std::fs::remove_file(f);
+        }
+    }
+
+    #[test]
+    fn test_soft_filter() {
+        let mut header = bcf::Header::new();
+        header.push_record(br#"##contig=<ID=chr1,length=10000>"#);
+        header.push_record(br#"##FILTER=<ID=q10,Description="Quality below 10">"#);
+        let mut vcf =
+            bcf::Writer::from_path("_test.softfilter.vcf", &header, true, bcf::Format::Vcf)
+                .unwrap();
+        for (pos, filter) in [
+            (9, None),
+            (19, Some(b"q10")),
+            (29, Some(b"q10")),
+            (39, None),
+        ] {
+            let mut record = vcf.empty_record();
+            record.set_rid(Some(0));
+            record.set_pos(pos);
+            record.set_alleles(&[b"A", b"T"]).unwrap();
+            if let Some(filter) = filter {
+                record.push_filter(filter.as_slice()).unwrap();
+            }
+            vcf.write(&record).unwrap();
+        }
+        drop(vcf);
+
+        for (append, expected) in [
+            (false, ["PASS", "Soft", "q10", "Soft"]),
+            (true, ["PASS", "q10;Soft", "q10", "Soft"]),
+        ] {
+            let lua = Lua::new();
+            let mut vcfexpr = VCFExpress::new(
+                &lua,
+                "_test.softfilter.vcf".to_string(),
+                vec!["return variant.pos == 9 or variant.pos == 29".to_string()],
+                vec![],
+                None,
+                vec![],
+                Some("_test.softfilter.out.vcf".to_string()),
+                false,
+            )
+            .unwrap();
+            assert!(vcfexpr.set_soft_filter("PASS", "", append).is_err());
+            vcfexpr.set_soft_filter("Soft", "failed", append).unwrap();
+            let mut reader = vcfexpr.reader();
+            let mut writer = vcfexpr.writer().unwrap();
+            for r in reader.records() {
+                let mut record = r.unwrap();
+                vcfexpr.translate(&mut writer, &mut record).unwrap();
+                let mut sob = vcfexpr.evaluate(record, HeaderMap::new()).unwrap();
+                writer.write(&mut sob).unwrap();
+            }
+            drop(writer);
+            let text = std::fs::read_to_string("_test.softfilter.out.vcf").unwrap();
+            assert!(text.contains(r#"##FILTER=<ID=Soft,Description="failed">"#));
+            let filters = text
+                .lines()
+                .filter(|l| !l.starts_with('#'))
+                .map(|l| l.split('\t').nth(6).unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(filters, expected);
+        }
+        for f in ["_test.softfilter.vcf", "_test.softfilter.out.vcf"] {
+            _ = std::fs::remove_file(f);
+        }
+    }
+
+    #[test]
+    fn test_record_index() {
+        let mut header = bcf::Header::new();
+        header.push_record(br#"##contig=<ID=chr1,length=10000>"#);
+        let mut vcf =
+            bcf::Writer::from_path("_test.recindex.vcf", &header, true, bcf::Format::Vcf).unwrap();
+        for pos in [9, 19, 29, 39, 49] {
+            let mut record = vcf.empty_record();
+            record.set_rid(Some(0));
+            record.set_pos(pos);
+            record.set_alleles(&[b"A", b"T"]).unwrap();
+            vcf.write(&record).unwrap();
+        }
+        drop(vcf);
+
+        let lua = Lua::new();
+        let mut vcfexpr = VCFExpress::new(
+            &lua,
+            "_test.recindex.vcf".to_string(),
+            vec![
+                "if variant.index == 4 then error('bad') end return variant.index == 2".to_string(),
+            ],
+            vec![],
+            None,
+            vec![],
+            None,
+            false,
+        )
+        .unwrap();
+        // the index is the record's place in the input, not the number of records evaluated.
+        vcfexpr.set_record_slice(1, None);
+        let mut reader = vcfexpr.reader();
+        let mut kept = vec![];
+        for r in reader.records() {
+            let record = r.unwrap();
+            if !vcfexpr.in_slice().unwrap() {
+                continue;
+            }
+            match vcfexpr.evaluate(record, HeaderMap::new()) {
+                Ok(StringOrVariant::Variant(Some(record))) => kept.push(record.pos()),
+                Ok(_) => {}
+                Err(e) => assert!(e.to_string().contains("record 4 (0-based) at chr1:50")),
+            }
+        }
+        assert_eq!(kept, vec![29]);
+        _ = std::fs::remove_file("_test.recindex.vcf");
+    }
+
+    #[test]
+    fn test_file_offset() {
+        let mut header = bcf::Header::new();
+        header.push_record(br#"##contig=<ID=chr1,length=10000>"#);
+        for (path, format) in [
+            ("_test.offset.bcf", bcf::Format::Bcf),
+            ("_test.offset.vcf", bcf::Format::Vcf),
+        ] {
+            let mut wtr =
+                bcf::Writer::from_path(path, &header, path.ends_with(".vcf"), format).unwrap();
+            for pos in [9, 19] {
+                let mut record = wtr.empty_record();
+                record.set_rid(Some(0));
+                record.set_pos(pos);
+                record.set_alleles(&[b"A", b"T"]).unwrap();
+                wtr.write(&record).unwrap();
+            }
+        }
+        assert!(BgzfOffset::new(&bcf::Reader::from_path("_test.offset.vcf").unwrap()).is_none());
+
+        let lua = Lua::new();
+        let mut vcfexpr = VCFExpress::new(
+            &lua,
+            "_test.offset.bcf".to_string(),
+            vec!["return variant.file_offset".to_string()],
+            vec![],
+            Some("{variant.file_offset}".to_string()),
+            vec![],
+            None,
+            false,
+        )
+        .unwrap();
+        let mut reader = vcfexpr.reader();
+        let offset = BgzfOffset::new(&reader).unwrap();
+        let mut offsets = vec![];
+        loop {
+            let before = offset.tell();
+            let mut record = reader.empty_record();
+            match reader.read(&mut record) {
+                Some(r) => r.unwrap(),
+                None => break,
+            }
+            vcfexpr.set_file_offset(Some(before));
+            match vcfexpr.evaluate(record, HeaderMap::new()).unwrap() {
+                StringOrVariant::String(s) => offsets.push(s.parse::<u64>().unwrap()),
+                _ => panic!("expected the offset"),
+            }
+        }
+        // the records follow the header in the first block.
+        assert_eq!(offsets.len(), 2);
+        assert!(offsets[0] > 0 && offsets[0] >> 16 == 0 && offsets[1] > offsets[0]);
+        // without an offset, the expression returns nil and the record does not pass.
+        let mut record = reader.empty_record();
+        record.set_pos(9);
+        assert!(matches!(
+            vcfexpr.evaluate(record, HeaderMap::new()).unwrap(),
+            StringOrVariant::None
+        ));
+        for f in ["_test.offset.bcf", "_test.offset.vcf"] {
+            _ = std::fs::remove_file(f);
+        }
+    }
+
     #[test]
     fn test_process_template_with_none() {
         let lua = Lua::new();
@@ -433,6 +2622,15 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_format_set_tags() {
+        assert_eq!(
+            format_set_tags(r#"variant:format_set('AB', x); variant:format_set( "VAF", y)"#),
+            vec!["AB", "VAF"]
+        );
+        assert!(format_set_tags("variant:format_set(name, x)").is_empty());
+    }
+
     #[test]
     #[should_panic(expected = "error in template")]
     fn test_process_template_with_invalid_lua() {
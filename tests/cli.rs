@@ -0,0 +1,31 @@
+//! Tests that run the vcfexpress binary.
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+const VCF: &str = "##fileformat=VCFv4.2
+##contig=<ID=chr1,length=10000>
+#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO
+chr1\t100\t.\tA\tG\t50\tPASS\t.
+";
+
+#[test]
+fn test_two_pass_rejects_stdin() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_vcfexpress"))
+        .args(["filter", "--two-pass", "-e", "return true", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // the input may not be read at all.
+    _ = child.stdin.take().unwrap().write_all(VCF.as_bytes());
+    let out = child.wait_with_output().unwrap();
+    assert!(!out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains("--two-pass requires an input file; stdin can not be read twice"),
+        "{}",
+        stderr
+    );
+    assert!(out.stdout.is_empty());
+}